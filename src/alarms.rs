@@ -0,0 +1,381 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::notifications::{Notification, Notifier};
+use crate::database::{
+    AlarmDirection, AlarmEvent, AlarmLevel, AlarmMetric, AlarmRule, AlarmSeriesState, DatabaseManager, QueryParams,
+    SeriesKey, TelemetryData, MAX_QUERY_LIMIT,
+};
+
+// 后台定时判断的间隔（秒），设为0关闭后台判断
+const EVALUATION_INTERVAL_ENV: &str = "LDC_ALARM_INTERVAL";
+const DEFAULT_EVALUATION_INTERVAL: u64 = 60;
+
+// 同一时间只运行一次判断，避免后台任务和手动触发重复生成事件
+static EVALUATION_LOCK: Mutex<()> = Mutex::new(());
+
+/// 一次告警判断的统计
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct AlarmEvaluation {
+    pub rules_evaluated: usize,
+    pub series_evaluated: usize,
+    pub points_evaluated: usize,
+    /// 新触发的告警
+    pub raised: usize,
+    /// 升级的告警
+    pub escalated: usize,
+    /// 恢复的告警
+    pub cleared: usize,
+}
+
+/// 启动后台告警判断，间隔由环境变量 LDC_ALARM_INTERVAL 指定
+pub fn spawn_evaluator(db: Arc<DatabaseManager>, notifier: Arc<Notifier>) {
+    let interval = std::env::var(EVALUATION_INTERVAL_ENV).ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_EVALUATION_INTERVAL);
+    if interval == 0 {
+        println!("Alarm evaluation disabled by {}", EVALUATION_INTERVAL_ENV);
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            let (db, notifier) = (db.clone(), notifier.clone());
+            match tokio::task::spawn_blocking(move || evaluate_all(&db, &notifier)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => eprintln!("Alarm evaluation failed: {:#}", e),
+                Err(e) => eprintln!("Alarm evaluation task failed: {}", e),
+            }
+        }
+    });
+}
+
+/// 判断全部启用的规则，每个序列只处理上次判断之后的新数据，告警的触发、升级和恢复发布为通知
+pub fn evaluate_all(db: &DatabaseManager, notifier: &Notifier) -> Result<AlarmEvaluation> {
+    let _guard = EVALUATION_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut summary = AlarmEvaluation::default();
+    for rule in db.list_alarm_rules(None)?.iter().filter(|rule| rule.enabled) {
+        for notification in evaluate_rule(db, rule, &mut summary)? {
+            notifier.publish_logged(db, &notification);
+        }
+        summary.rules_evaluated += 1;
+    }
+    Ok(summary)
+}
+
+// 判断一条规则，返回需要发布的通知
+fn evaluate_rule(db: &DatabaseManager, rule: &AlarmRule, summary: &mut AlarmEvaluation) -> Result<Vec<Notification>> {
+    let rule_id = rule.id.ok_or_else(|| anyhow::anyhow!("Alarm rule has no id"))?;
+    let mut states: HashMap<SeriesKey, AlarmSeriesState> = db.load_alarm_states(rule_id)?.into_iter()
+        .map(|state| (state.key.clone(), state))
+        .collect();
+    let mut open_events: HashMap<SeriesKey, AlarmEvent> = db.load_open_alarm_events(rule_id)?.into_iter()
+        .map(|event| (event_key(&event), event))
+        .collect();
+
+    // 各序列从自己的进度往前一个速率窗口开始读取；没有进度的序列和累计基准需要重新确定的序列从头读取。
+    // 每页每个序列最多 MAX_QUERY_LIMIT 个点，读满且有进展的序列接着读下一页
+    let lookback = Duration::seconds(rule.rate_window.unwrap_or(0) as i64);
+    let history_start = match rule.metric {
+        AlarmMetric::Cumulative => rule.reference_time,
+        _ => None,
+    };
+    let params = series_params(rule, history_start);
+    let mut since: Vec<(SeriesKey, DateTime<Utc>)> = states.values()
+        .filter(|state| rule.metric != AlarmMetric::Cumulative || state.reference_value.is_some())
+        .map(|state| (state.key.clone(), state.watermark - lookback))
+        .collect();
+    let mut include_others = true;
+
+    let mut events = Vec::new();
+    let mut notices = Vec::new();
+    let mut evaluated: Vec<SeriesKey> = Vec::new();
+    let mut initial_peaks: HashMap<SeriesKey, Option<AlarmLevel>> = HashMap::new();
+    loop {
+        let mut more = Vec::new();
+        for s in db.query_series_from(&params, &since, include_others, MAX_QUERY_LIMIT)? {
+            let state = states.remove(&s.key);
+            let watermark = state.as_ref().map(|state| state.watermark);
+            let open_event = open_events.remove(&s.key);
+            initial_peaks.entry(s.key.clone())
+                .or_insert_with(|| open_event.as_ref().map(|event| event.peak_level));
+            let mut tracker = SeriesAlarm {
+                rule,
+                state: state.unwrap_or(AlarmSeriesState {
+                    rule_id,
+                    key: s.key.clone(),
+                    watermark: DateTime::<Utc>::MIN_UTC,
+                    pending_since: None,
+                    reference_value: None,
+                }),
+                event: open_event,
+                closed: Vec::new(),
+                summary,
+            };
+            let points = tracker.run(&s.data, watermark);
+            let SeriesAlarm { state, event, closed, .. } = tracker;
+
+            if points > 0 {
+                if !evaluated.contains(&s.key) {
+                    summary.series_evaluated += 1;
+                    evaluated.push(s.key.clone());
+                }
+                summary.points_evaluated += points;
+                if s.data.len() >= MAX_QUERY_LIMIT {
+                    more.push((s.key.clone(), state.watermark - lookback));
+                }
+            }
+
+            // 本次判断中触发又恢复的告警只记录，不通知（例如新规则判断历史数据时）
+            for closed in closed {
+                if closed.id.is_some() {
+                    notices.push((AlarmNotice::Cleared, events.len()));
+                }
+                events.push(closed);
+            }
+            if let Some(event) = event {
+                open_events.insert(s.key.clone(), event);
+            }
+            states.insert(s.key, state);
+        }
+
+        if more.is_empty() {
+            break;
+        }
+        since = more;
+        include_others = false;
+    }
+
+    let mut new_states = Vec::new();
+    for key in evaluated {
+        if let Some(event) = open_events.remove(&key) {
+            match initial_peaks.get(&key).copied().flatten() {
+                None => notices.push((AlarmNotice::Raised, events.len())),
+                Some(peak) if event.peak_level > peak => notices.push((AlarmNotice::Escalated, events.len())),
+                Some(_) => {}
+            }
+            events.push(event);
+        }
+        new_states.extend(states.remove(&key));
+    }
+
+    db.save_alarm_evaluation(&mut events, &new_states)?;
+    Ok(notices.into_iter().map(|(notice, i)| notice.notification(&events[i])).collect())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AlarmNotice {
+    Raised,
+    Escalated,
+    Cleared,
+}
+
+impl AlarmNotice {
+    fn notification(self, event: &AlarmEvent) -> Notification {
+        let (name, action) = match self {
+            AlarmNotice::Raised => ("alarm.raised", "raised"),
+            AlarmNotice::Escalated => ("alarm.escalated", "escalated"),
+            AlarmNotice::Cleared => ("alarm.cleared", "cleared"),
+        };
+        let level = match self {
+            AlarmNotice::Cleared => event.peak_level,
+            _ => event.level,
+        };
+        let series = format!("{}/{}/{}/{}", event.asset_name, event.device_name, event.target_name, event.key_name);
+        Notification::new(
+            name,
+            format!("[{}] {} {}", level.as_str(), event.rule_name, action),
+            format!("{} {} at level {}, value {}", series, action, level.as_str(), event.last_value),
+        )
+        .with_level(level)
+        .with_data(serde_json::to_value(event).unwrap_or_default())
+    }
+}
+
+// 规则范围内的查询参数
+fn series_params(rule: &AlarmRule, start_time: Option<DateTime<Utc>>) -> QueryParams {
+    QueryParams {
+        asset_name: rule.asset_name.clone(),
+        device_name: rule.device_name.clone(),
+        target_names: rule.target_name.clone().into_iter().collect(),
+        key_names: vec![rule.key_name.clone()],
+        start_time,
+        end_time: None,
+        remove_outliers: false,
+        outlier_method: "iqr".to_string(),
+        custom_filter: None,
+        limit: None,
+        sampling_config: None,
+        reference_values: None,
+        time_of_day_filter: None,
+        allowed_assets: None,
+    }
+}
+
+fn event_key(event: &AlarmEvent) -> SeriesKey {
+    SeriesKey {
+        asset_name: event.asset_name.clone(),
+        device_name: event.device_name.clone(),
+        target_name: event.target_name.clone(),
+        key_name: event.key_name.clone(),
+    }
+}
+
+// 判断量是否达到阈值，margin 为回差（放宽阈值）
+fn exceeds(direction: AlarmDirection, value: f64, threshold: f64, margin: f64) -> bool {
+    match direction {
+        AlarmDirection::Above => value >= threshold - margin,
+        AlarmDirection::Below => value <= threshold + margin,
+        AlarmDirection::Both => value.abs() >= threshold - margin,
+    }
+}
+
+// 越大越严重
+fn severity(direction: AlarmDirection, value: f64) -> f64 {
+    match direction {
+        AlarmDirection::Above => value,
+        AlarmDirection::Below => -value,
+        AlarmDirection::Both => value.abs(),
+    }
+}
+
+// 判断量达到的最高级别
+fn level_for(rule: &AlarmRule, value: f64, margin: f64) -> Option<AlarmLevel> {
+    rule.levels.iter()
+        .filter(|t| exceeds(rule.direction, value, t.threshold, margin))
+        .map(|t| t.level)
+        .max()
+}
+
+/// 一个序列上的告警状态机
+///
+/// 超过更高级别的阈值并持续 duration 后触发或升级；判断量回到（阈值 - 回差）以内时立即降级或恢复
+struct SeriesAlarm<'a> {
+    rule: &'a AlarmRule,
+    state: AlarmSeriesState,
+    event: Option<AlarmEvent>,
+    closed: Vec<AlarmEvent>,
+    summary: &'a mut AlarmEvaluation,
+}
+
+impl SeriesAlarm<'_> {
+    /// 依次判断水位线之后的数据，之前的数据只用于计算速率和累计基准，返回判断的点数
+    fn run(&mut self, data: &[TelemetryData], watermark: Option<DateTime<Utc>>) -> usize {
+        let window = Duration::seconds(self.rule.rate_window.unwrap_or(0) as i64);
+        let mut previous = 0;
+        let mut points = 0;
+
+        for (i, point) in data.iter().enumerate() {
+            if self.rule.metric == AlarmMetric::Cumulative && self.state.reference_value.is_none()
+                && self.rule.reference_time.is_none_or(|t| point.timestamp >= t)
+            {
+                self.state.reference_value = Some(point.value);
+            }
+            if watermark.is_some_and(|w| point.timestamp <= w) {
+                continue;
+            }
+
+            let value = match self.rule.metric {
+                AlarmMetric::Value => Some(point.value),
+                AlarmMetric::Rate => {
+                    // 窗口起点取不晚于 (当前时间 - rate_window) 的最后一个读数
+                    while previous + 1 < i && data[previous + 1].timestamp <= point.timestamp - window {
+                        previous += 1;
+                    }
+                    let base = &data[previous];
+                    let hours = (point.timestamp - base.timestamp).num_milliseconds() as f64 / 3_600_000.0;
+                    (previous < i && base.timestamp <= point.timestamp - window && hours > 0.0)
+                        .then(|| (point.value - base.value) / hours)
+                }
+                AlarmMetric::Cumulative => self.state.reference_value.map(|reference| point.value - reference),
+            };
+            if let Some(value) = value {
+                self.step(point.timestamp, value);
+            }
+            self.state.watermark = point.timestamp;
+            points += 1;
+        }
+        points
+    }
+
+    fn step(&mut self, time: DateTime<Utc>, value: f64) {
+        let rule = self.rule;
+        let raw = level_for(rule, value, 0.0);
+        // 已有告警时，判断量在回差范围内保持当前级别
+        let held = self.event.as_ref()
+            .and_then(|event| level_for(rule, value, rule.hysteresis).map(|level| level.min(event.level)));
+
+        let mut started = time;
+        let level = if raw > held {
+            let since = *self.state.pending_since.get_or_insert(time);
+            if (time - since).num_seconds() >= rule.duration as i64 {
+                self.state.pending_since = None;
+                started = since;
+                raw
+            } else {
+                held
+            }
+        } else {
+            self.state.pending_since = None;
+            held
+        };
+
+        match (self.event.as_mut(), level) {
+            (None, None) => {}
+            (None, Some(level)) => {
+                let now = Utc::now();
+                self.event = Some(AlarmEvent {
+                    id: None,
+                    rule_id: self.state.rule_id,
+                    rule_name: rule.name.clone(),
+                    asset_name: self.state.key.asset_name.clone(),
+                    device_name: self.state.key.device_name.clone(),
+                    target_name: self.state.key.target_name.clone(),
+                    key_name: self.state.key.key_name.clone(),
+                    level,
+                    peak_level: level,
+                    start_time: started,
+                    end_time: None,
+                    trigger_value: value,
+                    peak_value: value,
+                    last_value: value,
+                    acknowledged_by: None,
+                    acknowledged_at: None,
+                    ack_comment: None,
+                    created_at: now,
+                    updated_at: now,
+                });
+                self.summary.raised += 1;
+            }
+            (Some(event), Some(level)) => {
+                if level > event.level {
+                    self.summary.escalated += 1;
+                }
+                if level > event.peak_level {
+                    // 升级到新的最高级别需要重新确认
+                    event.peak_level = level;
+                    event.acknowledged_by = None;
+                    event.acknowledged_at = None;
+                    event.ack_comment = None;
+                }
+                if severity(rule.direction, value) > severity(rule.direction, event.peak_value) {
+                    event.peak_value = value;
+                }
+                event.level = level;
+                event.last_value = value;
+            }
+            (Some(event), None) => {
+                event.end_time = Some(time);
+                event.last_value = value;
+                self.closed.extend(self.event.take());
+                self.summary.cleared += 1;
+            }
+        }
+    }
+}
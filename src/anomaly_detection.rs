@@ -1,9 +1,12 @@
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use futures::stream::{self, StreamExt};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use utoipa::ToSchema;
 
-use crate::database::{DatabaseManager, TelemetryData, DataOperation, OperationType};
+use crate::database::{DatabaseManager, TelemetryData, DataOperation, OperationType, QueryParams, SeriesData, SeriesKey, SeriesState, MAX_QUERY_LIMIT};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnomalyDetectionConfig {
@@ -13,10 +16,94 @@ pub struct AnomalyDetectionConfig {
     pub min_window_size: usize,
     /// 最大突变幅度（超过此值认为是突变）
     pub max_jump_threshold: f64,
-    /// 连续异常点数量阈值
+    /// 连续异常点数量阈值，突变检测中连续超限达到此点数视为水平变化并重新建立基线
     pub consecutive_anomaly_threshold: usize,
+    /// 突变检测的基线统计方法
+    pub jump_baseline: JumpBaseline,
     /// 是否启用自动纠正
     pub auto_correction: bool,
+    /// 批量检测时同时处理的最大序列数
+    pub max_workers: usize,
+    /// CUSUM允许的漂移量（标准差倍数），小于此值的缓慢变化不累积
+    pub cusum_drift: f64,
+    /// CUSUM报警阈值（标准差倍数），越大越不敏感
+    pub cusum_threshold: f64,
+    /// 噪声检测的滚动窗口点数
+    pub noise_window_size: usize,
+    /// 计算参考噪声水平所用的前序平稳窗口数
+    pub noise_reference_windows: usize,
+    /// 窗口噪声与参考噪声之比超过此值认为噪声增加
+    pub noise_ratio_threshold: f64,
+    /// 时间间隔超过预期采样间隔的多少倍认为数据缺失
+    pub gap_multiplier: f64,
+    /// 按数据类型指定的预期采样间隔（秒），未指定的序列使用相邻数据时间差的中位数
+    pub expected_intervals: HashMap<String, u64>,
+    /// 连续多少个（近似）相同的读数认为传感器卡死
+    pub flatline_min_points: usize,
+    /// 读数变化范围不超过此值视为相同
+    pub flatline_tolerance: f64,
+    /// 读数保持不变超过此时长（秒）也认为卡死，不设置则只按点数判断
+    pub flatline_min_duration: Option<u64>,
+    /// 按数据类型指定的物理量程和最大变化速率
+    pub value_limits: HashMap<String, ValueLimits>,
+    /// 时间戳晚于当前时间超过此秒数认为是未来时间（设备时钟错误）
+    pub future_tolerance: u64,
+    /// 检测前去除的周期成分，突变和噪声在残差上检测
+    pub seasonality: Seasonality,
+    /// 一致性检测中偏离同组中位数超过此值（稳健标准差倍数）认为异常
+    pub consensus_threshold: f64,
+    /// 一致性检测每个时刻至少需要的同组其他标靶数
+    pub consensus_min_peers: usize,
+}
+
+/// 数据类型的物理限值，未设置的限值不检查
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ValueLimits {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// 相邻两点之间允许的最大变化速率（每秒）
+    pub max_rate: Option<f64>,
+}
+
+impl AnomalyDetectionConfig {
+    /// 检查参数取值，用于请求中按检测器覆盖的参数
+    pub fn check(&self) -> Result<(), String> {
+        let positive = [
+            ("sensitivity", self.sensitivity),
+            ("max_jump_threshold", self.max_jump_threshold),
+            ("cusum_threshold", self.cusum_threshold),
+            ("noise_ratio_threshold", self.noise_ratio_threshold),
+            ("consensus_threshold", self.consensus_threshold),
+        ];
+        for (field, value) in positive {
+            if !(value.is_finite() && value > 0.0) {
+                return Err(format!("{} must be a positive number", field));
+            }
+        }
+        if !(self.cusum_drift.is_finite() && self.cusum_drift >= 0.0) {
+            return Err("cusum_drift must be a non-negative number".to_string());
+        }
+        if !(self.flatline_tolerance.is_finite() && self.flatline_tolerance >= 0.0) {
+            return Err("flatline_tolerance must be a non-negative number".to_string());
+        }
+        if !(self.gap_multiplier.is_finite() && self.gap_multiplier >= 1.0) {
+            return Err("gap_multiplier must be at least 1".to_string());
+        }
+        if self.min_window_size < 3 || self.noise_window_size < 5 || self.flatline_min_points < 2 {
+            return Err("min_window_size must be at least 3, noise_window_size at least 5 and flatline_min_points at least 2".to_string());
+        }
+        if self.expected_intervals.values().any(|&secs| secs == 0) || self.flatline_min_duration == Some(0) {
+            return Err("intervals and durations must be positive numbers of seconds".to_string());
+        }
+        for (key_name, limits) in &self.value_limits {
+            if let (Some(min), Some(max)) = (limits.min, limits.max) {
+                if min > max {
+                    return Err(format!("value_limits.{}: max must not be less than min", key_name));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Default for AnomalyDetectionConfig {
@@ -26,13 +113,123 @@ impl Default for AnomalyDetectionConfig {
             min_window_size: 50,
             max_jump_threshold: 5.0, // 5个标准差
             consecutive_anomaly_threshold: 3,
+            jump_baseline: JumpBaseline::MeanStd,
             auto_correction: false,
+            max_workers: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            cusum_drift: 0.5,
+            cusum_threshold: 5.0,
+            noise_window_size: 20,
+            noise_reference_windows: 10,
+            noise_ratio_threshold: 2.0,
+            gap_multiplier: 3.0,
+            expected_intervals: HashMap::new(),
+            flatline_min_points: 10,
+            flatline_tolerance: 1e-9,
+            flatline_min_duration: None,
+            value_limits: HashMap::new(),
+            future_tolerance: 300,
+            seasonality: Seasonality::None,
+            consensus_threshold: 5.0,
+            consensus_min_peers: 2,
         }
     }
 }
 
+/// 突变检测的基线统计方法
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JumpBaseline {
+    /// 滚动均值和标准差
+    MeanStd,
+    /// 滚动中位数和MAD，不受窗口内离群点影响
+    MedianMad,
+}
+
+// 一天和一周的毫秒数
+const DAY_MS: i64 = 86_400_000;
+const WEEK_MS: i64 = 7 * DAY_MS;
+
+// 数据至少覆盖几个周期才估计该周期的季节项，每个相位箱取中位数需要多个周期的样本
+const MIN_SEASONAL_CYCLES: i64 = 3;
+
+// 季节项一个周期内的最多分箱数，采样比这更密时多个点共用一个箱
+const MAX_SEASONAL_BINS: i64 = 288;
+
+/// 季节性分解的周期
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Seasonality {
+    /// 不分解，直接在原始数据上检测
+    #[default]
+    None,
+    /// 日周期（温度、日照引起的日变化）
+    Daily,
+    /// 日周期和周周期
+    DailyWeekly,
+}
+
+impl Seasonality {
+    // 从短到长的周期（毫秒）
+    fn periods(&self) -> &'static [i64] {
+        match self {
+            Seasonality::None => &[],
+            Seasonality::Daily => &[DAY_MS],
+            Seasonality::DailyWeekly => &[DAY_MS, WEEK_MS],
+        }
+    }
+}
+
+// 一个周期的季节项：按相位分箱的水平，均值为0；增量检测时保存，新数据不足以重新估计时沿用
 #[derive(Debug, Clone, Serialize, Deserialize)]
+struct SeasonalProfile {
+    period: i64,
+    bin_width: i64,
+    levels: Vec<Option<f64>>,
+}
+
+impl SeasonalProfile {
+    fn level_at(&self, ts: i64) -> Option<f64> {
+        self.levels.get((ts.rem_euclid(self.period) / self.bin_width) as usize).copied().flatten()
+    }
+
+    // 去掉各箱水平的均值，整体水平归入趋势
+    fn center(&mut self) {
+        let filled: Vec<f64> = self.levels.iter().flatten().copied().collect();
+        let mean = filled.iter().sum::<f64>() / filled.len().max(1) as f64;
+        for level in self.levels.iter_mut().flatten() {
+            *level -= mean;
+        }
+    }
+}
+
+/// 分解后的数据点，value = trend + seasonal + residual
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DecomposedPoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
+    pub trend: f64,
+    /// 各周期季节项之和
+    pub seasonal: f64,
+    pub residual: f64,
+}
+
+/// 单个序列的季节性分解结果
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SeriesDecomposition {
+    pub asset_name: String,
+    pub device_name: String,
+    pub target_name: String,
+    pub key_name: String,
+    pub seasonality: Seasonality,
+    pub points: Vec<DecomposedPoint>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DetectedAnomaly {
+    /// 保存后的异常记录ID
+    pub id: Option<i64>,
+    pub asset_name: String,
+    pub device_name: String,
     pub target_name: String,
     pub key_name: String,
     pub anomaly_type: AnomalyType,
@@ -40,12 +237,22 @@ pub struct DetectedAnomaly {
     pub end_time: Option<DateTime<Utc>>,
     pub baseline_value: f64,
     pub anomaly_value: f64,
+    /// 异常幅度，含义随类型不同：突变为z分数，偏移为偏移量，噪声为放大倍数，卡死为点数，超限为超出量
     pub jump_magnitude: f64,
     pub confidence: f64,
+    /// 建议的纠正操作，接受异常时据此创建数据操作
     pub suggested_correction: Option<DataOperation>,
+    /// 参考噪声水平（标准差，仅噪声增加时有值）
+    pub baseline_noise: Option<f64>,
+    /// 异常区间内的噪声水平（标准差，仅噪声增加时有值）
+    pub observed_noise: Option<f64>,
+    /// 补充说明，例如时间戳问题的数量和示例
+    pub details: Option<String>,
+    /// 一致性检测中参与比较的同组标靶
+    pub peers: Option<Vec<String>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
 pub enum AnomalyType {
     /// 突然跳跃（阳光干扰等）
     SuddenJump,
@@ -55,30 +262,450 @@ pub enum AnomalyType {
     IncreasedNoise,
     /// 数据缺失
     DataGap,
+    /// 读数长时间不变（传感器卡死）
+    Flatline,
+    /// 超出物理量程或变化速率过大
+    OutOfRange,
+    /// 重复时间戳（时钟重新同步、同一时刻的突发数据）
+    DuplicateTimestamp,
+    /// 时间戳晚于当前时间
+    FutureTimestamp,
+    /// 与同一设备或资产上其他标靶的变化不一致
+    ConsensusDeviation,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl AnomalyType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AnomalyType::SuddenJump => "SuddenJump",
+            AnomalyType::PersistentOffset => "PersistentOffset",
+            AnomalyType::IncreasedNoise => "IncreasedNoise",
+            AnomalyType::DataGap => "DataGap",
+            AnomalyType::Flatline => "Flatline",
+            AnomalyType::OutOfRange => "OutOfRange",
+            AnomalyType::DuplicateTimestamp => "DuplicateTimestamp",
+            AnomalyType::FutureTimestamp => "FutureTimestamp",
+            AnomalyType::ConsensusDeviation => "ConsensusDeviation",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "SuddenJump" => Some(AnomalyType::SuddenJump),
+            "PersistentOffset" => Some(AnomalyType::PersistentOffset),
+            "IncreasedNoise" => Some(AnomalyType::IncreasedNoise),
+            "DataGap" => Some(AnomalyType::DataGap),
+            "Flatline" => Some(AnomalyType::Flatline),
+            "OutOfRange" => Some(AnomalyType::OutOfRange),
+            "DuplicateTimestamp" => Some(AnomalyType::DuplicateTimestamp),
+            "FutureTimestamp" => Some(AnomalyType::FutureTimestamp),
+            "ConsensusDeviation" => Some(AnomalyType::ConsensusDeviation),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AnomalyDetectionResult {
+    /// 保存后的检测批次ID
+    pub run_id: Option<i64>,
     pub anomalies: Vec<DetectedAnomaly>,
     pub suggested_operations: Vec<DataOperation>,
     pub summary: AnomalyDetectionSummary,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AnomalyDetectionSummary {
     pub total_anomalies: usize,
+    /// 检测的序列数（资产/设备/标靶/数据类型组合）
+    pub series_analyzed: usize,
     pub targets_affected: usize,
+    /// [开始时间, 结束时间]
+    #[schema(value_type = Vec<DateTime<Utc>>)]
     pub time_range_analyzed: (DateTime<Utc>, DateTime<Utc>),
     pub confidence_distribution: HashMap<String, usize>,
+    /// 各序列的数据完整率
+    pub series_completeness: Vec<SeriesCompleteness>,
+}
+
+/// 单个序列在检测范围内的数据完整率
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SeriesCompleteness {
+    pub asset_name: String,
+    pub device_name: String,
+    pub target_name: String,
+    pub key_name: String,
+    /// 预期采样间隔（毫秒）
+    pub expected_interval_ms: i64,
+    pub expected_points: usize,
+    pub actual_points: usize,
+    /// 完整率百分比（0-100）
+    pub completeness: f64,
+}
+
+/// 一致性检测的标靶分组：指定设备时为该设备下的标靶，否则为整个资产下的标靶
+#[derive(Debug, Clone)]
+pub struct ConsensusGroup {
+    pub asset_name: String,
+    pub device_name: Option<String>,
+    pub key_name: String,
+}
+
+/// 批量检测的序列筛选条件，未设置的条件不限制
+#[derive(Debug, Clone, Default)]
+pub struct SeriesFilter {
+    pub asset_name: Option<String>,
+    pub device_name: Option<String>,
+    pub key_names: Vec<String>,
+}
+
+/// 可插拔的单序列异常检测器
+///
+/// 注册表中保存的是未配置的检测器，每次检测请求用通用配置和该检测器的参数调用 `configure` 得到检测实例
+pub trait Detector: Send + Sync {
+    /// 检测器名称，请求中按名称选择
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// 参数的JSON Schema
+    fn config_schema(&self) -> serde_json::Value;
+
+    /// 创建检测实例，参数无效时返回原因
+    fn configure(&self, config: &AnomalyDetectionConfig, params: &serde_json::Value) -> Result<Arc<dyn Detector>, String>;
+
+    /// 检测单个序列（按时间升序），时间范围用于检查两端的数据缺失
+    fn detect(
+        &self,
+        data: &[TelemetryData],
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Result<Vec<DetectedAnomaly>>;
+
+    /// 增量检测时需要放在新数据之前的历史点数，用于恢复滚动窗口
+    fn context_points(&self, _data: &[TelemetryData]) -> usize {
+        0
+    }
+
+    /// 增量检测：data 以保存的上下文开头，state 为该检测器上次返回的状态（首次为None）。
+    /// 返回检测结果和需要保存的新状态，默认不保存状态
+    fn detect_incremental(
+        &self,
+        data: &[TelemetryData],
+        _state: Option<&serde_json::Value>,
+    ) -> Result<(Vec<DetectedAnomaly>, Option<serde_json::Value>)> {
+        Ok((self.detect(data, None, None)?, None))
+    }
+
+    /// 状态还不完整（例如季节项尚未学到）时，需要在水位线之前补读的历史时长（毫秒）
+    fn history_span(&self, _state: Option<&serde_json::Value>) -> Option<i64> {
+        None
+    }
+}
+
+/// 检测器及其参数说明
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DetectorInfo {
+    pub name: String,
+    pub description: String,
+    /// 参数的JSON Schema
+    #[schema(value_type = Object)]
+    pub parameters: serde_json::Value,
+}
+
+/// 检测器注册表，启动时创建，之后只读
+#[derive(Clone, Default)]
+pub struct DetectorRegistry {
+    detectors: Vec<Arc<dyn Detector>>,
+}
+
+impl DetectorRegistry {
+    /// 包含全部内置检测器的注册表
+    pub fn with_builtin() -> Self {
+        let mut registry = Self::default();
+        for spec in BUILTIN_DETECTORS {
+            registry.register(Arc::new(BuiltinDetector::new(spec, AnomalyDetectionConfig::default())));
+        }
+        registry
+    }
+
+    /// 注册检测器，同名的检测器会被替换
+    pub fn register(&mut self, detector: Arc<dyn Detector>) {
+        match self.detectors.iter_mut().find(|d| d.name() == detector.name()) {
+            Some(existing) => *existing = detector,
+            None => self.detectors.push(detector),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Detector>> {
+        self.detectors.iter().find(|d| d.name() == name)
+    }
+
+    /// 按注册顺序排列的全部检测器
+    pub fn detectors(&self) -> &[Arc<dyn Detector>] {
+        &self.detectors
+    }
+
+    pub fn info(&self) -> Vec<DetectorInfo> {
+        self.detectors.iter().map(|d| DetectorInfo {
+            name: d.name().to_string(),
+            description: d.description().to_string(),
+            parameters: d.config_schema(),
+        }).collect()
+    }
+}
+
+// 内置检测器的参数：可以在请求中覆盖的配置字段
+struct ParamSpec {
+    field: &'static str,
+    kind: &'static str,
+    description: &'static str,
+}
+
+// 增量检测的一个任务：（新数据, 之前的上下文, 状态）
+type PageJob = (SeriesData, Vec<TelemetryData>, Option<SeriesState>);
+
+// 增量检测一个序列的结果：（序列, 异常, 新数据的完整性, 新状态）
+type PageResult = (SeriesKey, Vec<DetectedAnomaly>, Option<SeriesCompleteness>, SeriesState);
+
+// 参数依次为数据、检测范围和保存的季节项（非增量检测时为空）
+type DetectFn = fn(
+    &AnomalyDetector,
+    &[TelemetryData],
+    Option<DateTime<Utc>>,
+    Option<DateTime<Utc>>,
+    &[SeasonalProfile],
+) -> Result<Vec<DetectedAnomaly>>;
+
+struct BuiltinSpec {
+    name: &'static str,
+    description: &'static str,
+    params: &'static [ParamSpec],
+    detect: DetectFn,
+}
+
+const MIN_WINDOW_SIZE: ParamSpec = ParamSpec { field: "min_window_size", kind: "integer", description: "点数少于此值的序列不做统计检测" };
+const CONSECUTIVE_THRESHOLD: ParamSpec = ParamSpec {
+    field: "consecutive_anomaly_threshold",
+    kind: "integer",
+    description: "连续超限多少个点视为水平变化",
+};
+const SEASONALITY: ParamSpec = ParamSpec { field: "seasonality", kind: "string", description: "检测前去除的周期成分: none, daily, daily_weekly" };
+
+// 内置检测器，未选择检测器时按此顺序全部运行
+const BUILTIN_DETECTORS: &[BuiltinSpec] = &[
+    BuiltinSpec {
+        name: "timestamp",
+        description: "重复和未来时间戳",
+        params: &[ParamSpec { field: "future_tolerance", kind: "integer", description: "晚于当前时间超过此秒数认为是未来时间" }],
+        detect: |d, data, _, _, _| d.detect_timestamp_issues(data),
+    },
+    BuiltinSpec {
+        name: "data_gap",
+        description: "数据缺失，包括检测范围两端",
+        params: &[
+            ParamSpec { field: "gap_multiplier", kind: "number", description: "时间间隔超过预期采样间隔的多少倍认为缺失" },
+            ParamSpec { field: "expected_intervals", kind: "object", description: "按数据类型指定的预期采样间隔（秒）" },
+        ],
+        detect: |d, data, start, end, _| d.detect_data_gaps(data, start, end),
+    },
+    BuiltinSpec {
+        name: "flatline",
+        description: "读数长时间不变（传感器卡死）",
+        params: &[
+            ParamSpec { field: "flatline_min_points", kind: "integer", description: "连续多少个相同读数认为卡死" },
+            ParamSpec { field: "flatline_tolerance", kind: "number", description: "读数变化范围不超过此值视为相同" },
+            ParamSpec { field: "flatline_min_duration", kind: "integer", description: "读数不变超过此时长（秒）也认为卡死" },
+        ],
+        detect: |d, data, _, _, _| d.detect_flatlines(data),
+    },
+    BuiltinSpec {
+        name: "out_of_range",
+        description: "超出物理量程或变化速率过大",
+        params: &[ParamSpec { field: "value_limits", kind: "object", description: "按数据类型指定的min、max和max_rate" }],
+        detect: |d, data, _, _, _| d.detect_out_of_range(data),
+    },
+    BuiltinSpec {
+        name: "sudden_jump",
+        description: "相对滚动基线的突变",
+        params: &[
+            MIN_WINDOW_SIZE,
+            ParamSpec { field: "max_jump_threshold", kind: "number", description: "偏离基线超过多少倍标准差认为突变" },
+            CONSECUTIVE_THRESHOLD,
+            ParamSpec { field: "jump_baseline", kind: "string", description: "基线统计方法: mean_std, median_mad" },
+            SEASONALITY,
+        ],
+        detect: |d, data, _, _, profiles| d.windowed_detect(data, profiles, |p| p.residual, AnomalyDetector::detect_sudden_jumps),
+    },
+    BuiltinSpec {
+        name: "persistent_offset",
+        description: "持续的水平偏移（CUSUM）",
+        params: &[
+            MIN_WINDOW_SIZE,
+            ParamSpec { field: "sensitivity", kind: "number", description: "跳变至少为多少倍噪声水平" },
+            ParamSpec { field: "cusum_drift", kind: "number", description: "CUSUM允许的漂移量（标准差倍数）" },
+            ParamSpec { field: "cusum_threshold", kind: "number", description: "CUSUM报警阈值（标准差倍数）" },
+            CONSECUTIVE_THRESHOLD,
+            SEASONALITY,
+        ],
+        detect: |d, data, _, _, profiles| {
+            d.windowed_detect(data, profiles, |p| p.value - p.seasonal, AnomalyDetector::detect_persistent_offsets)
+        },
+    },
+    BuiltinSpec {
+        name: "increased_noise",
+        description: "噪声水平相对平稳时段增加",
+        params: &[
+            MIN_WINDOW_SIZE,
+            ParamSpec { field: "noise_window_size", kind: "integer", description: "滚动窗口点数" },
+            ParamSpec { field: "noise_reference_windows", kind: "integer", description: "计算参考噪声所用的前序窗口数" },
+            ParamSpec { field: "noise_ratio_threshold", kind: "number", description: "噪声放大超过此倍数认为异常" },
+            SEASONALITY,
+        ],
+        detect: |d, data, _, _, profiles| d.windowed_detect(data, profiles, |p| p.residual, AnomalyDetector::detect_increased_noise),
+    },
+];
+
+// 内置检测器：在请求配置的基础上覆盖该检测器的参数
+struct BuiltinDetector {
+    spec: &'static BuiltinSpec,
+    detector: AnomalyDetector,
+}
+
+impl BuiltinDetector {
+    fn new(spec: &'static BuiltinSpec, config: AnomalyDetectionConfig) -> Self {
+        Self { spec, detector: AnomalyDetector::with_config(config) }
+    }
+
+    // 该检测器在季节性分解后的数据上检测
+    fn seasonal(&self) -> bool {
+        self.detector.config.seasonality != Seasonality::None
+            && self.spec.params.iter().any(|p| p.field == SEASONALITY.field)
+    }
+
+    // 保存的季节项，状态无法解析时重新学习
+    fn saved_profiles(&self, state: Option<&serde_json::Value>) -> Vec<SeasonalProfile> {
+        match state {
+            Some(state) if self.seasonal() => serde_json::from_value(state.clone()).unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Detector for BuiltinDetector {
+    fn name(&self) -> &str {
+        self.spec.name
+    }
+
+    fn description(&self) -> &str {
+        self.spec.description
+    }
+
+    fn config_schema(&self) -> serde_json::Value {
+        let defaults = serde_json::to_value(&self.detector.config).unwrap_or_default();
+        let properties: serde_json::Map<String, serde_json::Value> = self.spec.params.iter().map(|p| {
+            (p.field.to_string(), serde_json::json!({
+                "type": p.kind,
+                "description": p.description,
+                "default": defaults.get(p.field),
+            }))
+        }).collect();
+        serde_json::json!({
+            "type": "object",
+            "properties": properties,
+            "additionalProperties": false,
+        })
+    }
+
+    fn configure(&self, config: &AnomalyDetectionConfig, params: &serde_json::Value) -> Result<Arc<dyn Detector>, String> {
+        let params = match params {
+            serde_json::Value::Null => serde_json::Map::new(),
+            serde_json::Value::Object(map) => map.clone(),
+            _ => return Err("params must be an object".to_string()),
+        };
+        let mut merged = serde_json::to_value(config).map_err(|e| e.to_string())?;
+        for (field, value) in params {
+            if !self.spec.params.iter().any(|p| p.field == field) {
+                return Err(format!("Unknown parameter '{}' for detector '{}'", field, self.spec.name));
+            }
+            merged[field] = value;
+        }
+        let config: AnomalyDetectionConfig = serde_json::from_value(merged)
+            .map_err(|e| format!("Invalid parameters for detector '{}': {}", self.spec.name, e))?;
+        config.check()?;
+        Ok(Arc::new(BuiltinDetector::new(self.spec, config)))
+    }
+
+    fn detect(
+        &self,
+        data: &[TelemetryData],
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Result<Vec<DetectedAnomaly>> {
+        (self.spec.detect)(&self.detector, data, start_time, end_time, &[])
+    }
+
+    fn context_points(&self, _data: &[TelemetryData]) -> usize {
+        self.detector.window_points()
+    }
+
+    fn detect_incremental(
+        &self,
+        data: &[TelemetryData],
+        state: Option<&serde_json::Value>,
+    ) -> Result<(Vec<DetectedAnomaly>, Option<serde_json::Value>)> {
+        let saved = self.saved_profiles(state);
+        let anomalies = (self.spec.detect)(&self.detector, data, None, None, &saved)?;
+        if !self.seasonal() {
+            return Ok((anomalies, None));
+        }
+        let learned = self.detector.learn_profiles(data, &saved);
+        Ok((anomalies, Some(serde_json::to_value(learned)?)))
+    }
+
+    fn history_span(&self, state: Option<&serde_json::Value>) -> Option<i64> {
+        if !self.seasonal() {
+            return None;
+        }
+        let saved = self.saved_profiles(state);
+        self.detector.config.seasonality.periods().iter()
+            .filter(|&&period| !saved.iter().any(|p| p.period == period))
+            .max()
+            .map(|period| period * MIN_SEASONAL_CYCLES)
+    }
 }
 
+#[derive(Clone)]
 pub struct AnomalyDetector {
     config: AnomalyDetectionConfig,
+    allowed_assets: Option<Vec<String>>, // 用户可访问的资产范围
+    detectors: Vec<Arc<dyn Detector>>,   // 对每个序列运行的检测器
 }
 
 impl AnomalyDetector {
+    /// 使用全部内置检测器
     pub fn new(config: AnomalyDetectionConfig) -> Self {
-        Self { config }
+        let detectors = BUILTIN_DETECTORS.iter()
+            .map(|spec| Arc::new(BuiltinDetector::new(spec, config.clone())) as Arc<dyn Detector>)
+            .collect();
+        Self { detectors, ..Self::with_config(config) }
+    }
+
+    // 不带检测器，供内置检测器调用各检测方法
+    fn with_config(config: AnomalyDetectionConfig) -> Self {
+        Self { config, allowed_assets: None, detectors: Vec::new() }
+    }
+
+    /// 只检测指定资产范围内的数据
+    pub fn with_asset_scope(mut self, allowed_assets: Option<Vec<String>>) -> Self {
+        self.allowed_assets = allowed_assets;
+        self
+    }
+
+    /// 使用指定的（已配置的）检测器代替内置检测器
+    pub fn with_detectors(mut self, detectors: Vec<Arc<dyn Detector>>) -> Self {
+        self.detectors = detectors;
+        self
     }
 
     /// 检测指定标靶和指标的异常
@@ -90,227 +717,1116 @@ impl AnomalyDetector {
         start_time: Option<DateTime<Utc>>,
         end_time: Option<DateTime<Utc>>,
     ) -> Result<Vec<DetectedAnomaly>> {
-        // 获取数据
-        let data = self.fetch_data(db, target_name, key_name, start_time, end_time).await?;
-        
-        if data.len() < self.config.min_window_size {
-            return Ok(Vec::new());
-        }
+        // 同名标靶可能属于不同设备，按序列分别检测
+        let mut params = self.series_params(start_time, end_time);
+        params.target_names = vec![target_name.to_string()];
+        params.key_names = vec![key_name.to_string()];
+        let series = db.query_series_data(&params, MAX_QUERY_LIMIT)?;
 
         let mut anomalies = Vec::new();
+        for s in &series {
+            anomalies.extend(self.detect_series(&s.data, start_time, end_time)?);
+        }
+        Ok(anomalies)
+    }
 
-        // 1. 检测突然跳跃
-        anomalies.extend(self.detect_sudden_jumps(&data)?);
-
-        // 2. 检测持续偏移
-        anomalies.extend(self.detect_persistent_offsets(&data)?);
+    /// 检测同组标靶中与其他标靶变化不一致的标靶
+    pub async fn detect_consensus(
+        &self,
+        db: &DatabaseManager,
+        group: &ConsensusGroup,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Result<Vec<DetectedAnomaly>> {
+        let mut params = self.series_params(start_time, end_time);
+        params.asset_name = Some(group.asset_name.clone());
+        params.device_name = group.device_name.clone();
+        params.key_names = vec![group.key_name.clone()];
+        let series = db.query_series_data(&params, MAX_QUERY_LIMIT)?;
 
-        // 3. 检测噪声增加
-        anomalies.extend(self.detect_increased_noise(&data)?);
+        let detector = self.clone();
+        let mut anomalies = tokio::task::spawn_blocking(move || detector.consensus_deviations(&series)).await
+            .map_err(|e| anyhow::anyhow!("Detection task failed: {}", e))?;
+        for anomaly in &mut anomalies {
+            anomaly.suggested_correction = self.generate_correction_operation(anomaly);
+        }
+        Ok(anomalies)
+    }
 
-        // 4. 检测数据缺失
-        anomalies.extend(self.detect_data_gaps(&data)?);
+    /// 对指定标靶和指标做季节性分解，用于绘制趋势、季节和残差曲线
+    pub async fn decompose(
+        &self,
+        db: &DatabaseManager,
+        target_name: &str,
+        key_name: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Result<Vec<SeriesDecomposition>> {
+        let mut params = self.series_params(start_time, end_time);
+        params.target_names = vec![target_name.to_string()];
+        params.key_names = vec![key_name.to_string()];
+        let series = db.query_series_data(&params, MAX_QUERY_LIMIT)?;
 
-        Ok(anomalies)
+        Ok(series.iter().map(|s| SeriesDecomposition {
+            asset_name: s.key.asset_name.clone(),
+            device_name: s.key.device_name.clone(),
+            target_name: s.key.target_name.clone(),
+            key_name: s.key.key_name.clone(),
+            seasonality: self.config.seasonality,
+            points: self.decompose_series(&s.data),
+        }).collect())
     }
 
-    /// 为所有标靶和指标检测异常
+    /// 为所有匹配筛选条件的序列检测异常
     pub async fn detect_all_anomalies(
         &self,
         db: &DatabaseManager,
+        filter: &SeriesFilter,
         start_time: Option<DateTime<Utc>>,
         end_time: Option<DateTime<Utc>>,
     ) -> Result<AnomalyDetectionResult> {
-        // 获取所有标靶和指标组合
-        let targets_keys = self.get_all_target_key_combinations(db).await?;
-        
+        // 一次扫描读取所有序列，避免逐个序列查询数据库
+        let mut params = self.series_params(start_time, end_time);
+        params.asset_name = filter.asset_name.clone();
+        params.device_name = filter.device_name.clone();
+        params.key_names = filter.key_names.clone();
+        let series = db.query_series_data(&params, MAX_QUERY_LIMIT)?;
+        let series_analyzed = series.len();
+
+        let results = self.detect_batch(series, start_time, end_time).await?;
+        Ok(self.collect_results(results, series_analyzed, start_time, end_time))
+    }
+
+    /// 增量检测：只检测各序列上次检测（水位线）之后的新数据
+    ///
+    /// 各序列从自己的水位线之后读取，新数据之前拼接保存的上下文恢复滚动基线和噪声参考，
+    /// 季节项等基线由各检测器保存在状态中；只返回水位线之后开始的异常和起点完整的延续异常。
+    /// 积压的数据按页读取，每页每个序列最多 MAX_QUERY_LIMIT 个点，读满的序列接着读下一页。
+    /// 状态按配置指纹区分，配置变化后自动从头检测；full_rebuild 忽略已有状态，重新检测全部历史。
+    /// 返回结果和需要保存的新状态（只包含有新数据的序列）
+    pub async fn detect_incremental(
+        &self,
+        db: &DatabaseManager,
+        filter: &SeriesFilter,
+        fingerprint: &str,
+        full_rebuild: bool,
+    ) -> Result<(AnomalyDetectionResult, Vec<SeriesState>)> {
+        let mut states: HashMap<SeriesKey, SeriesState> = if full_rebuild {
+            HashMap::new()
+        } else {
+            db.load_series_states(fingerprint)?.into_iter()
+                .map(|state| (state.key.clone(), state))
+                .collect()
+        };
+
+        let mut params = self.series_params(None, None);
+        params.asset_name = filter.asset_name.clone();
+        params.device_name = filter.device_name.clone();
+        params.key_names = filter.key_names.clone();
+
+        // 第一页读取有状态序列水位线之后的数据和其余序列的全部数据，之后只读上一页读满的序列
+        let mut since: Vec<(SeriesKey, DateTime<Utc>)> = states.values()
+            .map(|state| (state.key.clone(), state.watermark + Duration::milliseconds(1)))
+            .collect();
+        let mut include_others = true;
+        let mut results: Vec<(SeriesKey, Vec<DetectedAnomaly>, Option<SeriesCompleteness>)> = Vec::new();
+        let mut positions: HashMap<SeriesKey, usize> = HashMap::new();
+        loop {
+            let page = db.query_series_from(&params, &since, include_others, MAX_QUERY_LIMIT)?;
+            let mut more = Vec::new();
+            let mut jobs = Vec::new();
+            for s in page {
+                let Some(last) = s.data.last() else { continue };
+                if s.data.len() >= MAX_QUERY_LIMIT {
+                    more.push((s.key.clone(), last.timestamp + Duration::milliseconds(1)));
+                }
+                let state = states.remove(&s.key);
+                let context = match &state {
+                    Some(state) => self.series_context(db, &params, &s.key, state)?,
+                    None => Vec::new(),
+                };
+                jobs.push((s, context, state));
+            }
+
+            for (key, anomalies, completeness, state) in self.detect_page(jobs, fingerprint).await? {
+                match positions.get(&key) {
+                    Some(&i) => {
+                        // 跨页持续的异常在下一页会重新检测到，以后一页的结果为准
+                        let (_, previous, merged) = &mut results[i];
+                        previous.retain(|a| !anomalies.iter()
+                            .any(|n| n.anomaly_type == a.anomaly_type && n.start_time == a.start_time));
+                        previous.extend(anomalies);
+                        *merged = match (merged.take(), completeness) {
+                            (Some(a), Some(b)) => Some(merge_completeness(a, b)),
+                            (a, b) => a.or(b),
+                        };
+                    }
+                    None => {
+                        positions.insert(key.clone(), results.len());
+                        results.push((key.clone(), anomalies, completeness));
+                    }
+                }
+                states.insert(key, state);
+            }
+
+            if more.is_empty() {
+                break;
+            }
+            since = more;
+            include_others = false;
+        }
+
+        let series_analyzed = results.len();
+        let new_states = results.iter()
+            .filter_map(|(key, _, _)| states.remove(key))
+            .collect();
+        let results = results.into_iter()
+            .map(|(_, anomalies, completeness)| (anomalies, completeness))
+            .collect();
+        Ok((self.collect_results(results, series_analyzed, None, None), new_states))
+    }
+
+    /// 有状态序列放在新数据之前的数据：通常为保存的上下文；
+    /// 检测器还需要更长的历史（例如季节项尚未学到）时，重新读取水位线之前的这段历史
+    fn series_context(
+        &self,
+        db: &DatabaseManager,
+        params: &QueryParams,
+        key: &SeriesKey,
+        state: &SeriesState,
+    ) -> Result<Vec<TelemetryData>> {
+        let span = self.detectors.iter()
+            .filter_map(|d| d.history_span(state.detectors.get(d.name())))
+            .max();
+        if let Some(span) = span {
+            let mut params = params.clone();
+            params.start_time = Some(state.watermark - Duration::milliseconds(span));
+            params.end_time = Some(state.watermark);
+            params.asset_name = Some(key.asset_name.clone());
+            params.device_name = Some(key.device_name.clone());
+            params.target_names = vec![key.target_name.clone()];
+            params.key_names = vec![key.key_name.clone()];
+            if let Some(history) = db.query_series_data(&params, MAX_QUERY_LIMIT)?.into_iter().next() {
+                if history.data.len() > state.context.len() {
+                    return Ok(history.data);
+                }
+            }
+        }
+
+        Ok(state.context.iter()
+            .filter_map(|&(ts, value)| Some(TelemetryData {
+                timestamp: DateTime::from_timestamp_millis(ts)?,
+                asset_name: key.asset_name.clone(),
+                device_name: key.device_name.clone(),
+                target_name: key.target_name.clone(),
+                key_name: key.key_name.clone(),
+                value,
+            }))
+            .collect())
+    }
+
+    /// 在阻塞线程池中并行增量检测一页数据
+    async fn detect_page(&self, jobs: Vec<PageJob>, fingerprint: &str) -> Result<Vec<PageResult>> {
+        let detector = Arc::new(self.clone());
+        stream::iter(jobs)
+            .map(|(s, context, state): PageJob| {
+                let detector = detector.clone();
+                let fingerprint = fingerprint.to_string();
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        let completeness = detector.series_completeness(&s, None, None);
+                        let context_start = context.first().map(|d| d.timestamp);
+                        let mut data = context;
+                        data.extend(s.data);
+
+                        let mut anomalies = Vec::new();
+                        let mut detector_states = BTreeMap::new();
+                        for d in &detector.detectors {
+                            let saved = state.as_ref().and_then(|state| state.detectors.get(d.name()));
+                            let (found, next) = d.detect_incremental(&data, saved)?;
+                            anomalies.extend(found);
+                            if let Some(next) = next {
+                                detector_states.insert(d.name().to_string(), next);
+                            }
+                        }
+                        // 水位线之前开始的异常上次已经报告：延续到水位线之后的异常在起点完整时按起点更新已保存的记录，
+                        // 起点落在上下文第一个点上的可能被上下文截断，再报告会产生重复记录
+                        if let Some(state) = &state {
+                            anomalies.retain(|a| {
+                                a.start_time > state.watermark
+                                    || (a.end_time.is_none_or(|end| end > state.watermark)
+                                        && context_start.is_some_and(|start| a.start_time > start))
+                            });
+                        }
+                        for anomaly in &mut anomalies {
+                            anomaly.suggested_correction = detector.generate_correction_operation(anomaly);
+                        }
+
+                        let keep = detector.context_points(&data).min(data.len());
+                        let state = SeriesState {
+                            key: s.key.clone(),
+                            fingerprint,
+                            watermark: data.last().map_or(Utc::now(), |d| d.timestamp),
+                            context: data[data.len() - keep..].iter()
+                                .map(|d| (d.timestamp.timestamp_millis(), d.value))
+                                .collect(),
+                            detectors: detector_states,
+                        };
+                        Ok((s.key, anomalies, completeness, state))
+                    }).await
+                        .map_err(|e| anyhow::anyhow!("Detection task failed: {}", e))?
+                }
+            })
+            .buffered(self.config.max_workers.max(1))
+            .collect::<Vec<Result<_>>>()
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    /// 增量检测需要保留的历史点数，取各检测器需要的最大值
+    fn context_points(&self, data: &[TelemetryData]) -> usize {
+        self.detectors.iter().map(|d| d.context_points(data)).max().unwrap_or(0)
+    }
+
+    /// 在阻塞线程池中并行检测各序列（各序列互不依赖）
+    async fn detect_batch(
+        &self,
+        series: Vec<SeriesData>,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Result<Vec<(Vec<DetectedAnomaly>, Option<SeriesCompleteness>)>> {
+        let detector = Arc::new(self.clone());
+        stream::iter(series)
+            .map(|s: SeriesData| {
+                let detector = detector.clone();
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        let anomalies = detector.detect_series(&s.data, start_time, end_time)?;
+                        let completeness = detector.series_completeness(&s, start_time, end_time);
+                        Ok((anomalies, completeness))
+                    }).await
+                        .map_err(|e| anyhow::anyhow!("Detection task failed: {}", e))?
+                }
+            })
+            .buffered(self.config.max_workers.max(1))
+            .collect::<Vec<Result<_>>>()
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    fn collect_results(
+        &self,
+        results: Vec<(Vec<DetectedAnomaly>, Option<SeriesCompleteness>)>,
+        series_analyzed: usize,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> AnomalyDetectionResult {
         let mut all_anomalies = Vec::new();
         let mut suggested_operations = Vec::new();
+        let mut series_completeness = Vec::new();
 
-        for (target_name, key_name) in targets_keys {
-            let anomalies = self.detect_anomalies(db, &target_name, &key_name, start_time, end_time).await?;
-            
+        for (anomalies, completeness) in results {
+            series_completeness.extend(completeness);
             for anomaly in anomalies {
-                // 生成建议的纠正操作
-                if let Some(operation) = self.generate_correction_operation(&anomaly) {
-                    suggested_operations.push(operation);
+                if self.config.auto_correction {
+                    suggested_operations.extend(anomaly.suggested_correction.clone());
                 }
                 all_anomalies.push(anomaly);
             }
         }
 
         // 生成摘要
-        let summary = self.generate_summary(&all_anomalies, start_time, end_time);
+        let summary = self.generate_summary(&all_anomalies, series_analyzed, series_completeness, start_time, end_time);
 
-        Ok(AnomalyDetectionResult {
+        AnomalyDetectionResult {
+            run_id: None,
             anomalies: all_anomalies,
             suggested_operations,
             summary,
-        })
+        }
     }
 
-    /// 获取数据
-    async fn fetch_data(
+    /// 增量检测需要保留的点数：覆盖滚动基线、噪声参考窗口和卡死判断。
+    /// 季节项单独保存，不需要保留整个周期的数据
+    fn window_points(&self) -> usize {
+        let config = &self.config;
+        (config.min_window_size * 2)
+            .max(config.noise_window_size * (config.noise_reference_windows + 3))
+            .max(config.flatline_min_points * 2)
+    }
+
+    /// 对单个序列（按时间升序）运行全部检测器
+    fn detect_series(
         &self,
-        db: &DatabaseManager,
-        target_name: &str,
-        key_name: &str,
+        data: &[TelemetryData],
         start_time: Option<DateTime<Utc>>,
         end_time: Option<DateTime<Utc>>,
-    ) -> Result<Vec<TelemetryData>> {
-        use crate::database::QueryParams;
-
-        let params = QueryParams {
-            asset_name: None,
-            device_name: None,
-            target_names: vec![target_name.to_string()],
-            key_names: vec![key_name.to_string()],
-            start_time,
-            end_time,
-            remove_outliers: false, // 我们要检测异常，所以不预先过滤
-            outlier_method: "iqr".to_string(),
-            custom_filter: None,
-            limit: Some(100000), // 大量数据用于分析
-            sampling_config: None,
-            reference_values: None,
-            time_of_day_filter: None,
-        };
+    ) -> Result<Vec<DetectedAnomaly>> {
+        let mut anomalies = Vec::new();
+        for detector in &self.detectors {
+            anomalies.extend(detector.detect(data, start_time, end_time)?);
+        }
 
-        let response = db.query_telemetry_data(&params)?;
-        Ok(response.data)
+        // 生成建议的纠正操作
+        for anomaly in &mut anomalies {
+            anomaly.suggested_correction = self.generate_correction_operation(anomaly);
+        }
+        Ok(anomalies)
     }
 
-    /// 检测突然跳跃
-    fn detect_sudden_jumps(&self, data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
-        let mut anomalies = Vec::new();
-        
+    /// 运行依赖统计窗口的检测：点数不足 min_window_size 时跳过；
+    /// 设置了季节性分解时在 component 给出的调整后序列上检测，结果换算回原始数值
+    ///
+    /// 日变化等周期波动会冒充或掩盖突变，因此突变和噪声在残差上检测；
+    /// 持续偏移会被趋势吸收，在只去除季节项的数据上检测
+    fn windowed_detect(
+        &self,
+        data: &[TelemetryData],
+        profiles: &[SeasonalProfile],
+        component: fn(&DecomposedPoint) -> f64,
+        detect: fn(&Self, &[TelemetryData]) -> Result<Vec<DetectedAnomaly>>,
+    ) -> Result<Vec<DetectedAnomaly>> {
         if data.len() < self.config.min_window_size {
-            return Ok(anomalies);
+            return Ok(Vec::new());
+        }
+        if self.config.seasonality == Seasonality::None {
+            return detect(self, data);
         }
 
-        let values: Vec<f64> = data.iter().map(|d| d.value).collect();
-        
-        // 计算移动窗口的统计信息
-        let window_size = self.config.min_window_size;
-        
-        for i in window_size..values.len() {
-            let window = &values[i-window_size..i];
-            let mean = window.iter().sum::<f64>() / window.len() as f64;
-            let variance = window.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / window.len() as f64;
-            let std_dev = variance.sqrt();
-            
-            let current_value = values[i];
-            let z_score = (current_value - mean) / std_dev;
-            
-            // 检测突然跳跃
-            if z_score.abs() > self.config.max_jump_threshold {
-                let confidence = (z_score.abs() / self.config.max_jump_threshold).min(1.0);
-                
-                anomalies.push(DetectedAnomaly {
-                    target_name: data[i].target_name.clone(),
-                    key_name: data[i].key_name.clone(),
-                    anomaly_type: AnomalyType::SuddenJump,
-                    start_time: data[i].timestamp,
-                    end_time: None,
-                    baseline_value: mean,
-                    anomaly_value: current_value,
-                    jump_magnitude: z_score.abs(),
-                    confidence,
-                    suggested_correction: None,
-                });
+        let (mut decomposition, _) = self.decompose_with(data, profiles);
+        // 卡死的读数不随周期变化，减去季节项反而会在残差中制造出波动
+        for flatline in self.detect_flatlines(data)? {
+            for p in decomposition.iter_mut()
+                .filter(|p| p.timestamp >= flatline.start_time && Some(p.timestamp) <= flatline.end_time)
+            {
+                p.residual += p.seasonal;
+                p.seasonal = 0.0;
             }
         }
+        let adjusted = adjusted_series(data, &decomposition, component);
+        Ok(restore_values(detect(self, &adjusted)?, data, &adjusted))
+    }
 
-        Ok(anomalies)
+    /// 按配置的周期把序列（按时间升序）分解为趋势、季节项和残差
+    ///
+    /// 只估计数据覆盖了 MIN_SEASONAL_CYCLES 个周期以上的周期。趋势为其中最长周期（都不满足时为最短周期）的居中滑动中位数，
+    /// 水平跳变不会被摊平到整个窗口。季节项从短到长依次计算：按相位把一个周期分箱，
+    /// 取去趋势数据在每个箱中的中位数，个别异常点不会进入季节项
+    fn decompose_series(&self, data: &[TelemetryData]) -> Vec<DecomposedPoint> {
+        self.decompose_with(data, &[]).0
     }
 
-    /// 检测持续偏移
-    fn detect_persistent_offsets(&self, _data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
-        let anomalies = Vec::new();
-        
-        // 实现持续偏移检测逻辑
-        // 这里可以使用CUSUM算法或类似的变化点检测方法
-        
-        Ok(anomalies)
+    // 数据覆盖的周期数足够估计季节项的周期
+    fn estimable_periods(&self, times: &[i64]) -> Vec<i64> {
+        let span = times.last().zip(times.first()).map_or(0, |(last, first)| last - first);
+        self.config.seasonality.periods().iter().copied()
+            .filter(|&period| span >= MIN_SEASONAL_CYCLES * period)
+            .collect()
     }
 
-    /// 检测噪声增加
-    fn detect_increased_noise(&self, _data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
-        let anomalies = Vec::new();
-        
-        // 实现噪声增加检测逻辑
-        // 比较不同时间窗口的方差
-        
-        Ok(anomalies)
+    /// 分解序列，数据不足以估计的周期沿用 saved 中的季节项；同时返回用到的全部季节项
+    ///
+    /// 沿用的季节项先从数据中减去，趋势在去季节的数据上以该周期为窗口计算
+    fn decompose_with(&self, data: &[TelemetryData], saved: &[SeasonalProfile]) -> (Vec<DecomposedPoint>, Vec<SeasonalProfile>) {
+        let times: Vec<i64> = data.iter().map(|d| d.timestamp.timestamp_millis()).collect();
+        let values: Vec<f64> = data.iter().map(|d| d.value).collect();
+        let all_periods = self.config.seasonality.periods();
+        let periods = self.estimable_periods(&times);
+        let reused: Vec<&SeasonalProfile> = saved.iter()
+            .filter(|p| all_periods.contains(&p.period) && !periods.contains(&p.period))
+            .collect();
+
+        let mut seasonal = vec![0.0; data.len()];
+        for profile in &reused {
+            for (i, &ts) in times.iter().enumerate() {
+                seasonal[i] += profile.level_at(ts).unwrap_or(0.0);
+            }
+        }
+        let base: Vec<f64> = values.iter().zip(&seasonal).map(|(v, s)| v - s).collect();
+        let window = periods.last().or(reused.iter().map(|p| &p.period).max()).or(all_periods.first());
+        let trend = match window {
+            Some(&period) => moving_median(&times, &base, period),
+            None => base.clone(),
+        };
+
+        let interval = self.expected_interval(data).unwrap_or(1).max(1);
+        let mut remainder: Vec<f64> = base.iter().zip(&trend).map(|(v, t)| v - t).collect();
+        let mut profiles: Vec<SeasonalProfile> = reused.into_iter().cloned().collect();
+        for period in periods {
+            let bin_width = (period / MAX_SEASONAL_BINS).max(interval);
+            let bin_of = |ts: i64| (ts.rem_euclid(period) / bin_width) as usize;
+            let mut bins: Vec<Vec<f64>> = vec![Vec::new(); bin_of(period - 1) + 1];
+            for (&ts, &r) in times.iter().zip(&remainder) {
+                bins[bin_of(ts)].push(r);
+            }
+            let mut profile = SeasonalProfile {
+                period,
+                bin_width,
+                levels: bins.iter().map(|b| (!b.is_empty()).then(|| median(b))).collect(),
+            };
+            profile.center();
+            for (i, &ts) in times.iter().enumerate() {
+                if let Some(level) = profile.level_at(ts) {
+                    seasonal[i] += level;
+                    remainder[i] -= level;
+                }
+            }
+            profiles.push(profile);
+        }
+
+        let points = data.iter().enumerate().map(|(i, d)| DecomposedPoint {
+            timestamp: d.timestamp,
+            value: d.value,
+            trend: trend[i],
+            seasonal: seasonal[i],
+            residual: remainder[i],
+        }).collect();
+        (points, profiles)
     }
 
-    /// 检测数据缺失
-    fn detect_data_gaps(&self, data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
+    /// 增量检测后更新季节项：数据足够时重新估计；沿用的季节项按新数据在各箱中残差的中位数修正，
+    /// 每次修正的权重为 1 / MIN_SEASONAL_CYCLES，与一次估计所用的周期数相当
+    fn learn_profiles(&self, data: &[TelemetryData], saved: &[SeasonalProfile]) -> Vec<SeasonalProfile> {
+        let (points, mut profiles) = self.decompose_with(data, saved);
+        let times: Vec<i64> = points.iter().map(|p| p.timestamp.timestamp_millis()).collect();
+        let estimated = self.estimable_periods(&times);
+        for profile in profiles.iter_mut().filter(|p| !estimated.contains(&p.period)) {
+            let mut bins: Vec<Vec<f64>> = vec![Vec::new(); profile.levels.len()];
+            for (&ts, point) in times.iter().zip(&points) {
+                if let Some(bin) = bins.get_mut((ts.rem_euclid(profile.period) / profile.bin_width) as usize) {
+                    bin.push(point.residual);
+                }
+            }
+            for (level, bin) in profile.levels.iter_mut().zip(&bins) {
+                if !bin.is_empty() {
+                    let correction = median(bin) / MIN_SEASONAL_CYCLES as f64;
+                    *level = Some(level.unwrap_or(0.0) + correction);
+                }
+            }
+            profile.center();
+        }
+        profiles
+    }
+
+    /// 一致性检测
+    ///
+    /// 各序列减去开头 min_window_size 个点的中位数得到相对变化，按公共采样间隔对齐时刻。
+    /// 每个时刻以其他标靶相对变化的中位数作为共识，标靶与共识之差按其自身的稳健标准差（以0为中心的MAD）标准化，
+    /// 超过 consensus_threshold 的点至少连续 consecutive_anomaly_threshold 个才报告（单点尖峰由突变检测负责），
+    /// 间隔不超过同样点数的超限段合并为一个异常
+    fn consensus_deviations(&self, series: &[SeriesData]) -> Vec<DetectedAnomaly> {
         let mut anomalies = Vec::new();
-        
-        // 检测时间间隔异常大的情况
-        for i in 1..data.len() {
-            let time_diff = data[i].timestamp.timestamp_millis() - data[i-1].timestamp.timestamp_millis();
-            
-            // 如果时间间隔超过预期（比如超过1小时），认为是数据缺失
-            if time_diff > 3600000 { // 1小时
+        let series: Vec<&SeriesData> = series.iter()
+            .filter(|s| s.data.len() >= self.config.min_window_size.max(3))
+            .collect();
+        if series.len() < self.config.consensus_min_peers + 1 {
+            return anomalies;
+        }
+        let intervals: Vec<f64> = series.iter().filter_map(|s| self.expected_interval(&s.data)).map(|ms| ms as f64).collect();
+        if intervals.is_empty() {
+            return anomalies;
+        }
+        let interval = (median(&intervals) as i64).max(1);
+
+        // 每个序列在各对齐时刻的相对变化
+        let slot_of = |d: &TelemetryData| d.timestamp.timestamp_millis().div_euclid(interval);
+        let changes: Vec<HashMap<i64, f64>> = series.iter().map(|s| {
+            let head: Vec<f64> = s.data.iter().take(self.config.min_window_size.max(3)).map(|d| d.value).collect();
+            let reference = median(&head);
+            s.data.iter().map(|d| (slot_of(d), d.value - reference)).collect()
+        }).collect();
+
+        let min_run = self.config.consecutive_anomaly_threshold.max(1);
+        for (k, s) in series.iter().enumerate() {
+            let peers: Vec<String> = series.iter().enumerate()
+                .filter(|&(j, _)| j != k)
+                .map(|(_, p)| p.key.target_name.clone())
+                .collect();
+
+            // (数据点下标, 与共识之差)
+            let mut deviations: Vec<(usize, f64)> = Vec::new();
+            let mut peer_values = Vec::with_capacity(peers.len());
+            for (i, d) in s.data.iter().enumerate() {
+                let slot = slot_of(d);
+                peer_values.clear();
+                peer_values.extend(changes.iter().enumerate().filter(|&(j, _)| j != k).filter_map(|(_, c)| c.get(&slot)));
+                if peer_values.len() >= self.config.consensus_min_peers {
+                    deviations.push((i, changes[k][&slot] - median(&peer_values)));
+                }
+            }
+            let abs: Vec<f64> = deviations.iter().map(|&(_, r)| r.abs()).collect();
+            if abs.is_empty() {
+                continue;
+            }
+            let scale = (median(&abs) * 1.4826).max(f64::EPSILON);
+            let flagged: Vec<bool> = deviations.iter().map(|&(_, r)| (r / scale).abs() > self.config.consensus_threshold).collect();
+
+            let mut n = 0;
+            while n < deviations.len() {
+                if !flagged[n] {
+                    n += 1;
+                    continue;
+                }
+                let first = n;
+                let mut last = n;
+                let mut count = 0;
+                while n < deviations.len() && n - last <= min_run {
+                    if flagged[n] {
+                        last = n;
+                        count += 1;
+                    }
+                    n += 1;
+                }
+                n = last + 1;
+                if count < min_run {
+                    continue;
+                }
+
+                let run = &deviations[first..=last];
+                let actual: Vec<f64> = run.iter().map(|&(i, _)| s.data[i].value).collect();
+                let expected: Vec<f64> = run.iter().map(|&(i, r)| s.data[i].value - r).collect();
+                let deviation = median(&run.iter().map(|&(_, r)| r).collect::<Vec<_>>());
+                let peak_z = run.iter().map(|&(_, r)| (r / scale).abs()).fold(0.0, f64::max);
                 anomalies.push(DetectedAnomaly {
-                    target_name: data[i].target_name.clone(),
-                    key_name: data[i].key_name.clone(),
-                    anomaly_type: AnomalyType::DataGap,
-                    start_time: data[i-1].timestamp,
-                    end_time: Some(data[i].timestamp),
-                    baseline_value: data[i-1].value,
-                    anomaly_value: data[i].value,
-                    jump_magnitude: 0.0,
-                    confidence: 1.0,
-                    suggested_correction: None,
+                    end_time: Some(s.data[run[run.len() - 1].0].timestamp),
+                    baseline_value: median(&expected),
+                    anomaly_value: median(&actual),
+                    // 一致性异常的幅度为与共识之差的中位数
+                    jump_magnitude: deviation,
+                    confidence: (peak_z / (2.0 * self.config.consensus_threshold)).min(1.0),
+                    details: Some(format!(
+                        "{} 个点偏离同组 {} 个标靶的共识，偏差中位数 {:.3}，稳健标准差 {:.3}",
+                        count, peers.len(), deviation, scale
+                    )),
+                    peers: Some(peers.clone()),
+                    ..anomaly_at(&s.data[run[0].0], AnomalyType::ConsensusDeviation)
                 });
             }
         }
-        
-        Ok(anomalies)
-    }
 
-    /// 获取所有标靶和指标组合
-    async fn get_all_target_key_combinations(&self, _db: &DatabaseManager) -> Result<Vec<(String, String)>> {
-        use crate::database::QueryParams;
+        anomalies
+    }
 
-        // 获取所有唯一的target_name和key_name组合
-        let _params = QueryParams {
+    /// 检测用的基础查询参数
+    fn series_params(&self, start_time: Option<DateTime<Utc>>, end_time: Option<DateTime<Utc>>) -> QueryParams {
+        QueryParams {
             asset_name: None,
             device_name: None,
             target_names: vec![],
             key_names: vec![],
-            start_time: None,
-            end_time: None,
-            remove_outliers: false,
+            start_time,
+            end_time,
+            remove_outliers: false, // 我们要检测异常，所以不预先过滤
             outlier_method: "iqr".to_string(),
             custom_filter: None,
-            limit: Some(1), // 只需要获取组合，不需要实际数据
+            limit: None,
             sampling_config: None,
             reference_values: None,
             time_of_day_filter: None,
+            allowed_assets: self.allowed_assets.clone(),
+        }
+    }
+
+    /// 检测突然跳跃
+    ///
+    /// 将每个点与之前 min_window_size 个正常点的基线比较，超限的点不进入基线。
+    /// 连续超限的点合并为一个异常；连续达到 consecutive_anomaly_threshold 个点说明水平已经改变，
+    /// 此时结束该异常，把基线窗口平移到新水平后继续检测
+    fn detect_sudden_jumps(&self, data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
+        let mut anomalies = Vec::new();
+        let window_size = self.config.min_window_size.max(2);
+        if data.len() <= window_size {
+            return Ok(anomalies);
+        }
+
+        let values: Vec<f64> = data.iter().map(|d| d.value).collect();
+        let min_baseline = (window_size / 2).max(3);
+        // 离散度的下限取整个序列噪声水平的一部分（差分的方差是噪声方差的两倍），
+        // 卡死等平稳段之后恢复正常波动时不会被当作突变
+        let diffs: Vec<f64> = values.windows(2).map(|w| w[1] - w[0]).collect();
+        let min_spread = robust_std(&diffs) / std::f64::consts::SQRT_2 * MIN_NOISE_SPREAD;
+        let mut window = RollingWindow::new(window_size);
+        for &value in &values[..window_size] {
+            window.push(value);
+        }
+
+        let mut run: Option<JumpRun> = None;
+        for (i, &value) in values.iter().enumerate().skip(window_size) {
+            // 基线点数不足时无法计算z分数，直接作为正常点
+            if window.len() < min_baseline {
+                if let Some(r) = run.take() {
+                    anomalies.push(self.jump_anomaly(data, &r, i - 1));
+                }
+                window.push(value);
+                continue;
+            }
+
+            let (center, spread) = window.baseline(self.config.jump_baseline, min_spread);
+            let z_score = (value - center) / spread;
+            if z_score.abs() <= self.config.max_jump_threshold {
+                if let Some(r) = run.take() {
+                    anomalies.push(self.jump_anomaly(data, &r, i - 1));
+                }
+                window.push(value);
+                continue;
+            }
+
+            let r = run.get_or_insert(JumpRun { start: i, baseline: center, peak: i, peak_z: 0.0 });
+            if z_score.abs() > r.peak_z {
+                r.peak = i;
+                r.peak_z = z_score.abs();
+            }
+            let sustained = i + 1 - r.start >= self.config.consecutive_anomaly_threshold.max(1);
+            if let Some(r) = run.take_if(|_| sustained) {
+                anomalies.push(self.jump_anomaly(data, &r, i));
+                let level = &values[r.start..=i];
+                window.rebase(r.baseline, median(level), level);
+            }
+        }
+
+        if let Some(r) = run {
+            anomalies.push(self.jump_anomaly(data, &r, values.len() - 1));
+        }
+
+        Ok(anomalies)
+    }
+
+    fn jump_anomaly(&self, data: &[TelemetryData], run: &JumpRun, end: usize) -> DetectedAnomaly {
+        DetectedAnomaly {
+            end_time: Some(data[end].timestamp),
+            baseline_value: run.baseline,
+            anomaly_value: data[run.peak].value,
+            jump_magnitude: run.peak_z,
+            confidence: (run.peak_z / self.config.max_jump_threshold).min(1.0),
+            ..anomaly_at(&data[run.start], AnomalyType::SuddenJump)
+        }
+    }
+
+    /// 检测持续偏移
+    ///
+    /// 对一阶差分做双边CUSUM寻找水平跳变：差分对趋势和缓慢周期变化不敏感，跳变则表现为差分的突增。
+    /// 报警后比较变化点前后各 consecutive_anomaly_threshold 个点的中位数确认跳变，
+    /// 单点尖峰会被中位数过滤。跳变累计后不为零的区间报告为持续偏移，直到数据回到原水平；
+    /// 不足确认窗口就回落的区间视为尖峰，不报告
+    fn detect_persistent_offsets(&self, data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
+        let mut anomalies = Vec::new();
+        let window = self.config.min_window_size.max(2);
+        let confirm = self.config.consecutive_anomaly_threshold.max(3);
+        if data.len() < window * 2 {
+            return Ok(anomalies);
+        }
+
+        let values: Vec<f64> = data.iter().map(|d| d.value).collect();
+        let diffs: Vec<f64> = values.windows(2).map(|w| w[1] - w[0]).collect();
+        let diff_sigma = robust_std(&diffs);
+        if diff_sigma <= f64::EPSILON {
+            return Ok(anomalies);
+        }
+
+        // 差分的方差是原始噪声方差的两倍
+        let noise = diff_sigma / std::f64::consts::SQRT_2;
+
+        // 有效变化点：(索引, 跳变量)
+        let mut change_points: Vec<(usize, f64)> = Vec::new();
+        let (mut s_high, mut s_low) = (0.0_f64, 0.0_f64);
+        // 报警后的确认窗口内不再累积同方向的差分，避免同一跳变重复计数；
+        // 反方向照常累积，尖峰回落等窗口内的反向跳变仍能定位到准确位置。(截止索引, 是否上升)
+        let mut hold: Option<(usize, bool)> = None;
+
+        for i in confirm..=values.len().saturating_sub(confirm) {
+            // diffs[i - 1] 是 values[i] 相对前一点的变化
+            let z = diffs[i - 1] / diff_sigma;
+            s_high = (s_high + z - self.config.cusum_drift).max(0.0);
+            s_low = (s_low - z - self.config.cusum_drift).max(0.0);
+            match hold {
+                Some((until, rising)) if i < until => {
+                    if rising {
+                        s_high = 0.0;
+                    } else {
+                        s_low = 0.0;
+                    }
+                }
+                _ => hold = None,
+            }
+
+            if s_high > self.config.cusum_threshold || s_low > self.config.cusum_threshold {
+                let before = median(&values[i - confirm..i]);
+                let after = median(&values[i..i + confirm]);
+
+                // 变化点附近噪声明显增大时交给噪声检测处理，避免随机波动累积成偏移
+                let local = &diffs[i.saturating_sub(window)..(i + window).min(diffs.len())];
+                let noisy = robust_std(local) > 2.0 * diff_sigma;
+                if !noisy && (after - before).abs() >= self.config.sensitivity * noise {
+                    change_points.push((i, after - before));
+                }
+
+                hold = Some((i + confirm, s_high > self.config.cusum_threshold));
+                s_high = 0.0;
+                s_low = 0.0;
+            }
+        }
+
+        // 累计偏移不为零的区间即为持续偏移
+        let mut cumulative = 0.0;
+        let mut baseline = None;
+        for (n, &(change, step)) in change_points.iter().enumerate() {
+            let level_before = median(&values[change - confirm..change]);
+            let origin = *baseline.get_or_insert(level_before);
+            cumulative += step;
+
+            let z = cumulative.abs() / noise;
+            if z < self.config.sensitivity {
+                // 回到原水平，下一次跳变重新确定基线
+                cumulative = 0.0;
+                baseline = None;
+                continue;
+            }
+
+            let segment_end = change_points.get(n + 1).map(|&(next, _)| next);
+            if segment_end.is_some_and(|next| next - change < confirm) {
+                // 不足确认窗口就回落的是尖峰，交给突变检测
+                continue;
+            }
+            anomalies.push(DetectedAnomaly {
+                // 持续到数据末尾的偏移没有结束时间
+                end_time: segment_end.map(|next| data[next - 1].timestamp),
+                baseline_value: origin,
+                anomaly_value: origin + cumulative,
+                jump_magnitude: cumulative.abs(),
+                confidence: (z / (2.0 * self.config.sensitivity)).min(1.0),
+                ..anomaly_at(&data[change], AnomalyType::PersistentOffset)
+            });
+        }
+
+        Ok(anomalies)
+    }
+
+    /// 检测噪声增加
+    ///
+    /// 将序列切成 noise_window_size 个点的窗口，用一阶差分的MAD估计每个窗口的噪声（不受趋势影响），
+    /// 与前面若干个平稳窗口的噪声中位数比较。比值超过阈值的连续窗口合并为一个区间
+    fn detect_increased_noise(&self, data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
+        let mut anomalies = Vec::new();
+        let window = self.config.noise_window_size.max(5);
+        let values: Vec<f64> = data.iter().map(|d| d.value).collect();
+        let diffs: Vec<f64> = values.windows(2).map(|w| w[1] - w[0]).collect();
+        if diffs.len() < window * 3 {
+            return Ok(anomalies);
+        }
+
+        // 每个窗口的噪声水平，差分的方差是原始噪声方差的两倍
+        let levels: Vec<f64> = diffs.chunks_exact(window)
+            .map(|chunk| robust_std(chunk) / std::f64::consts::SQRT_2)
+            .collect();
+        let overall = median(&levels);
+
+        // 参考噪声只取平稳窗口，噪声区间内参考水平保持不变；
+        // 几乎没有波动的窗口（数据卡死）不作为参考，否则之后的正常窗口都会被误判
+        let mut quiet: Vec<f64> = Vec::new();
+        let mut noisy = Vec::with_capacity(levels.len());
+        for &level in &levels {
+            let reference = if quiet.len() >= 3 {
+                median(&quiet[quiet.len().saturating_sub(self.config.noise_reference_windows.max(1))..])
+            } else {
+                overall
+            };
+            let is_noisy = reference > f64::EPSILON && level / reference > self.config.noise_ratio_threshold;
+            if !is_noisy && level > 0.1 * overall {
+                quiet.push(level);
+            }
+            noisy.push(is_noisy.then_some(reference));
+        }
+
+        let mut k = 0;
+        while k < noisy.len() {
+            let Some(reference) = noisy[k] else {
+                k += 1;
+                continue;
+            };
+            let first = k;
+            while k < noisy.len() && noisy[k].is_some() {
+                k += 1;
+            }
+            // 单个窗口的MAD估计波动较大，至少连续两个窗口超限才报告
+            if k - first < 2 {
+                continue;
+            }
+
+            // 窗口k覆盖差分 [k*window, (k+1)*window)，即数据点 [k*window, (k+1)*window]
+            let start = first * window;
+            let end = k * window;
+            let segment = &values[start..=end];
+            let observed = robust_std(&diffs[start..end]) / std::f64::consts::SQRT_2;
+            let ratio = observed / reference;
+            if ratio <= self.config.noise_ratio_threshold {
+                continue;
+            }
+
+            let level = median(segment);
+            let extreme = segment.iter().copied()
+                .max_by(|a, b| (a - level).abs().total_cmp(&(b - level).abs()))
+                .unwrap_or(level);
+            anomalies.push(DetectedAnomaly {
+                end_time: Some(data[end].timestamp),
+                baseline_value: level,
+                anomaly_value: extreme,
+                // 噪声异常的幅度为噪声放大倍数
+                jump_magnitude: ratio,
+                confidence: (ratio / (2.0 * self.config.noise_ratio_threshold)).min(1.0),
+                baseline_noise: Some(reference),
+                observed_noise: Some(observed),
+                ..anomaly_at(&data[start], AnomalyType::IncreasedNoise)
+            });
+        }
+
+        Ok(anomalies)
+    }
+
+    /// 检测时间戳问题：重复和未来时间
+    ///
+    /// 每类问题在一个序列中汇总为一个异常，jump_magnitude 为涉及的点数，details 中给出前几个示例。
+    /// 查询结果总是按时间排序，看不到写入顺序，乱序由 `import_ts_kv check` 在原始表上检查
+    fn detect_timestamp_issues(&self, data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
+        let mut anomalies = Vec::new();
+        let future_limit = Utc::now() + Duration::seconds(self.config.future_tolerance as i64);
+
+        // 每个时间戳第一次出现的位置和该时刻的点数
+        let mut seen: HashMap<DateTime<Utc>, (usize, usize)> = HashMap::new();
+        let mut duplicates = Vec::new();
+        let mut future = Vec::new();
+        for (i, point) in data.iter().enumerate() {
+            let entry = seen.entry(point.timestamp).or_insert((i, 0));
+            entry.1 += 1;
+            if entry.1 > 1 {
+                duplicates.push((entry.0, i));
+            }
+            if point.timestamp > future_limit {
+                future.push(i);
+            }
+        }
+
+        if let (Some(&(first, duplicate)), Some(&(_, last))) = (duplicates.first(), duplicates.last()) {
+            let conflicting = duplicates.iter().filter(|&&(a, b)| data[a].value != data[b].value).count();
+            let burst = seen.values().map(|&(_, count)| count).max().unwrap_or(0);
+            let examples = examples(duplicates.iter().map(|&(a, b)| {
+                format!("{} ({} / {})", data[b].timestamp.to_rfc3339(), data[a].value, data[b].value)
+            }));
+            anomalies.push(DetectedAnomaly {
+                end_time: Some(data[last].timestamp),
+                baseline_value: data[first].value,
+                anomaly_value: data[duplicate].value,
+                jump_magnitude: duplicates.len() as f64,
+                details: Some(format!(
+                    "{} 个点与之前的时间戳重复，其中 {} 个取值不同，同一时刻最多 {} 条，例如: {}",
+                    duplicates.len(), conflicting, burst, examples
+                )),
+                ..anomaly_at(&data[duplicate], AnomalyType::DuplicateTimestamp)
+            });
+        }
+
+        if let (Some(&first), Some(&last)) = (future.first(), future.last()) {
+            let examples = examples(future.iter().map(|&i| data[i].timestamp.to_rfc3339()));
+            anomalies.push(DetectedAnomaly {
+                end_time: Some(data[last].timestamp),
+                jump_magnitude: future.len() as f64,
+                details: Some(format!("{} 个点的时间戳晚于当前时间，例如: {}", future.len(), examples)),
+                ..anomaly_at(&data[first], AnomalyType::FutureTimestamp)
+            });
+        }
+
+        Ok(anomalies)
+    }
+
+    /// 检测读数卡死：变化范围不超过 flatline_tolerance 的连续读数达到 flatline_min_points 个，
+    /// 或持续时间达到 flatline_min_duration
+    fn detect_flatlines(&self, data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
+        let mut anomalies = Vec::new();
+        let min_points = self.config.flatline_min_points.max(2);
+        let min_duration_ms = self.config.flatline_min_duration.map(|secs| secs as i64 * 1000);
+
+        let mut start = 0;
+        while start < data.len() {
+            let (mut low, mut high) = (data[start].value, data[start].value);
+            let mut end = start;
+            while end + 1 < data.len() {
+                let value = data[end + 1].value;
+                if value.max(high) - value.min(low) > self.config.flatline_tolerance {
+                    break;
+                }
+                low = low.min(value);
+                high = high.max(value);
+                end += 1;
+            }
+
+            let points = end + 1 - start;
+            let duration_ms = (data[end].timestamp - data[start].timestamp).num_milliseconds();
+            let long_enough = min_duration_ms.is_some_and(|min| points >= 2 && duration_ms >= min);
+            if points >= min_points || long_enough {
+                anomalies.push(DetectedAnomaly {
+                    end_time: Some(data[end].timestamp),
+                    baseline_value: if start > 0 { data[start - 1].value } else { data[start].value },
+                    jump_magnitude: points as f64,
+                    confidence: if long_enough { 1.0 } else { (points as f64 / (2 * min_points) as f64).min(1.0) },
+                    ..anomaly_at(&data[start], AnomalyType::Flatline)
+                });
+            }
+            start = end + 1;
+        }
+
+        Ok(anomalies)
+    }
+
+    /// 检测超出物理量程或变化速率过大的读数，连续超限的点合并为一个异常
+    fn detect_out_of_range(&self, data: &[TelemetryData]) -> Result<Vec<DetectedAnomaly>> {
+        let mut anomalies = Vec::new();
+        let Some(limits) = data.first().and_then(|d| self.config.value_limits.get(&d.key_name)) else {
+            return Ok(anomalies);
         };
 
-        // 这里需要一个专门的方法来获取所有target_name和key_name组合
-        // 暂时返回一些示例数据，实际应该查询数据库
-        let combinations = vec![
-            ("Target1".to_string(), "displacement_x".to_string()),
-            ("Target1".to_string(), "displacement_y".to_string()),
-            ("Target2".to_string(), "displacement_x".to_string()),
-            ("Target2".to_string(), "displacement_y".to_string()),
-        ];
+        // 每个点的超限量和对应的参考值（量程边界或前一个读数）
+        let violation = |i: usize| -> Option<(f64, f64)> {
+            let value = data[i].value;
+            if let Some(min) = limits.min.filter(|&min| value < min) {
+                return Some((min - value, min));
+            }
+            if let Some(max) = limits.max.filter(|&max| value > max) {
+                return Some((value - max, max));
+            }
+            let max_rate = limits.max_rate?;
+            let previous = data.get(i.checked_sub(1)?)?;
+            let seconds = (data[i].timestamp - previous.timestamp).num_milliseconds() as f64 / 1000.0;
+            let allowed = max_rate * seconds;
+            let change = (value - previous.value).abs();
+            (seconds > 0.0 && change > allowed).then_some((change - allowed, previous.value))
+        };
+
+        // (开始, 最严重的点, 超限量, 参考值)
+        type Run = (usize, usize, f64, f64);
+        let anomaly = |(start, worst, excess, reference): Run, end: usize| DetectedAnomaly {
+            end_time: Some(data[end].timestamp),
+            baseline_value: reference,
+            anomaly_value: data[worst].value,
+            jump_magnitude: excess,
+            ..anomaly_at(&data[start], AnomalyType::OutOfRange)
+        };
 
-        Ok(combinations)
+        let mut run: Option<Run> = None;
+        for i in 0..data.len() {
+            match violation(i) {
+                Some((excess, reference)) => {
+                    let r = run.get_or_insert((i, i, excess, reference));
+                    if excess > r.2 {
+                        *r = (r.0, i, excess, reference);
+                    }
+                }
+                None => {
+                    if let Some(r) = run.take() {
+                        anomalies.push(anomaly(r, i - 1));
+                    }
+                }
+            }
+        }
+        if let Some(r) = run {
+            anomalies.push(anomaly(r, data.len() - 1));
+        }
+
+        Ok(anomalies)
     }
 
-    /// 生成纠正操作建议
-    fn generate_correction_operation(&self, anomaly: &DetectedAnomaly) -> Option<DataOperation> {
-        if !self.config.auto_correction {
-            return None;
+    /// 序列的预期采样间隔（毫秒）：优先使用按数据类型配置的值，否则取相邻数据时间差的中位数
+    fn expected_interval(&self, data: &[TelemetryData]) -> Option<i64> {
+        if let Some(&secs) = data.first().and_then(|d| self.config.expected_intervals.get(&d.key_name)) {
+            return Some(secs as i64 * 1000).filter(|&ms| ms > 0);
+        }
+
+        let intervals: Vec<f64> = data.windows(2)
+            .map(|w| (w[1].timestamp - w[0].timestamp).num_milliseconds() as f64)
+            .filter(|&ms| ms > 0.0)
+            .collect();
+        if intervals.is_empty() {
+            None
+        } else {
+            Some(median(&intervals) as i64)
         }
+    }
 
+    /// 检测数据缺失
+    ///
+    /// 相邻数据的时间差超过预期间隔的 gap_multiplier 倍认为缺失。检测范围两端也会检查：
+    /// 开始时间之后迟迟没有数据，或最后一条数据之后再无数据（未指定结束时间时以当前时间为准）
+    fn detect_data_gaps(
+        &self,
+        data: &[TelemetryData],
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Result<Vec<DetectedAnomaly>> {
+        let mut anomalies = Vec::new();
+        let Some(interval) = self.expected_interval(data) else {
+            return Ok(anomalies);
+        };
+        let max_gap = (interval as f64 * self.config.gap_multiplier) as i64;
+        let gap = |from: &TelemetryData, to: &TelemetryData, start: DateTime<Utc>, end: Option<DateTime<Utc>>| DetectedAnomaly {
+            start_time: start,
+            end_time: end,
+            baseline_value: from.value,
+            anomaly_value: to.value,
+            ..anomaly_at(from, AnomalyType::DataGap)
+        };
+
+        let (Some(first), Some(last)) = (data.first(), data.last()) else {
+            return Ok(anomalies);
+        };
+        // 只取最近 MAX_QUERY_LIMIT 条时开头被截断，此时不检查开始端
+        if let Some(start) = start_time {
+            if data.len() < MAX_QUERY_LIMIT && (first.timestamp - start).num_milliseconds() > max_gap {
+                anomalies.push(gap(first, first, start, Some(first.timestamp)));
+            }
+        }
+
+        for w in data.windows(2) {
+            if (w[1].timestamp - w[0].timestamp).num_milliseconds() > max_gap {
+                anomalies.push(gap(&w[0], &w[1], w[0].timestamp, Some(w[1].timestamp)));
+            }
+        }
+
+        // 末端缺失：指定了结束时间时到结束时间为止，否则表示至今没有新数据
+        if (end_time.unwrap_or_else(Utc::now) - last.timestamp).num_milliseconds() > max_gap {
+            anomalies.push(gap(last, last, last.timestamp, end_time));
+        }
+
+        Ok(anomalies)
+    }
+
+    /// 计算序列的数据完整率，范围为指定的时间范围，未指定的一端使用数据本身的边界
+    fn series_completeness(
+        &self,
+        series: &SeriesData,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Option<SeriesCompleteness> {
+        let interval = self.expected_interval(&series.data)?;
+        let first = series.data.first()?.timestamp;
+        let last = series.data.last()?.timestamp;
+        let span = (end_time.unwrap_or(last) - start_time.unwrap_or(first)).num_milliseconds().max(0);
+        let expected_points = (span / interval) as usize + 1;
+        let actual_points = series.data.len();
+
+        Some(SeriesCompleteness {
+            asset_name: series.key.asset_name.clone(),
+            device_name: series.key.device_name.clone(),
+            target_name: series.key.target_name.clone(),
+            key_name: series.key.key_name.clone(),
+            expected_interval_ms: interval,
+            expected_points,
+            actual_points,
+            completeness: (actual_points as f64 / expected_points as f64 * 100.0).min(100.0),
+        })
+    }
+
+    /// 生成纠正操作建议
+    fn generate_correction_operation(&self, anomaly: &DetectedAnomaly) -> Option<DataOperation> {
         match anomaly.anomaly_type {
             AnomalyType::SuddenJump => {
                 // 对于突然跳跃，建议添加偏移纠正
@@ -334,6 +1850,50 @@ impl AnomalyDetector {
                     updated_at: Utc::now(),
                 })
             },
+            AnomalyType::ConsensusDeviation => {
+                // 偏离同组标靶多为测量问题，在偏离区间内扣除偏差
+                let correction_value = anomaly.baseline_value - anomaly.anomaly_value;
+
+                Some(DataOperation {
+                    id: None,
+                    name: Some(format!("自动纠正-偏离-{}-{}", anomaly.target_name, anomaly.key_name)),
+                    description: Some(format!(
+                        "与同组标靶不一致，预期 {:.3}，实际 {:.3}，建议纠正 {:.3}",
+                        anomaly.baseline_value, anomaly.anomaly_value, correction_value
+                    )),
+                    target_name: anomaly.target_name.clone(),
+                    key_name: anomaly.key_name.clone(),
+                    operation_type: OperationType::Offset,
+                    value: correction_value,
+                    start_time: Some(anomaly.start_time),
+                    end_time: anomaly.end_time,
+                    is_active: false, // 默认不激活，需要用户确认
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                })
+            },
+            AnomalyType::PersistentOffset => {
+                // 对于持续偏移，在偏移区间内整体扣除偏移量
+                let correction_value = anomaly.baseline_value - anomaly.anomaly_value;
+
+                Some(DataOperation {
+                    id: None,
+                    name: Some(format!("自动纠正-偏移-{}-{}", anomaly.target_name, anomaly.key_name)),
+                    description: Some(format!(
+                        "检测到持续偏移，水平从 {:.3} 变为 {:.3}，建议纠正 {:.3}",
+                        anomaly.baseline_value, anomaly.anomaly_value, correction_value
+                    )),
+                    target_name: anomaly.target_name.clone(),
+                    key_name: anomaly.key_name.clone(),
+                    operation_type: OperationType::Offset,
+                    value: correction_value,
+                    start_time: Some(anomaly.start_time),
+                    end_time: anomaly.end_time,
+                    is_active: false, // 默认不激活，需要用户确认
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                })
+            },
             _ => None, // 其他类型的异常暂时不自动生成纠正操作
         }
     }
@@ -342,6 +1902,8 @@ impl AnomalyDetector {
     fn generate_summary(
         &self,
         anomalies: &[DetectedAnomaly],
+        series_analyzed: usize,
+        series_completeness: Vec<SeriesCompleteness>,
         start_time: Option<DateTime<Utc>>,
         end_time: Option<DateTime<Utc>>,
     ) -> AnomalyDetectionSummary {
@@ -363,15 +1925,654 @@ impl AnomalyDetector {
         }
 
         let time_range = (
-            start_time.unwrap_or_else(|| Utc::now()),
-            end_time.unwrap_or_else(|| Utc::now()),
+            start_time.unwrap_or_else(Utc::now),
+            end_time.unwrap_or_else(Utc::now),
         );
 
         AnomalyDetectionSummary {
             total_anomalies: anomalies.len(),
+            series_analyzed,
             targets_affected: targets_affected.len(),
             time_range_analyzed: time_range,
             confidence_distribution,
+            series_completeness,
+        }
+    }
+}
+
+// 以某个数据点为起点的异常，其余字段由各检测方法填写
+fn anomaly_at(point: &TelemetryData, anomaly_type: AnomalyType) -> DetectedAnomaly {
+    DetectedAnomaly {
+        id: None,
+        asset_name: point.asset_name.clone(),
+        device_name: point.device_name.clone(),
+        target_name: point.target_name.clone(),
+        key_name: point.key_name.clone(),
+        anomaly_type,
+        start_time: point.timestamp,
+        end_time: None,
+        baseline_value: point.value,
+        anomaly_value: point.value,
+        jump_magnitude: 0.0,
+        confidence: 1.0,
+        suggested_correction: None,
+        baseline_noise: None,
+        observed_noise: None,
+        details: None,
+        peers: None,
+    }
+}
+
+// 用分解结果中的某个分量替换数值，得到供检测的序列
+fn adjusted_series(data: &[TelemetryData], decomposition: &[DecomposedPoint], value: impl Fn(&DecomposedPoint) -> f64) -> Vec<TelemetryData> {
+    data.iter().zip(decomposition)
+        .map(|(d, p)| TelemetryData { value: value(p), ..d.clone() })
+        .collect()
+}
+
+// 在调整后序列上检测到的异常，把基线和异常值换算回原始数值（按异常起点处被去除的分量）
+fn restore_values(mut anomalies: Vec<DetectedAnomaly>, original: &[TelemetryData], adjusted: &[TelemetryData]) -> Vec<DetectedAnomaly> {
+    for anomaly in &mut anomalies {
+        let i = original.partition_point(|d| d.timestamp < anomaly.start_time);
+        if let (Some(o), Some(a)) = (original.get(i), adjusted.get(i)) {
+            anomaly.baseline_value += o.value - a.value;
+            anomaly.anomaly_value += o.value - a.value;
+        }
+    }
+    anomalies
+}
+
+// 合并同一序列分页检测的完整性，预期间隔以第一页为准
+fn merge_completeness(a: SeriesCompleteness, b: SeriesCompleteness) -> SeriesCompleteness {
+    let expected_points = a.expected_points + b.expected_points;
+    let actual_points = a.actual_points + b.actual_points;
+    SeriesCompleteness {
+        expected_points,
+        actual_points,
+        completeness: (actual_points as f64 / expected_points.max(1) as f64 * 100.0).min(100.0),
+        ..a
+    }
+}
+
+// 按时间的居中滑动中位数，窗口为 window_ms（数据须按时间升序），两端窗口不完整
+fn moving_median(times: &[i64], values: &[f64], window_ms: i64) -> Vec<f64> {
+    let half = window_ms / 2;
+    let mut buffer = Vec::new();
+    let (mut lo, mut hi) = (0, 0);
+    times.iter().map(|&ts| {
+        while times[lo] < ts - half {
+            lo += 1;
+        }
+        while hi < times.len() && times[hi] <= ts + half {
+            hi += 1;
+        }
+        buffer.clear();
+        buffer.extend_from_slice(&values[lo..hi]);
+        let mid = buffer.len() / 2;
+        *buffer.select_nth_unstable_by(mid, f64::total_cmp).1
+    }).collect()
+}
+
+// 异常说明中最多列出的示例数
+const MAX_EXAMPLES: usize = 5;
+
+fn examples(items: impl Iterator<Item = String>) -> String {
+    items.take(MAX_EXAMPLES).collect::<Vec<_>>().join("; ")
+}
+
+// 一段连续超限的突变点
+struct JumpRun {
+    start: usize,
+    baseline: f64,
+    peak: usize,
+    peak_z: f64,
+}
+
+// 突变基线离散度的下限：序列噪声水平的比例、中心值幅度的比例和绝对最小值
+const MIN_NOISE_SPREAD: f64 = 0.8;
+const MIN_RELATIVE_SPREAD: f64 = 1e-6;
+const MIN_SPREAD: f64 = 1e-9;
+
+// 突变检测的滚动基线窗口，均值和方差增量维护，中位数和MAD由有序树按秩查询
+struct RollingWindow {
+    values: VecDeque<f64>,
+    sorted: OrderStatTree,
+    capacity: usize,
+    // 以窗口建立时的第一个值为参照累加，避免大数值相减损失精度
+    shift: f64,
+    sum: f64,
+    sum_sq: f64,
+}
+
+impl RollingWindow {
+    fn new(capacity: usize) -> Self {
+        Self {
+            values: VecDeque::with_capacity(capacity),
+            sorted: OrderStatTree::with_capacity(capacity),
+            capacity,
+            shift: 0.0,
+            sum: 0.0,
+            sum_sq: 0.0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn push(&mut self, value: f64) {
+        if self.values.is_empty() {
+            self.shift = value;
+            self.sum = 0.0;
+            self.sum_sq = 0.0;
+        }
+        if self.values.len() == self.capacity {
+            if let Some(old) = self.values.pop_front() {
+                let d = old - self.shift;
+                self.sum -= d;
+                self.sum_sq -= d * d;
+                self.sorted.remove(old);
+            }
+        }
+        let d = value - self.shift;
+        self.sum += d;
+        self.sum_sq += d * d;
+        self.values.push_back(value);
+        self.sorted.insert(value);
+    }
+
+    // 水平改变后把原窗口平移到新水平再加入新数据，保留原有的波动特征，基线不会因点数不足而中断
+    fn rebase(&mut self, from: f64, to: f64, values: &[f64]) {
+        let offset = to - from;
+        let previous: Vec<f64> = self.values.drain(..).collect();
+        self.sorted.clear();
+        for value in previous {
+            self.push(value + offset);
+        }
+        for &value in values {
+            self.push(value);
+        }
+    }
+
+    // 返回 (中心, 离散度)。完全平稳的窗口离散度为0，离散度不低于 min_spread 和按中心值幅度取的下限，
+    // 平稳信号上的台阶仍能得到有限的z分数
+    fn baseline(&self, method: JumpBaseline, min_spread: f64) -> (f64, f64) {
+        let n = self.values.len() as f64;
+        let mean = self.sum / n;
+        let std = (self.sum_sq / n - mean * mean).max(0.0).sqrt();
+        let (center, spread) = match method {
+            JumpBaseline::MeanStd => (self.shift + mean, std),
+            JumpBaseline::MedianMad => {
+                // 超过一半的读数相同时MAD为0（例如量化的读数），此时退回标准差
+                let center = self.median();
+                let mad = self.mad(center) * 1.4826;
+                (center, if mad > 0.0 { mad } else { std })
+            }
+        };
+        (center, spread.max(min_spread).max(center.abs() * MIN_RELATIVE_SPREAD).max(MIN_SPREAD))
+    }
+
+    fn median(&self) -> f64 {
+        let n = self.sorted.len();
+        if n % 2 == 1 {
+            self.sorted.select(n / 2)
+        } else {
+            (self.sorted.select(n / 2 - 1) + self.sorted.select(n / 2)) / 2.0
+        }
+    }
+
+    // 中位数两侧的偏差各自有序，MAD是两段偏差合并后的中位数
+    fn mad(&self, center: f64) -> f64 {
+        let n = self.sorted.len();
+        if n % 2 == 1 {
+            self.deviation_rank(center, n / 2)
+        } else {
+            (self.deviation_rank(center, n / 2 - 1) + self.deviation_rank(center, n / 2)) / 2.0
+        }
+    }
+
+    // 第k小的 |x - center|：左段为中位数以下的值从近到远，右段为中位数以上的值从近到远
+    fn deviation_rank(&self, center: f64, k: usize) -> f64 {
+        let n = self.sorted.len();
+        let split = n / 2;
+        let left = |i: usize| center - self.sorted.select(split - 1 - i);
+        let right = |j: usize| self.sorted.select(split + j) - center;
+
+        // 二分取自左段的个数i，使前k+1小的偏差恰好由左段i个和右段k+1-i个组成
+        let (mut lo, mut hi) = ((k + 1).saturating_sub(n - split), split.min(k + 1));
+        while lo < hi {
+            let i = (lo + hi) / 2;
+            if left(i) < right(k - i) {
+                lo = i + 1;
+            } else {
+                hi = i;
+            }
         }
+        let j = k + 1 - lo;
+        let from_left = if lo > 0 { left(lo - 1) } else { f64::NEG_INFINITY };
+        let from_right = if j > 0 { right(j - 1) } else { f64::NEG_INFINITY };
+        from_left.max(from_right)
+    }
+}
+
+const NIL: usize = usize::MAX;
+
+struct TreeNode {
+    value: f64,
+    priority: u64,
+    size: usize,
+    left: usize,
+    right: usize,
+}
+
+// 按值排序、可按秩查询的treap，插入、删除和查询第k小都是期望O(log n)
+struct OrderStatTree {
+    nodes: Vec<TreeNode>,
+    free: Vec<usize>,
+    root: usize,
+    seed: u64,
+}
+
+impl OrderStatTree {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            root: NIL,
+            seed: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.size(self.root)
+    }
+
+    fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = NIL;
+    }
+
+    fn insert(&mut self, value: f64) {
+        // xorshift生成优先级，只需要分布均匀
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        let node = TreeNode { value, priority: self.seed, size: 1, left: NIL, right: NIL };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        let (less, rest) = self.split(self.root, value, false);
+        let left = self.merge(less, index);
+        self.root = self.merge(left, rest);
+    }
+
+    // 删除一个等于value的值
+    fn remove(&mut self, value: f64) {
+        let (less, rest) = self.split(self.root, value, false);
+        let (mut equal, greater) = self.split(rest, value, true);
+        if equal != NIL {
+            self.free.push(equal);
+            equal = self.merge(self.nodes[equal].left, self.nodes[equal].right);
+        }
+        let right = self.merge(equal, greater);
+        self.root = self.merge(less, right);
+    }
+
+    // 第k小的值（从0开始），调用方保证 k < len
+    fn select(&self, mut k: usize) -> f64 {
+        let mut t = self.root;
+        loop {
+            let node = &self.nodes[t];
+            let left_size = self.size(node.left);
+            match k.cmp(&left_size) {
+                std::cmp::Ordering::Less => t = node.left,
+                std::cmp::Ordering::Equal => return node.value,
+                std::cmp::Ordering::Greater => {
+                    k -= left_size + 1;
+                    t = node.right;
+                }
+            }
+        }
+    }
+
+    fn size(&self, t: usize) -> usize {
+        if t == NIL { 0 } else { self.nodes[t].size }
+    }
+
+    fn update(&mut self, t: usize) {
+        self.nodes[t].size = 1 + self.size(self.nodes[t].left) + self.size(self.nodes[t].right);
+    }
+
+    // 分成小于value（inclusive时为小于等于）和其余两棵树
+    fn split(&mut self, t: usize, value: f64, inclusive: bool) -> (usize, usize) {
+        if t == NIL {
+            return (NIL, NIL);
+        }
+        let ordering = self.nodes[t].value.total_cmp(&value);
+        let goes_left = ordering.is_lt() || (inclusive && ordering.is_eq());
+        if goes_left {
+            let (left, right) = self.split(self.nodes[t].right, value, inclusive);
+            self.nodes[t].right = left;
+            self.update(t);
+            (t, right)
+        } else {
+            let (left, right) = self.split(self.nodes[t].left, value, inclusive);
+            self.nodes[t].left = right;
+            self.update(t);
+            (left, t)
+        }
+    }
+
+    // 合并两棵树，a中的值都不大于b中的值
+    fn merge(&mut self, a: usize, b: usize) -> usize {
+        if a == NIL {
+            return b;
+        }
+        if b == NIL {
+            return a;
+        }
+        if self.nodes[a].priority > self.nodes[b].priority {
+            self.nodes[a].right = self.merge(self.nodes[a].right, b);
+            self.update(a);
+            a
+        } else {
+            self.nodes[b].left = self.merge(a, self.nodes[b].left);
+            self.update(b);
+            b
+        }
+    }
+}
+
+// 中位数，输入为空时返回0
+fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+// 基于MAD的稳健标准差估计（正态分布下与标准差一致）
+fn robust_std(values: &[f64]) -> f64 {
+    let center = median(values);
+    let deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+    median(&deviations) * 1.4826
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::AnomalyQuery;
+
+    // 可重复的伪随机数，取值含大量重复值
+    fn pseudo_random(count: usize) -> Vec<f64> {
+        let mut seed = 12345u64;
+        (0..count).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) % 50) as f64 / 4.0 - 5.0
+        }).collect()
+    }
+
+    fn series(values: &[f64]) -> Vec<TelemetryData> {
+        values.iter().enumerate().map(|(i, &value)| TelemetryData {
+            timestamp: DateTime::from_timestamp(1_700_000_000 + i as i64 * 60, 0).unwrap(),
+            asset_name: "A".to_string(),
+            device_name: "D".to_string(),
+            target_name: "T".to_string(),
+            key_name: "dx".to_string(),
+            value,
+        }).collect()
+    }
+
+    #[test]
+    fn order_stat_tree_matches_sorted_vec() {
+        let mut tree = OrderStatTree::with_capacity(16);
+        let mut reference: Vec<f64> = Vec::new();
+        let values = pseudo_random(400);
+        for (i, &value) in values.iter().enumerate() {
+            tree.insert(value);
+            reference.push(value);
+            // 每插入三个删除一个较早插入的值，覆盖重复值的删除和节点复用
+            if i % 3 == 2 {
+                let old = values[i / 2];
+                tree.remove(old);
+                let position = reference.iter().position(|&v| v == old).unwrap();
+                reference.remove(position);
+            }
+            reference.sort_by(|a, b| a.total_cmp(b));
+            assert_eq!(tree.len(), reference.len());
+            for (k, &expected) in reference.iter().enumerate() {
+                assert_eq!(tree.select(k), expected);
+            }
+        }
+
+        // 删除不存在的值不改变树
+        tree.remove(1000.0);
+        assert_eq!(tree.len(), reference.len());
+        tree.clear();
+        assert_eq!(tree.len(), 0);
+        tree.insert(1.5);
+        assert_eq!(tree.select(0), 1.5);
+    }
+
+    #[test]
+    fn rolling_window_deviation_rank_matches_sorted_vec() {
+        let values = pseudo_random(300);
+        for capacity in [3, 4, 7, 10, 31] {
+            let mut window = RollingWindow::new(capacity);
+            for (i, &value) in values.iter().enumerate() {
+                window.push(value);
+                let current = &values[(i + 1).saturating_sub(capacity)..=i];
+                let center = window.median();
+                assert_eq!(center, median(current));
+
+                let mut deviations: Vec<f64> = current.iter().map(|v| (v - center).abs()).collect();
+                deviations.sort_by(|a, b| a.total_cmp(b));
+                for (k, &expected) in deviations.iter().enumerate() {
+                    assert_eq!(window.deviation_rank(center, k), expected, "capacity {} step {} rank {}", capacity, i, k);
+                }
+                assert_eq!(window.mad(center), median(&deviations));
+            }
+        }
+    }
+
+    #[test]
+    fn rolling_window_rebase_shifts_to_new_level() {
+        let mut window = RollingWindow::new(4);
+        for value in [1.0, 2.0, 3.0, 4.0] {
+            window.push(value);
+        }
+        window.rebase(2.5, 12.5, &[13.0]);
+        assert_eq!(window.len(), 4);
+        // 平移后为 11, 12, 13, 14，加入13后移出最早的11
+        assert_eq!(window.median(), 13.0);
+        let (center, spread) = window.baseline(JumpBaseline::MeanStd, 0.0);
+        assert!((center - 13.0).abs() < 1e-12);
+        assert!((spread - 0.5f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn step_on_flat_signal_is_detected() {
+        let mut values = vec![10.0; 60];
+        values.extend([10.5; 10]);
+        let data = series(&values);
+
+        for jump_baseline in [JumpBaseline::MeanStd, JumpBaseline::MedianMad] {
+            let detector = AnomalyDetector::with_config(AnomalyDetectionConfig {
+                min_window_size: 20,
+                jump_baseline,
+                ..Default::default()
+            });
+            let anomalies = detector.detect_sudden_jumps(&data).unwrap();
+            assert_eq!(anomalies.len(), 1, "{:?}", jump_baseline);
+            assert_eq!(anomalies[0].start_time, data[60].timestamp);
+            assert_eq!(anomalies[0].end_time, Some(data[62].timestamp));
+            assert_eq!(anomalies[0].baseline_value, 10.0);
+            assert_eq!(anomalies[0].anomaly_value, 10.5);
+
+            // 新水平平稳后不再报告
+            let flat = series(&[10.0; 80]);
+            assert!(detector.detect_sudden_jumps(&flat).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn recovery_after_flatline_is_not_a_jump() {
+        // 卡死段之后恢复正常波动，按序列噪声水平取的下限不把恢复当作突变
+        let noise = pseudo_random(200);
+        let mut values = noise[..80].to_vec();
+        values.extend([noise[79]; 60]);
+        values.extend(&noise[80..]);
+        let detector = AnomalyDetector::with_config(AnomalyDetectionConfig {
+            min_window_size: 20,
+            ..Default::default()
+        });
+        assert!(detector.detect_sudden_jumps(&series(&values)).unwrap().is_empty());
+    }
+
+    // 增量检测一次并保存结果和状态，返回本次检测到的异常
+    async fn detect_and_save(detector: &AnomalyDetector, db: &DatabaseManager) -> Vec<DetectedAnomaly> {
+        let filter = SeriesFilter { asset_name: None, device_name: None, key_names: vec![] };
+        let (mut result, states) = detector.detect_incremental(db, &filter, "test", false).await.unwrap();
+        db.save_incremental_run("admin", result.summary.series_analyzed, &mut result.anomalies, &states).unwrap();
+        result.anomalies
+    }
+
+    #[tokio::test]
+    async fn incremental_detection_does_not_repeat_open_anomalies() {
+        // 正常波动之后传感器卡死，卡死段比保存的上下文更长
+        let row = |i: usize, value: f64| (i as i64 * 60_000, "A", "D", "T", "dx", value);
+        let mut rows: Vec<_> = pseudo_random(400).into_iter().enumerate().map(|(i, v)| row(i, v)).collect();
+        rows.extend((400..800).map(|i| row(i, 1.25)));
+        let test = crate::database::test_db::TestDb::new("incremental_open", &rows);
+        let detector = AnomalyDetector::new(AnomalyDetectionConfig::default());
+
+        let first = detect_and_save(&detector, &test.db).await;
+        let flatlines: Vec<_> = first.iter().filter(|a| a.anomaly_type == AnomalyType::Flatline).collect();
+        assert_eq!(flatlines.len(), 1);
+        assert_eq!(flatlines[0].start_time.timestamp_millis(), 400 * 60_000);
+        let stored = |db: &DatabaseManager| db.list_anomalies(&AnomalyQuery {
+            status: None, run_id: None, target_name: None, key_name: None, allowed_assets: None, limit: 1000,
+        }).unwrap().len();
+        let count = stored(&test.db);
+
+        // 没有新数据和卡死段继续延长时都不再报告已经报告过的异常
+        assert!(detect_and_save(&detector, &test.db).await.is_empty());
+        test.insert(&(800..850).map(|i| row(i, 1.25)).collect::<Vec<_>>());
+        let second = detect_and_save(&detector, &test.db).await;
+        assert!(second.iter().all(|a| a.anomaly_type != AnomalyType::Flatline), "{:?}", second);
+        let watermark = 799 * 60_000;
+        let new = second.iter().filter(|a| a.start_time.timestamp_millis() > watermark).count();
+        assert_eq!(stored(&test.db), count + new);
+
+        // 从水位线开始的数据缺失在有新数据后结束，按起点更新上次保存的未结束记录
+        let count = stored(&test.db);
+        test.insert(&(900..910).map(|i| row(i, 1.25)).collect::<Vec<_>>());
+        let third = detect_and_save(&detector, &test.db).await;
+        let gap = third.iter().find(|a| a.anomaly_type == AnomalyType::DataGap && a.end_time.is_some()).unwrap();
+        assert_eq!(gap.start_time.timestamp_millis(), 849 * 60_000);
+        assert_eq!(gap.end_time.unwrap().timestamp_millis(), 900 * 60_000);
+        let new = third.iter().filter(|a| a.start_time.timestamp_millis() > 849 * 60_000).count();
+        assert_eq!(stored(&test.db), count + new);
+    }
+
+    // 10附近的小噪声，在 [from, to) 区间加上 offset
+    fn offset_series(len: usize, segments: &[(usize, usize, f64)]) -> Vec<TelemetryData> {
+        let values: Vec<f64> = pseudo_random(len).iter().enumerate()
+            .map(|(i, noise)| {
+                let shift: f64 = segments.iter().filter(|(from, to, _)| (*from..*to).contains(&i)).map(|s| s.2).sum();
+                10.0 + noise * 0.02 + shift
+            })
+            .collect();
+        series(&values)
+    }
+
+    fn offset_detector() -> AnomalyDetector {
+        AnomalyDetector::with_config(AnomalyDetectionConfig { min_window_size: 20, ..Default::default() })
+    }
+
+    #[test]
+    fn cusum_reports_offset_segment_and_correction() {
+        let data = offset_series(300, &[(100, 200, 2.0)]);
+        let detector = offset_detector();
+        let anomalies = detector.detect_persistent_offsets(&data).unwrap();
+        assert_eq!(anomalies.len(), 1, "{:?}", anomalies);
+
+        let offset = &anomalies[0];
+        assert_eq!(offset.anomaly_type, AnomalyType::PersistentOffset);
+        assert_eq!(offset.start_time, data[100].timestamp);
+        assert_eq!(offset.end_time, Some(data[199].timestamp));
+        assert!((offset.baseline_value - 10.0).abs() < 0.1);
+        assert!((offset.jump_magnitude - 2.0).abs() < 0.1);
+        assert_eq!(offset.confidence, 1.0);
+
+        // 纠正操作在偏移区间内扣除偏移量
+        let correction = detector.generate_correction_operation(offset).unwrap();
+        assert!(matches!(correction.operation_type, OperationType::Offset));
+        assert!((correction.value + 2.0).abs() < 0.1);
+        assert_eq!(correction.start_time, Some(data[100].timestamp));
+        assert_eq!(correction.end_time, Some(data[199].timestamp));
+        assert!(!correction.is_active);
+    }
+
+    #[test]
+    fn cusum_offsets_accumulate_until_level_returns() {
+        // 两级台阶：第二级相对原水平累计为3，最后一级持续到数据末尾
+        let data = offset_series(400, &[(100, 400, 1.0), (200, 300, 2.0)]);
+        let anomalies = offset_detector().detect_persistent_offsets(&data).unwrap();
+        let summary: Vec<_> = anomalies.iter()
+            .map(|a| (a.start_time, a.end_time, (a.anomaly_value - a.baseline_value).round()))
+            .collect();
+        assert_eq!(summary, [
+            (data[100].timestamp, Some(data[199].timestamp), 1.0),
+            (data[200].timestamp, Some(data[299].timestamp), 3.0),
+            (data[300].timestamp, None, 1.0),
+        ]);
+
+        // 回到原水平后不再报告
+        let data = offset_series(400, &[(100, 200, 1.0), (200, 300, -1.0)]);
+        let anomalies = offset_detector().detect_persistent_offsets(&data).unwrap();
+        assert_eq!(anomalies.iter().map(|a| a.end_time).collect::<Vec<_>>(), [Some(data[199].timestamp), Some(data[299].timestamp)]);
+    }
+
+    #[test]
+    fn cusum_ignores_spikes_and_trends() {
+        let detector = offset_detector();
+        // 两个点的尖峰：中位数确认了跳变，但不足确认窗口就回落
+        let spike = offset_series(300, &[(150, 152, 5.0)]);
+        assert!(detector.detect_persistent_offsets(&spike).unwrap().is_empty());
+
+        // 缓慢的线性趋势在差分上是常数
+        let trend: Vec<f64> = pseudo_random(300).iter().enumerate().map(|(i, noise)| i as f64 * 0.05 + noise * 0.02).collect();
+        assert!(detector.detect_persistent_offsets(&series(&trend)).unwrap().is_empty());
+
+        // 数据过短
+        assert!(detector.detect_persistent_offsets(&offset_series(30, &[(15, 30, 2.0)])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn quantized_readings_use_std_when_mad_is_zero() {
+        // 超过一半的读数相同，MAD为0；偶尔的一个量化步长不是突变
+        let values: Vec<f64> = (0..100).map(|i| if i % 5 == 0 { 1.01 } else { 1.0 }).collect();
+        let detector = AnomalyDetector::with_config(AnomalyDetectionConfig {
+            min_window_size: 20,
+            jump_baseline: JumpBaseline::MedianMad,
+            ..Default::default()
+        });
+        assert!(detector.detect_sudden_jumps(&series(&values)).unwrap().is_empty());
     }
 }
@@ -1,75 +1,170 @@
+use anyhow::Context;
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{Extension, Query, State},
+    http::{header, HeaderMap},
     response::{Json, sse::{Event, Sse}},
-    routing::{get, post, put, delete},
+    routing::{get, post, put},
     Router,
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 use futures::stream::Stream;
 use std::convert::Infallible;
-use tokio_stream::StreamExt;
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    IntoParams, Modify, OpenApi, ToSchema,
+};
 
-use crate::database::{DatabaseManager, FilterOptions, QueryParams, TelemetryResponse, CustomFilter, SamplingConfig, SamplingMethod, ReferenceValue, TimeOfDayFilter, TimeRange, DataOperation, OperationType};
-use crate::anomaly_detection::{AnomalyDetector, AnomalyDetectionConfig};
+use crate::auth::{self, CurrentUser};
+use crate::database::{DatabaseManager, FilterOptions, TelemetryResponse, TelemetryData, DataStats, ReferenceValue, DataOperation, OperationType, Role, User, UserUpdate, ApiToken, AnomalyStatus, AnomalyRun, StoredAnomaly, ReviewOutcome, AlarmRule, AlarmEvent, AlarmThreshold, AlarmLevel, AlarmMetric, AlarmDirection, AcknowledgeOutcome, NotificationStatus, OutboxEntry, DerivedMetric, DerivedInput, Baseline, ReferenceMode, SavedQuery, QueryParams, SeriesStatistics, SamplingConfig, SamplingMethod, MAX_QUERY_LIMIT};
+use crate::notifications::{ChannelTestResult, Notification, Notifier};
+use crate::alarms::{self, AlarmEvaluation};
+use crate::anomaly_detection::{AnomalyDetector, AnomalyDetectionConfig, AnomalyDetectionResult, AnomalyDetectionSummary, AnomalyType, ConsensusGroup, DecomposedPoint, DetectedAnomaly, DetectorInfo, DetectorRegistry, JumpBaseline, Seasonality, SeriesCompleteness, SeriesDecomposition, SeriesFilter, ValueLimits};
+use crate::error::{ApiError, ErrorBody, FieldError, new_correlation_id};
+use crate::trend::{self, RatePoint, SeriesTrend, TrendMethod};
+use crate::forecast::{self, ForecastModel, ForecastPoint, SeriesForecast};
+use crate::validation::{self, Validator};
 
 pub type AppState = Arc<DatabaseManager>;
 
-#[derive(Debug, Deserialize)]
+type ApiResult<T> = Result<Json<ApiResponse<T>>, ApiError>;
+
+// 单次数据库调用的超时时间
+const DB_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct FilterQuery {
+    /// 资产名称
     asset_name: Option<String>,
+    /// 设备名称（仅 /api/targets 需要）
     device_name: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateOperationRequest {
     pub name: Option<String>,  // 可选，不填则自动生成
     pub description: Option<String>,
     pub target_name: String,
     pub key_name: String,
-    pub operation_type: String, // "add", "subtract", "multiply", "divide"
+    /// "add", "subtract", "multiply", "divide", "offset"
+    #[schema(example = "add")]
+    pub operation_type: String,
     pub value: f64,
+    /// RFC 3339 时间，例如 2024-01-01T00:00:00+08:00
     pub start_time: Option<String>,
+    /// RFC 3339 时间
     pub end_time: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateOperationRequest {
+    /// 必须与路径中的ID一致
     pub id: i64,
     pub name: Option<String>,
     pub description: Option<String>,
     pub target_name: String,
     pub key_name: String,
+    /// "add", "subtract", "multiply", "divide", "offset"
+    #[schema(example = "add")]
     pub operation_type: String,
     pub value: f64,
+    /// RFC 3339 时间
     pub start_time: Option<String>,
+    /// RFC 3339 时间
     pub end_time: Option<String>,
     pub is_active: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct TelemetryQuery {
-    asset_name: Option<String>,
-    device_name: Option<String>,
-    target_names: Option<String>, // 逗号分隔的字符串
-    key_names: Option<String>, // 改为支持多个数据类型，逗号分隔
-    start_time: Option<String>,
-    end_time: Option<String>,
-    remove_outliers: Option<bool>, // 是否去除异常值
-    outlier_method: Option<String>, // 异常值检测方法: "iqr" 或 "zscore"
-    min_value: Option<f64>, // 最小值过滤
-    max_value: Option<f64>, // 最大值过滤
-    exclude_values: Option<String>, // 排除的值，逗号分隔
-    limit: Option<usize>, // 限制返回数据量
-    sampling_interval: Option<i64>, // 采样间隔（毫秒）
-    sampling_method: Option<String>, // 采样方法: "first", "last", "avg", "max", "min"
-    reference_values: Option<String>, // 参考值配置，JSON格式
-    time_ranges: Option<String>, // 时间段配置，JSON格式: [{"start":"HH:MM","end":"HH:MM"}]
-}
-
-#[derive(Debug, Serialize)]
+    pub asset_name: Option<String>,
+    pub device_name: Option<String>,
+    /// 标靶名称，逗号分隔，例如 `T1,T2`
+    pub target_names: Option<String>,
+    /// 数据类型，逗号分隔，例如 `dx,dy`
+    pub key_names: Option<String>,
+    /// RFC 3339 开始时间
+    pub start_time: Option<String>,
+    /// RFC 3339 结束时间，不能早于开始时间
+    pub end_time: Option<String>,
+    /// 开始时间，可以是相对当前时间的 `-7d`（单位 m, h, d, w）、`now` 或 RFC 3339 时间，不能与 start_time 同时使用
+    pub start: Option<String>,
+    /// 结束时间，格式同 start，不能与 end_time 同时使用
+    pub end: Option<String>,
+    /// 回放保存的查询（短ID），此时只能另外指定时间范围
+    pub saved: Option<String>,
+    /// 是否去除异常值
+    pub remove_outliers: Option<bool>,
+    /// 异常值检测方法: "iqr"（默认）或 "zscore"
+    pub outlier_method: Option<String>,
+    /// 最小值过滤
+    pub min_value: Option<f64>,
+    /// 最大值过滤
+    pub max_value: Option<f64>,
+    /// 排除的值，逗号分隔，例如 `0,9999`
+    pub exclude_values: Option<String>,
+    /// 限制返回数据量（1-100000）
+    pub limit: Option<usize>,
+    /// 采样间隔（毫秒，必须为正）
+    pub sampling_interval: Option<i64>,
+    /// 采样方法: "first"（默认）, "last", "avg", "max", "min"
+    pub sampling_method: Option<String>,
+    /// 参考值配置，ReferenceValue数组的JSON，例如 `[{"target_name":"T1","key_name":"dx","reference_value":1.5}]`
+    /// 或 `[{"target_name":"T1","key_name":"dx","mode":"epoch","reference_time":"2024-01-01T00:00:00+08:00"}]`
+    pub reference_values: Option<String>,
+    /// 使用已保存的基准（名称），不能与 reference_values 同时使用
+    pub baseline: Option<String>,
+    /// 每日时间段配置，JSON格式，例如 `[{"start":"08:00","end":"18:00"}]`，支持跨午夜
+    pub time_ranges: Option<String>,
+}
+
+/// 统一响应格式
+#[derive(Debug, Serialize, ToSchema)]
+#[aliases(
+    FilterOptionsResponse = ApiResponse<FilterOptions>,
+    NameListResponse = ApiResponse<Vec<String>>,
+    TelemetryDataResponse = ApiResponse<TelemetryResponse>,
+    StatisticsResponse = ApiResponse<Vec<SeriesStatistics>>,
+    TrendResponse = ApiResponse<Vec<SeriesTrend>>,
+    ForecastResponse = ApiResponse<Vec<SeriesForecast>>,
+    OperationListResponse = ApiResponse<Vec<DataOperation>>,
+    OperationIdResponse = ApiResponse<i64>,
+    OperationIdListResponse = ApiResponse<Vec<i64>>,
+    ExportDataResponse = ApiResponse<Vec<ExportData>>,
+    AnomalyListResponse = ApiResponse<Vec<DetectedAnomaly>>,
+    AnomalyResultResponse = ApiResponse<AnomalyDetectionResult>,
+    DecompositionResponse = ApiResponse<Vec<SeriesDecomposition>>,
+    DetectorListResponse = ApiResponse<Vec<DetectorInfo>>,
+    StoredAnomalyListResponse = ApiResponse<Vec<StoredAnomaly>>,
+    AnomalyRunListResponse = ApiResponse<Vec<AnomalyRun>>,
+    ReviewResultResponse = ApiResponse<ReviewResult>,
+    AlarmRuleListResponse = ApiResponse<Vec<AlarmRule>>,
+    AlarmRuleIdResponse = ApiResponse<i64>,
+    AlarmEventListResponse = ApiResponse<Vec<AlarmEvent>>,
+    AlarmEvaluationResponse = ApiResponse<AlarmEvaluation>,
+    OutboxEntryListResponse = ApiResponse<Vec<OutboxEntry>>,
+    ChannelTestResultListResponse = ApiResponse<Vec<ChannelTestResult>>,
+    DerivedMetricListResponse = ApiResponse<Vec<DerivedMetric>>,
+    DerivedMetricIdResponse = ApiResponse<i64>,
+    BaselineListResponse = ApiResponse<Vec<Baseline>>,
+    BaselineResponse = ApiResponse<Baseline>,
+    BaselineIdResponse = ApiResponse<i64>,
+    SavedQueryResponse = ApiResponse<SavedQuery>,
+    SavedQueryListResponse = ApiResponse<Vec<SavedQuery>>,
+    SavedQueryIdResponse = ApiResponse<String>,
+    EmptyResponse = ApiResponse<serde_json::Value>,
+    UserResponse = ApiResponse<User>,
+    UserListResponse = ApiResponse<Vec<User>>,
+    UserIdResponse = ApiResponse<i64>,
+    TokenListResponse = ApiResponse<Vec<ApiToken>>,
+    CreatedTokenResponse = ApiResponse<CreatedToken>,
+)]
 pub struct ApiResponse<T> {
     success: bool,
     data: Option<T>,
@@ -84,25 +179,103 @@ impl<T> ApiResponse<T> {
             error: None,
         }
     }
+}
 
-    pub fn error(error: String) -> Self {
-        Self {
-            success: false,
-            data: None,
-            error: Some(error),
-        }
+// 在阻塞线程池中执行数据库调用，超时则返回Timeout错误
+pub(crate) async fn run_db<T, F>(db: &AppState, f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&DatabaseManager) -> anyhow::Result<T> + Send + 'static,
+{
+    let db = db.clone();
+    match tokio::time::timeout(DB_TIMEOUT, tokio::task::spawn_blocking(move || f(&db))).await {
+        Ok(Ok(result)) => result.map_err(ApiError::from),
+        Ok(Err(join_error)) => Err(ApiError::Database(anyhow::anyhow!("Database task failed: {}", join_error))),
+        Err(_) => Err(ApiError::Timeout(format!("Database query exceeded {} seconds", DB_TIMEOUT.as_secs()))),
     }
 }
 
-pub fn create_router(db_manager: DatabaseManager) -> Router {
-    let state = Arc::new(db_manager);
-    
+// 有资产限制的用户只能管理其资产范围内标靶的数据操作
+async fn check_target_access(db: &AppState, user: &CurrentUser, target_name: &str) -> Result<(), ApiError> {
+    let Some(assets) = user.asset_scope() else {
+        return Ok(());
+    };
+    let target = target_name.to_string();
+    let allowed = run_db(db, move |db| {
+        db.target_in_assets(&target, &assets).context("Error checking target access")
+    }).await?;
+    if allowed {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden(format!("No access to target '{}'", target_name)))
+    }
+}
+
+// 数据操作按标靶名称生效，会修改所有资产中的同名标靶；有资产限制的用户只能操作不出现在其他资产中的标靶
+async fn check_operation_target(db: &AppState, user: &CurrentUser, target_name: &str) -> Result<(), ApiError> {
+    check_target_access(db, user, target_name).await?;
+    let Some(assets) = user.asset_scope() else {
+        return Ok(());
+    };
+    let target = target_name.to_string();
+    let shared = run_db(db, move |db| {
+        db.target_outside_assets(&target, &assets).context("Error checking target access")
+    }).await?;
+    if shared {
+        Err(ApiError::forbidden(format!(
+            "Target '{}' also exists in assets outside your access, operations on it would change their data",
+            target_name
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+async fn check_operation_access(db: &AppState, user: &CurrentUser, id: i64) -> Result<(), ApiError> {
+    if user.asset_scope().is_none() {
+        return Ok(());
+    }
+    let target = run_db(db, move |db| db.get_operation_target(id).context("Error getting operation")).await?
+        .ok_or_else(|| ApiError::not_found(format!("Operation {} not found", id)))?;
+    check_operation_target(db, user, &target).await
+}
+
+// 有资产限制的用户可以看到的标靶，None表示不限制
+async fn visible_targets(db: &AppState, user: &CurrentUser) -> Result<Option<HashSet<String>>, ApiError> {
+    let Some(assets) = user.asset_scope() else {
+        return Ok(None);
+    };
+    let targets = run_db(db, move |db| db.targets_in_assets(&assets).context("Error getting targets")).await?;
+    Ok(Some(targets))
+}
+
+fn baseline_in_scope(baseline: &Baseline, targets: &HashSet<String>) -> bool {
+    baseline.reference_values.iter().all(|r| targets.contains(&r.target_name))
+}
+
+fn saved_query_in_scope(user: &CurrentUser, saved: &SavedQuery, targets: &HashSet<String>) -> bool {
+    saved.query.asset_name.as_deref().is_none_or(|asset| user.check_asset(asset).is_ok())
+        && saved.query.target_names.iter().all(|t| targets.contains(t))
+}
+
+// 为异步检测任务施加同样的超时
+async fn with_timeout<T>(future: impl std::future::Future<Output = anyhow::Result<T>>) -> Result<T, ApiError> {
+    match tokio::time::timeout(DB_TIMEOUT, future).await {
+        Ok(result) => result.map_err(ApiError::from),
+        Err(_) => Err(ApiError::Timeout(format!("Request exceeded {} seconds", DB_TIMEOUT.as_secs()))),
+    }
+}
+
+pub fn create_router(state: AppState, detectors: DetectorRegistry, notifier: Arc<Notifier>) -> Router {
     Router::new()
         .route("/api/filters", get(get_filter_options))
         .route("/api/devices", get(get_devices_by_asset))
         .route("/api/targets", get(get_targets_by_device))
         .route("/api/telemetry", get(get_telemetry_data))
         .route("/api/telemetry/stream", get(get_telemetry_data_stream))
+        .route("/api/statistics", get(get_statistics))
+        .route("/api/trend", get(get_trend))
+        .route("/api/forecast", get(get_forecast))
         .route("/api/operations", get(get_operations).post(create_operation))
         .route("/api/operations/export", get(export_operations))
         .route("/api/operations/import", post(import_operations))
@@ -110,272 +283,193 @@ pub fn create_router(db_manager: DatabaseManager) -> Router {
         .route("/api/operations/:id/toggle", post(toggle_operation))
         .route("/api/anomaly/detect", post(detect_anomalies))
         .route("/api/anomaly/detect-all", post(detect_all_anomalies))
+        .route("/api/anomaly/consensus", post(detect_consensus_anomalies))
+        .route("/api/anomaly/decompose", get(decompose_series))
+        .route("/api/anomaly/detectors", get(list_detectors))
+        .route("/api/anomaly/runs", get(list_anomaly_runs))
+        .route("/api/anomaly/findings", get(list_anomalies))
+        .route("/api/anomaly/findings/:id/accept", post(accept_anomaly))
+        .route("/api/anomaly/findings/:id/reject", post(reject_anomaly))
+        .route("/api/anomaly/findings/:id/fix", post(fix_anomaly))
+        .route("/api/alarms", get(list_alarms))
+        .route("/api/alarms/rules", get(list_alarm_rules).post(create_alarm_rule))
+        .route("/api/alarms/rules/:id", put(update_alarm_rule).delete(delete_alarm_rule))
+        .route("/api/alarms/evaluate", post(evaluate_alarms))
+        .route("/api/alarms/:id/acknowledge", post(acknowledge_alarm))
+        .route("/api/notifications", get(list_notifications))
+        .route("/api/notifications/test", post(test_notifications))
+        .route("/api/derived-metrics", get(list_derived_metrics).post(create_derived_metric))
+        .route("/api/derived-metrics/:id", put(update_derived_metric).delete(delete_derived_metric))
+        .route("/api/baselines", get(list_baselines).post(create_baseline))
+        .route("/api/baselines/:id", get(get_baseline).put(update_baseline).delete(delete_baseline))
+        .route("/api/saved-queries", get(list_saved_queries).post(create_saved_query))
+        .route("/api/saved-queries/:id", get(get_saved_query).put(update_saved_query).delete(delete_saved_query))
+        .route("/api/auth/login", post(login))
+        .route("/api/auth/logout", post(logout))
+        .route("/api/auth/me", get(get_current_user))
+        .route("/api/users", get(list_users).post(create_user))
+        .route("/api/users/:id", put(update_user).delete(delete_user))
+        .route("/api/tokens", get(list_tokens).post(create_token))
+        .route("/api/tokens/:id", axum::routing::delete(delete_token))
+        .route("/api/openapi.json", get(get_openapi))
+        .layer(Extension(Arc::new(detectors)))
+        .layer(Extension(notifier))
         .with_state(state)
 }
 
+/// 获取所有可用的筛选选项
+#[utoipa::path(
+    get,
+    path = "/api/filters",
+    tag = "filters",
+    responses(
+        (status = 200, description = "资产、设备、标靶和数据类型列表", body = FilterOptionsResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn get_filter_options(
     State(db): State<AppState>,
-) -> Result<Json<ApiResponse<FilterOptions>>, StatusCode> {
-    match db.get_filter_options() {
-        Ok(options) => Ok(Json(ApiResponse::success(options))),
-        Err(e) => {
-            eprintln!("Error getting filter options: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
-    }
+    user: CurrentUser,
+) -> ApiResult<FilterOptions> {
+    let scope = user.asset_scope();
+    let options = run_db(&db, move |db| {
+        db.get_filter_options(scope.as_deref()).context("Error getting filter options")
+    }).await?;
+    Ok(Json(ApiResponse::success(options)))
 }
 
+/// 获取特定资产下的所有设备
+#[utoipa::path(
+    get,
+    path = "/api/devices",
+    tag = "filters",
+    params(FilterQuery),
+    responses(
+        (status = 200, description = "设备名称列表", body = NameListResponse),
+        (status = 400, description = "缺少asset_name", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "无权访问该资产", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn get_devices_by_asset(
     State(db): State<AppState>,
+    user: CurrentUser,
     Query(params): Query<FilterQuery>,
-) -> Result<Json<ApiResponse<Vec<String>>>, StatusCode> {
-    let asset_name = match params.asset_name {
-        Some(name) => name,
-        None => return Ok(Json(ApiResponse::error("asset_name parameter is required".to_string()))),
-    };
+) -> ApiResult<Vec<String>> {
+    let asset_name = params.asset_name
+        .ok_or_else(|| ApiError::validation("asset_name", "asset_name parameter is required"))?;
+    user.check_asset(&asset_name)?;
 
-    match db.get_devices_by_asset(&asset_name) {
-        Ok(devices) => Ok(Json(ApiResponse::success(devices))),
-        Err(e) => {
-            eprintln!("Error getting devices: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
-    }
+    let devices = run_db(&db, move |db| db.get_devices_by_asset(&asset_name).context("Error getting devices")).await?;
+    Ok(Json(ApiResponse::success(devices)))
 }
 
+/// 获取特定设备下的所有标靶
+#[utoipa::path(
+    get,
+    path = "/api/targets",
+    tag = "filters",
+    params(FilterQuery),
+    responses(
+        (status = 200, description = "标靶名称列表", body = NameListResponse),
+        (status = 400, description = "缺少asset_name或device_name", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "无权访问该资产", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn get_targets_by_device(
     State(db): State<AppState>,
+    user: CurrentUser,
     Query(params): Query<FilterQuery>,
-) -> Result<Json<ApiResponse<Vec<String>>>, StatusCode> {
-    let asset_name = match params.asset_name {
-        Some(name) => name,
-        None => return Ok(Json(ApiResponse::error("asset_name parameter is required".to_string()))),
-    };
-
-    let device_name = match params.device_name {
-        Some(name) => name,
-        None => return Ok(Json(ApiResponse::error("device_name parameter is required".to_string()))),
-    };
-
-    match db.get_targets_by_device(&asset_name, &device_name) {
-        Ok(targets) => Ok(Json(ApiResponse::success(targets))),
-        Err(e) => {
-            eprintln!("Error getting targets: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
-    }
+) -> ApiResult<Vec<String>> {
+    let asset_name = params.asset_name
+        .ok_or_else(|| ApiError::validation("asset_name", "asset_name parameter is required"))?;
+    user.check_asset(&asset_name)?;
+
+    let device_name = params.device_name
+        .ok_or_else(|| ApiError::validation("device_name", "device_name parameter is required"))?;
+
+    let targets = run_db(&db, move |db| {
+        db.get_targets_by_device(&asset_name, &device_name).context("Error getting targets")
+    }).await?;
+    Ok(Json(ApiResponse::success(targets)))
 }
 
+/// 查询遥测数据，已应用激活的数据操作
+#[utoipa::path(
+    get,
+    path = "/api/telemetry",
+    tag = "telemetry",
+    params(TelemetryQuery),
+    responses(
+        (status = 200, description = "遥测数据及统计信息", body = TelemetryDataResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 404, description = "saved或baseline指定的查询或基准不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+        (status = 504, description = "查询超时", body = ErrorBody),
+    )
+)]
 async fn get_telemetry_data(
     State(db): State<AppState>,
+    user: CurrentUser,
     Query(params): Query<TelemetryQuery>,
-) -> Result<Json<ApiResponse<TelemetryResponse>>, StatusCode> {
-    let target_names = if let Some(targets_str) = params.target_names {
-        targets_str.split(',').map(|s| s.trim().to_string()).collect()
-    } else {
-        Vec::new()
-    };
-
-    let key_names = if let Some(keys_str) = params.key_names {
-        keys_str.split(',').map(|s| s.trim().to_string()).collect()
-    } else {
-        Vec::new()
-    };
-
-    let start_time = if let Some(time_str) = params.start_time {
-        match DateTime::parse_from_rfc3339(&time_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid start_time format".to_string()))),
-        }
-    } else {
-        None
-    };
-
-    let end_time = if let Some(time_str) = params.end_time {
-        match DateTime::parse_from_rfc3339(&time_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid end_time format".to_string()))),
-        }
-    } else {
-        None
-    };
-
-    // 解析自定义过滤参数
-    let custom_filter = if params.min_value.is_some() || params.max_value.is_some() || params.exclude_values.is_some() {
-        let exclude_values = if let Some(exclude_str) = &params.exclude_values {
-            exclude_str.split(',')
-                .filter_map(|s| s.trim().parse::<f64>().ok())
-                .collect()
-        } else {
-            Vec::new()
-        };
-
-        Some(CustomFilter {
-            min_value: params.min_value,
-            max_value: params.max_value,
-            exclude_values,
-        })
-    } else {
-        None
-    };
-
-    // 构建采样配置
-    let sampling_config = if let Some(interval) = params.sampling_interval {
-        let method = match params.sampling_method.as_deref().unwrap_or("first") {
-            "last" => SamplingMethod::Last,
-            "avg" => SamplingMethod::Avg,
-            "max" => SamplingMethod::Max,
-            "min" => SamplingMethod::Min,
-            _ => SamplingMethod::First,
-        };
-        Some(SamplingConfig {
-            interval_ms: interval,
-            method,
-        })
-    } else {
-        None
-    };
-
-    // 解析参考值配置
-    let reference_values = if let Some(ref_str) = &params.reference_values {
-        match serde_json::from_str::<Vec<ReferenceValue>>(ref_str) {
-            Ok(refs) => Some(refs),
-            Err(_) => {
-                return Ok(Json(ApiResponse::error("Invalid reference_values format".to_string())));
-            }
-        }
-    } else {
-        None
-    };
-
-    // 解析每日时间段过滤
-    let time_of_day_filter = if let Some(time_ranges_str) = &params.time_ranges {
-        match parse_time_ranges(time_ranges_str) {
-            Ok(time_ranges) => {
-                if time_ranges.is_empty() {
-                    None
-                } else {
-                    Some(TimeOfDayFilter { time_ranges })
-                }
-            },
-            Err(e) => {
-                return Ok(Json(ApiResponse::error(format!("Invalid time ranges format: {}", e))));
-            }
-        }
-    } else {
-        None
-    };
-
-    let query_params = QueryParams {
-        asset_name: params.asset_name,
-        device_name: params.device_name,
-        target_names,
-        key_names,
-        start_time,
-        end_time,
-        remove_outliers: params.remove_outliers.unwrap_or(false),
-        outlier_method: params.outlier_method.unwrap_or_else(|| "iqr".to_string()),
-        custom_filter,
-        limit: params.limit,
-        sampling_config,
-        reference_values,
-        time_of_day_filter,
-    };
-
-    match db.query_telemetry_data(&query_params) {
-        Ok(data) => Ok(Json(ApiResponse::success(data))),
-        Err(e) => {
-            eprintln!("Error querying telemetry data: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
-    }
-}
-
-// 解析时间字符串 "HH:MM" 格式
-fn parse_time_string(time_str: &str) -> Option<(u8, u8)> {
-    let parts: Vec<&str> = time_str.split(':').collect();
-    if parts.len() != 2 {
-        return None;
-    }
-
-    let hour = parts[0].parse::<u8>().ok()?;
-    let minute = parts[1].parse::<u8>().ok()?;
-
-    if hour > 23 || minute > 59 {
-        return None;
-    }
-
-    Some((hour, minute))
-}
-
-// 解析时间段数组 JSON格式
-fn parse_time_ranges(time_ranges_str: &str) -> Result<Vec<TimeRange>, String> {
-    #[derive(serde::Deserialize)]
-    struct TimeRangeInput {
-        start: String,
-        end: String,
-    }
-
-    let input_ranges: Vec<TimeRangeInput> = serde_json::from_str(time_ranges_str)
-        .map_err(|e| format!("JSON parse error: {}", e))?;
-
-    let mut time_ranges = Vec::new();
+) -> ApiResult<TelemetryResponse> {
+    let query_params = resolve_telemetry_query(&db, &user, params).await?;
 
-    for input_range in input_ranges {
-        let (start_hour, start_minute) = parse_time_string(&input_range.start)
-            .ok_or_else(|| format!("Invalid start time format: {}", input_range.start))?;
-        let (end_hour, end_minute) = parse_time_string(&input_range.end)
-            .ok_or_else(|| format!("Invalid end time format: {}", input_range.end))?;
-
-        time_ranges.push(TimeRange {
-            start_hour,
-            start_minute,
-            end_hour,
-            end_minute,
-        });
-    }
-
-    Ok(time_ranges)
+    let data = run_db(&db, move |db| {
+        db.query_telemetry_data(&query_params).context("Error querying telemetry data")
+    }).await?;
+    Ok(Json(ApiResponse::success(data)))
 }
 
+/// 获取所有数据操作
+#[utoipa::path(
+    get,
+    path = "/api/operations",
+    tag = "operations",
+    responses(
+        (status = 200, description = "数据操作列表", body = OperationListResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn get_operations(
     State(db): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<DataOperation>>>, StatusCode> {
-    match db.get_operations(false) {
-        Ok(operations) => Ok(Json(ApiResponse::success(operations))),
-        Err(e) => {
-            eprintln!("Error getting operations: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
+    user: CurrentUser,
+) -> ApiResult<Vec<DataOperation>> {
+    let mut operations = run_db(&db, |db| db.get_operations(false).context("Error getting operations")).await?;
+    if let Some(targets) = visible_targets(&db, &user).await? {
+        operations.retain(|op| targets.contains(&op.target_name));
     }
+    Ok(Json(ApiResponse::success(operations)))
 }
 
+/// 创建数据操作
+#[utoipa::path(
+    post,
+    path = "/api/operations",
+    tag = "operations",
+    request_body = CreateOperationRequest,
+    responses(
+        (status = 200, description = "新操作的ID", body = OperationIdResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该标靶", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn create_operation(
     State(db): State<AppState>,
+    user: CurrentUser,
     Json(request): Json<CreateOperationRequest>,
-) -> Result<Json<ApiResponse<i64>>, StatusCode> {
-    let operation_type = match OperationType::from_str(&request.operation_type) {
-        Some(op_type) => op_type,
-        None => return Ok(Json(ApiResponse::error(format!("Invalid operation type: {}", request.operation_type))))
-    };
-
-    let start_time = if let Some(time_str) = request.start_time {
-        match DateTime::parse_from_rfc3339(&time_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid start_time format".to_string()))),
-        }
-    } else {
-        None
-    };
-
-    let end_time = if let Some(time_str) = request.end_time {
-        match DateTime::parse_from_rfc3339(&time_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid end_time format".to_string()))),
-        }
-    } else {
-        None
-    };
+) -> ApiResult<i64> {
+    user.require(Role::Operator)?;
+    let validation::OperationFields { operation_type, start_time, end_time } = validation::create_operation(&request)?;
+    check_operation_target(&db, &user, &request.target_name).await?;
 
     let now = Utc::now();
     
@@ -406,42 +500,36 @@ async fn create_operation(
         updated_at: now,
     };
 
-    match db.create_operation(&operation) {
-        Ok(id) => Ok(Json(ApiResponse::success(id))),
-        Err(e) => {
-            eprintln!("Error creating operation: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
-    }
+    let id = run_db(&db, move |db| db.create_operation(&operation).context("Error creating operation")).await?;
+    Ok(Json(ApiResponse::success(id)))
 }
 
+/// 更新数据操作
+#[utoipa::path(
+    put,
+    path = "/api/operations/{id}",
+    tag = "operations",
+    params(("id" = i64, Path, description = "操作ID")),
+    request_body = UpdateOperationRequest,
+    responses(
+        (status = 200, description = "更新成功", body = EmptyResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该标靶", body = ErrorBody),
+        (status = 404, description = "操作不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn update_operation(
     State(db): State<AppState>,
+    user: CurrentUser,
     axum::extract::Path(id): axum::extract::Path<i64>,
     Json(request): Json<UpdateOperationRequest>,
-) -> Result<Json<ApiResponse<()>>, StatusCode> {
-    let operation_type = match OperationType::from_str(&request.operation_type) {
-        Some(op_type) => op_type,
-        None => return Ok(Json(ApiResponse::error(format!("Invalid operation type: {}", request.operation_type))))
-    };
-
-    let start_time = if let Some(time_str) = request.start_time {
-        match DateTime::parse_from_rfc3339(&time_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid start_time format".to_string()))),
-        }
-    } else {
-        None
-    };
-
-    let end_time = if let Some(time_str) = request.end_time {
-        match DateTime::parse_from_rfc3339(&time_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid end_time format".to_string()))),
-        }
-    } else {
-        None
-    };
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    let validation::OperationFields { operation_type, start_time, end_time } = validation::update_operation(id, &request)?;
+    check_operation_access(&db, &user, id).await?;
+    check_operation_target(&db, &user, &request.target_name).await?;
 
     let operation = DataOperation {
         id: Some(id),
@@ -458,55 +546,83 @@ async fn update_operation(
         updated_at: Utc::now(),
     };
 
-    match db.update_operation(&operation) {
-        Ok(()) => Ok(Json(ApiResponse::success(()))),
-        Err(e) => {
-            eprintln!("Error updating operation: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
+    let found = run_db(&db, move |db| db.update_operation(&operation).context("Error updating operation")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Operation {} not found", id)));
     }
+    Ok(Json(ApiResponse::success(())))
 }
 
+/// 删除数据操作
+#[utoipa::path(
+    delete,
+    path = "/api/operations/{id}",
+    tag = "operations",
+    params(("id" = i64, Path, description = "操作ID")),
+    responses(
+        (status = 200, description = "删除成功", body = EmptyResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该标靶", body = ErrorBody),
+        (status = 404, description = "操作不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn delete_operation(
     State(db): State<AppState>,
+    user: CurrentUser,
     axum::extract::Path(id): axum::extract::Path<i64>,
-) -> Result<Json<ApiResponse<()>>, StatusCode> {
-    match db.delete_operation(id) {
-        Ok(()) => Ok(Json(ApiResponse::success(()))),
-        Err(e) => {
-            eprintln!("Error deleting operation: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    check_operation_access(&db, &user, id).await?;
+    let found = run_db(&db, move |db| db.delete_operation(id).context("Error deleting operation")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Operation {} not found", id)));
     }
+    Ok(Json(ApiResponse::success(())))
 }
 
+/// 切换数据操作的激活状态
+#[utoipa::path(
+    post,
+    path = "/api/operations/{id}/toggle",
+    tag = "operations",
+    params(("id" = i64, Path, description = "操作ID")),
+    responses(
+        (status = 200, description = "切换成功", body = EmptyResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该标靶", body = ErrorBody),
+        (status = 404, description = "操作不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn toggle_operation(
     State(db): State<AppState>,
+    user: CurrentUser,
     axum::extract::Path(id): axum::extract::Path<i64>,
-) -> Result<Json<ApiResponse<()>>, StatusCode> {
-    match db.toggle_operation(id) {
-        Ok(()) => Ok(Json(ApiResponse::success(()))),
-        Err(e) => {
-            eprintln!("Error toggling operation: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    check_operation_access(&db, &user, id).await?;
+    let found = run_db(&db, move |db| db.toggle_operation(id).context("Error toggling operation")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Operation {} not found", id)));
     }
+    Ok(Json(ApiResponse::success(())))
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ExportData {
     pub target_name: String,
     pub operations: Vec<DataOperation>,
     pub export_time: DateTime<Utc>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ImportData {
     pub target_name: String,
     pub operations: Vec<ImportOperation>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ImportOperation {
     pub name: Option<String>,  // 可选
     pub description: Option<String>,
@@ -518,74 +634,87 @@ pub struct ImportOperation {
     pub is_active: bool,
 }
 
+/// 按标靶分组导出所有数据操作
+#[utoipa::path(
+    get,
+    path = "/api/operations/export",
+    tag = "operations",
+    responses(
+        (status = 200, description = "按标靶分组的操作", body = ExportDataResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn export_operations(
     State(db): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<ExportData>>>, StatusCode> {
-    match db.get_operations(false) {
-        Ok(operations) => {
-            // 按标靶分组
-            let mut grouped: std::collections::HashMap<String, Vec<DataOperation>> = std::collections::HashMap::new();
-            
-            for op in operations {
-                grouped.entry(op.target_name.clone())
-                    .or_insert_with(Vec::new)
-                    .push(op);
-            }
-            
-            let export_time = Utc::now();
-            let export_data: Vec<ExportData> = grouped.into_iter()
-                .map(|(target_name, operations)| ExportData {
-                    target_name,
-                    operations,
-                    export_time,
-                })
-                .collect();
-            
-            Ok(Json(ApiResponse::success(export_data)))
-        }
-        Err(e) => {
-            eprintln!("Error exporting operations: {}", e);
-            Ok(Json(ApiResponse::error(format!("Database error: {}", e))))
-        }
+    user: CurrentUser,
+) -> ApiResult<Vec<ExportData>> {
+    let mut operations = run_db(&db, |db| db.get_operations(false).context("Error exporting operations")).await?;
+    if let Some(targets) = visible_targets(&db, &user).await? {
+        operations.retain(|op| targets.contains(&op.target_name));
+    }
+
+    // 按标靶分组
+    let mut grouped: HashMap<String, Vec<DataOperation>> = HashMap::new();
+    
+    for op in operations {
+        grouped.entry(op.target_name.clone())
+            .or_default()
+            .push(op);
     }
+    
+    let export_time = Utc::now();
+    let export_data: Vec<ExportData> = grouped.into_iter()
+        .map(|(target_name, operations)| ExportData {
+            target_name,
+            operations,
+            export_time,
+        })
+        .collect();
+    
+    Ok(Json(ApiResponse::success(export_data)))
 }
 
+/// 导入数据操作，任一操作校验失败时不写入任何数据
+#[utoipa::path(
+    post,
+    path = "/api/operations/import",
+    tag = "operations",
+    request_body = Vec<ImportData>,
+    responses(
+        (status = 200, description = "新操作的ID列表", body = OperationIdListResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该标靶", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
 async fn import_operations(
     State(db): State<AppState>,
+    user: CurrentUser,
     Json(import_data): Json<Vec<ImportData>>,
-) -> Result<Json<ApiResponse<Vec<i64>>>, StatusCode> {
-    let mut created_ids = Vec::new();
+) -> ApiResult<Vec<i64>> {
+    user.require(Role::Operator)?;
+    let mut operations = Vec::new();
+    let mut v = Validator::new();
     
-    for data in import_data {
-        for import_op in data.operations {
-            let operation_type = match OperationType::from_str(&import_op.operation_type) {
-                Some(op_type) => op_type,
-                None => {
-                    return Ok(Json(ApiResponse::error(format!(
-                        "Invalid operation type: {}", 
-                        import_op.operation_type
-                    ))));
-                }
+    for (data_index, data) in import_data.into_iter().enumerate() {
+        for (op_index, import_op) in data.operations.into_iter().enumerate() {
+            // 收集整个导入批次中的全部错误
+            let fields = validation::operation_fields(
+                &mut v,
+                &format!("[{}].operations[{}]", data_index, op_index),
+                &data.target_name,
+                &import_op.key_name,
+                &import_op.operation_type,
+                import_op.value,
+                import_op.start_time.as_deref(),
+                import_op.end_time.as_deref(),
+            );
+            let Some(validation::OperationFields { operation_type, start_time, end_time }) = fields else {
+                continue;
             };
-            
-            let start_time = if let Some(time_str) = import_op.start_time {
-                match DateTime::parse_from_rfc3339(&time_str) {
-                    Ok(dt) => Some(dt.with_timezone(&Utc)),
-                    Err(_) => return Ok(Json(ApiResponse::error("Invalid start_time format".to_string()))),
-                }
-            } else {
-                None
-            };
-            
-            let end_time = if let Some(time_str) = import_op.end_time {
-                match DateTime::parse_from_rfc3339(&time_str) {
-                    Ok(dt) => Some(dt.with_timezone(&Utc)),
-                    Err(_) => return Ok(Json(ApiResponse::error("Invalid end_time format".to_string()))),
-                }
-            } else {
-                None
-            };
-            
+
             let now = Utc::now();
             
             // 自动生成默认名称（如果未提供）
@@ -600,7 +729,7 @@ async fn import_operations(
                 Some(format!("{} {} {}", import_op.key_name, op_symbol, import_op.value))
             });
             
-            let operation = DataOperation {
+            operations.push(DataOperation {
                 id: None,
                 name,
                 description: import_op.description,
@@ -613,69 +742,201 @@ async fn import_operations(
                 is_active: import_op.is_active,
                 created_at: now,
                 updated_at: now,
-            };
-            
-            match db.create_operation(&operation) {
-                Ok(id) => created_ids.push(id),
-                Err(e) => {
-                    return Ok(Json(ApiResponse::error(format!(
-                        "Failed to create operation: {}", 
-                        e
-                    ))));
-                }
-            }
+            });
         }
     }
+    v.finish()?;
+
+    let targets: std::collections::BTreeSet<&String> = operations.iter().map(|op| &op.target_name).collect();
+    for target_name in targets {
+        check_operation_target(&db, &user, target_name).await?;
+    }
+
+    let created_ids = run_db(&db, move |db| {
+        let mut created_ids = Vec::with_capacity(operations.len());
+        for operation in &operations {
+            created_ids.push(db.create_operation(operation).context("Failed to create operation")?);
+        }
+        Ok(created_ids)
+    }).await?;
     
     Ok(Json(ApiResponse::success(created_ids)))
 }
 
-// 流式查询端点
-async fn get_telemetry_data_stream(
+/// 按序列统计遥测数据
+///
+/// 参数与 /api/telemetry 相同，在DuckDB中对时间范围内的全部数据计算，采样和limit不生效
+#[utoipa::path(
+    get,
+    path = "/api/statistics",
+    tag = "telemetry",
+    params(TelemetryQuery),
+    responses(
+        (status = 200, description = "每个序列的统计信息", body = StatisticsResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 404, description = "saved或baseline指定的查询或基准不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+        (status = 504, description = "查询超时", body = ErrorBody),
+    )
+)]
+async fn get_statistics(
     State(db): State<AppState>,
+    user: CurrentUser,
     Query(params): Query<TelemetryQuery>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    // 解析查询参数
-    let target_names = if let Some(targets_str) = params.target_names {
-        targets_str.split(',').map(|s| s.trim().to_string()).collect()
-    } else {
-        Vec::new()
-    };
+) -> ApiResult<Vec<SeriesStatistics>> {
+    let query_params = resolve_telemetry_query(&db, &user, params).await?;
 
-    let key_names = if let Some(keys_str) = params.key_names {
-        keys_str.split(',').map(|s| s.trim().to_string()).collect()
-    } else {
-        Vec::new()
-    };
+    let statistics = run_db(&db, move |db| {
+        db.query_statistics(&query_params).context("Error computing statistics")
+    }).await?;
+    Ok(Json(ApiResponse::success(statistics)))
+}
 
-    let start_time = params.start_time.and_then(|time_str| {
-        DateTime::parse_from_rfc3339(&time_str)
-            .ok()
-            .map(|dt| dt.with_timezone(&Utc))
-    });
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TrendQuery {
+    /// 斜率拟合方法: "ols"（默认，最小二乘）或 "theil_sen"（稳健）
+    pub method: Option<TrendMethod>,
+    /// 滚动速度和加速度的窗口（毫秒），默认一天
+    pub window_ms: Option<i64>,
+    /// 斜率置信区间的置信度（0.5-0.999），默认0.95
+    pub confidence: Option<f64>,
+}
+
+/// 按序列分析趋势：整体斜率及其置信区间、R²，以及滚动速度和加速度
+///
+/// 查询参数与 /api/telemetry 相同（limit不生效），筛选、参考值、采样和数据操作的处理也相同；
+/// 斜率和速度的单位为每天，加速度为每天²
+#[utoipa::path(
+    get,
+    path = "/api/trend",
+    tag = "telemetry",
+    params(TelemetryQuery, TrendQuery),
+    responses(
+        (status = 200, description = "每个序列的趋势分析结果", body = TrendResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 404, description = "saved或baseline指定的查询或基准不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+        (status = 504, description = "查询超时", body = ErrorBody),
+    )
+)]
+async fn get_trend(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Query(params): Query<TelemetryQuery>,
+    Query(query): Query<TrendQuery>,
+) -> ApiResult<Vec<SeriesTrend>> {
+    let options = validation::trend_query(&query)?;
+    let query_params = resolve_telemetry_query(&db, &user, params).await?;
+
+    let trends = run_db(&db, move |db| {
+        let series = db.query_filtered_series(&query_params, MAX_QUERY_LIMIT)
+            .context("Error querying series data")?;
+        Ok(series.iter().map(|s| trend::analyze_trend(s, &options)).collect())
+    }).await?;
+    Ok(Json(ApiResponse::success(trends)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ForecastQuery {
+    /// 预测模型: "holt"（默认，线性趋势）或 "holt_winters"（加日周期季节项）
+    pub model: Option<ForecastModel>,
+    /// 预测范围（毫秒），默认3天，最长30天
+    pub horizon_ms: Option<i64>,
+    /// 阈值，返回预测值和95%预测区间到达阈值的时间
+    pub threshold: Option<f64>,
+}
 
-    let end_time = params.end_time.and_then(|time_str| {
-        DateTime::parse_from_rfc3339(&time_str)
-            .ok()
-            .map(|dt| dt.with_timezone(&Utc))
+/// 按序列做短期预测，返回80%、95%预测区间和到达阈值的时间
+///
+/// 查询参数与 /api/telemetry 相同（limit不生效），输入先按采样配置规整为等间隔，
+/// 未指定 sampling_interval 时按一小时取平均，缺失的采样窗口线性插值
+#[utoipa::path(
+    get,
+    path = "/api/forecast",
+    tag = "telemetry",
+    params(TelemetryQuery, ForecastQuery),
+    responses(
+        (status = 200, description = "每个序列的预测结果", body = ForecastResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 404, description = "saved或baseline指定的查询或基准不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+        (status = 504, description = "查询超时", body = ErrorBody),
+    )
+)]
+async fn get_forecast(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Query(params): Query<TelemetryQuery>,
+    Query(query): Query<ForecastQuery>,
+) -> ApiResult<Vec<SeriesForecast>> {
+    let mut query_params = resolve_telemetry_query(&db, &user, params).await?;
+    let sampling = query_params.sampling_config.get_or_insert(SamplingConfig {
+        interval_ms: forecast::DEFAULT_INTERVAL_MS,
+        method: SamplingMethod::Avg,
     });
+    let options = validation::forecast_query(&query, sampling.interval_ms)?;
+
+    let forecasts = run_db(&db, move |db| {
+        let series = db.query_filtered_series(&query_params, MAX_QUERY_LIMIT)
+            .context("Error querying series data")?;
+        Ok(series.iter().map(|s| forecast::forecast_series(s, &options)).collect())
+    }).await?;
+    Ok(Json(ApiResponse::success(forecasts)))
+}
 
-    // 构建查询参数
-    let query_params = QueryParams {
-        asset_name: params.asset_name,
-        device_name: params.device_name,
-        target_names,
-        key_names,
-        start_time,
-        end_time,
-        remove_outliers: params.remove_outliers.unwrap_or(false),
-        outlier_method: params.outlier_method.unwrap_or_else(|| "iqr".to_string()),
-        custom_filter: None,
-        limit: params.limit,
-        sampling_config: None,
-        reference_values: None,
-        time_of_day_filter: None,
+// 解析遥测查询参数：回放保存的查询、读取基准，并限制在用户的资产范围内
+async fn resolve_telemetry_query(db: &AppState, user: &CurrentUser, params: TelemetryQuery) -> Result<QueryParams, ApiError> {
+    let (mut query_params, baseline) = match params.saved.clone() {
+        Some(id) => {
+            let saved = run_db(db, {
+                let id = id.clone();
+                move |db| db.get_saved_query(&id).context("Error getting saved query")
+            }).await?
+                .ok_or_else(|| ApiError::not_found(format!("Saved query '{}' not found", id)))?;
+            let baseline = saved.baseline.clone();
+            (validation::replay_saved_query(&params, saved)?, baseline)
+        }
+        None => {
+            let baseline = params.baseline.clone();
+            (validation::telemetry_query(params)?, baseline)
+        }
     };
+    query_params.allowed_assets = user.asset_scope();
+    if let Some(name) = baseline {
+        query_params.reference_values = Some(load_baseline_references(db, name).await?);
+    }
+    Ok(query_params)
+}
+
+/// 以Server-Sent Events流式返回遥测数据
+///
+/// 每个事件的data为JSON：`{"type":"data","item":TelemetryData}`、
+/// `{"type":"progress","loaded":n}`、`{"type":"stats","total":n,"completed":true}` 或
+/// `{"type":"error","code":"DATABASE_ERROR","message":"...","correlation_id":"..."}`
+#[utoipa::path(
+    get,
+    path = "/api/telemetry/stream",
+    tag = "telemetry",
+    params(TelemetryQuery),
+    responses(
+        (status = 200, description = "SSE事件流", content_type = "text/event-stream", body = String),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 404, description = "saved或baseline指定的查询或基准不存在", body = ErrorBody),
+    )
+)]
+async fn get_telemetry_data_stream(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Query(params): Query<TelemetryQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    // 解析并校验查询参数
+    let query_params = resolve_telemetry_query(&db, &user, params).await?;
 
     // 创建流
     let stream = async_stream::stream! {
@@ -739,10 +1000,14 @@ async fn get_telemetry_data_stream(
                     }
                 }
                 Err(e) => {
-                    // 发送错误信息
+                    // 错误细节只写入日志，客户端通过关联ID定位
+                    let correlation_id = new_correlation_id();
+                    eprintln!("[{}] Error streaming telemetry data: {:#}", correlation_id, e);
                     let error = serde_json::json!({
                         "type": "error",
-                        "message": e.to_string()
+                        "code": "DATABASE_ERROR",
+                        "message": "Internal database error",
+                        "correlation_id": correlation_id
                     });
                     yield Ok(Event::default().data(error.to_string()));
                     break;
@@ -751,116 +1016,2125 @@ async fn get_telemetry_data_stream(
         }
     };
 
-    Sse::new(stream)
+    Ok(Sse::new(stream))
 }
 
 // 异常检测相关的请求结构
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AnomalyDetectionRequest {
     pub target_name: String,
     pub key_name: String,
     pub start_time: Option<String>,
     pub end_time: Option<String>,
+    /// 预期采样间隔（秒），默认使用相邻数据时间差的中位数
+    pub expected_interval: Option<u64>,
+    #[serde(flatten)]
+    pub options: DetectionOptions,
+}
+
+/// 两种检测请求共用的检测参数，未设置的使用默认值
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct DetectionOptions {
     pub sensitivity: Option<f64>,
     pub auto_correction: Option<bool>,
+    /// 时间间隔超过预期采样间隔的多少倍认为数据缺失，默认3
+    pub gap_multiplier: Option<f64>,
+    /// 突变检测的基线统计方法，默认mean_std
+    pub jump_baseline: Option<JumpBaseline>,
+    /// 连续多少个相同读数认为传感器卡死，默认10
+    pub flatline_min_points: Option<usize>,
+    /// 读数变化范围不超过此值视为相同，默认1e-9
+    pub flatline_tolerance: Option<f64>,
+    /// 读数保持不变超过此时长（秒）也认为卡死
+    pub flatline_min_duration: Option<u64>,
+    /// 按数据类型指定的物理量程和最大变化速率，例如 `{"dx": {"min": -50, "max": 50, "max_rate": 0.01}}`
+    pub value_limits: Option<HashMap<String, ValueLimits>>,
+    /// 检测前去除的周期成分: "none", "daily", "daily_weekly"，默认none
+    pub seasonality: Option<Seasonality>,
+    /// 要运行的检测器及其参数，默认运行全部检测器
+    pub detectors: Option<Vec<DetectorSelection>>,
+}
+
+/// 选择的检测器，可用的检测器和参数见 `GET /api/anomaly/detectors`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DetectorSelection {
+    pub name: String,
+    /// 覆盖该检测器的参数，例如 `{"max_jump_threshold": 4}`
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub params: serde_json::Value,
+}
+
+impl DetectionOptions {
+    fn to_config(&self) -> AnomalyDetectionConfig {
+        let mut config = AnomalyDetectionConfig::default();
+        if let Some(sensitivity) = self.sensitivity {
+            config.sensitivity = sensitivity;
+        }
+        if let Some(auto_correction) = self.auto_correction {
+            config.auto_correction = auto_correction;
+        }
+        if let Some(gap_multiplier) = self.gap_multiplier {
+            config.gap_multiplier = gap_multiplier;
+        }
+        if let Some(jump_baseline) = self.jump_baseline {
+            config.jump_baseline = jump_baseline;
+        }
+        if let Some(flatline_min_points) = self.flatline_min_points {
+            config.flatline_min_points = flatline_min_points;
+        }
+        if let Some(flatline_tolerance) = self.flatline_tolerance {
+            config.flatline_tolerance = flatline_tolerance;
+        }
+        config.flatline_min_duration = self.flatline_min_duration;
+        if let Some(value_limits) = &self.value_limits {
+            config.value_limits = value_limits.clone();
+        }
+        if let Some(seasonality) = self.seasonality {
+            config.seasonality = seasonality;
+        }
+        config
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AnomalyDetectionAllRequest {
     pub start_time: Option<String>,
     pub end_time: Option<String>,
-    pub sensitivity: Option<f64>,
-    pub auto_correction: Option<bool>,
+    /// 只检测该资产下的序列
+    pub asset_name: Option<String>,
+    /// 只检测该设备下的序列
+    pub device_name: Option<String>,
+    /// 只检测这些数据类型
+    pub key_names: Option<Vec<String>>,
+    /// 同时检测的最大序列数（1-64），默认为CPU核数
+    pub max_workers: Option<usize>,
+    /// 按数据类型指定的预期采样间隔（秒），未指定的数据类型使用相邻数据时间差的中位数
+    pub expected_intervals: Option<HashMap<String, u64>>,
+    /// 只检测上次增量检测之后的新数据，不能与时间范围同时使用
+    pub incremental: Option<bool>,
+    /// 忽略保存的增量状态，重新检测全部历史并重建状态（隐含incremental）
+    pub full_rebuild: Option<bool>,
+    #[serde(flatten)]
+    pub options: DetectionOptions,
+}
+
+impl AnomalyDetectionAllRequest {
+    fn incremental(&self) -> bool {
+        self.incremental.unwrap_or(false) || self.full_rebuild.unwrap_or(false)
+    }
+
+    // 检测参数的指纹，参数变化后之前保存的增量状态不再使用
+    fn fingerprint(&self) -> anyhow::Result<String> {
+        let value = serde_json::to_value((&self.options, &self.expected_intervals))?;
+        Ok(Sha256::digest(value.to_string().as_bytes()).iter().map(|b| format!("{:02x}", b)).collect())
+    }
 }
 
-// 检测指定标靶和指标的异常
+/// 检测指定标靶和指标的异常
+#[utoipa::path(
+    post,
+    path = "/api/anomaly/detect",
+    tag = "anomaly",
+    request_body = AnomalyDetectionRequest,
+    responses(
+        (status = 200, description = "检测到的异常", body = AnomalyListResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+        (status = 504, description = "检测超时", body = ErrorBody),
+    )
+)]
 async fn detect_anomalies(
     State(db): State<AppState>,
+    Extension(registry): Extension<Arc<DetectorRegistry>>,
+    Extension(notifier): Extension<Arc<Notifier>>,
+    user: CurrentUser,
     Json(request): Json<AnomalyDetectionRequest>,
-) -> Result<Json<ApiResponse<Vec<crate::anomaly_detection::DetectedAnomaly>>>, StatusCode> {
-    // 解析时间参数
-    let start_time = if let Some(start_str) = &request.start_time {
-        match DateTime::parse_from_rfc3339(start_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid start_time format".to_string()))),
-        }
-    } else {
-        None
-    };
-
-    let end_time = if let Some(end_str) = &request.end_time {
-        match DateTime::parse_from_rfc3339(end_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid end_time format".to_string()))),
-        }
-    } else {
-        None
-    };
+) -> ApiResult<Vec<DetectedAnomaly>> {
+    user.require(Role::Operator)?;
+    // 校验并解析时间参数
+    let (start_time, end_time) = validation::anomaly_request(&request)?;
 
     // 创建检测配置
-    let mut config = AnomalyDetectionConfig::default();
-    if let Some(sensitivity) = request.sensitivity {
-        config.sensitivity = sensitivity;
-    }
-    if let Some(auto_correction) = request.auto_correction {
-        config.auto_correction = auto_correction;
+    let mut config = request.options.to_config();
+    if let Some(expected_interval) = request.expected_interval {
+        config.expected_intervals.insert(request.key_name.clone(), expected_interval);
     }
 
     // 创建检测器
-    let detector = AnomalyDetector::new(config);
+    let detectors = validation::detectors(&registry, request.options.detectors.as_deref(), &config)?;
+    let detector = AnomalyDetector::new(config)
+        .with_asset_scope(user.asset_scope())
+        .with_detectors(detectors);
 
     // 执行异常检测
-    match detector.detect_anomalies(&db, &request.target_name, &request.key_name, start_time, end_time).await {
-        Ok(anomalies) => Ok(Json(ApiResponse::success(anomalies))),
-        Err(e) => {
-            eprintln!("Error detecting anomalies: {}", e);
-            Ok(Json(ApiResponse::error(format!("Failed to detect anomalies: {}", e))))
+    let mut anomalies = with_timeout(async {
+        detector.detect_anomalies(&db, &request.target_name, &request.key_name, start_time, end_time).await
+            .context("Error detecting anomalies")
+    }).await?;
+
+    // 保存检测结果，返回的异常带有记录ID
+    let username = user.0.username.clone();
+    let anomalies = run_db(&db, move |db| {
+        let run_id = db.save_anomaly_run(&username, start_time, end_time, None, &mut anomalies)
+            .context("Error saving anomalies")?;
+        if let Some(notification) = anomaly_notification(run_id, None, &anomalies) {
+            notifier.publish_logged(db, &notification);
         }
-    }
+        Ok(anomalies)
+    }).await?;
+    Ok(Json(ApiResponse::success(anomalies)))
 }
 
-// 检测所有标靶和指标的异常
+/// 检测所有序列的异常，可按资产、设备和数据类型筛选
+#[utoipa::path(
+    post,
+    path = "/api/anomaly/detect-all",
+    tag = "anomaly",
+    request_body = AnomalyDetectionAllRequest,
+    responses(
+        (status = 200, description = "检测结果、建议操作和摘要", body = AnomalyResultResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+        (status = 504, description = "检测超时", body = ErrorBody),
+    )
+)]
 async fn detect_all_anomalies(
     State(db): State<AppState>,
+    Extension(registry): Extension<Arc<DetectorRegistry>>,
+    Extension(notifier): Extension<Arc<Notifier>>,
+    user: CurrentUser,
     Json(request): Json<AnomalyDetectionAllRequest>,
-) -> Result<Json<ApiResponse<crate::anomaly_detection::AnomalyDetectionResult>>, StatusCode> {
-    // 解析时间参数
-    let start_time = if let Some(start_str) = &request.start_time {
-        match DateTime::parse_from_rfc3339(start_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid start_time format".to_string()))),
-        }
-    } else {
-        None
-    };
-
-    let end_time = if let Some(end_str) = &request.end_time {
-        match DateTime::parse_from_rfc3339(end_str) {
-            Ok(dt) => Some(dt.with_timezone(&Utc)),
-            Err(_) => return Ok(Json(ApiResponse::error("Invalid end_time format".to_string()))),
-        }
-    } else {
-        None
-    };
+) -> ApiResult<AnomalyDetectionResult> {
+    user.require(Role::Operator)?;
+    // 校验并解析时间参数
+    let (start_time, end_time) = validation::anomaly_all_request(&request)?;
+    if let Some(asset_name) = &request.asset_name {
+        user.check_asset(asset_name)?;
+    }
+    // 增量检测的状态按检测参数的指纹保存
+    let fingerprint = request.incremental().then(|| request.fingerprint()).transpose()
+        .context("Error computing detection fingerprint")?;
 
     // 创建检测配置
-    let mut config = AnomalyDetectionConfig::default();
-    if let Some(sensitivity) = request.sensitivity {
-        config.sensitivity = sensitivity;
+    let mut config = request.options.to_config();
+    if let Some(max_workers) = request.max_workers {
+        config.max_workers = max_workers;
     }
-    if let Some(auto_correction) = request.auto_correction {
-        config.auto_correction = auto_correction;
+    if let Some(expected_intervals) = request.expected_intervals {
+        config.expected_intervals = expected_intervals;
     }
 
     // 创建检测器
-    let detector = AnomalyDetector::new(config);
+    let detectors = validation::detectors(&registry, request.options.detectors.as_deref(), &config)?;
+    let detector = AnomalyDetector::new(config)
+        .with_asset_scope(user.asset_scope())
+        .with_detectors(detectors);
+    let filter = SeriesFilter {
+        asset_name: request.asset_name,
+        device_name: request.device_name,
+        key_names: request.key_names.unwrap_or_default(),
+    };
+
+    if let Some(fingerprint) = fingerprint {
+        let full_rebuild = request.full_rebuild.unwrap_or(false);
+        let (mut result, states) = with_timeout(async {
+            detector.detect_incremental(&db, &filter, &fingerprint, full_rebuild).await
+                .context("Error detecting anomalies incrementally")
+        }).await?;
+
+        // 异常和新的序列状态在同一事务中保存
+        let username = user.0.username.clone();
+        let result = run_db(&db, move |db| {
+            let run_id = db.save_incremental_run(&username, result.summary.series_analyzed, &mut result.anomalies, &states)
+                .context("Error saving anomalies")?;
+            if let Some(notification) = anomaly_notification(run_id, Some(result.summary.series_analyzed), &result.anomalies) {
+                notifier.publish_logged(db, &notification);
+            }
+            result.run_id = Some(run_id);
+            Ok(result)
+        }).await?;
+        return Ok(Json(ApiResponse::success(result)));
+    }
 
     // 执行全面异常检测
-    match detector.detect_all_anomalies(&db, start_time, end_time).await {
-        Ok(result) => Ok(Json(ApiResponse::success(result))),
-        Err(e) => {
-            eprintln!("Error detecting all anomalies: {}", e);
-            Ok(Json(ApiResponse::error(format!("Failed to detect anomalies: {}", e))))
+    let mut result = with_timeout(async {
+        detector.detect_all_anomalies(&db, &filter, start_time, end_time).await
+            .context("Error detecting all anomalies")
+    }).await?;
+
+    // 保存检测结果，返回的异常带有记录ID
+    let username = user.0.username.clone();
+    let result = run_db(&db, move |db| {
+        let series_analyzed = Some(result.summary.series_analyzed);
+        let run_id = db.save_anomaly_run(&username, start_time, end_time, series_analyzed, &mut result.anomalies)
+            .context("Error saving anomalies")?;
+        if let Some(notification) = anomaly_notification(run_id, series_analyzed, &result.anomalies) {
+            notifier.publish_logged(db, &notification);
         }
+        result.run_id = Some(run_id);
+        Ok(result)
+    }).await?;
+    Ok(Json(ApiResponse::success(result)))
+}
+
+// 检测到异常时发布的通知，按类型统计数量
+fn anomaly_notification(run_id: i64, series_analyzed: Option<usize>, anomalies: &[DetectedAnomaly]) -> Option<Notification> {
+    if anomalies.is_empty() {
+        return None;
+    }
+    let mut by_type: std::collections::BTreeMap<String, usize> = std::collections::BTreeMap::new();
+    let mut series = std::collections::BTreeSet::new();
+    for anomaly in anomalies {
+        *by_type.entry(format!("{:?}", anomaly.anomaly_type)).or_default() += 1;
+        series.insert(format!("{}/{}", anomaly.target_name, anomaly.key_name));
+    }
+    let counts: Vec<String> = by_type.iter().map(|(t, n)| format!("{} {}", n, t)).collect();
+    Some(
+        Notification::new(
+            "anomaly.detected",
+            format!("{} anomalies detected in run {}", anomalies.len(), run_id),
+            format!("{} in {} series", counts.join(", "), series.len()),
+        )
+        .with_data(serde_json::json!({
+            "run_id": run_id,
+            "anomaly_count": anomalies.len(),
+            "series_analyzed": series_analyzed,
+            "by_type": by_type,
+            "series": series,
+        })),
+    )
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ConsensusDetectionRequest {
+    pub asset_name: String,
+    /// 只比较该设备下的标靶，不指定时比较整个资产下的标靶
+    pub device_name: Option<String>,
+    pub key_name: String,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    /// 偏离共识超过多少倍稳健标准差认为异常，默认5
+    pub threshold: Option<f64>,
+    /// 每个时刻至少需要的其他标靶数，默认2
+    pub min_peers: Option<usize>,
+}
+
+/// 检测与同一设备或资产上其他标靶变化不一致的标靶
+#[utoipa::path(
+    post,
+    path = "/api/anomaly/consensus",
+    tag = "anomaly",
+    request_body = ConsensusDetectionRequest,
+    responses(
+        (status = 200, description = "检测到的异常，peers为参与比较的标靶", body = AnomalyListResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该资产", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+        (status = 504, description = "检测超时", body = ErrorBody),
+    )
+)]
+async fn detect_consensus_anomalies(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Json(request): Json<ConsensusDetectionRequest>,
+) -> ApiResult<Vec<DetectedAnomaly>> {
+    user.require(Role::Operator)?;
+    let (start_time, end_time) = validation::consensus_request(&request)?;
+    user.check_asset(&request.asset_name)?;
+
+    let mut config = AnomalyDetectionConfig::default();
+    if let Some(threshold) = request.threshold {
+        config.consensus_threshold = threshold;
+    }
+    if let Some(min_peers) = request.min_peers {
+        config.consensus_min_peers = min_peers;
     }
+    let detector = AnomalyDetector::new(config).with_asset_scope(user.asset_scope());
+    let group = ConsensusGroup {
+        asset_name: request.asset_name,
+        device_name: request.device_name.filter(|s| !s.is_empty()),
+        key_name: request.key_name,
+    };
+
+    let mut anomalies = with_timeout(async {
+        detector.detect_consensus(&db, &group, start_time, end_time).await
+            .context("Error detecting consensus anomalies")
+    }).await?;
+
+    // 保存检测结果，返回的异常带有记录ID
+    let username = user.0.username.clone();
+    let anomalies = run_db(&db, move |db| {
+        db.save_anomaly_run(&username, start_time, end_time, None, &mut anomalies)
+            .context("Error saving anomalies")?;
+        Ok(anomalies)
+    }).await?;
+    Ok(Json(ApiResponse::success(anomalies)))
+}
+
+/// 列出可用的检测器及其参数
+#[utoipa::path(
+    get,
+    path = "/api/anomaly/detectors",
+    tag = "anomaly",
+    responses(
+        (status = 200, description = "检测器名称、说明和参数的JSON Schema", body = DetectorListResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+    )
+)]
+async fn list_detectors(
+    Extension(registry): Extension<Arc<DetectorRegistry>>,
+    _user: CurrentUser,
+) -> ApiResult<Vec<DetectorInfo>> {
+    Ok(Json(ApiResponse::success(registry.info())))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DecomposeQuery {
+    pub target_name: String,
+    pub key_name: String,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    /// 分解的周期: "daily" 或 "daily_weekly"，默认daily
+    pub seasonality: Option<Seasonality>,
+}
+
+/// 把指定标靶和指标分解为趋势、季节项和残差
+#[utoipa::path(
+    get,
+    path = "/api/anomaly/decompose",
+    tag = "anomaly",
+    params(DecomposeQuery),
+    responses(
+        (status = 200, description = "每个序列的分解结果", body = DecompositionResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+        (status = 504, description = "查询超时", body = ErrorBody),
+    )
+)]
+async fn decompose_series(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Query(query): Query<DecomposeQuery>,
+) -> ApiResult<Vec<SeriesDecomposition>> {
+    let (start_time, end_time) = validation::decompose_query(&query)?;
+
+    let config = AnomalyDetectionConfig {
+        seasonality: query.seasonality.unwrap_or(Seasonality::Daily),
+        ..AnomalyDetectionConfig::default()
+    };
+    let detector = AnomalyDetector::new(config).with_asset_scope(user.asset_scope());
+
+    let decomposition = with_timeout(async {
+        detector.decompose(&db, &query.target_name, &query.key_name, start_time, end_time).await
+            .context("Error decomposing series")
+    }).await?;
+    Ok(Json(ApiResponse::success(decomposition)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AnomalyListQuery {
+    /// 审核状态: "new", "accepted", "rejected", "fixed"
+    pub status: Option<String>,
+    /// 检测批次ID
+    pub run_id: Option<i64>,
+    pub target_name: Option<String>,
+    pub key_name: Option<String>,
+    /// 最多返回的条数（1-1000），默认100
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AnomalyRunQuery {
+    /// 最多返回的批次数（1-1000），默认20
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ReviewAnomalyRequest {
+    /// 审核意见
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReviewResult {
+    pub id: i64,
+    pub status: AnomalyStatus,
+    /// 接受异常时创建的数据操作ID
+    pub operation_id: Option<i64>,
+}
+
+/// 查询保存的异常，按开始时间倒序
+#[utoipa::path(
+    get,
+    path = "/api/anomaly/findings",
+    tag = "anomaly",
+    params(AnomalyListQuery),
+    responses(
+        (status = 200, description = "异常记录及审核信息", body = StoredAnomalyListResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_anomalies(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Query(params): Query<AnomalyListQuery>,
+) -> ApiResult<Vec<StoredAnomaly>> {
+    let mut query = validation::anomaly_list_query(params)?;
+    query.allowed_assets = user.asset_scope();
+    let anomalies = run_db(&db, move |db| db.list_anomalies(&query).context("Error listing anomalies")).await?;
+    Ok(Json(ApiResponse::success(anomalies)))
+}
+
+/// 查询最近的检测批次
+#[utoipa::path(
+    get,
+    path = "/api/anomaly/runs",
+    tag = "anomaly",
+    params(AnomalyRunQuery),
+    responses(
+        (status = 200, description = "检测批次，最新的在前", body = AnomalyRunListResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_anomaly_runs(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Query(params): Query<AnomalyRunQuery>,
+) -> ApiResult<Vec<AnomalyRun>> {
+    let limit = validation::anomaly_run_query(&params)?;
+    let scope = user.asset_scope();
+    let username = user.0.username.clone();
+    let runs = run_db(&db, move |db| {
+        db.list_anomaly_runs(limit, scope.as_deref().map(|assets| (username.as_str(), assets)))
+            .context("Error listing anomaly runs")
+    }).await?;
+    Ok(Json(ApiResponse::success(runs)))
+}
+
+/// 接受异常，在同一事务中创建（并激活）建议的纠正操作
+#[utoipa::path(
+    post,
+    path = "/api/anomaly/findings/{id}/accept",
+    tag = "anomaly",
+    params(("id" = i64, Path, description = "异常ID")),
+    request_body = ReviewAnomalyRequest,
+    responses(
+        (status = 200, description = "审核结果", body = ReviewResultResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该资产", body = ErrorBody),
+        (status = 404, description = "异常不存在", body = ErrorBody),
+        (status = 409, description = "当前状态不允许接受", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn accept_anomaly(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+    Json(request): Json<ReviewAnomalyRequest>,
+) -> ApiResult<ReviewResult> {
+    review_anomaly(&db, &user, id, AnomalyStatus::Accepted, request).await
+}
+
+/// 驳回异常（误报）
+#[utoipa::path(
+    post,
+    path = "/api/anomaly/findings/{id}/reject",
+    tag = "anomaly",
+    params(("id" = i64, Path, description = "异常ID")),
+    request_body = ReviewAnomalyRequest,
+    responses(
+        (status = 200, description = "审核结果", body = ReviewResultResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该资产", body = ErrorBody),
+        (status = 404, description = "异常不存在", body = ErrorBody),
+        (status = 409, description = "当前状态不允许驳回", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn reject_anomaly(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+    Json(request): Json<ReviewAnomalyRequest>,
+) -> ApiResult<ReviewResult> {
+    review_anomaly(&db, &user, id, AnomalyStatus::Rejected, request).await
+}
+
+/// 标记异常已在现场修复，待审核或已接受的异常可以标记
+#[utoipa::path(
+    post,
+    path = "/api/anomaly/findings/{id}/fix",
+    tag = "anomaly",
+    params(("id" = i64, Path, description = "异常ID")),
+    request_body = ReviewAnomalyRequest,
+    responses(
+        (status = 200, description = "审核结果", body = ReviewResultResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该资产", body = ErrorBody),
+        (status = 404, description = "异常不存在", body = ErrorBody),
+        (status = 409, description = "当前状态不允许标记修复", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn fix_anomaly(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+    Json(request): Json<ReviewAnomalyRequest>,
+) -> ApiResult<ReviewResult> {
+    review_anomaly(&db, &user, id, AnomalyStatus::Fixed, request).await
+}
+
+async fn review_anomaly(
+    db: &AppState,
+    user: &CurrentUser,
+    id: i64,
+    status: AnomalyStatus,
+    request: ReviewAnomalyRequest,
+) -> ApiResult<ReviewResult> {
+    user.require(Role::Operator)?;
+    validation::review_anomaly(&request)?;
+
+    if user.asset_scope().is_some() {
+        let stored = run_db(db, move |db| db.get_anomaly(id).context("Error getting anomaly")).await?
+            .ok_or_else(|| ApiError::not_found(format!("Anomaly {} not found", id)))?;
+        user.check_asset(&stored.anomaly.asset_name)?;
+        // 接受异常时创建纠正操作
+        if status == AnomalyStatus::Accepted && stored.anomaly.suggested_correction.is_some() {
+            check_operation_target(db, user, &stored.anomaly.target_name).await?;
+        }
+    }
+
+    let reviewer = user.0.username.clone();
+    let comment = request.comment.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+    let outcome = run_db(db, move |db| {
+        db.review_anomaly(id, status, &reviewer, comment.as_deref()).context("Error reviewing anomaly")
+    }).await?;
+
+    match outcome {
+        ReviewOutcome::NotFound => Err(ApiError::not_found(format!("Anomaly {} not found", id))),
+        ReviewOutcome::InvalidTransition(current) => Err(ApiError::Conflict(format!(
+            "Anomaly {} is {} and cannot be marked {}",
+            id, current.as_str(), status.as_str()
+        ))),
+        ReviewOutcome::Reviewed { operation_id } => Ok(Json(ApiResponse::success(ReviewResult { id, status, operation_id }))),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AlarmRuleRequest {
+    pub name: String,
+    /// 不填表示不限制资产，有资产限制的用户必须填写
+    pub asset_name: Option<String>,
+    pub device_name: Option<String>,
+    pub target_name: Option<String>,
+    pub key_name: String,
+    /// 判断的量，默认 "value"
+    pub metric: Option<AlarmMetric>,
+    /// 超限方向，默认 "above"
+    pub direction: Option<AlarmDirection>,
+    /// 各级别的阈值，级别越高阈值越严格
+    pub levels: Vec<AlarmThreshold>,
+    /// 回差，默认0
+    pub hysteresis: Option<f64>,
+    /// 超限持续多少秒才触发或升级，默认0
+    pub duration: Option<u64>,
+    /// 计算变化速率的时间窗口（秒），metric为rate时必填
+    pub rate_window: Option<u64>,
+    /// RFC 3339 累计变化的起算时间，metric为cumulative时必填
+    pub reference_time: Option<String>,
+    /// 默认启用
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AlarmListQuery {
+    /// "active"（未恢复）或 "cleared"（已恢复）
+    pub status: Option<String>,
+    pub acknowledged: Option<bool>,
+    /// 最高级别不低于此级别: "blue", "yellow", "orange", "red"
+    pub min_level: Option<String>,
+    pub rule_id: Option<i64>,
+    pub target_name: Option<String>,
+    pub key_name: Option<String>,
+    /// RFC 3339 开始时间，返回与时间范围有重叠的事件
+    pub start_time: Option<String>,
+    /// RFC 3339 结束时间
+    pub end_time: Option<String>,
+    /// 最多返回的条数（1-1000），默认100
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AcknowledgeAlarmRequest {
+    /// 确认意见
+    pub comment: Option<String>,
+}
+
+// 有资产限制的用户只能管理其资产范围内的规则
+fn check_rule_asset(user: &CurrentUser, asset_name: Option<&str>) -> Result<(), ApiError> {
+    match asset_name {
+        Some(asset_name) => user.check_asset(asset_name),
+        None if user.asset_scope().is_some() => {
+            Err(ApiError::forbidden("Rules without asset_name apply to all assets"))
+        }
+        None => Ok(()),
+    }
+}
+
+fn alarm_rule_from_request(
+    request: AlarmRuleRequest,
+    reference_time: Option<DateTime<Utc>>,
+    created_by: String,
+) -> AlarmRule {
+    let mut levels = request.levels;
+    levels.sort_by_key(|level| level.level);
+    let now = Utc::now();
+    AlarmRule {
+        id: None,
+        name: request.name.trim().to_string(),
+        asset_name: request.asset_name,
+        device_name: request.device_name,
+        target_name: request.target_name,
+        key_name: request.key_name,
+        metric: request.metric.unwrap_or(AlarmMetric::Value),
+        direction: request.direction.unwrap_or(AlarmDirection::Above),
+        levels,
+        hysteresis: request.hysteresis.unwrap_or(0.0),
+        duration: request.duration.unwrap_or(0),
+        rate_window: request.rate_window,
+        reference_time,
+        enabled: request.enabled.unwrap_or(true),
+        created_by,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+/// 获取告警规则，有资产限制的用户只能看到其资产的规则
+#[utoipa::path(
+    get,
+    path = "/api/alarms/rules",
+    tag = "alarms",
+    responses(
+        (status = 200, description = "告警规则列表", body = AlarmRuleListResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_alarm_rules(
+    State(db): State<AppState>,
+    user: CurrentUser,
+) -> ApiResult<Vec<AlarmRule>> {
+    let assets = user.asset_scope();
+    let rules = run_db(&db, move |db| {
+        db.list_alarm_rules(assets.as_deref()).context("Error listing alarm rules")
+    }).await?;
+    Ok(Json(ApiResponse::success(rules)))
+}
+
+/// 创建告警规则
+#[utoipa::path(
+    post,
+    path = "/api/alarms/rules",
+    tag = "alarms",
+    request_body = AlarmRuleRequest,
+    responses(
+        (status = 200, description = "新建规则的ID", body = AlarmRuleIdResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该资产", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn create_alarm_rule(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Json(request): Json<AlarmRuleRequest>,
+) -> ApiResult<i64> {
+    user.require(Role::Operator)?;
+    let reference_time = validation::alarm_rule(&request)?;
+    check_rule_asset(&user, request.asset_name.as_deref())?;
+
+    let rule = alarm_rule_from_request(request, reference_time, user.0.username.clone());
+    let id = run_db(&db, move |db| db.create_alarm_rule(&rule).context("Error creating alarm rule")).await?;
+    Ok(Json(ApiResponse::success(id)))
+}
+
+/// 修改告警规则，规则下未恢复的告警随之结束，新数据按新规则判断
+#[utoipa::path(
+    put,
+    path = "/api/alarms/rules/{id}",
+    tag = "alarms",
+    params(("id" = i64, Path, description = "规则ID")),
+    request_body = AlarmRuleRequest,
+    responses(
+        (status = 200, description = "更新成功", body = EmptyResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该资产", body = ErrorBody),
+        (status = 404, description = "规则不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn update_alarm_rule(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+    Json(request): Json<AlarmRuleRequest>,
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    let reference_time = validation::alarm_rule(&request)?;
+    let existing = run_db(&db, move |db| db.get_alarm_rule(id).context("Error getting alarm rule")).await?
+        .ok_or_else(|| ApiError::not_found(format!("Alarm rule {} not found", id)))?;
+    check_rule_asset(&user, existing.asset_name.as_deref())?;
+    check_rule_asset(&user, request.asset_name.as_deref())?;
+
+    let mut rule = alarm_rule_from_request(request, reference_time, existing.created_by);
+    rule.id = Some(id);
+    let found = run_db(&db, move |db| db.update_alarm_rule(&rule).context("Error updating alarm rule")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Alarm rule {} not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// 删除告警规则，已有的告警事件保留
+#[utoipa::path(
+    delete,
+    path = "/api/alarms/rules/{id}",
+    tag = "alarms",
+    params(("id" = i64, Path, description = "规则ID")),
+    responses(
+        (status = 200, description = "删除成功", body = EmptyResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该资产", body = ErrorBody),
+        (status = 404, description = "规则不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn delete_alarm_rule(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    if user.asset_scope().is_some() {
+        let existing = run_db(&db, move |db| db.get_alarm_rule(id).context("Error getting alarm rule")).await?
+            .ok_or_else(|| ApiError::not_found(format!("Alarm rule {} not found", id)))?;
+        check_rule_asset(&user, existing.asset_name.as_deref())?;
+    }
+
+    let found = run_db(&db, move |db| db.delete_alarm_rule(id).context("Error deleting alarm rule")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Alarm rule {} not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// 查询告警事件，按开始时间倒序
+#[utoipa::path(
+    get,
+    path = "/api/alarms",
+    tag = "alarms",
+    params(AlarmListQuery),
+    responses(
+        (status = 200, description = "告警事件", body = AlarmEventListResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_alarms(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Query(params): Query<AlarmListQuery>,
+) -> ApiResult<Vec<AlarmEvent>> {
+    let mut query = validation::alarm_list_query(params)?;
+    query.allowed_assets = user.asset_scope();
+    let events = run_db(&db, move |db| db.list_alarm_events(&query).context("Error listing alarms")).await?;
+    Ok(Json(ApiResponse::success(events)))
+}
+
+/// 确认告警，升级到新的最高级别后需要重新确认
+#[utoipa::path(
+    post,
+    path = "/api/alarms/{id}/acknowledge",
+    tag = "alarms",
+    params(("id" = i64, Path, description = "告警事件ID")),
+    request_body = AcknowledgeAlarmRequest,
+    responses(
+        (status = 200, description = "确认成功", body = EmptyResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问该资产", body = ErrorBody),
+        (status = 404, description = "告警不存在", body = ErrorBody),
+        (status = 409, description = "告警已被确认", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn acknowledge_alarm(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+    Json(request): Json<AcknowledgeAlarmRequest>,
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    validation::acknowledge_alarm(&request)?;
+
+    if user.asset_scope().is_some() {
+        let event = run_db(&db, move |db| db.get_alarm_event(id).context("Error getting alarm")).await?
+            .ok_or_else(|| ApiError::not_found(format!("Alarm {} not found", id)))?;
+        user.check_asset(&event.asset_name)?;
+    }
+
+    let username = user.0.username.clone();
+    let comment = request.comment.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+    let outcome = run_db(&db, move |db| {
+        db.acknowledge_alarm_event(id, &username, comment.as_deref()).context("Error acknowledging alarm")
+    }).await?;
+
+    match outcome {
+        AcknowledgeOutcome::NotFound => Err(ApiError::not_found(format!("Alarm {} not found", id))),
+        AcknowledgeOutcome::AlreadyAcknowledged(by) => {
+            Err(ApiError::Conflict(format!("Alarm {} was already acknowledged by {}", id, by)))
+        }
+        AcknowledgeOutcome::Acknowledged => Ok(Json(ApiResponse::success(()))),
+    }
+}
+
+/// 立即判断全部启用的规则，不必等待后台定时判断
+#[utoipa::path(
+    post,
+    path = "/api/alarms/evaluate",
+    tag = "alarms",
+    responses(
+        (status = 200, description = "本次判断的统计", body = AlarmEvaluationResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+        (status = 504, description = "判断超时", body = ErrorBody),
+    )
+)]
+async fn evaluate_alarms(
+    State(db): State<AppState>,
+    Extension(notifier): Extension<Arc<Notifier>>,
+    user: CurrentUser,
+) -> ApiResult<AlarmEvaluation> {
+    user.require(Role::Operator)?;
+    let summary = run_db(&db, move |db| {
+        alarms::evaluate_all(db, &notifier).context("Error evaluating alarm rules")
+    }).await?;
+    Ok(Json(ApiResponse::success(summary)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NotificationListQuery {
+    /// 发送状态: "pending", "sent", "failed"
+    pub status: Option<String>,
+    /// 最多返回的条数（1-1000），默认100
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TestNotificationRequest {
+    /// 通道名称，不填则测试全部通道
+    pub channel: Option<String>,
+    /// 测试通知的内容
+    pub message: Option<String>,
+}
+
+/// 查询发件箱中的通知，最新的在前（管理员）
+#[utoipa::path(
+    get,
+    path = "/api/notifications",
+    tag = "notifications",
+    params(NotificationListQuery),
+    responses(
+        (status = 200, description = "通知及其发送状态", body = OutboxEntryListResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要admin角色", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_notifications(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Query(params): Query<NotificationListQuery>,
+) -> ApiResult<Vec<OutboxEntry>> {
+    user.require(Role::Admin)?;
+    let (status, limit) = validation::notification_list_query(&params)?;
+    let entries = run_db(&db, move |db| {
+        db.list_notifications(status, limit).context("Error listing notifications")
+    }).await?;
+    Ok(Json(ApiResponse::success(entries)))
+}
+
+/// 向通道直接发送一条测试通知并返回各通道的结果，不经过发件箱和速率限制（管理员）
+#[utoipa::path(
+    post,
+    path = "/api/notifications/test",
+    tag = "notifications",
+    request_body = TestNotificationRequest,
+    responses(
+        (status = 200, description = "各通道的发送结果", body = ChannelTestResultListResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要admin角色", body = ErrorBody),
+        (status = 404, description = "通道不存在", body = ErrorBody),
+        (status = 504, description = "发送超时", body = ErrorBody),
+    )
+)]
+async fn test_notifications(
+    State(db): State<AppState>,
+    Extension(notifier): Extension<Arc<Notifier>>,
+    user: CurrentUser,
+    Json(request): Json<TestNotificationRequest>,
+) -> ApiResult<Vec<ChannelTestResult>> {
+    user.require(Role::Admin)?;
+    validation::test_notification(&request)?;
+    if let Some(channel) = &request.channel {
+        if !notifier.channel_names().contains(channel) {
+            return Err(ApiError::not_found(format!("Notification channel '{}' not found", channel)));
+        }
+    }
+
+    let notification = Notification::new(
+        "test",
+        "Test notification",
+        request.message.unwrap_or_else(|| format!("Test notification sent by {}", user.0.username)),
+    )
+    .with_data(serde_json::json!({ "username": user.0.username }));
+    // 发送是阻塞的网络调用，同样放在阻塞线程池中执行
+    let results = run_db(&db, move |_| Ok(notifier.send_test(request.channel.as_deref(), &notification))).await?;
+    Ok(Json(ApiResponse::success(results)))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DerivedMetricRequest {
+    /// 作为数据类型使用的名称，不能与已有的数据类型同名
+    #[schema(example = "horizontal")]
+    pub name: String,
+    pub description: Option<String>,
+    /// 算术表达式，支持 `+ - * / ^`、括号、常量pi和函数 sqrt, abs, exp, ln, log10, sin, cos, tan, asin, acos, atan, atan2, pow, degrees, radians, least, greatest
+    #[schema(example = "sqrt(dx^2 + dy^2)")]
+    pub expression: String,
+    /// 表达式中每个变量对应的数据类型
+    pub inputs: Vec<DerivedInput>,
+    /// 对齐时间戳的容差（毫秒，0-86400000），默认0
+    pub tolerance_ms: Option<i64>,
+}
+
+// 派生指标不能与原始数据类型同名，输入也不能是其他派生指标
+async fn check_derived_names(db: &AppState, request: &DerivedMetricRequest, id: Option<i64>) -> Result<(), ApiError> {
+    let name = request.name.trim().to_string();
+    let keys: Vec<String> = request.inputs.iter().map(|input| input.key_name.clone()).collect();
+    let (name_exists, derived) = run_db(db, move |db| {
+        let name_exists = db.key_name_exists(&name).context("Error checking key names")?;
+        let derived = db.list_derived_metrics().context("Error listing derived metrics")?;
+        Ok((name_exists, derived))
+    }).await?;
+    if name_exists {
+        return Err(ApiError::Conflict(format!("'{}' is already a key name in the telemetry data", request.name.trim())));
+    }
+    let errors: Vec<FieldError> = keys.iter().enumerate()
+        .filter(|(_, key)| derived.iter().any(|metric| metric.id != id && &metric.name == *key))
+        .map(|(i, key)| FieldError::new(
+            format!("inputs[{}].key_name", i),
+            format!("{} is a derived metric, inputs must be raw key names", key),
+        ))
+        .collect();
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+    Ok(())
+}
+
+fn derived_metric_from_request(request: DerivedMetricRequest, created_by: String) -> DerivedMetric {
+    let now = Utc::now();
+    DerivedMetric {
+        id: None,
+        name: request.name.trim().to_string(),
+        description: request.description,
+        expression: request.expression,
+        inputs: request.inputs,
+        tolerance_ms: request.tolerance_ms.unwrap_or(0),
+        created_by,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+/// 获取派生指标
+#[utoipa::path(
+    get,
+    path = "/api/derived-metrics",
+    tag = "derived",
+    responses(
+        (status = 200, description = "派生指标列表", body = DerivedMetricListResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_derived_metrics(
+    State(db): State<AppState>,
+    _user: CurrentUser,
+) -> ApiResult<Vec<DerivedMetric>> {
+    let metrics = run_db(&db, |db| db.list_derived_metrics().context("Error listing derived metrics")).await?;
+    Ok(Json(ApiResponse::success(metrics)))
+}
+
+/// 创建派生指标，之后可以在 key_names 中像原始数据类型一样查询
+#[utoipa::path(
+    post,
+    path = "/api/derived-metrics",
+    tag = "derived",
+    request_body = DerivedMetricRequest,
+    responses(
+        (status = 200, description = "新建派生指标的ID", body = DerivedMetricIdResponse),
+        (status = 400, description = "参数校验失败或表达式无效", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问固定的标靶", body = ErrorBody),
+        (status = 409, description = "名称已被数据类型或其他派生指标使用", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn create_derived_metric(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Json(request): Json<DerivedMetricRequest>,
+) -> ApiResult<i64> {
+    user.require(Role::Operator)?;
+    validation::derived_metric(&request)?;
+    for target_name in request.inputs.iter().filter_map(|input| input.target_name.as_deref()) {
+        check_target_access(&db, &user, target_name).await?;
+    }
+    check_derived_names(&db, &request, None).await?;
+
+    let metric = derived_metric_from_request(request, user.0.username.clone());
+    let id = run_db(&db, move |db| db.create_derived_metric(&metric).context("Error creating derived metric")).await?;
+    Ok(Json(ApiResponse::success(id)))
+}
+
+/// 修改派生指标，已保存的数据操作、告警规则等按名称引用，改名后需要同步修改
+#[utoipa::path(
+    put,
+    path = "/api/derived-metrics/{id}",
+    tag = "derived",
+    params(("id" = i64, Path, description = "派生指标ID")),
+    request_body = DerivedMetricRequest,
+    responses(
+        (status = 200, description = "更新成功", body = EmptyResponse),
+        (status = 400, description = "参数校验失败或表达式无效", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问固定的标靶", body = ErrorBody),
+        (status = 404, description = "派生指标不存在", body = ErrorBody),
+        (status = 409, description = "名称已被数据类型或其他派生指标使用", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn update_derived_metric(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+    Json(request): Json<DerivedMetricRequest>,
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    validation::derived_metric(&request)?;
+    let existing = run_db(&db, move |db| db.get_derived_metric(id).context("Error getting derived metric")).await?
+        .ok_or_else(|| ApiError::not_found(format!("Derived metric {} not found", id)))?;
+    for target_name in request.inputs.iter().filter_map(|input| input.target_name.as_deref()) {
+        check_target_access(&db, &user, target_name).await?;
+    }
+    check_derived_names(&db, &request, Some(id)).await?;
+
+    let mut metric = derived_metric_from_request(request, existing.created_by);
+    metric.id = Some(id);
+    let found = run_db(&db, move |db| db.update_derived_metric(&metric).context("Error updating derived metric")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Derived metric {} not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// 删除派生指标
+#[utoipa::path(
+    delete,
+    path = "/api/derived-metrics/{id}",
+    tag = "derived",
+    params(("id" = i64, Path, description = "派生指标ID")),
+    responses(
+        (status = 200, description = "删除成功", body = EmptyResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色", body = ErrorBody),
+        (status = 404, description = "派生指标不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn delete_derived_metric(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    let found = run_db(&db, move |db| db.delete_derived_metric(id).context("Error deleting derived metric")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Derived metric {} not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BaselineRequest {
+    pub name: String,
+    pub description: Option<String>,
+    /// 参考值，格式与 /api/telemetry 的 reference_values 相同
+    pub reference_values: Vec<ReferenceValue>,
+}
+
+// 按名称读取基准的参考值
+async fn load_baseline_references(db: &AppState, name: String) -> Result<Vec<ReferenceValue>, ApiError> {
+    let baseline = run_db(db, {
+        let name = name.clone();
+        move |db| db.get_baseline_by_name(&name).context("Error getting baseline")
+    }).await?
+        .ok_or_else(|| ApiError::not_found(format!("Baseline '{}' not found", name)))?;
+    Ok(baseline.reference_values)
+}
+
+async fn check_baseline_targets(db: &AppState, user: &CurrentUser, request: &BaselineRequest) -> Result<(), ApiError> {
+    let mut targets: Vec<&str> = request.reference_values.iter().map(|r| r.target_name.as_str()).collect();
+    targets.sort();
+    targets.dedup();
+    for target_name in targets {
+        check_target_access(db, user, target_name).await?;
+    }
+    Ok(())
+}
+
+fn baseline_from_request(request: BaselineRequest, created_by: String) -> Baseline {
+    let now = Utc::now();
+    Baseline {
+        id: None,
+        name: request.name.trim().to_string(),
+        description: request.description,
+        reference_values: request.reference_values,
+        created_by,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+/// 获取保存的基准
+#[utoipa::path(
+    get,
+    path = "/api/baselines",
+    tag = "baselines",
+    responses(
+        (status = 200, description = "基准列表", body = BaselineListResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_baselines(
+    State(db): State<AppState>,
+    user: CurrentUser,
+) -> ApiResult<Vec<Baseline>> {
+    let mut baselines = run_db(&db, |db| db.list_baselines().context("Error listing baselines")).await?;
+    if let Some(targets) = visible_targets(&db, &user).await? {
+        baselines.retain(|b| baseline_in_scope(b, &targets));
+    }
+    Ok(Json(ApiResponse::success(baselines)))
+}
+
+/// 获取单个基准
+#[utoipa::path(
+    get,
+    path = "/api/baselines/{id}",
+    tag = "baselines",
+    params(("id" = i64, Path, description = "基准ID")),
+    responses(
+        (status = 200, description = "基准", body = BaselineResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "基准包含资产范围外的标靶", body = ErrorBody),
+        (status = 404, description = "基准不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn get_baseline(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> ApiResult<Baseline> {
+    let baseline = run_db(&db, move |db| db.get_baseline(id).context("Error getting baseline")).await?
+        .ok_or_else(|| ApiError::not_found(format!("Baseline {} not found", id)))?;
+    if let Some(targets) = visible_targets(&db, &user).await? {
+        if !baseline_in_scope(&baseline, &targets) {
+            return Err(ApiError::forbidden(format!("No access to baseline {}", id)));
+        }
+    }
+    Ok(Json(ApiResponse::success(baseline)))
+}
+
+/// 保存基准，之后查询时用 `baseline=名称` 引用
+#[utoipa::path(
+    post,
+    path = "/api/baselines",
+    tag = "baselines",
+    request_body = BaselineRequest,
+    responses(
+        (status = 200, description = "新建基准的ID", body = BaselineIdResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问标靶", body = ErrorBody),
+        (status = 409, description = "名称已存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn create_baseline(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Json(request): Json<BaselineRequest>,
+) -> ApiResult<i64> {
+    user.require(Role::Operator)?;
+    validation::baseline(&request)?;
+    check_baseline_targets(&db, &user, &request).await?;
+
+    let baseline = baseline_from_request(request, user.0.username.clone());
+    let id = run_db(&db, move |db| db.create_baseline(&baseline).context("Error creating baseline")).await?;
+    Ok(Json(ApiResponse::success(id)))
+}
+
+/// 修改基准
+#[utoipa::path(
+    put,
+    path = "/api/baselines/{id}",
+    tag = "baselines",
+    params(("id" = i64, Path, description = "基准ID")),
+    request_body = BaselineRequest,
+    responses(
+        (status = 200, description = "更新成功", body = EmptyResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色或无权访问标靶", body = ErrorBody),
+        (status = 404, description = "基准不存在", body = ErrorBody),
+        (status = 409, description = "名称已存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn update_baseline(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+    Json(request): Json<BaselineRequest>,
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    validation::baseline(&request)?;
+    let existing = run_db(&db, move |db| db.get_baseline(id).context("Error getting baseline")).await?
+        .ok_or_else(|| ApiError::not_found(format!("Baseline {} not found", id)))?;
+    check_baseline_targets(&db, &user, &request).await?;
+
+    let mut baseline = baseline_from_request(request, existing.created_by);
+    baseline.id = Some(id);
+    let found = run_db(&db, move |db| db.update_baseline(&baseline).context("Error updating baseline")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Baseline {} not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// 删除基准
+#[utoipa::path(
+    delete,
+    path = "/api/baselines/{id}",
+    tag = "baselines",
+    params(("id" = i64, Path, description = "基准ID")),
+    responses(
+        (status = 200, description = "删除成功", body = EmptyResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要operator角色", body = ErrorBody),
+        (status = 404, description = "基准不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn delete_baseline(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> ApiResult<()> {
+    user.require(Role::Operator)?;
+    let found = run_db(&db, move |db| db.delete_baseline(id).context("Error deleting baseline")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Baseline {} not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SavedQueryRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+    /// 与 /api/telemetry 的查询参数相同，例如 `{"target_names": "T1,T2", "key_names": "dx", "start": "-7d"}`；
+    /// 相对时间（start/end）保存原文，回放时重新计算
+    #[schema(value_type = Object)]
+    pub query: TelemetryQuery,
+    /// 前端的显示设置（图表类型、颜色等），原样保存
+    #[schema(value_type = Option<Object>)]
+    pub display: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SavedQueryListQuery {
+    /// 只返回该用户保存的查询
+    pub owner: Option<String>,
+    /// 只返回带有该标签的查询
+    pub tag: Option<String>,
+}
+
+// 只有所有者和admin可以修改或删除保存的查询
+fn check_saved_query_owner(user: &CurrentUser, saved: &SavedQuery) -> Result<(), ApiError> {
+    if saved.owner == user.0.username || user.0.role >= Role::Admin {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden(format!("Saved query '{}' belongs to {}", saved.id, saved.owner)))
+    }
+}
+
+fn saved_query_from_request(
+    request: SavedQueryRequest,
+    fields: (QueryParams, Option<String>, Option<String>),
+    id: String,
+    owner: String,
+) -> SavedQuery {
+    let (query, relative_start, relative_end) = fields;
+    let mut tags: Vec<String> = request.tags.unwrap_or_default().into_iter().map(|t| t.trim().to_string()).collect();
+    tags.sort();
+    tags.dedup();
+    let now = Utc::now();
+    SavedQuery {
+        id,
+        name: request.name.trim().to_string(),
+        description: request.description,
+        owner,
+        tags,
+        query,
+        baseline: request.query.baseline,
+        relative_start,
+        relative_end,
+        display: request.display,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+async fn load_saved_query(db: &AppState, id: String) -> Result<SavedQuery, ApiError> {
+    run_db(db, {
+        let id = id.clone();
+        move |db| db.get_saved_query(&id).context("Error getting saved query")
+    }).await?
+        .ok_or_else(|| ApiError::not_found(format!("Saved query '{}' not found", id)))
+}
+
+/// 获取保存的查询，有资产限制的用户只能看到其范围内的查询
+#[utoipa::path(
+    get,
+    path = "/api/saved-queries",
+    tag = "saved-queries",
+    params(SavedQueryListQuery),
+    responses(
+        (status = 200, description = "保存的查询，按更新时间倒序", body = SavedQueryListResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_saved_queries(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Query(query): Query<SavedQueryListQuery>,
+) -> ApiResult<Vec<SavedQuery>> {
+    let mut saved_queries = run_db(&db, move |db| {
+        db.list_saved_queries(query.owner.as_deref(), query.tag.as_deref()).context("Error listing saved queries")
+    }).await?;
+    if let Some(targets) = visible_targets(&db, &user).await? {
+        saved_queries.retain(|saved| saved_query_in_scope(&user, saved, &targets));
+    }
+    Ok(Json(ApiResponse::success(saved_queries)))
+}
+
+/// 获取单个保存的查询
+#[utoipa::path(
+    get,
+    path = "/api/saved-queries/{id}",
+    tag = "saved-queries",
+    params(("id" = String, Path, description = "短ID")),
+    responses(
+        (status = 200, description = "保存的查询", body = SavedQueryResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "查询包含资产范围外的资产或标靶", body = ErrorBody),
+        (status = 404, description = "保存的查询不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn get_saved_query(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> ApiResult<SavedQuery> {
+    let saved = load_saved_query(&db, id).await?;
+    if let Some(targets) = visible_targets(&db, &user).await? {
+        if !saved_query_in_scope(&user, &saved, &targets) {
+            return Err(ApiError::forbidden(format!("No access to saved query '{}'", saved.id)));
+        }
+    }
+    Ok(Json(ApiResponse::success(saved)))
+}
+
+/// 保存查询，返回短ID，之后用 `/api/telemetry?saved=<id>` 回放
+#[utoipa::path(
+    post,
+    path = "/api/saved-queries",
+    tag = "saved-queries",
+    request_body = SavedQueryRequest,
+    responses(
+        (status = 200, description = "新建查询的短ID", body = SavedQueryIdResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn create_saved_query(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Json(request): Json<SavedQueryRequest>,
+) -> ApiResult<String> {
+    let fields = validation::saved_query(&request)?;
+    let saved = saved_query_from_request(request, fields, auth::generate_short_id(), user.0.username.clone());
+    let id = saved.id.clone();
+    run_db(&db, move |db| db.create_saved_query(&saved).context("Error creating saved query")).await?;
+    Ok(Json(ApiResponse::success(id)))
+}
+
+/// 修改保存的查询，短ID和所有者不变
+#[utoipa::path(
+    put,
+    path = "/api/saved-queries/{id}",
+    tag = "saved-queries",
+    params(("id" = String, Path, description = "短ID")),
+    request_body = SavedQueryRequest,
+    responses(
+        (status = 200, description = "更新成功", body = EmptyResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "只有所有者和admin可以修改", body = ErrorBody),
+        (status = 404, description = "保存的查询不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn update_saved_query(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(request): Json<SavedQueryRequest>,
+) -> ApiResult<()> {
+    let fields = validation::saved_query(&request)?;
+    let existing = load_saved_query(&db, id.clone()).await?;
+    check_saved_query_owner(&user, &existing)?;
+
+    let saved = saved_query_from_request(request, fields, id.clone(), existing.owner);
+    let found = run_db(&db, move |db| db.update_saved_query(&saved).context("Error updating saved query")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Saved query '{}' not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// 删除保存的查询
+#[utoipa::path(
+    delete,
+    path = "/api/saved-queries/{id}",
+    tag = "saved-queries",
+    params(("id" = String, Path, description = "短ID")),
+    responses(
+        (status = 200, description = "删除成功", body = EmptyResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "只有所有者和admin可以删除", body = ErrorBody),
+        (status = 404, description = "保存的查询不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn delete_saved_query(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> ApiResult<()> {
+    let existing = load_saved_query(&db, id.clone()).await?;
+    check_saved_query_owner(&user, &existing)?;
+
+    let found = run_db(&db, move |db| db.delete_saved_query(&id).context("Error deleting saved query")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Saved query '{}' not found", existing.id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateUserRequest {
+    pub username: String,
+    /// 至少8个字符
+    pub password: String,
+    pub role: Role,
+    /// 允许访问的资产，不填或为空表示不限制
+    pub assets: Option<Vec<String>>,
+}
+
+/// 未填写的字段保持不变
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateUserRequest {
+    pub password: Option<String>,
+    pub role: Option<Role>,
+    pub is_active: Option<bool>,
+    /// 允许访问的资产，空数组表示不限制
+    pub assets: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateTokenRequest {
+    /// 令牌用途说明，例如 "nightly export"
+    pub name: String,
+    /// 有效天数（1-3650），不填则永不过期
+    pub expires_in_days: Option<i64>,
+}
+
+/// 新建的API令牌，`token` 只在创建时返回一次
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedToken {
+    pub id: i64,
+    pub name: String,
+    pub token: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// 用户名密码登录，成功后通过Cookie下发会话
+#[utoipa::path(
+    post,
+    path = "/api/auth/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    security(()),
+    responses(
+        (status = 200, description = "登录成功，响应头Set-Cookie包含会话", body = UserResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "用户名或密码错误", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn login(
+    State(db): State<AppState>,
+    Json(request): Json<LoginRequest>,
+) -> Result<([(header::HeaderName, String); 1], Json<ApiResponse<User>>), ApiError> {
+    validation::login_request(&request)?;
+
+    // 密码校验较慢，放在阻塞线程池中执行
+    let session = run_db(&db, move |db| {
+        let Some((user, password_hash)) = db.get_user_credentials(&request.username)? else {
+            return Ok(None);
+        };
+        if !user.is_active || !auth::verify_password(&request.password, &password_hash) {
+            return Ok(None);
+        }
+        let token = auth::generate_token();
+        db.create_session(&auth::hash_token(&token), user.id, Utc::now() + auth::SESSION_TTL)
+            .context("Error creating session")?;
+        Ok(Some((user, token)))
+    }).await?;
+
+    let (user, token) = session
+        .ok_or_else(|| ApiError::Unauthorized("Invalid username or password".to_string()))?;
+    Ok(([(header::SET_COOKIE, auth::session_cookie(&token))], Json(ApiResponse::success(user))))
+}
+
+/// 注销当前会话
+#[utoipa::path(
+    post,
+    path = "/api/auth/logout",
+    tag = "auth",
+    security(()),
+    responses(
+        (status = 200, description = "已注销并清除Cookie", body = EmptyResponse),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn logout(
+    State(db): State<AppState>,
+    headers: HeaderMap,
+) -> Result<([(header::HeaderName, String); 1], Json<ApiResponse<()>>), ApiError> {
+    if let Some(token) = auth::session_token(&headers) {
+        let token_hash = auth::hash_token(&token);
+        run_db(&db, move |db| db.delete_session(&token_hash).context("Error deleting session")).await?;
+    }
+    Ok(([(header::SET_COOKIE, auth::clear_session_cookie())], Json(ApiResponse::success(()))))
+}
+
+/// 获取当前登录用户
+#[utoipa::path(
+    get,
+    path = "/api/auth/me",
+    tag = "auth",
+    responses(
+        (status = 200, description = "当前用户", body = UserResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+    )
+)]
+async fn get_current_user(user: CurrentUser) -> ApiResult<User> {
+    Ok(Json(ApiResponse::success(user.0)))
+}
+
+/// 获取所有用户（管理员）
+#[utoipa::path(
+    get,
+    path = "/api/users",
+    tag = "users",
+    responses(
+        (status = 200, description = "用户列表", body = UserListResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要admin角色", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_users(
+    State(db): State<AppState>,
+    user: CurrentUser,
+) -> ApiResult<Vec<User>> {
+    user.require(Role::Admin)?;
+    let users = run_db(&db, |db| db.list_users().context("Error listing users")).await?;
+    Ok(Json(ApiResponse::success(users)))
+}
+
+/// 创建用户（管理员）
+#[utoipa::path(
+    post,
+    path = "/api/users",
+    tag = "users",
+    request_body = CreateUserRequest,
+    responses(
+        (status = 200, description = "新用户的ID", body = UserIdResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要admin角色", body = ErrorBody),
+        (status = 409, description = "用户名已存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn create_user(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Json(request): Json<CreateUserRequest>,
+) -> ApiResult<i64> {
+    user.require(Role::Admin)?;
+    validation::create_user(&request)?;
+
+    let id = run_db(&db, move |db| {
+        let password_hash = auth::hash_password(&request.password)?;
+        let assets = request.assets.unwrap_or_default();
+        db.create_user(request.username.trim(), &password_hash, request.role, &assets)
+            .context("Error creating user")
+    }).await?;
+    Ok(Json(ApiResponse::success(id)))
+}
+
+/// 更新用户（管理员），修改密码或停用后该用户的会话全部失效
+#[utoipa::path(
+    put,
+    path = "/api/users/{id}",
+    tag = "users",
+    params(("id" = i64, Path, description = "用户ID")),
+    request_body = UpdateUserRequest,
+    responses(
+        (status = 200, description = "更新成功", body = EmptyResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要admin角色", body = ErrorBody),
+        (status = 404, description = "用户不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn update_user(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+    Json(request): Json<UpdateUserRequest>,
+) -> ApiResult<()> {
+    user.require(Role::Admin)?;
+    validation::update_user(id, user.0.id, &request)?;
+
+    let found = run_db(&db, move |db| {
+        let update = UserUpdate {
+            password_hash: request.password.as_deref().map(auth::hash_password).transpose()?,
+            role: request.role,
+            is_active: request.is_active,
+            assets: request.assets,
+        };
+        db.update_user(id, &update).context("Error updating user")
+    }).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("User {} not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// 删除用户及其会话和API令牌（管理员）
+#[utoipa::path(
+    delete,
+    path = "/api/users/{id}",
+    tag = "users",
+    params(("id" = i64, Path, description = "用户ID")),
+    responses(
+        (status = 200, description = "删除成功", body = EmptyResponse),
+        (status = 400, description = "不能删除自己", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 403, description = "需要admin角色", body = ErrorBody),
+        (status = 404, description = "用户不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn delete_user(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> ApiResult<()> {
+    user.require(Role::Admin)?;
+    if id == user.0.id {
+        return Err(ApiError::validation("id", "You cannot delete your own account"));
+    }
+
+    let found = run_db(&db, move |db| db.delete_user(id).context("Error deleting user")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("User {} not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// 获取API令牌，管理员可以看到所有用户的令牌
+#[utoipa::path(
+    get,
+    path = "/api/tokens",
+    tag = "users",
+    responses(
+        (status = 200, description = "令牌列表（不含令牌本身）", body = TokenListResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn list_tokens(
+    State(db): State<AppState>,
+    user: CurrentUser,
+) -> ApiResult<Vec<ApiToken>> {
+    let owner = if user.0.role == Role::Admin { None } else { Some(user.0.id) };
+    let tokens = run_db(&db, move |db| db.list_api_tokens(owner).context("Error listing tokens")).await?;
+    Ok(Json(ApiResponse::success(tokens)))
+}
+
+/// 为当前用户创建API令牌，用于脚本通过 `Authorization: Bearer <token>` 访问
+#[utoipa::path(
+    post,
+    path = "/api/tokens",
+    tag = "users",
+    request_body = CreateTokenRequest,
+    responses(
+        (status = 200, description = "新令牌，只返回这一次", body = CreatedTokenResponse),
+        (status = 400, description = "参数校验失败", body = ErrorBody),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn create_token(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    Json(request): Json<CreateTokenRequest>,
+) -> ApiResult<CreatedToken> {
+    validation::create_token(&request)?;
+
+    let token = auth::generate_token();
+    let token_hash = auth::hash_token(&token);
+    let expires_at = request.expires_in_days.map(|days| Utc::now() + chrono::Duration::days(days));
+    let name = request.name.trim().to_string();
+    let user_id = user.0.id;
+
+    let token_name = name.clone();
+    let id = run_db(&db, move |db| {
+        db.create_api_token(user_id, &token_name, &token_hash, expires_at).context("Error creating token")
+    }).await?;
+    Ok(Json(ApiResponse::success(CreatedToken { id, name, token, expires_at })))
+}
+
+/// 删除API令牌，非管理员只能删除自己的令牌
+#[utoipa::path(
+    delete,
+    path = "/api/tokens/{id}",
+    tag = "users",
+    params(("id" = i64, Path, description = "令牌ID")),
+    responses(
+        (status = 200, description = "删除成功", body = EmptyResponse),
+        (status = 401, description = "未登录", body = ErrorBody),
+        (status = 404, description = "令牌不存在", body = ErrorBody),
+        (status = 500, description = "数据库错误", body = ErrorBody),
+    )
+)]
+async fn delete_token(
+    State(db): State<AppState>,
+    user: CurrentUser,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> ApiResult<()> {
+    let owner = if user.0.role == Role::Admin { None } else { Some(user.0.id) };
+    let found = run_db(&db, move |db| db.delete_api_token(id, owner).context("Error deleting token")).await?;
+    if !found {
+        return Err(ApiError::not_found(format!("Token {} not found", id)));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+// 注册Bearer令牌和会话Cookie两种认证方式
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "session_cookie",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(auth::SESSION_COOKIE))),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Local Data Client API",
+        description = "遥测数据查询、数据操作管理、异常检测和告警接口。除登录外的接口都需要会话Cookie或Bearer令牌",
+    ),
+    modifiers(&SecurityAddon),
+    security(("session_cookie" = []), ("bearer_token" = [])),
+    paths(
+        get_filter_options,
+        get_devices_by_asset,
+        get_targets_by_device,
+        get_telemetry_data,
+        get_telemetry_data_stream,
+        get_statistics,
+        get_trend,
+        get_forecast,
+        get_operations,
+        create_operation,
+        update_operation,
+        delete_operation,
+        toggle_operation,
+        export_operations,
+        import_operations,
+        detect_anomalies,
+        detect_all_anomalies,
+        detect_consensus_anomalies,
+        decompose_series,
+        list_detectors,
+        list_anomalies,
+        list_anomaly_runs,
+        accept_anomaly,
+        reject_anomaly,
+        fix_anomaly,
+        list_alarm_rules,
+        create_alarm_rule,
+        update_alarm_rule,
+        delete_alarm_rule,
+        list_alarms,
+        acknowledge_alarm,
+        evaluate_alarms,
+        list_notifications,
+        test_notifications,
+        list_derived_metrics,
+        create_derived_metric,
+        update_derived_metric,
+        delete_derived_metric,
+        list_baselines,
+        get_baseline,
+        create_baseline,
+        update_baseline,
+        delete_baseline,
+        list_saved_queries,
+        get_saved_query,
+        create_saved_query,
+        update_saved_query,
+        delete_saved_query,
+        login,
+        logout,
+        get_current_user,
+        list_users,
+        create_user,
+        update_user,
+        delete_user,
+        list_tokens,
+        create_token,
+        delete_token,
+    ),
+    components(schemas(
+        FilterOptionsResponse,
+        NameListResponse,
+        TelemetryDataResponse,
+        StatisticsResponse,
+        SeriesStatistics,
+        TrendResponse,
+        SeriesTrend,
+        RatePoint,
+        TrendMethod,
+        ForecastResponse,
+        SeriesForecast,
+        ForecastPoint,
+        ForecastModel,
+        OperationListResponse,
+        OperationIdResponse,
+        OperationIdListResponse,
+        ExportDataResponse,
+        AnomalyListResponse,
+        AnomalyResultResponse,
+        DecompositionResponse,
+        DetectorListResponse,
+        EmptyResponse,
+        ErrorBody,
+        FieldError,
+        FilterOptions,
+        TelemetryResponse,
+        TelemetryData,
+        DataStats,
+        ReferenceValue,
+        DataOperation,
+        OperationType,
+        CreateOperationRequest,
+        UpdateOperationRequest,
+        ExportData,
+        ImportData,
+        ImportOperation,
+        AnomalyDetectionRequest,
+        AnomalyDetectionAllRequest,
+        ConsensusDetectionRequest,
+        DetectionOptions,
+        DetectorSelection,
+        DetectorInfo,
+        ValueLimits,
+        DetectedAnomaly,
+        AnomalyType,
+        JumpBaseline,
+        Seasonality,
+        SeriesDecomposition,
+        DecomposedPoint,
+        AnomalyDetectionResult,
+        AnomalyDetectionSummary,
+        SeriesCompleteness,
+        StoredAnomalyListResponse,
+        AnomalyRunListResponse,
+        ReviewResultResponse,
+        AnomalyStatus,
+        AnomalyRun,
+        StoredAnomaly,
+        ReviewAnomalyRequest,
+        ReviewResult,
+        AlarmRuleListResponse,
+        AlarmRuleIdResponse,
+        AlarmEventListResponse,
+        AlarmEvaluationResponse,
+        AlarmRule,
+        AlarmEvent,
+        AlarmThreshold,
+        AlarmLevel,
+        AlarmMetric,
+        AlarmDirection,
+        AlarmRuleRequest,
+        AcknowledgeAlarmRequest,
+        AlarmEvaluation,
+        OutboxEntryListResponse,
+        ChannelTestResultListResponse,
+        OutboxEntry,
+        NotificationStatus,
+        Notification,
+        TestNotificationRequest,
+        ChannelTestResult,
+        DerivedMetricListResponse,
+        DerivedMetricIdResponse,
+        DerivedMetric,
+        DerivedInput,
+        DerivedMetricRequest,
+        BaselineListResponse,
+        BaselineResponse,
+        BaselineIdResponse,
+        Baseline,
+        ReferenceMode,
+        BaselineRequest,
+        SavedQueryResponse,
+        SavedQueryListResponse,
+        SavedQueryIdResponse,
+        SavedQuery,
+        SavedQueryRequest,
+        UserResponse,
+        UserListResponse,
+        UserIdResponse,
+        TokenListResponse,
+        CreatedTokenResponse,
+        Role,
+        User,
+        ApiToken,
+        LoginRequest,
+        CreateUserRequest,
+        UpdateUserRequest,
+        CreateTokenRequest,
+        CreatedToken,
+    )),
+    tags(
+        (name = "filters", description = "资产/设备/标靶层级和筛选选项"),
+        (name = "telemetry", description = "遥测数据查询"),
+        (name = "operations", description = "数据操作（校准）管理"),
+        (name = "anomaly", description = "异常检测"),
+        (name = "alarms", description = "告警规则和告警事件"),
+        (name = "notifications", description = "通知发件箱和通道测试"),
+        (name = "derived", description = "由表达式定义的派生指标"),
+        (name = "baselines", description = "保存的参考值基准"),
+        (name = "saved-queries", description = "保存和分享的查询视图"),
+        (name = "auth", description = "登录和会话"),
+        (name = "users", description = "用户和API令牌管理"),
+    )
+)]
+pub struct ApiDoc;
+
+// OpenAPI 3 文档
+async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
 }
@@ -0,0 +1,161 @@
+use anyhow::Result;
+use argon2::{
+    password_hash::{rand_core::{OsRng, RngCore}, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap},
+};
+use chrono::Duration;
+use sha2::{Digest, Sha256};
+
+use crate::api::{run_db, AppState};
+use crate::database::{DatabaseManager, Role, User};
+use crate::error::ApiError;
+
+// 会话Cookie名称
+pub const SESSION_COOKIE: &str = "ldc_session";
+
+// 会话有效期
+pub const SESSION_TTL: Duration = Duration::hours(12);
+
+// 首次启动时创建管理员账号所用的环境变量
+const ADMIN_PASSWORD_ENV: &str = "LDC_ADMIN_PASSWORD";
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buf);
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 生成会话或API令牌，数据库中只保存其哈希
+pub fn generate_token() -> String {
+    format!("ldc_{}", random_hex(32))
+}
+
+// 短ID使用的字符，去掉了容易混淆的 0/O、1/l/I
+const SHORT_ID_ALPHABET: &[u8] = b"23456789abcdefghijkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ";
+
+/// 生成用于分享链接的8位短ID
+pub fn generate_short_id() -> String {
+    let mut buf = [0u8; 8];
+    OsRng.fill_bytes(&mut buf);
+    buf.iter().map(|b| SHORT_ID_ALPHABET[*b as usize % SHORT_ID_ALPHABET.len()] as char).collect()
+}
+
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn session_cookie(token: &str) -> String {
+    format!(
+        "{}={}; HttpOnly; SameSite=Strict; Path=/; Max-Age={}",
+        SESSION_COOKIE, token, SESSION_TTL.num_seconds()
+    )
+}
+
+pub fn clear_session_cookie() -> String {
+    format!("{}=; HttpOnly; SameSite=Strict; Path=/; Max-Age=0", SESSION_COOKIE)
+}
+
+/// 从Cookie头中读取会话令牌
+pub fn session_token(headers: &HeaderMap) -> Option<String> {
+    headers.get_all(header::COOKIE).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| token.to_string())
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers.get(header::AUTHORIZATION)?
+        .to_str().ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+/// 没有任何用户时创建初始管理员账号
+pub fn ensure_admin_user(db: &DatabaseManager) -> Result<()> {
+    if db.count_users()? > 0 {
+        return Ok(());
+    }
+
+    let (password, generated) = match std::env::var(ADMIN_PASSWORD_ENV) {
+        Ok(password) if !password.is_empty() => (password, false),
+        _ => (random_hex(8), true),
+    };
+    db.create_user("admin", &hash_password(&password)?, Role::Admin, &[])?;
+
+    if generated {
+        println!("Created initial admin user 'admin' with password: {}", password);
+        println!("Change it after logging in, or set {} before the first start", ADMIN_PASSWORD_ENV);
+    } else {
+        println!("Created initial admin user 'admin' from {}", ADMIN_PASSWORD_ENV);
+    }
+    Ok(())
+}
+
+/// 当前请求的已认证用户，支持 `Authorization: Bearer <API令牌>` 或会话Cookie
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub User);
+
+impl CurrentUser {
+    /// 要求至少具有指定角色
+    pub fn require(&self, role: Role) -> Result<(), ApiError> {
+        if self.0.role >= role {
+            Ok(())
+        } else {
+            Err(ApiError::forbidden(format!("This action requires the {} role", role.as_str())))
+        }
+    }
+
+    /// 检查用户是否可以访问指定资产
+    pub fn check_asset(&self, asset_name: &str) -> Result<(), ApiError> {
+        if self.0.assets.is_empty() || self.0.assets.iter().any(|a| a == asset_name) {
+            Ok(())
+        } else {
+            Err(ApiError::forbidden(format!("No access to asset '{}'", asset_name)))
+        }
+    }
+
+    pub fn asset_scope(&self) -> Option<Vec<String>> {
+        self.0.asset_scope()
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for CurrentUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let user = if let Some(token) = bearer_token(&parts.headers) {
+            let token_hash = hash_token(&token);
+            run_db(state, move |db| db.get_token_user(&token_hash)).await?
+        } else if let Some(token) = session_token(&parts.headers) {
+            let token_hash = hash_token(&token);
+            run_db(state, move |db| db.get_session_user(&token_hash)).await?
+        } else {
+            return Err(ApiError::Unauthorized("Authentication required".to_string()));
+        };
+
+        user.map(CurrentUser)
+            .ok_or_else(|| ApiError::Unauthorized("Invalid or expired credentials".to_string()))
+    }
+}
@@ -0,0 +1,468 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap::Parser;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use local_data_client::anomaly_detection::{AnomalyDetectionConfig, AnomalyType, DetectedAnomaly, DetectorRegistry};
+use local_data_client::database::TelemetryData;
+
+#[derive(Parser)]
+#[command(name = "bench_anomaly")]
+#[command(about = "Benchmark the anomaly detectors on synthetic series with labelled anomalies")]
+struct Cli {
+    /// Number of synthetic series
+    #[arg(long, default_value_t = 20)]
+    series: usize,
+
+    /// Points per series
+    #[arg(long, default_value_t = 4032)]
+    points: usize,
+
+    /// Sampling interval in seconds
+    #[arg(long, default_value_t = 600)]
+    interval: i64,
+
+    /// Seed of the random generator, the same seed produces the same series
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+
+    /// How many sampling intervals a detection may lie outside a label and still match it
+    #[arg(long, default_value_t = 5)]
+    tolerance: i64,
+
+    /// JSON file with detection config fields, unspecified fields use the defaults
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Override sensitivity
+    #[arg(long)]
+    sensitivity: Option<f64>,
+
+    /// Override max_jump_threshold
+    #[arg(long)]
+    max_jump_threshold: Option<f64>,
+
+    /// Override min_window_size
+    #[arg(long)]
+    min_window_size: Option<usize>,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// 注入的异常种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Injection {
+    /// 1-3个点的尖峰
+    Jump,
+    /// 之后一直保持的水平偏移
+    Offset,
+    /// 一段时间内噪声放大
+    NoiseBurst,
+    /// 一段时间没有数据
+    Gap,
+    /// 读数停在某个值不变
+    Flatline,
+}
+
+const INJECTIONS: [Injection; 5] = [
+    Injection::Jump,
+    Injection::Offset,
+    Injection::NoiseBurst,
+    Injection::Gap,
+    Injection::Flatline,
+];
+
+impl Injection {
+    // 召回率按此类型统计
+    fn expected(&self) -> AnomalyType {
+        match self {
+            Injection::Jump => AnomalyType::SuddenJump,
+            Injection::Offset => AnomalyType::PersistentOffset,
+            Injection::NoiseBurst => AnomalyType::IncreasedNoise,
+            Injection::Gap => AnomalyType::DataGap,
+            Injection::Flatline => AnomalyType::Flatline,
+        }
+    }
+
+    // 与该标注重叠时不算误报的检测类型，例如偏移的起点同时也是一次突变
+    fn accepts(&self, anomaly_type: AnomalyType) -> bool {
+        anomaly_type == self.expected()
+            || match self {
+                Injection::Jump | Injection::Gap => false,
+                Injection::Offset => anomaly_type == AnomalyType::SuddenJump,
+                Injection::NoiseBurst | Injection::Flatline => matches!(
+                    anomaly_type,
+                    AnomalyType::SuddenJump | AnomalyType::PersistentOffset
+                ),
+            }
+    }
+}
+
+/// 注入的异常，时间范围包含两端
+#[derive(Debug, Clone)]
+struct Label {
+    injection: Injection,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+/// 确定性的伪随机数（SplitMix64），便于用同一个种子复现结果
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// [0, 1) 均匀分布
+    fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.uniform()
+    }
+
+    fn index(&mut self, low: usize, high: usize) -> usize {
+        low + (self.next_u64() % (high - low).max(1) as u64) as usize
+    }
+
+    fn sign(&mut self) -> f64 {
+        if self.uniform() < 0.5 { -1.0 } else { 1.0 }
+    }
+
+    /// 标准正态分布（Box-Muller）
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+}
+
+/// 生成一个合成序列：线性趋势 + 日周期 + 高斯噪声，每种异常各注入一次
+///
+/// 序列开头留出一段正常数据建立基线，之后分为等长的段，每段前半部分随机放置一个异常
+fn generate_series(rng: &mut Rng, index: usize, points: usize, interval: i64) -> (Vec<TelemetryData>, Vec<Label>) {
+    let origin = DateTime::from_timestamp(1_704_067_200, 0).unwrap_or_default();
+    let slope = rng.range(-0.5, 0.5) / (7.0 * 86_400.0);
+    let amplitude = rng.range(0.3, 1.0);
+    let phase = rng.range(0.0, std::f64::consts::TAU);
+    let sigma = rng.range(0.05, 0.15);
+
+    let mut values: Vec<f64> = (0..points)
+        .map(|i| {
+            let secs = i as f64 * interval as f64;
+            slope * secs
+                + amplitude * (std::f64::consts::TAU * secs / 86_400.0 + phase).sin()
+                + sigma * rng.normal()
+        })
+        .collect();
+    let mut present = vec![true; points];
+
+    let mut order = INJECTIONS;
+    for i in (1..order.len()).rev() {
+        order.swap(i, rng.index(0, i + 1));
+    }
+
+    let warmup = (points / 10).max(300).min(points);
+    let segment = (points - warmup) / order.len();
+    let mut labels = Vec::new();
+    for (k, &injection) in order.iter().enumerate() {
+        let segment_start = warmup + k * segment;
+        if segment < 20 {
+            break;
+        }
+        let start = rng.index(segment_start, segment_start + segment / 2);
+        let max_len = segment / 2;
+        let end = match injection {
+            Injection::Jump => {
+                let len = rng.index(1, 4);
+                let magnitude = rng.sign() * rng.range(4.0, 20.0) * sigma;
+                for value in &mut values[start..start + len] {
+                    *value += magnitude;
+                }
+                start + len - 1
+            }
+            Injection::Offset => {
+                let magnitude = rng.sign() * rng.range(5.0, 15.0) * sigma;
+                for value in &mut values[start..] {
+                    *value += magnitude;
+                }
+                // 偏移一直持续，标注到本段结束为止
+                segment_start + segment - 1
+            }
+            Injection::NoiseBurst => {
+                let len = rng.index(50, 200.min(max_len).max(51));
+                let factor = rng.range(3.0, 8.0);
+                for value in &mut values[start..start + len] {
+                    *value += (factor - 1.0) * sigma * rng.normal();
+                }
+                start + len - 1
+            }
+            Injection::Gap => {
+                let len = rng.index(20, 150.min(max_len).max(21));
+                for p in &mut present[start..start + len] {
+                    *p = false;
+                }
+                start + len - 1
+            }
+            Injection::Flatline => {
+                let len = rng.index(20, 100.min(max_len).max(21));
+                let stuck = values[start];
+                for value in &mut values[start..start + len] {
+                    *value = stuck;
+                }
+                start + len - 1
+            }
+        };
+        labels.push(Label {
+            injection,
+            start: origin + Duration::seconds(start as i64 * interval),
+            end: origin + Duration::seconds(end as i64 * interval),
+        });
+    }
+
+    let data = values.into_iter().enumerate()
+        .filter(|&(i, _)| present[i])
+        .map(|(i, value)| TelemetryData {
+            timestamp: origin + Duration::seconds(i as i64 * interval),
+            asset_name: "BENCH".to_string(),
+            device_name: "D1".to_string(),
+            target_name: format!("S{:03}", index),
+            key_name: "dx".to_string(),
+            value,
+        })
+        .collect();
+    (data, labels)
+}
+
+/// 单个异常类型的统计
+#[derive(Debug, Default, Serialize)]
+struct TypeReport {
+    anomaly_type: String,
+    /// 以此类型为预期结果的标注数
+    labels: usize,
+    /// 被检测到的标注数
+    detected_labels: usize,
+    /// 此类型的检测数
+    detections: usize,
+    /// 与某个标注匹配的检测数
+    true_positives: usize,
+    precision: Option<f64>,
+    recall: Option<f64>,
+    /// 检测起点晚于标注起点的时间（秒），早于标注起点记为0
+    mean_delay_secs: Option<f64>,
+    max_delay_secs: Option<i64>,
+    #[serde(skip)]
+    delays: Vec<i64>,
+}
+
+impl TypeReport {
+    fn finish(&mut self) {
+        self.precision = ratio(self.true_positives, self.detections);
+        self.recall = ratio(self.detected_labels, self.labels);
+        if !self.delays.is_empty() {
+            self.mean_delay_secs = Some(self.delays.iter().sum::<i64>() as f64 / self.delays.len() as f64);
+            self.max_delay_secs = self.delays.iter().copied().max();
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Overall {
+    labels: usize,
+    detected_labels: usize,
+    detections: usize,
+    true_positives: usize,
+    precision: Option<f64>,
+    recall: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct BenchReport {
+    series: usize,
+    points: usize,
+    interval_secs: i64,
+    seed: u64,
+    tolerance_intervals: i64,
+    config: AnomalyDetectionConfig,
+    types: Vec<TypeReport>,
+    overall: Overall,
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f64> {
+    (denominator > 0).then(|| numerator as f64 / denominator as f64)
+}
+
+// 检测与标注的时间范围（标注两端放宽 tolerance）有重叠
+fn overlaps(anomaly: &DetectedAnomaly, label: &Label, tolerance: Duration) -> bool {
+    let end = anomaly.end_time.unwrap_or(anomaly.start_time);
+    anomaly.start_time <= label.end + tolerance && end >= label.start - tolerance
+}
+
+fn load_config(cli: &Cli) -> Result<AnomalyDetectionConfig> {
+    let mut merged = serde_json::to_value(AnomalyDetectionConfig::default())?;
+    if let Some(path) = &cli.config {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let overrides: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&text)
+            .with_context(|| format!("Config file {} must contain a JSON object", path.display()))?;
+        for (field, value) in overrides {
+            if merged.get(&field).is_none() {
+                anyhow::bail!("Unknown config field '{}'", field);
+            }
+            merged[field] = value;
+        }
+    }
+    let mut config: AnomalyDetectionConfig = serde_json::from_value(merged).context("Invalid detection config")?;
+    if let Some(sensitivity) = cli.sensitivity {
+        config.sensitivity = sensitivity;
+    }
+    if let Some(max_jump_threshold) = cli.max_jump_threshold {
+        config.max_jump_threshold = max_jump_threshold;
+    }
+    if let Some(min_window_size) = cli.min_window_size {
+        config.min_window_size = min_window_size;
+    }
+    config.check().map_err(|e| anyhow::anyhow!("Invalid detection config: {}", e))?;
+    Ok(config)
+}
+
+fn run(cli: &Cli, config: AnomalyDetectionConfig) -> Result<BenchReport> {
+    let detectors = DetectorRegistry::with_builtin().detectors().iter()
+        .map(|d| d.configure(&config, &serde_json::Value::Null).map_err(|e| anyhow::anyhow!(e)))
+        .collect::<Result<Vec<_>>>()?;
+    let tolerance = Duration::seconds(cli.tolerance * cli.interval);
+
+    let mut reports: BTreeMap<String, TypeReport> = BTreeMap::new();
+    let mut total_labels = 0;
+    let mut total_detected = 0;
+    let mut rng = Rng(cli.seed);
+
+    for index in 0..cli.series {
+        let (data, labels) = generate_series(&mut rng, index, cli.points, cli.interval);
+        let start_time = data.first().map(|d| d.timestamp);
+        let end_time = data.last().map(|d| d.timestamp);
+        let mut anomalies = Vec::new();
+        for detector in &detectors {
+            anomalies.extend(detector.detect(&data, start_time, end_time)?);
+        }
+
+        for anomaly in &anomalies {
+            let report = reports.entry(anomaly.anomaly_type.as_str().to_string()).or_default();
+            report.detections += 1;
+            if labels.iter().any(|l| l.injection.accepts(anomaly.anomaly_type) && overlaps(anomaly, l, tolerance)) {
+                report.true_positives += 1;
+            }
+        }
+
+        for label in &labels {
+            let expected = label.injection.expected();
+            let report = reports.entry(expected.as_str().to_string()).or_default();
+            report.labels += 1;
+            total_labels += 1;
+            let first = anomalies.iter()
+                .filter(|a| a.anomaly_type == expected && overlaps(a, label, tolerance))
+                .map(|a| a.start_time)
+                .min();
+            if let Some(detected_at) = first {
+                report.detected_labels += 1;
+                report.delays.push((detected_at - label.start).num_seconds().max(0));
+                total_detected += 1;
+            }
+        }
+    }
+
+    let types: Vec<TypeReport> = reports.into_iter()
+        .map(|(anomaly_type, mut report)| {
+            report.anomaly_type = anomaly_type;
+            report.finish();
+            report
+        })
+        .collect();
+    let detections = types.iter().map(|t| t.detections).sum();
+    let true_positives = types.iter().map(|t| t.true_positives).sum();
+
+    Ok(BenchReport {
+        series: cli.series,
+        points: cli.points,
+        interval_secs: cli.interval,
+        seed: cli.seed,
+        tolerance_intervals: cli.tolerance,
+        config,
+        types,
+        overall: Overall {
+            labels: total_labels,
+            detected_labels: total_detected,
+            detections,
+            true_positives,
+            precision: ratio(true_positives, detections),
+            recall: ratio(total_detected, total_labels),
+        },
+    })
+}
+
+fn format_ratio(value: Option<f64>) -> String {
+    value.map(|v| format!("{:.3}", v)).unwrap_or_else(|| "-".to_string())
+}
+
+fn print_report(report: &BenchReport) {
+    println!(
+        "{} series x {} points, interval {}s, seed {}",
+        report.series, report.points, report.interval_secs, report.seed
+    );
+    println!(
+        "{:<20} {:>7} {:>9} {:>11} {:>5} {:>10} {:>7} {:>15} {:>14}",
+        "type", "labels", "detected", "detections", "tp", "precision", "recall", "mean_delay_s", "max_delay_s"
+    );
+    for t in &report.types {
+        println!(
+            "{:<20} {:>7} {:>9} {:>11} {:>5} {:>10} {:>7} {:>15} {:>14}",
+            t.anomaly_type,
+            t.labels,
+            t.detected_labels,
+            t.detections,
+            t.true_positives,
+            format_ratio(t.precision),
+            format_ratio(t.recall),
+            t.mean_delay_secs.map(|d| format!("{:.0}", d)).unwrap_or_else(|| "-".to_string()),
+            t.max_delay_secs.map(|d| d.to_string()).unwrap_or_else(|| "-".to_string()),
+        );
+    }
+    let o = &report.overall;
+    println!(
+        "{:<20} {:>7} {:>9} {:>11} {:>5} {:>10} {:>7}",
+        "overall",
+        o.labels,
+        o.detected_labels,
+        o.detections,
+        o.true_positives,
+        format_ratio(o.precision),
+        format_ratio(o.recall),
+    );
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    anyhow::ensure!(cli.points >= 1000, "--points must be at least 1000");
+    anyhow::ensure!(cli.interval > 0, "--interval must be positive");
+    anyhow::ensure!(cli.tolerance >= 0, "--tolerance must not be negative");
+
+    let config = load_config(&cli)?;
+    let report = run(&cli, config)?;
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(())
+}
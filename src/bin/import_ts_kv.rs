@@ -31,6 +31,20 @@ enum Commands {
         #[arg(long, default_value = "temp_ts_kv")]
         temp_table: String,
     },
+    /// Check ts_kv for future timestamps and list the values replaced by imports
+    Check {
+        /// Path to the DuckDB database file
+        #[arg(short, long, default_value = "data.db")]
+        database: String,
+
+        /// Number of examples to print for each issue type
+        #[arg(long, default_value_t = 5)]
+        examples: usize,
+
+        /// Seconds a timestamp may lie in the future before it is reported
+        #[arg(long, default_value_t = 300)]
+        future_tolerance: u64,
+    },
 }
 
 fn import_ts_kv(
@@ -118,6 +132,10 @@ fn import_ts_kv(
         .context("Failed to count rows in temporary table")?;
     
     println!("Temporary table contains {} rows", temp_count);
+
+    // Keep the values that the UPSERT is about to discard
+    let conflicts = record_conflicts(&conn, temp_table)?;
+    let temp_count = temp_count - conflicts.dropped;
     
     // Perform UPSERT operation
     println!("Performing UPSERT operation...");
@@ -168,6 +186,13 @@ fn import_ts_kv(
         .context("Failed to count final rows in ts_kv")?;
     
     println!("Final row count in ts_kv table: {}", final_count);
+    if conflicts.recorded > 0 {
+        println!(
+            "{} replaced values were kept in {} (see `import_ts_kv check`)",
+            conflicts.recorded,
+            CONFLICTS_TABLE
+        );
+    }
     
     // Clean up temporary table
     if !skip_temp_table {
@@ -181,6 +206,196 @@ fn import_ts_kv(
     Ok(())
 }
 
+/// Rows replaced during import, with the values that were discarded
+const CONFLICTS_TABLE: &str = "ts_kv_conflicts";
+
+struct ImportConflicts {
+    /// Rows removed from the temporary table because a later CSV row has the same key
+    dropped: i64,
+    /// Discarded rows whose values differ from the row that was kept
+    recorded: i64,
+}
+
+/// Report duplicate keys in the CSV and rows that would overwrite different values in ts_kv.
+///
+/// Clock resyncs produce the same timestamp with a different value; ON CONFLICT DO UPDATE would
+/// keep only the last one silently. The discarded values go to `ts_kv_conflicts` and in-file
+/// duplicates are reduced to the last row, since one UPSERT cannot update the same key twice.
+fn record_conflicts(conn: &Connection, temp_table: &str) -> Result<ImportConflicts> {
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                entity_id VARCHAR NOT NULL,
+                key INTEGER NOT NULL,
+                ts BIGINT NOT NULL,
+                bool_v BOOLEAN,
+                str_v VARCHAR,
+                long_v BIGINT,
+                dbl_v DOUBLE,
+                source VARCHAR NOT NULL,
+                recorded_at BIGINT NOT NULL
+            )",
+            CONFLICTS_TABLE
+        ),
+        [],
+    )
+    .context("Failed to create conflicts table")?;
+    let now = chrono::Utc::now().timestamp_millis();
+    let differs = "(a.bool_v IS DISTINCT FROM b.bool_v OR a.str_v IS DISTINCT FROM b.str_v
+                    OR a.long_v IS DISTINCT FROM b.long_v OR a.dbl_v IS DISTINCT FROM b.dbl_v)";
+
+    // Duplicates within the CSV: the last row of each key wins
+    let in_file = format!(
+        "SELECT entity_id, key, ts, format('{{}} rows in CSV, last one kept', COUNT(*)) AS detail
+         FROM {}
+         GROUP BY entity_id, key, ts
+         HAVING COUNT(*) > 1
+         ORDER BY COUNT(*) DESC, entity_id, key, ts",
+        temp_table
+    );
+    report_issue(conn, "Duplicate timestamps in CSV", &in_file, 5)?;
+
+    let superseded = format!(
+        "FROM {t} a
+         JOIN (SELECT entity_id, key, ts, MAX(rowid) AS last_row FROM {t} GROUP BY entity_id, key, ts) l
+           ON a.entity_id = l.entity_id AND a.key = l.key AND a.ts = l.ts AND a.rowid <> l.last_row",
+        t = temp_table
+    );
+    let mut recorded = conn
+        .execute(
+            &format!(
+                "INSERT INTO {c}
+                 SELECT a.entity_id, a.key, a.ts, a.bool_v, a.str_v, a.long_v, a.dbl_v, 'csv', {now}
+                 {superseded}
+                 JOIN {t} b ON b.rowid = l.last_row
+                 WHERE {differs}",
+                c = CONFLICTS_TABLE,
+                t = temp_table,
+            ),
+            [],
+        )
+        .context("Failed to record duplicate CSV rows")? as i64;
+    let dropped = conn
+        .execute(
+            &format!(
+                "DELETE FROM {t} WHERE rowid IN (SELECT a.rowid {superseded})",
+                t = temp_table
+            ),
+            [],
+        )
+        .context("Failed to remove duplicate CSV rows")? as i64;
+
+    // Existing rows that the UPSERT would overwrite with a different value
+    let overwritten = format!(
+        "SELECT a.entity_id, a.key, a.ts,
+                format('stored {{}} replaced by {{}}',
+                       COALESCE(CAST(a.dbl_v AS VARCHAR), CAST(a.long_v AS VARCHAR), a.str_v, CAST(a.bool_v AS VARCHAR)),
+                       COALESCE(CAST(b.dbl_v AS VARCHAR), CAST(b.long_v AS VARCHAR), b.str_v, CAST(b.bool_v AS VARCHAR))) AS detail,
+                a.bool_v, a.str_v, a.long_v, a.dbl_v
+         FROM ts_kv a
+         JOIN {} b ON a.entity_id = b.entity_id AND a.key = b.key AND a.ts = b.ts
+         WHERE {}",
+        temp_table, differs
+    );
+    report_issue(
+        conn,
+        "Existing timestamps with different values",
+        &format!("SELECT entity_id, key, ts, detail FROM ({}) ORDER BY entity_id, key, ts", overwritten),
+        5,
+    )?;
+    recorded += conn
+        .execute(
+            &format!(
+                "INSERT INTO {} SELECT entity_id, key, ts, bool_v, str_v, long_v, dbl_v, 'ts_kv', {} FROM ({})",
+                CONFLICTS_TABLE, now, overwritten
+            ),
+            [],
+        )
+        .context("Failed to record overwritten rows")? as i64;
+
+    Ok(ImportConflicts { dropped, recorded })
+}
+
+/// Print the count and a few example rows of one timestamp issue, returns the count
+fn report_issue(conn: &Connection, title: &str, query: &str, examples: usize) -> Result<i64> {
+    let count: i64 = conn
+        .query_row(&format!("SELECT COUNT(*) FROM ({})", query), [], |row| row.get(0))
+        .with_context(|| format!("Failed to count {}", title))?;
+
+    println!("{}: {}", title, count);
+    if count == 0 {
+        return Ok(0);
+    }
+
+    let mut stmt = conn
+        .prepare(&format!("SELECT * FROM ({}) LIMIT {}", query, examples))
+        .with_context(|| format!("Failed to query {}", title))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i32>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (entity_id, key, ts, detail) in rows {
+        let time = chrono::DateTime::from_timestamp_millis(ts)
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_else(|| "invalid".to_string());
+        println!("  entity_id={} key={} ts={} ({}) {}", entity_id, key, ts, time, detail);
+    }
+    Ok(count)
+}
+
+/// Check the integrity of the ts column, returns the total number of issues found
+fn check_ts_kv(database: &str, examples: usize, future_tolerance: u64) -> Result<i64> {
+    println!("Connecting to database: {}", database);
+    let conn = Connection::open(database)
+        .context("Failed to open database connection")?;
+
+    let total_rows: i64 = conn
+        .query_row("SELECT COUNT(*) FROM ts_kv", [], |row| row.get(0))
+        .context("Failed to count rows in ts_kv")?;
+    println!("Checking {} rows in ts_kv", total_rows);
+
+    // ts_kv keeps one row per (entity_id, key, ts); the values that imports replaced are in the conflicts table.
+    // They are history that no later import can resolve, so they are listed but not counted as issues
+    let conflicts_exist: bool = conn
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM information_schema.tables WHERE table_name = ?",
+            [CONFLICTS_TABLE],
+            |row| row.get(0),
+        )
+        .context("Failed to check if conflicts table exists")?;
+    let duplicates = if conflicts_exist {
+        format!(
+            "SELECT entity_id, key, ts,
+                    format('{{}} replaced values ({{}})', COUNT(*), string_agg(DISTINCT source, ', ')) AS detail
+             FROM {}
+             GROUP BY entity_id, key, ts
+             ORDER BY COUNT(*) DESC, entity_id, key, ts",
+            CONFLICTS_TABLE
+        )
+    } else {
+        "SELECT NULL::VARCHAR, NULL::INTEGER, NULL::BIGINT, NULL::VARCHAR WHERE false".to_string()
+    };
+
+    let future_limit = chrono::Utc::now().timestamp_millis() + future_tolerance as i64 * 1000;
+    let future = format!(
+        "SELECT entity_id, key, ts, format('{{}} ms ahead of the check time', ts - {limit} + {tolerance}) AS detail
+         FROM ts_kv
+         WHERE ts > {limit}
+         ORDER BY ts DESC",
+        limit = future_limit,
+        tolerance = future_tolerance * 1000
+    );
+
+    report_issue(&conn, "Timestamps with values replaced by imports (not counted)", &duplicates, examples)?;
+    report_issue(&conn, "Future timestamps", &future, examples)
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
     
@@ -193,6 +408,18 @@ fn main() -> Result<()> {
         } => {
             import_ts_kv(&csv_file, &database, skip_temp_table, &temp_table)?;
         }
+        Commands::Check {
+            database,
+            examples,
+            future_tolerance,
+        } => {
+            let issues = check_ts_kv(&database, examples, future_tolerance)?;
+            if issues > 0 {
+                println!("Found {} timestamp issues", issues);
+                std::process::exit(1);
+            }
+            println!("No timestamp issues found");
+        }
     }
     
     Ok(())
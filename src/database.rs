@@ -2,10 +2,15 @@ use anyhow::Result;
 use chrono::{DateTime, Utc, FixedOffset};
 use duckdb::{Connection, Result as DuckResult};
 use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashSet};
 use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::anomaly_detection::{AnomalyType, DetectedAnomaly};
+use crate::expression;
 
 // 自定义序列化函数，将UTC时间转换为上海时间
-fn serialize_shanghai_time<S>(datetime: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+pub(crate) fn serialize_shanghai_time<S>(datetime: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -27,7 +32,7 @@ where
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TelemetryData {
     #[serde(serialize_with = "serialize_shanghai_time")]
     pub timestamp: DateTime<Utc>,
@@ -38,22 +43,63 @@ pub struct TelemetryData {
     pub value: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TelemetryResponse {
     pub data: Vec<TelemetryData>,
     pub stats: DataStats,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DataStats {
     pub total_points: usize,
     pub target_count: usize,
+    /// [最早时间, 最晚时间]
+    #[schema(value_type = Option<Vec<DateTime<Utc>>>)]
     pub time_range: Option<(DateTime<Utc>, DateTime<Utc>)>,
     pub outliers_removed: Option<usize>,
     pub outlier_method: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 单个序列的统计信息
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SeriesStatistics {
+    pub asset_name: String,
+    pub device_name: String,
+    pub target_name: String,
+    pub key_name: String,
+    pub count: usize,
+    pub min: f64,
+    /// 最小值出现的时间
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub min_time: DateTime<Utc>,
+    pub max: f64,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub max_time: DateTime<Utc>,
+    pub mean: f64,
+    /// 样本标准差，只有一个点时为空
+    pub stddev: Option<f64>,
+    pub p5: f64,
+    pub p25: f64,
+    /// 中位数
+    pub p50: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub first_value: f64,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub first_time: DateTime<Utc>,
+    pub last_value: f64,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub last_time: DateTime<Utc>,
+    /// 净变化：最后一个值减第一个值
+    pub net_change: f64,
+    /// 相邻数据时间差的中位数（毫秒），少于两个点时为空
+    pub expected_interval_ms: Option<i64>,
+    pub expected_points: Option<usize>,
+    /// 完整率百分比（0-100）
+    pub completeness: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct FilterOptions {
     pub assets: Vec<String>,
     pub devices: Vec<String>,
@@ -61,6 +107,19 @@ pub struct FilterOptions {
     pub key_names: Vec<String>,
 }
 
+// 资产权限范围条件；范围为空时不匹配任何数据
+fn asset_scope_clause(assets: &[String], value: impl Fn(&String) -> String) -> String {
+    if assets.is_empty() {
+        "1 = 0".to_string()
+    } else {
+        let values = assets.iter().map(value).collect::<Vec<_>>().join(", ");
+        format!("asset_name IN ({})", values)
+    }
+}
+
+// 单次查询允许返回的最大数据量
+pub const MAX_QUERY_LIMIT: usize = 100_000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryParams {
     pub asset_name: Option<String>,
@@ -76,6 +135,8 @@ pub struct QueryParams {
     pub sampling_config: Option<SamplingConfig>, // 采样配置
     pub reference_values: Option<Vec<ReferenceValue>>, // 参考值配置
     pub time_of_day_filter: Option<TimeOfDayFilter>, // 每日时间段过滤
+    #[serde(default)]
+    pub allowed_assets: Option<Vec<String>>, // 用户可访问的资产范围，None表示不限制
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -91,7 +152,7 @@ pub struct TimeRange {
     pub end_minute: u8,    // 结束分钟 (0-59)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DataOperation {
     pub id: Option<i64>,
     pub name: Option<String>,  // 改为可选，自动生成默认名称
@@ -111,7 +172,7 @@ pub struct DataOperation {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub enum OperationType {
     Add,
     Subtract,
@@ -143,11 +204,89 @@ impl OperationType {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 参考值的计算方式
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceMode {
+    /// 减去固定值 `reference_value`
+    #[default]
+    Value,
+    /// 减去最接近 `reference_time` 的读数，例如初始测量
+    Epoch,
+    /// 减去 `reference_start` 到 `reference_end` 之间读数的平均值
+    PeriodMean,
+}
+
+impl ReferenceMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ReferenceMode::Value => "value",
+            ReferenceMode::Epoch => "epoch",
+            ReferenceMode::PeriodMean => "period_mean",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ReferenceValue {
     pub target_name: String,
     pub key_name: String,
-    pub reference_value: f64,
+    /// 默认 "value"
+    #[serde(default)]
+    pub mode: ReferenceMode,
+    /// mode为value时必填
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_value: Option<f64>,
+    /// RFC 3339 时间，mode为epoch时必填
+    #[serde(default, serialize_with = "serialize_optional_shanghai_time", skip_serializing_if = "Option::is_none")]
+    pub reference_time: Option<DateTime<Utc>>,
+    /// RFC 3339 参考期开始时间，mode为period_mean时必填
+    #[serde(default, serialize_with = "serialize_optional_shanghai_time", skip_serializing_if = "Option::is_none")]
+    pub reference_start: Option<DateTime<Utc>>,
+    /// RFC 3339 参考期结束时间，mode为period_mean时必填
+    #[serde(default, serialize_with = "serialize_optional_shanghai_time", skip_serializing_if = "Option::is_none")]
+    pub reference_end: Option<DateTime<Utc>>,
+}
+
+/// 保存的查询视图，可以通过短ID分享和回放
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SavedQuery {
+    /// 8位短ID
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub owner: String,
+    pub tags: Vec<String>,
+    /// 查询参数，时间为绝对时间
+    #[schema(value_type = Object)]
+    pub query: QueryParams,
+    /// 引用的基准名称，回放时读取基准的当前内容
+    pub baseline: Option<String>,
+    /// 相对开始时间，例如 "-7d"，回放时相对当前时间计算
+    pub relative_start: Option<String>,
+    /// 相对结束时间，例如 "now"
+    pub relative_end: Option<String>,
+    /// 前端的显示设置，服务端不解析
+    #[schema(value_type = Option<Object>)]
+    pub display: Option<serde_json::Value>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// 保存在服务端的一组参考值，查询时可以按名称引用
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Baseline {
+    pub id: Option<i64>,
+    pub name: String,
+    pub description: Option<String>,
+    pub reference_values: Vec<ReferenceValue>,
+    pub created_by: String,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -172,195 +311,1073 @@ pub struct CustomFilter {
     pub exclude_values: Vec<f64>, // 排除特定值
 }
 
-pub struct DatabaseManager {
-    connection: Arc<Mutex<Connection>>,
-    db_path: String,
+/// 用户角色，权限从低到高依次为 viewer < operator < admin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// 查看遥测数据
+    Viewer,
+    /// 管理数据操作、执行异常检测
+    Operator,
+    /// 管理用户
+    Admin,
 }
 
-impl DatabaseManager {
-    pub fn new(db_path: &str) -> Result<Self> {
-        let conn = Connection::open(db_path)?;
-        let manager = DatabaseManager {
-            connection: Arc::new(Mutex::new(conn)),
-            db_path: db_path.to_string(),
-        };
-        manager.init_tables()?;
-        Ok(manager)
-    }
-    
-    // 创建新连接用于查询，避免锁竞争
-    fn get_read_connection(&self) -> Result<Connection> {
-        // DuckDB支持多个连接同时读取
-        Ok(Connection::open(&self.db_path)?)
+impl Role {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
     }
 
-    fn init_tables(&self) -> Result<()> {
-        let conn = self.connection.lock().unwrap();
-        
-        // 创建序列
-        conn.execute(
-            "CREATE SEQUENCE IF NOT EXISTS seq_operations_id START 1",
-            [],
-        )?;
-        
-        // 创建数据操作记录表
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS data_operations (
-                id INTEGER PRIMARY KEY DEFAULT nextval('seq_operations_id'),
-                name VARCHAR,
-                description VARCHAR,
-                target_name VARCHAR NOT NULL,
-                key_name VARCHAR NOT NULL,
-                operation_type VARCHAR NOT NULL,
-                value DOUBLE NOT NULL,
-                start_time BIGINT,
-                end_time BIGINT,
-                is_active BOOLEAN DEFAULT TRUE,
-                created_at BIGINT NOT NULL,
-                updated_at BIGINT NOT NULL
-            )",
-            [],
-        )?;
-        
-        // 暂时禁用索引创建，避免DuckDB断言错误
-        // 问题可能与在视图基础表上创建索引有关
-        println!("Skipping index creation to avoid DuckDB assertion error...");
-        
-        // 只创建数据操作表的索引（这个表是我们创建的，应该没问题）
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_operations_active 
-             ON data_operations(is_active)",
-            [],
-        ).ok();
-        
-        // 更新统计信息
-        conn.execute("ANALYZE data_operations", []).ok();
-        
-        Ok(())
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "viewer" => Some(Role::Viewer),
+            "operator" => Some(Role::Operator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
     }
+}
 
-    pub fn get_filter_options(&self) -> Result<FilterOptions> {
-        // 使用独立连接避免死锁
-        let conn = self.get_read_connection()?;
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub role: Role,
+    pub is_active: bool,
+    /// 允许访问的资产，为空表示不限制
+    pub assets: Vec<String>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub updated_at: DateTime<Utc>,
+}
 
-        // 获取所有资产名称
-        let mut stmt = conn.prepare("SELECT DISTINCT asset_name FROM a_d_t_telemetry ORDER BY asset_name")?;
-        let assets: Vec<String> = stmt.query_map([], |row| {
-            Ok(row.get::<_, String>(0)?)
-        })?.collect::<DuckResult<Vec<_>>>()?;
+impl User {
+    /// 用于查询的资产范围，None表示不限制
+    pub fn asset_scope(&self) -> Option<Vec<String>> {
+        if self.assets.is_empty() {
+            None
+        } else {
+            Some(self.assets.clone())
+        }
+    }
+}
 
-        // 获取所有设备名称 (使用d_name字段)
-        let mut stmt = conn.prepare("SELECT DISTINCT d_name FROM a_d_t_telemetry ORDER BY d_name")?;
-        let devices: Vec<String> = stmt.query_map([], |row| {
-            Ok(row.get::<_, String>(0)?)
-        })?.collect::<DuckResult<Vec<_>>>()?;
+// 用户更新内容，None的字段保持不变
+#[derive(Debug, Clone, Default)]
+pub struct UserUpdate {
+    pub password_hash: Option<String>,
+    pub role: Option<Role>,
+    pub is_active: Option<bool>,
+    pub assets: Option<Vec<String>>,
+}
 
-        // 获取所有标靶名称
-        let mut stmt = conn.prepare("SELECT DISTINCT target_name FROM a_d_t_telemetry ORDER BY target_name")?;
-        let targets: Vec<String> = stmt.query_map([], |row| {
-            Ok(row.get::<_, String>(0)?)
-        })?.collect::<DuckResult<Vec<_>>>()?;
+/// API令牌信息（不包含令牌本身）
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_optional_shanghai_time")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "serialize_optional_shanghai_time")]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
 
-        // 获取所有key_name
-        let mut stmt = conn.prepare("SELECT DISTINCT key_name FROM a_d_t_telemetry ORDER BY key_name")?;
-        let key_names: Vec<String> = stmt.query_map([], |row| {
-            Ok(row.get::<_, String>(0)?)
-        })?.collect::<DuckResult<Vec<_>>>()?;
+/// 异常的审核状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AnomalyStatus {
+    /// 待审核
+    New,
+    /// 已接受，建议的纠正操作已创建
+    Accepted,
+    /// 已驳回（误报）
+    Rejected,
+    /// 问题已在现场修复
+    Fixed,
+}
 
-        Ok(FilterOptions {
-            assets,
-            devices,
-            targets,
-            key_names,
-        })
+impl AnomalyStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AnomalyStatus::New => "new",
+            AnomalyStatus::Accepted => "accepted",
+            AnomalyStatus::Rejected => "rejected",
+            AnomalyStatus::Fixed => "fixed",
+        }
     }
 
-    pub fn get_devices_by_asset(&self, asset_name: &str) -> Result<Vec<String>> {
-        // 使用独立连接避免死锁
-        let conn = self.get_read_connection()?;
-        let mut stmt = conn.prepare(
-            "SELECT DISTINCT d_name FROM a_d_t_telemetry WHERE asset_name = ? ORDER BY d_name"
-        )?;
-        let devices: Vec<String> = stmt.query_map([asset_name], |row| {
-            Ok(row.get::<_, String>(0)?)
-        })?.collect::<DuckResult<Vec<_>>>()?;
-        Ok(devices)
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "new" => Some(AnomalyStatus::New),
+            "accepted" => Some(AnomalyStatus::Accepted),
+            "rejected" => Some(AnomalyStatus::Rejected),
+            "fixed" => Some(AnomalyStatus::Fixed),
+            _ => None,
+        }
     }
 
-    pub fn get_targets_by_device(&self, asset_name: &str, device_name: &str) -> Result<Vec<String>> {
-        // 使用独立连接避免死锁
-        let conn = self.get_read_connection()?;
-        let mut stmt = conn.prepare(
-            "SELECT DISTINCT target_name FROM a_d_t_telemetry WHERE asset_name = ? AND d_name = ? ORDER BY target_name"
-        )?;
-        let targets: Vec<String> = stmt.query_map([asset_name, device_name], |row| {
-            Ok(row.get::<_, String>(0)?)
-        })?.collect::<DuckResult<Vec<_>>>()?;
-        Ok(targets)
+    /// 是否允许从当前状态审核为目标状态
+    pub fn can_change_to(&self, status: AnomalyStatus) -> bool {
+        matches!(
+            (self, status),
+            (AnomalyStatus::New, AnomalyStatus::Accepted | AnomalyStatus::Rejected | AnomalyStatus::Fixed)
+                | (AnomalyStatus::Accepted, AnomalyStatus::Fixed)
+        )
     }
+}
 
-    // 批量查询数据（用于流式传输）- 使用独立的只读连接避免死锁
-    pub fn query_telemetry_data_batch(&self, params: &QueryParams, offset: usize, limit: usize) -> Result<Vec<TelemetryData>> {
-        // 使用独立的只读连接，避免长时间持有主连接的锁
-        let conn = self.get_read_connection()?;
-        
-        // 构建基础查询
-        let mut query = format!(
-            "SELECT ts, asset_name, d_name as device_name, target_name, key_name, dbl_v 
-             FROM a_d_t_telemetry 
-             WHERE dbl_v IS NOT NULL"
-        );
-        
-        let mut conditions = Vec::new();
-        
-        if let Some(asset) = &params.asset_name {
-            conditions.push(format!("asset_name = '{}'", asset));
-        }
-        
-        if let Some(device) = &params.device_name {
-            conditions.push(format!("d_name = '{}'", device));
+/// 一次异常检测的记录
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AnomalyRun {
+    pub id: i64,
+    /// 执行检测的用户名
+    pub created_by: String,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_optional_shanghai_time")]
+    pub start_time: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "serialize_optional_shanghai_time")]
+    pub end_time: Option<DateTime<Utc>>,
+    /// 检测的序列数，单标靶检测时为空
+    pub series_analyzed: Option<i64>,
+    pub anomaly_count: i64,
+}
+
+/// 增量检测中一个序列的状态
+///
+/// context 是已检测数据末尾的一段，点数只取决于滚动基线、噪声参考和卡死判断的窗口大小，
+/// 下次检测时放在新数据之前恢复这些窗口；季节项等学到的基线由各检测器自己保存在 detectors 中
+#[derive(Debug, Clone)]
+pub struct SeriesState {
+    pub key: SeriesKey,
+    /// 检测配置的指纹，配置变化后状态失效
+    pub fingerprint: String,
+    /// 已检测的最后一条数据的时间
+    pub watermark: DateTime<Utc>,
+    /// (时间戳毫秒, 数值)
+    pub context: Vec<(i64, f64)>,
+    /// 检测器名称到该检测器保存的状态
+    pub detectors: BTreeMap<String, serde_json::Value>,
+}
+
+/// 保存的异常及其审核信息
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StoredAnomaly {
+    #[serde(flatten)]
+    pub anomaly: DetectedAnomaly,
+    /// 最近一次检测到该异常的批次
+    pub run_id: i64,
+    pub status: AnomalyStatus,
+    pub reviewed_by: Option<String>,
+    #[serde(serialize_with = "serialize_optional_shanghai_time")]
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_comment: Option<String>,
+    /// 接受异常时创建的数据操作
+    pub operation_id: Option<i64>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// 异常列表的查询条件，None表示不限制
+#[derive(Debug, Clone, Default)]
+pub struct AnomalyQuery {
+    pub status: Option<AnomalyStatus>,
+    pub run_id: Option<i64>,
+    pub target_name: Option<String>,
+    pub key_name: Option<String>,
+    pub allowed_assets: Option<Vec<String>>,
+    pub limit: usize,
+}
+
+/// 审核异常的结果
+#[derive(Debug)]
+pub enum ReviewOutcome {
+    NotFound,
+    /// 当前状态不允许该审核
+    InvalidTransition(AnomalyStatus),
+    /// 审核成功，接受异常时返回创建的数据操作ID
+    Reviewed { operation_id: Option<i64> },
+}
+
+/// 告警级别，严重程度从低到高
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmLevel {
+    Blue,
+    Yellow,
+    Orange,
+    Red,
+}
+
+impl AlarmLevel {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AlarmLevel::Blue => "blue",
+            AlarmLevel::Yellow => "yellow",
+            AlarmLevel::Orange => "orange",
+            AlarmLevel::Red => "red",
         }
-        
-        if !params.target_names.is_empty() {
-            let targets = params.target_names.iter()
-                .map(|t| format!("'{}'", t))
-                .collect::<Vec<_>>()
-                .join(", ");
-            conditions.push(format!("target_name IN ({})", targets));
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "blue" => Some(AlarmLevel::Blue),
+            "yellow" => Some(AlarmLevel::Yellow),
+            "orange" => Some(AlarmLevel::Orange),
+            "red" => Some(AlarmLevel::Red),
+            _ => None,
         }
-        
-        if !params.key_names.is_empty() {
-            let keys = params.key_names.iter()
-                .map(|k| format!("'{}'", k))
-                .collect::<Vec<_>>()
-                .join(", ");
-            conditions.push(format!("key_name IN ({})", keys));
+    }
+}
+
+/// 告警规则判断的量
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmMetric {
+    /// 读数本身
+    Value,
+    /// 每小时的变化速率，由读数与 rate_window 之前的读数计算
+    Rate,
+    /// 相对 reference_time 之后第一个读数的累计变化
+    Cumulative,
+}
+
+impl AlarmMetric {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AlarmMetric::Value => "value",
+            AlarmMetric::Rate => "rate",
+            AlarmMetric::Cumulative => "cumulative",
         }
-        
-        if let Some(start) = params.start_time {
-            conditions.push(format!("ts >= {}", start.timestamp_millis()));
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "value" => Some(AlarmMetric::Value),
+            "rate" => Some(AlarmMetric::Rate),
+            "cumulative" => Some(AlarmMetric::Cumulative),
+            _ => None,
         }
-        
-        if let Some(end) = params.end_time {
-            conditions.push(format!("ts <= {}", end.timestamp_millis()));
+    }
+}
+
+/// 超限方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmDirection {
+    /// 不低于阈值
+    Above,
+    /// 不高于阈值
+    Below,
+    /// 绝对值不低于阈值
+    Both,
+}
+
+impl AlarmDirection {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AlarmDirection::Above => "above",
+            AlarmDirection::Below => "below",
+            AlarmDirection::Both => "both",
         }
-        
-        if !conditions.is_empty() {
-            query.push_str(" AND ");
-            query.push_str(&conditions.join(" AND "));
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "above" => Some(AlarmDirection::Above),
+            "below" => Some(AlarmDirection::Below),
+            "both" => Some(AlarmDirection::Both),
+            _ => None,
         }
-        
-        // 添加排序和分页
-        query.push_str(&format!(" ORDER BY ts DESC LIMIT {} OFFSET {}", limit, offset));
-        
-        // 执行查询
-        let mut stmt = conn.prepare(&query)?;
-        let mut rows = stmt.query([])?;
-        
-        let mut data = Vec::new();
-        while let Some(row) = rows.next()? {
-            let ts_millis: i64 = row.get(0)?;
-            let timestamp = DateTime::from_timestamp_millis(ts_millis)
+    }
+}
+
+/// 一个告警级别的阈值
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AlarmThreshold {
+    pub level: AlarmLevel,
+    pub threshold: f64,
+}
+
+/// 告警规则，范围中未指定的资产/设备/标靶不限制
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AlarmRule {
+    pub id: Option<i64>,
+    pub name: String,
+    pub asset_name: Option<String>,
+    pub device_name: Option<String>,
+    pub target_name: Option<String>,
+    pub key_name: String,
+    pub metric: AlarmMetric,
+    pub direction: AlarmDirection,
+    /// 各级别的阈值，按级别从低到高排列
+    pub levels: Vec<AlarmThreshold>,
+    /// 回差：判断量回到阈值以内超过此值才降级或恢复
+    pub hysteresis: f64,
+    /// 超限持续多少秒才触发或升级
+    pub duration: u64,
+    /// 计算变化速率的时间窗口（秒），仅rate使用
+    pub rate_window: Option<u64>,
+    /// 累计变化的起算时间，仅cumulative使用
+    #[serde(serialize_with = "serialize_optional_shanghai_time")]
+    pub reference_time: Option<DateTime<Utc>>,
+    pub enabled: bool,
+    pub created_by: String,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// 告警事件：一个序列从触发告警到恢复的过程，期间级别可以升降
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AlarmEvent {
+    pub id: Option<i64>,
+    pub rule_id: i64,
+    pub rule_name: String,
+    pub asset_name: String,
+    pub device_name: String,
+    pub target_name: String,
+    pub key_name: String,
+    /// 当前级别
+    pub level: AlarmLevel,
+    /// 事件期间达到的最高级别
+    pub peak_level: AlarmLevel,
+    /// 开始超限的数据时间
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub start_time: DateTime<Utc>,
+    /// 恢复的数据时间，未恢复时为空
+    #[serde(serialize_with = "serialize_optional_shanghai_time")]
+    pub end_time: Option<DateTime<Utc>>,
+    /// 触发时的判断量
+    pub trigger_value: f64,
+    /// 事件期间最严重的判断量
+    pub peak_value: f64,
+    /// 最近一次的判断量
+    pub last_value: f64,
+    pub acknowledged_by: Option<String>,
+    #[serde(serialize_with = "serialize_optional_shanghai_time")]
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    pub ack_comment: Option<String>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// 告警规则在一个序列上的判断进度
+#[derive(Debug, Clone)]
+pub struct AlarmSeriesState {
+    pub rule_id: i64,
+    pub key: SeriesKey,
+    /// 已判断的最后一条数据的时间
+    pub watermark: DateTime<Utc>,
+    /// 开始超过当前级别的时间，用于判断持续时间
+    pub pending_since: Option<DateTime<Utc>>,
+    /// 累计变化的基准读数
+    pub reference_value: Option<f64>,
+}
+
+/// 告警事件列表的查询条件，None表示不限制
+#[derive(Debug, Clone, Default)]
+pub struct AlarmQuery {
+    /// true只返回未恢复的事件，false只返回已恢复的事件
+    pub active: Option<bool>,
+    pub acknowledged: Option<bool>,
+    /// 最高级别不低于此级别
+    pub min_level: Option<AlarmLevel>,
+    pub rule_id: Option<i64>,
+    pub target_name: Option<String>,
+    pub key_name: Option<String>,
+    /// 与此时间范围有重叠的事件
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub allowed_assets: Option<Vec<String>>,
+    pub limit: usize,
+}
+
+/// 确认告警的结果
+#[derive(Debug)]
+pub enum AcknowledgeOutcome {
+    NotFound,
+    /// 已被确认，返回确认人
+    AlreadyAcknowledged(String),
+    Acknowledged,
+}
+
+/// 通知发送状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationStatus {
+    /// 等待发送或等待重试
+    Pending,
+    Sent,
+    /// 重试次数用尽
+    Failed,
+}
+
+impl NotificationStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            NotificationStatus::Pending => "pending",
+            NotificationStatus::Sent => "sent",
+            NotificationStatus::Failed => "failed",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "pending" => Some(NotificationStatus::Pending),
+            "sent" => Some(NotificationStatus::Sent),
+            "failed" => Some(NotificationStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// 发件箱中的一条通知，每个通道一条
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub channel: String,
+    pub event: String,
+    /// 通知内容
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    pub status: NotificationStatus,
+    pub attempts: u32,
+    /// 下次尝试发送的时间
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_optional_shanghai_time")]
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+/// 派生指标的一个输入序列
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DerivedInput {
+    /// 表达式中的变量名
+    pub variable: String,
+    pub key_name: String,
+    /// 指定时固定使用该标靶的数据（例如计算两个标靶的差异沉降），不指定时在每个标靶上分别计算
+    pub target_name: Option<String>,
+}
+
+/// 派生指标：由表达式和输入序列定义的虚拟数据类型，查询时在DuckDB中计算
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DerivedMetric {
+    pub id: Option<i64>,
+    /// 作为数据类型（key_name）使用的名称
+    pub name: String,
+    pub description: Option<String>,
+    /// 例如 "sqrt(dx^2 + dy^2)"
+    pub expression: String,
+    pub inputs: Vec<DerivedInput>,
+    /// 对齐时间戳的容差（毫秒），0表示时间戳必须相同
+    pub tolerance_ms: i64,
+    pub created_by: String,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+// 原始数据表的列，派生指标的查询输出相同的列
+const TELEMETRY_TABLE: &str = "a_d_t_telemetry";
+
+/// 派生指标的查询SQL，输出与原始数据表相同的列
+///
+/// 以第一个不固定标靶的输入（都固定时为第一个输入）的时间戳为准，其他输入取容差范围内最近的读数；
+/// 不固定标靶的输入在同一标靶上对齐，固定标靶的输入只按时间对齐。
+/// 指定 filter 时把其中的时间范围和资产、设备条件放进各输入，只读取需要的数据：
+/// 其他输入的时间范围按容差放宽，固定标靶的输入不限制资产和设备。
+/// 用户的资产范围 allowed_assets 对所有输入都生效，固定标靶不能读取范围外资产的数据
+fn derived_metric_sql(
+    metric: &DerivedMetric,
+    prefix: &str,
+    filter: Option<&QueryParams>,
+    allowed_assets: Option<&[String]>,
+) -> Result<String> {
+    let expr = expression::parse(&metric.expression)
+        .map_err(|e| anyhow::anyhow!("Invalid expression of derived metric {}: {}", metric.name, e))?;
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let alias = |i: usize| format!("{}_i{}", prefix, i);
+    let anchor = metric.inputs.iter().position(|input| input.target_name.is_none()).unwrap_or(0);
+
+    let mut ctes = Vec::new();
+    for (i, input) in metric.inputs.iter().enumerate() {
+        let mut condition = format!("key_name = {} AND dbl_v IS NOT NULL", quote(&input.key_name));
+        if let Some(target_name) = &input.target_name {
+            condition.push_str(&format!(" AND target_name = {}", quote(target_name)));
+        }
+        if let Some(assets) = allowed_assets {
+            condition.push_str(&format!(" AND {}", asset_scope_clause(assets, |a| quote(a))));
+        }
+        if let Some(filter) = filter {
+            let tolerance = if i == anchor { 0 } else { metric.tolerance_ms };
+            if let Some(start_time) = filter.start_time {
+                condition.push_str(&format!(" AND ts >= {}", start_time.timestamp_millis().saturating_sub(tolerance)));
+            }
+            if let Some(end_time) = filter.end_time {
+                condition.push_str(&format!(" AND ts <= {}", end_time.timestamp_millis().saturating_add(tolerance)));
+            }
+            if i == anchor || input.target_name.is_none() {
+                if let Some(asset) = &filter.asset_name {
+                    condition.push_str(&format!(" AND asset_name = {}", quote(asset)));
+                }
+                if let Some(device) = &filter.device_name {
+                    condition.push_str(&format!(" AND d_name = {}", quote(device)));
+                }
+            }
+        }
+        ctes.push(format!(
+            "{} AS (SELECT ts, asset_name, d_name, target_name, dbl_v FROM {} WHERE {})",
+            alias(i), TELEMETRY_TABLE, condition
+        ));
+    }
+
+    let a = alias(anchor);
+    let mut joins = Vec::new();
+    let mut distances = Vec::new();
+    for (i, input) in metric.inputs.iter().enumerate().filter(|(i, _)| *i != anchor) {
+        let b = alias(i);
+        let mut on = format!(
+            "{b}.ts BETWEEN {a}.ts - {t} AND {a}.ts + {t}",
+            a = a, b = b, t = metric.tolerance_ms
+        );
+        if input.target_name.is_none() {
+            on.push_str(&format!(
+                " AND {b}.asset_name = {a}.asset_name AND {b}.d_name = {a}.d_name AND {b}.target_name = {a}.target_name",
+                a = a, b = b
+            ));
+        }
+        joins.push(format!("JOIN {} ON {}", b, on));
+        distances.push(format!("abs({}.ts - {}.ts)", b, a));
+    }
+
+    let variables: std::collections::HashMap<&str, String> = metric.inputs.iter().enumerate()
+        .map(|(i, input)| (input.variable.as_str(), format!("{}.dbl_v", alias(i))))
+        .collect();
+    let value = expr.to_sql(&|name| variables.get(name).cloned().unwrap_or_else(|| "NULL".to_string()));
+    // 一个时刻在容差内有多个读数时取最近的一组
+    let nearest = if distances.is_empty() {
+        String::new()
+    } else {
+        format!(
+            " QUALIFY ROW_NUMBER() OVER (PARTITION BY {a}.asset_name, {a}.d_name, {a}.target_name, {a}.ts ORDER BY {d}) = 1",
+            a = a, d = distances.join(" + ")
+        )
+    };
+
+    Ok(format!(
+        "WITH {ctes}
+         SELECT ts, asset_name, d_name, target_name, key_name, dbl_v FROM (
+             SELECT {a}.ts, {a}.asset_name, {a}.d_name, {a}.target_name, {name} AS key_name, CAST({value} AS DOUBLE) AS dbl_v
+             FROM {a} {joins}{nearest}
+         ) WHERE dbl_v IS NOT NULL AND isfinite(dbl_v)",
+        ctes = ctes.join(", "),
+        a = a,
+        name = quote(&metric.name),
+        value = value,
+        joins = joins.join(" "),
+        nearest = nearest,
+    ))
+}
+
+const ALARM_RULE_COLUMNS: &str = "id, name, asset_name, device_name, target_name, key_name, metric, direction,
+    levels, hysteresis, duration, rate_window, reference_time, enabled, created_by, created_at, updated_at";
+
+const ALARM_EVENT_COLUMNS: &str = "id, rule_id, rule_name, asset_name, device_name, target_name, key_name,
+    level, peak_level, start_time, end_time, trigger_value, peak_value, last_value,
+    acknowledged_by, acknowledged_at, ack_comment, created_at, updated_at";
+
+const ANOMALY_COLUMNS: &str = "id, run_id, asset_name, device_name, target_name, key_name, anomaly_type,
+    start_time, end_time, baseline_value, anomaly_value, jump_magnitude, confidence,
+    baseline_noise, observed_noise, suggested_operation, status, reviewed_by, reviewed_at,
+    review_comment, operation_id, created_at, updated_at, details, peers";
+
+fn millis_to_datetime(ms: i64) -> Result<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(ms).ok_or_else(|| anyhow::anyhow!("Invalid timestamp: {}", ms))
+}
+
+/// 一条时间序列的标识（资产/设备/标靶/数据类型）
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SeriesKey {
+    pub asset_name: String,
+    pub device_name: String,
+    pub target_name: String,
+    pub key_name: String,
+}
+
+impl SeriesKey {
+    fn matches(&self, item: &TelemetryData) -> bool {
+        self.target_name == item.target_name
+            && self.key_name == item.key_name
+            && self.device_name == item.device_name
+            && self.asset_name == item.asset_name
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SeriesData {
+    pub key: SeriesKey,
+    pub data: Vec<TelemetryData>,
+}
+
+// 读取按序列和时间排序的查询结果（ts, asset_name, d_name, target_name, key_name, dbl_v），序列切换时开始新的分组
+fn read_series(rows: &mut duckdb::Rows<'_>) -> Result<Vec<SeriesData>> {
+    let mut series: Vec<SeriesData> = Vec::new();
+    while let Some(row) = rows.next()? {
+        let ts_millis: i64 = row.get(0)?;
+        let item = TelemetryData {
+            timestamp: DateTime::from_timestamp_millis(ts_millis)
+                .ok_or_else(|| anyhow::anyhow!("Invalid timestamp: {}", ts_millis))?,
+            asset_name: row.get(1)?,
+            device_name: row.get(2)?,
+            target_name: row.get(3)?,
+            key_name: row.get(4)?,
+            value: row.get(5)?,
+        };
+
+        // 结果按序列排序，序列切换时开始新的分组
+        match series.last_mut() {
+            Some(current) if current.key.matches(&item) => current.data.push(item),
+            _ => series.push(SeriesData {
+                key: SeriesKey {
+                    asset_name: item.asset_name.clone(),
+                    device_name: item.device_name.clone(),
+                    target_name: item.target_name.clone(),
+                    key_name: item.key_name.clone(),
+                },
+                data: vec![item],
+            }),
+        }
+    }
+    Ok(series)
+}
+
+pub struct DatabaseManager {
+    connection: Arc<Mutex<Connection>>,
+    db_path: String,
+}
+
+impl DatabaseManager {
+    pub fn new(db_path: &str) -> Result<Self> {
+        let conn = Connection::open(db_path)?;
+        let manager = DatabaseManager {
+            connection: Arc::new(Mutex::new(conn)),
+            db_path: db_path.to_string(),
+        };
+        manager.init_tables()?;
+        Ok(manager)
+    }
+    
+    // 创建新连接用于查询，避免锁竞争
+    fn get_read_connection(&self) -> Result<Connection> {
+        // DuckDB支持多个连接同时读取
+        Ok(Connection::open(&self.db_path)?)
+    }
+
+    fn init_tables(&self) -> Result<()> {
+        let conn = self.connection.lock().unwrap();
+        
+        // 创建序列
+        conn.execute(
+            "CREATE SEQUENCE IF NOT EXISTS seq_operations_id START 1",
+            [],
+        )?;
+        
+        // 创建数据操作记录表
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS data_operations (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_operations_id'),
+                name VARCHAR,
+                description VARCHAR,
+                target_name VARCHAR NOT NULL,
+                key_name VARCHAR NOT NULL,
+                operation_type VARCHAR NOT NULL,
+                value DOUBLE NOT NULL,
+                start_time BIGINT,
+                end_time BIGINT,
+                is_active BOOLEAN DEFAULT TRUE,
+                created_at BIGINT NOT NULL,
+                updated_at BIGINT NOT NULL
+            )",
+            [],
+        )?;
+        
+        // 用户与权限相关表（不使用外键，删除用户时手动清理关联数据）
+        conn.execute("CREATE SEQUENCE IF NOT EXISTS seq_users_id START 1", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_users_id'),
+                username VARCHAR NOT NULL UNIQUE,
+                password_hash VARCHAR NOT NULL,
+                role VARCHAR NOT NULL,
+                is_active BOOLEAN DEFAULT TRUE,
+                created_at BIGINT NOT NULL,
+                updated_at BIGINT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS user_assets (
+                user_id INTEGER NOT NULL,
+                asset_name VARCHAR NOT NULL,
+                PRIMARY KEY (user_id, asset_name)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS sessions (
+                token_hash VARCHAR PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                created_at BIGINT NOT NULL,
+                expires_at BIGINT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute("CREATE SEQUENCE IF NOT EXISTS seq_api_tokens_id START 1", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS api_tokens (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_api_tokens_id'),
+                user_id INTEGER NOT NULL,
+                name VARCHAR NOT NULL,
+                token_hash VARCHAR NOT NULL UNIQUE,
+                created_at BIGINT NOT NULL,
+                expires_at BIGINT,
+                last_used_at BIGINT
+            )",
+            [],
+        )?;
+
+        // 异常检测记录，同一序列同一起点的同类异常只保存一条
+        conn.execute("CREATE SEQUENCE IF NOT EXISTS seq_anomaly_runs_id START 1", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS anomaly_runs (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_anomaly_runs_id'),
+                created_by VARCHAR NOT NULL,
+                created_at BIGINT NOT NULL,
+                start_time BIGINT,
+                end_time BIGINT,
+                series_analyzed INTEGER,
+                anomaly_count INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute("CREATE SEQUENCE IF NOT EXISTS seq_anomalies_id START 1", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS anomalies (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_anomalies_id'),
+                run_id INTEGER NOT NULL,
+                asset_name VARCHAR NOT NULL,
+                device_name VARCHAR NOT NULL,
+                target_name VARCHAR NOT NULL,
+                key_name VARCHAR NOT NULL,
+                anomaly_type VARCHAR NOT NULL,
+                start_time BIGINT NOT NULL,
+                end_time BIGINT,
+                baseline_value DOUBLE NOT NULL,
+                anomaly_value DOUBLE NOT NULL,
+                jump_magnitude DOUBLE NOT NULL,
+                confidence DOUBLE NOT NULL,
+                baseline_noise DOUBLE,
+                observed_noise DOUBLE,
+                suggested_operation VARCHAR,
+                status VARCHAR NOT NULL DEFAULT 'new',
+                reviewed_by VARCHAR,
+                reviewed_at BIGINT,
+                review_comment VARCHAR,
+                operation_id INTEGER,
+                created_at BIGINT NOT NULL,
+                updated_at BIGINT NOT NULL,
+                details VARCHAR,
+                peers VARCHAR
+            )",
+            [],
+        )?;
+
+        // 增量检测的序列状态
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS anomaly_series_state (
+                asset_name VARCHAR NOT NULL,
+                device_name VARCHAR NOT NULL,
+                target_name VARCHAR NOT NULL,
+                key_name VARCHAR NOT NULL,
+                fingerprint VARCHAR NOT NULL,
+                watermark BIGINT NOT NULL,
+                context VARCHAR NOT NULL,
+                detector_state VARCHAR NOT NULL,
+                updated_at BIGINT NOT NULL,
+                PRIMARY KEY (asset_name, device_name, target_name, key_name)
+            )",
+            [],
+        )?;
+
+        // 告警规则、告警事件和各规则在序列上的判断进度
+        conn.execute("CREATE SEQUENCE IF NOT EXISTS seq_alarm_rules_id START 1", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS alarm_rules (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_alarm_rules_id'),
+                name VARCHAR NOT NULL,
+                asset_name VARCHAR,
+                device_name VARCHAR,
+                target_name VARCHAR,
+                key_name VARCHAR NOT NULL,
+                metric VARCHAR NOT NULL,
+                direction VARCHAR NOT NULL,
+                levels VARCHAR NOT NULL,
+                hysteresis DOUBLE NOT NULL,
+                duration BIGINT NOT NULL,
+                rate_window BIGINT,
+                reference_time BIGINT,
+                enabled BOOLEAN NOT NULL,
+                created_by VARCHAR NOT NULL,
+                created_at BIGINT NOT NULL,
+                updated_at BIGINT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute("CREATE SEQUENCE IF NOT EXISTS seq_alarm_events_id START 1", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS alarm_events (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_alarm_events_id'),
+                rule_id INTEGER NOT NULL,
+                rule_name VARCHAR NOT NULL,
+                asset_name VARCHAR NOT NULL,
+                device_name VARCHAR NOT NULL,
+                target_name VARCHAR NOT NULL,
+                key_name VARCHAR NOT NULL,
+                level VARCHAR NOT NULL,
+                peak_level VARCHAR NOT NULL,
+                start_time BIGINT NOT NULL,
+                end_time BIGINT,
+                trigger_value DOUBLE NOT NULL,
+                peak_value DOUBLE NOT NULL,
+                last_value DOUBLE NOT NULL,
+                acknowledged_by VARCHAR,
+                acknowledged_at BIGINT,
+                ack_comment VARCHAR,
+                created_at BIGINT NOT NULL,
+                updated_at BIGINT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS alarm_series_state (
+                rule_id INTEGER NOT NULL,
+                asset_name VARCHAR NOT NULL,
+                device_name VARCHAR NOT NULL,
+                target_name VARCHAR NOT NULL,
+                key_name VARCHAR NOT NULL,
+                watermark BIGINT NOT NULL,
+                pending_since BIGINT,
+                reference_value DOUBLE,
+                PRIMARY KEY (rule_id, asset_name, device_name, target_name, key_name)
+            )",
+            [],
+        )?;
+
+        conn.execute("CREATE SEQUENCE IF NOT EXISTS seq_notification_outbox_id START 1", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS notification_outbox (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_notification_outbox_id'),
+                channel VARCHAR NOT NULL,
+                event VARCHAR NOT NULL,
+                payload VARCHAR NOT NULL,
+                status VARCHAR NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at BIGINT NOT NULL,
+                last_error VARCHAR,
+                created_at BIGINT NOT NULL,
+                sent_at BIGINT
+            )",
+            [],
+        )?;
+
+        conn.execute("CREATE SEQUENCE IF NOT EXISTS seq_derived_metrics_id START 1", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS derived_metrics (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_derived_metrics_id'),
+                name VARCHAR NOT NULL UNIQUE,
+                description VARCHAR,
+                expression VARCHAR NOT NULL,
+                inputs VARCHAR NOT NULL,
+                tolerance_ms BIGINT NOT NULL DEFAULT 0,
+                created_by VARCHAR NOT NULL,
+                created_at BIGINT NOT NULL,
+                updated_at BIGINT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute("CREATE SEQUENCE IF NOT EXISTS seq_baselines_id START 1", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS baselines (
+                id INTEGER PRIMARY KEY DEFAULT nextval('seq_baselines_id'),
+                name VARCHAR NOT NULL UNIQUE,
+                description VARCHAR,
+                reference_values VARCHAR NOT NULL,
+                created_by VARCHAR NOT NULL,
+                created_at BIGINT NOT NULL,
+                updated_at BIGINT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS saved_queries (
+                id VARCHAR PRIMARY KEY,
+                name VARCHAR NOT NULL,
+                description VARCHAR,
+                owner VARCHAR NOT NULL,
+                tags VARCHAR NOT NULL,
+                query VARCHAR NOT NULL,
+                baseline VARCHAR,
+                relative_start VARCHAR,
+                relative_end VARCHAR,
+                display VARCHAR,
+                created_at BIGINT NOT NULL,
+                updated_at BIGINT NOT NULL
+            )",
+            [],
+        )?;
+
+        // 暂时禁用索引创建，避免DuckDB断言错误
+        // 问题可能与在视图基础表上创建索引有关
+        println!("Skipping index creation to avoid DuckDB assertion error...");
+        
+        // 只创建数据操作表的索引（这个表是我们创建的，应该没问题）
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_operations_active 
+             ON data_operations(is_active)",
+            [],
+        ).ok();
+        
+        // 更新统计信息
+        conn.execute("ANALYZE data_operations", []).ok();
+
+        // 建表和加列先落盘：查询使用独立连接，重放其他连接未落盘的WAL会触发DuckDB内部错误
+        conn.execute_batch("CHECKPOINT")?;
+        
+        Ok(())
+    }
+
+    pub fn get_filter_options(&self, allowed_assets: Option<&[String]>) -> Result<FilterOptions> {
+        // 使用独立连接避免死锁
+        let conn = self.get_read_connection()?;
+
+        // 按用户的资产权限限制可选项
+        let scope = match allowed_assets {
+            Some(assets) => format!(" WHERE {}", asset_scope_clause(assets, |_| "?".to_string())),
+            None => String::new(),
+        };
+        let scope_params: Vec<&dyn duckdb::ToSql> = allowed_assets.unwrap_or(&[]).iter()
+            .map(|a| a as &dyn duckdb::ToSql)
+            .collect();
+        let distinct = |column: &str| -> Result<Vec<String>> {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT DISTINCT {} FROM a_d_t_telemetry{} ORDER BY {}", column, scope, column
+            ))?;
+            let values = stmt.query_map(scope_params.as_slice(), |row| {
+                row.get::<_, String>(0)
+            })?.collect::<DuckResult<Vec<_>>>()?;
+            Ok(values)
+        };
+
+        // 获取所有资产名称
+        let assets = distinct("asset_name")?;
+
+        // 获取所有设备名称 (使用d_name字段)
+        let devices = distinct("d_name")?;
+
+        // 获取所有标靶名称
+        let targets = distinct("target_name")?;
+
+        // 获取所有key_name，派生指标和原始数据类型一样可选
+        let mut key_names = distinct("key_name")?;
+        let mut stmt = conn.prepare("SELECT name FROM derived_metrics")?;
+        let derived = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<DuckResult<Vec<_>>>()?;
+        key_names.extend(derived);
+        key_names.sort();
+        key_names.dedup();
+
+        Ok(FilterOptions {
+            assets,
+            devices,
+            targets,
+            key_names,
+        })
+    }
+
+    pub fn get_devices_by_asset(&self, asset_name: &str) -> Result<Vec<String>> {
+        // 使用独立连接避免死锁
+        let conn = self.get_read_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT d_name FROM a_d_t_telemetry WHERE asset_name = ? ORDER BY d_name"
+        )?;
+        let devices: Vec<String> = stmt.query_map([asset_name], |row| {
+            row.get::<_, String>(0)
+        })?.collect::<DuckResult<Vec<_>>>()?;
+        Ok(devices)
+    }
+
+    pub fn get_targets_by_device(&self, asset_name: &str, device_name: &str) -> Result<Vec<String>> {
+        // 使用独立连接避免死锁
+        let conn = self.get_read_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT target_name FROM a_d_t_telemetry WHERE asset_name = ? AND d_name = ? ORDER BY target_name"
+        )?;
+        let targets: Vec<String> = stmt.query_map([asset_name, device_name], |row| {
+            row.get::<_, String>(0)
+        })?.collect::<DuckResult<Vec<_>>>()?;
+        Ok(targets)
+    }
+
+    // 批量查询数据（用于流式传输）- 使用独立的只读连接避免死锁
+    //
+    // 与 query_telemetry_data 使用同一个筛选查询，参考值、自定义过滤、每日时间段和采样都生效，
+    // 总数量由调用方按 limit 控制
+    pub fn query_telemetry_data_batch(&self, params: &QueryParams, offset: usize, limit: usize) -> Result<Vec<TelemetryData>> {
+        // 使用独立的只读连接，避免长时间持有主连接的锁
+        let conn = self.get_read_connection()?;
+
+        // 所有筛选值都通过参数绑定，避免拼接用户输入
+        let (source, reference_source) = self.telemetry_sources(&conn, params)?;
+        let (query, bind_params) = self.build_filtered_query(params, &source, &reference_source);
+        let query = self.apply_sampling_and_limit(&query, &QueryParams { limit: None, ..params.clone() });
+
+        // 添加排序和分页
+        let query = format!(
+            "SELECT CAST(ts AS BIGINT) AS ts, asset_name, d_name AS device_name, target_name, key_name, dbl_v
+             FROM ({}) filtered
+             WHERE dbl_v IS NOT NULL
+             ORDER BY ts DESC, asset_name, device_name, target_name, key_name LIMIT {} OFFSET {}",
+            query, limit, offset
+        );
+        
+        // 执行查询
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn duckdb::ToSql> = bind_params.iter().map(|p| p.as_ref()).collect();
+        let mut rows = stmt.query(params_refs.as_slice())?;
+        
+        let mut data = Vec::new();
+        while let Some(row) = rows.next()? {
+            let ts_millis: i64 = row.get(0)?;
+            let timestamp = DateTime::from_timestamp_millis(ts_millis)
                 .ok_or_else(|| anyhow::anyhow!("Invalid timestamp: {}", ts_millis))?;
             
             let value: Option<f64> = row.get(5)?;
@@ -376,812 +1393,3011 @@ impl DatabaseManager {
             }
         }
         
-        // 应用数据操作（如果有）
-        let active_operations = self.get_operations(true)?;
-        if !active_operations.is_empty() {
-            self.apply_operations_to_data(&mut data, &active_operations);
+        // 应用数据操作（如果有）
+        let active_operations = self.active_operations_for(params)?;
+        if !active_operations.is_empty() {
+            self.apply_operations_to_data(&mut data, &active_operations);
+        }
+        
+        Ok(data)
+    }
+    
+    pub fn query_telemetry_data(&self, params: &QueryParams) -> Result<TelemetryResponse> {
+        // 使用新的连接避免阻塞
+        let conn = self.get_read_connection()?;
+        
+        // 设置性能限制，避免查询过多数据  
+        let effective_limit = params.limit.unwrap_or(1000).min(50000);
+
+        // 如果需要异常值统计，先获取原始数据量
+        let original_count = if params.remove_outliers {
+            Some(self.get_filtered_count(params, false)?)
+        } else {
+            None
+        };
+
+        // 构建查询（包含异常值过滤）
+        let (source, reference_source) = self.telemetry_sources(&conn, params)?;
+        let (query, bind_params) = self.build_complete_query(params, &source, &reference_source);
+
+        // 执行查询
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = if bind_params.is_empty() {
+            stmt.query([])?
+        } else {
+            let params_refs: Vec<&dyn duckdb::ToSql> = bind_params.iter().map(|p| p.as_ref()).collect();
+            stmt.query(params_refs.as_slice())?
+        };
+
+        let mut data = Vec::with_capacity(effective_limit.min(10000));
+        let mut row_count = 0;
+        
+        // 流式处理数据 - 避免一次性加载所有数据
+        while let Some(row) = rows.next()? {
+            let ts_millis: i64 = row.get(0)?;
+            let timestamp = DateTime::from_timestamp_millis(ts_millis)
+                .ok_or_else(|| anyhow::anyhow!("Invalid timestamp: {}", ts_millis))?;
+
+            // 处理可能为NULL的dbl_v字段
+            let value: Option<f64> = row.get(5)?;
+            if let Some(val) = value {
+                data.push(TelemetryData {
+                    timestamp,
+                    asset_name: row.get(1)?,
+                    device_name: row.get(2)?,
+                    target_name: row.get(3)?,
+                    key_name: row.get(4)?,
+                    value: val,
+                });
+                
+                row_count += 1;
+                
+                // 达到限制时停止
+                if row_count >= effective_limit {
+                    break;
+                }
+            }
+        }
+        
+        // 提前释放查询资源
+        drop(rows);
+        drop(stmt);
+
+        // 获取并应用激活的数据操作
+        let active_operations = self.active_operations_for(params)?;
+        
+        // 只有存在激活操作时才应用
+        if !active_operations.is_empty() {
+            self.apply_operations_to_data(&mut data, &active_operations);
+        }
+
+        // 计算统计信息
+        let stats = self.calculate_stats(&data, params, original_count)?;
+
+        Ok(TelemetryResponse { data, stats })
+    }
+
+    /// 在DuckDB中按序列计算统计信息，筛选条件、参考值和数据操作与遥测查询相同，不采样也不限制数据量
+    pub fn query_statistics(&self, params: &QueryParams) -> Result<Vec<SeriesStatistics>> {
+        let conn = self.get_read_connection()?;
+        let (source, reference_source) = self.telemetry_sources(&conn, params)?;
+        let (query, bind_params) = self.build_filtered_query(params, &source, &reference_source);
+        let query = self.apply_operations_sql(&query, &self.active_operations_for(params)?);
+
+        let sql = format!(
+            "WITH data AS (
+                SELECT ts, asset_name, d_name, target_name, key_name, dbl_v FROM ({}) filtered WHERE dbl_v IS NOT NULL
+            ),
+            gaps AS (
+                SELECT asset_name, d_name, target_name, key_name,
+                       ts - LAG(ts) OVER (PARTITION BY asset_name, d_name, target_name, key_name ORDER BY ts) AS gap
+                FROM data
+            ),
+            intervals AS (
+                SELECT asset_name, d_name, target_name, key_name, CAST(FLOOR(MEDIAN(gap)) AS BIGINT) AS expected_interval
+                FROM gaps WHERE gap > 0
+                GROUP BY asset_name, d_name, target_name, key_name
+            ),
+            stats AS (
+                SELECT asset_name, d_name, target_name, key_name,
+                       COUNT(*) AS count,
+                       MIN(dbl_v) AS min_value, arg_min(ts, dbl_v) AS min_ts,
+                       MAX(dbl_v) AS max_value, arg_max(ts, dbl_v) AS max_ts,
+                       AVG(dbl_v) AS mean, STDDEV_SAMP(dbl_v) AS stddev,
+                       quantile_cont(dbl_v, 0.05) AS p5, quantile_cont(dbl_v, 0.25) AS p25,
+                       quantile_cont(dbl_v, 0.5) AS p50, quantile_cont(dbl_v, 0.75) AS p75,
+                       quantile_cont(dbl_v, 0.95) AS p95,
+                       arg_min(dbl_v, ts) AS first_value, MIN(ts) AS first_ts,
+                       arg_max(dbl_v, ts) AS last_value, MAX(ts) AS last_ts
+                FROM data
+                GROUP BY asset_name, d_name, target_name, key_name
+            )
+            SELECT s.*, i.expected_interval
+            FROM stats s
+            LEFT JOIN intervals i
+              ON i.asset_name = s.asset_name AND i.d_name = s.d_name AND i.target_name = s.target_name AND i.key_name = s.key_name
+            ORDER BY s.asset_name, s.d_name, s.target_name, s.key_name",
+            query
+        );
+
+        let mut stmt = conn.prepare(&sql)?;
+        let params_refs: Vec<&dyn duckdb::ToSql> = bind_params.iter().map(|p| p.as_ref()).collect();
+        let mut rows = stmt.query(params_refs.as_slice())?;
+
+        let mut statistics = Vec::new();
+        while let Some(row) = rows.next()? {
+            let count: i64 = row.get(4)?;
+            let first_ts: i64 = row.get(17)?;
+            let last_ts: i64 = row.get(19)?;
+            let first_value: f64 = row.get(16)?;
+            let last_value: f64 = row.get(18)?;
+            let expected_interval: Option<i64> = row.get(20)?;
+
+            // 完整率的范围为查询的时间范围，未指定的一端使用数据本身的边界
+            let expected_points = expected_interval.map(|interval| {
+                let start = params.start_time.map(|t| t.timestamp_millis()).unwrap_or(first_ts);
+                let end = params.end_time.map(|t| t.timestamp_millis()).unwrap_or(last_ts);
+                ((end - start).max(0) / interval) as usize + 1
+            });
+
+            statistics.push(SeriesStatistics {
+                asset_name: row.get(0)?,
+                device_name: row.get(1)?,
+                target_name: row.get(2)?,
+                key_name: row.get(3)?,
+                count: count as usize,
+                min: row.get(5)?,
+                min_time: millis_to_datetime(row.get(6)?)?,
+                max: row.get(7)?,
+                max_time: millis_to_datetime(row.get(8)?)?,
+                mean: row.get(9)?,
+                stddev: row.get(10)?,
+                p5: row.get(11)?,
+                p25: row.get(12)?,
+                p50: row.get(13)?,
+                p75: row.get(14)?,
+                p95: row.get(15)?,
+                first_value,
+                first_time: millis_to_datetime(first_ts)?,
+                last_value,
+                last_time: millis_to_datetime(last_ts)?,
+                net_change: last_value - first_value,
+                expected_interval_ms: expected_interval,
+                expected_points,
+                completeness: expected_points.map(|expected| (count as f64 / expected as f64 * 100.0).min(100.0)),
+            });
+        }
+        Ok(statistics)
+    }
+
+    /// 一次扫描读取所有匹配序列的数据，按序列分组并按时间升序排列，已应用激活的数据操作
+    ///
+    /// 每个序列最多保留最近的 `max_points_per_series` 个点
+    pub fn query_series_data(&self, params: &QueryParams, max_points_per_series: usize) -> Result<Vec<SeriesData>> {
+        let conn = self.get_read_connection()?;
+
+        let mut conditions = vec!["dbl_v IS NOT NULL".to_string()];
+        let mut bind_params: Vec<Box<dyn duckdb::ToSql>> = Vec::new();
+        self.add_basic_conditions(&mut conditions, &mut bind_params, params);
+
+        let query = format!(
+            "SELECT ts, asset_name, d_name, target_name, key_name, dbl_v
+             FROM {}
+             WHERE {}
+             QUALIFY ROW_NUMBER() OVER (PARTITION BY asset_name, d_name, target_name, key_name ORDER BY ts DESC) <= {}
+             ORDER BY asset_name, d_name, target_name, key_name, ts",
+            self.telemetry_source(&conn, params)?,
+            conditions.join(" AND "),
+            max_points_per_series
+        );
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn duckdb::ToSql> = bind_params.iter().map(|p| p.as_ref()).collect();
+        let mut rows = stmt.query(params_refs.as_slice())?;
+        let mut series = read_series(&mut rows)?;
+        drop(rows);
+        drop(stmt);
+
+        self.apply_series_operations(&mut series)?;
+        Ok(series)
+    }
+
+    /// 按时间升序读取各序列从自己的起点开始的一页数据，每个序列最多 `page_size` 个点
+    ///
+    /// `since` 中的序列读取不早于各自起点的数据；`include_others` 为 true 时其余匹配的序列从头读取，
+    /// 否则只读取 `since` 中的序列。返回整页的序列还有更多数据，调用方以新的起点继续读取
+    pub fn query_series_from(
+        &self,
+        params: &QueryParams,
+        since: &[(SeriesKey, DateTime<Utc>)],
+        include_others: bool,
+        page_size: usize,
+    ) -> Result<Vec<SeriesData>> {
+        if since.is_empty() && !include_others {
+            return Ok(Vec::new());
+        }
+        let conn = self.get_read_connection()?;
+
+        let mut conditions = vec!["dbl_v IS NOT NULL".to_string()];
+        let mut basic_params: Vec<Box<dyn duckdb::ToSql>> = Vec::new();
+        self.add_basic_conditions(&mut conditions, &mut basic_params, params);
+        let base = format!(
+            "SELECT ts, asset_name, d_name, target_name, key_name, dbl_v FROM {} WHERE {}",
+            self.telemetry_source(&conn, params)?,
+            conditions.join(" AND ")
+        );
+
+        // 各序列的起点放在VALUES中与数据连接，占位符在基础条件之前
+        let mut bind_params: Vec<Box<dyn duckdb::ToSql>> = Vec::new();
+        let query = if since.is_empty() {
+            format!(
+                "SELECT ts, asset_name, d_name, target_name, key_name, dbl_v FROM ({}) t
+                 QUALIFY ROW_NUMBER() OVER (PARTITION BY asset_name, d_name, target_name, key_name ORDER BY ts) <= {}
+                 ORDER BY asset_name, d_name, target_name, key_name, ts",
+                base, page_size
+            )
+        } else {
+            for (key, start) in since {
+                bind_params.push(Box::new(key.asset_name.clone()));
+                bind_params.push(Box::new(key.device_name.clone()));
+                bind_params.push(Box::new(key.target_name.clone()));
+                bind_params.push(Box::new(key.key_name.clone()));
+                bind_params.push(Box::new(start.timestamp_millis()));
+            }
+            let marks = vec!["(?::VARCHAR, ?::VARCHAR, ?::VARCHAR, ?::VARCHAR, ?::BIGINT)"; since.len()].join(", ");
+            format!(
+                "WITH marks(asset_name, d_name, target_name, key_name, since) AS (VALUES {})
+                 SELECT t.ts, t.asset_name, t.d_name, t.target_name, t.key_name, t.dbl_v
+                 FROM ({}) t
+                 {} JOIN marks m
+                   ON t.asset_name = m.asset_name AND t.d_name = m.d_name
+                  AND t.target_name = m.target_name AND t.key_name = m.key_name
+                 WHERE m.since IS NULL OR t.ts >= m.since
+                 QUALIFY ROW_NUMBER() OVER (PARTITION BY t.asset_name, t.d_name, t.target_name, t.key_name ORDER BY t.ts) <= {}
+                 ORDER BY t.asset_name, t.d_name, t.target_name, t.key_name, t.ts",
+                marks,
+                base,
+                if include_others { "LEFT" } else { "INNER" },
+                page_size
+            )
+        };
+        bind_params.extend(basic_params);
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn duckdb::ToSql> = bind_params.iter().map(|p| p.as_ref()).collect();
+        let mut rows = stmt.query(params_refs.as_slice())?;
+        let mut series = read_series(&mut rows)?;
+        drop(rows);
+        drop(stmt);
+
+        self.apply_series_operations(&mut series)?;
+        Ok(series)
+    }
+
+    // 对按序列分组的数据应用启用的数据操作
+    fn apply_series_operations(&self, series: &mut [SeriesData]) -> Result<()> {
+        let active_operations = self.get_operations(true)?;
+        if active_operations.is_empty() {
+            return Ok(());
+        }
+        for s in series {
+            let relevant: Vec<DataOperation> = active_operations.iter()
+                .filter(|op| op.target_name == s.key.target_name && op.key_name == s.key.key_name)
+                .cloned()
+                .collect();
+            if !relevant.is_empty() {
+                self.apply_operations_to_data(&mut s.data, &relevant);
+            }
+        }
+        Ok(())
+    }
+
+    /// 按遥测查询的完整流程（筛选、异常值过滤、参考值、采样和数据操作）读取数据，按序列分组并按时间升序排列
+    ///
+    /// limit不生效，每个序列最多保留最近的 `max_points_per_series` 个点
+    pub fn query_filtered_series(&self, params: &QueryParams, max_points_per_series: usize) -> Result<Vec<SeriesData>> {
+        let conn = self.get_read_connection()?;
+        let (source, reference_source) = self.telemetry_sources(&conn, params)?;
+        let (query, bind_params) = self.build_filtered_query(params, &source, &reference_source);
+        let query = self.apply_sampling_and_limit(&query, &QueryParams { limit: None, ..params.clone() });
+
+        let query = format!(
+            "SELECT CAST(ts AS BIGINT) AS ts, asset_name, d_name, target_name, key_name, dbl_v
+             FROM ({}) filtered
+             WHERE dbl_v IS NOT NULL
+             QUALIFY ROW_NUMBER() OVER (PARTITION BY asset_name, d_name, target_name, key_name ORDER BY ts DESC) <= {}
+             ORDER BY asset_name, d_name, target_name, key_name, ts",
+            query, max_points_per_series
+        );
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn duckdb::ToSql> = bind_params.iter().map(|p| p.as_ref()).collect();
+        let mut rows = stmt.query(params_refs.as_slice())?;
+        let mut series = read_series(&mut rows)?;
+        drop(rows);
+        drop(stmt);
+
+        let active_operations = self.active_operations_for(params)?;
+        if !active_operations.is_empty() {
+            for s in &mut series {
+                self.apply_operations_to_data(&mut s.data, &active_operations);
+            }
+        }
+
+        Ok(series)
+    }
+
+    fn build_complete_query(&self, params: &QueryParams, source: &str, reference_source: &str) -> (String, Vec<Box<dyn duckdb::ToSql>>) {
+        let (query, bind_params) = self.build_filtered_query(params, source, reference_source);
+        (self.apply_sampling_and_limit(&query, params), bind_params)
+    }
+
+    /// 应用全部筛选条件、参考值和每日时间段过滤，不采样也不限制数据量
+    fn build_filtered_query(&self, params: &QueryParams, source: &str, reference_source: &str) -> (String, Vec<Box<dyn duckdb::ToSql>>) {
+        let mut bind_params: Vec<Box<dyn duckdb::ToSql>> = Vec::new();
+
+        let mut query = if params.remove_outliers {
+            // 异常值过滤查询已经包含了所有筛选条件
+            let mut query = self.build_outlier_filtered_query_with_conditions(params, source);
+
+            // 添加参数绑定（顺序要与查询中的?占位符一致）
+            self.add_basic_params(&mut bind_params, params);
+
+            // 添加自定义过滤条件
+            if let Some(custom_filter) = &params.custom_filter {
+                let (custom_conditions, custom_params) = self.build_custom_filter_conditions(custom_filter);
+                if !custom_conditions.is_empty() {
+                    query = format!("SELECT * FROM ({}) filtered WHERE {}", query, custom_conditions.join(" AND "));
+                    bind_params.extend(custom_params);
+                }
+            }
+            query
+        } else {
+            // 普通查询
+            let mut query = format!("SELECT ts, asset_name, d_name, target_name, key_name, dbl_v FROM {}", source);
+            let mut where_conditions = Vec::new();
+
+            // 添加基础筛选条件
+            self.add_basic_conditions(&mut where_conditions, &mut bind_params, params);
+
+            // 添加自定义过滤条件
+            if let Some(custom_filter) = &params.custom_filter {
+                let (custom_conditions, custom_params) = self.build_custom_filter_conditions(custom_filter);
+                where_conditions.extend(custom_conditions);
+                bind_params.extend(custom_params);
+            }
+
+            if !where_conditions.is_empty() {
+                query.push_str(&format!(" WHERE {}", where_conditions.join(" AND ")));
+            }
+            query
+        };
+
+        // 如果设置了参考值，应用参考值减法
+        if let Some(ref_values) = &params.reference_values {
+            query = self.apply_reference_values(&query, ref_values, reference_source);
+        }
+
+        // 如果设置了每日时间段过滤，应用时间段过滤
+        if let Some(time_filter) = &params.time_of_day_filter {
+            query = self.apply_time_of_day_filter(&query, time_filter);
+        }
+
+        (query, bind_params)
+    }
+
+    fn add_basic_params(&self, bind_params: &mut Vec<Box<dyn duckdb::ToSql>>, params: &QueryParams) {
+        if let Some(asset) = &params.asset_name {
+            bind_params.push(Box::new(asset.clone()));
+        }
+        for asset in params.allowed_assets.iter().flatten() {
+            bind_params.push(Box::new(asset.clone()));
+        }
+        if let Some(device) = &params.device_name {
+            bind_params.push(Box::new(device.clone()));
+        }
+        for target in &params.target_names {
+            bind_params.push(Box::new(target.clone()));
+        }
+        for key_name in &params.key_names {
+            bind_params.push(Box::new(key_name.clone()));
+        }
+        if let Some(start_time) = &params.start_time {
+            bind_params.push(Box::new(start_time.timestamp_millis()));
+        }
+        if let Some(end_time) = &params.end_time {
+            bind_params.push(Box::new(end_time.timestamp_millis()));
+        }
+    }
+
+    fn add_basic_conditions(&self, conditions: &mut Vec<String>, bind_params: &mut Vec<Box<dyn duckdb::ToSql>>, params: &QueryParams) {
+        if let Some(asset) = &params.asset_name {
+            conditions.push("asset_name = ?".to_string());
+            bind_params.push(Box::new(asset.clone()));
+        }
+        if let Some(assets) = &params.allowed_assets {
+            conditions.push(asset_scope_clause(assets, |_| "?".to_string()));
+            for asset in assets {
+                bind_params.push(Box::new(asset.clone()));
+            }
+        }
+        if let Some(device) = &params.device_name {
+            conditions.push("d_name = ?".to_string());
+            bind_params.push(Box::new(device.clone()));
+        }
+        if !params.target_names.is_empty() {
+            let placeholders = params.target_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            conditions.push(format!("target_name IN ({})", placeholders));
+            for target in &params.target_names {
+                bind_params.push(Box::new(target.clone()));
+            }
+        }
+        if !params.key_names.is_empty() {
+            let placeholders = params.key_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            conditions.push(format!("key_name IN ({})", placeholders));
+            for key_name in &params.key_names {
+                bind_params.push(Box::new(key_name.clone()));
+            }
+        }
+        if let Some(start_time) = &params.start_time {
+            conditions.push("ts >= ?".to_string());
+            bind_params.push(Box::new(start_time.timestamp_millis()));
+        }
+        if let Some(end_time) = &params.end_time {
+            conditions.push("ts <= ?".to_string());
+            bind_params.push(Box::new(end_time.timestamp_millis()));
+        }
+    }
+
+    fn build_custom_filter_conditions(&self, custom_filter: &CustomFilter) -> (Vec<String>, Vec<Box<dyn duckdb::ToSql>>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+
+        if let Some(min_val) = custom_filter.min_value {
+            conditions.push("dbl_v >= ?".to_string());
+            params.push(Box::new(min_val) as Box<dyn duckdb::ToSql>);
+        }
+
+        if let Some(max_val) = custom_filter.max_value {
+            conditions.push("dbl_v <= ?".to_string());
+            params.push(Box::new(max_val) as Box<dyn duckdb::ToSql>);
+        }
+
+        if !custom_filter.exclude_values.is_empty() {
+            let placeholders = custom_filter.exclude_values.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            conditions.push(format!("dbl_v NOT IN ({})", placeholders));
+            for val in &custom_filter.exclude_values {
+                params.push(Box::new(*val) as Box<dyn duckdb::ToSql>);
+            }
+        }
+
+        (conditions, params)
+    }
+
+    fn calculate_stats(&self, data: &[TelemetryData], params: &QueryParams, original_count: Option<usize>) -> Result<DataStats> {
+        let total_points = data.len();
+
+        // 计算唯一标靶数量
+        let mut targets = std::collections::HashSet::new();
+        for item in data {
+            targets.insert(&item.target_name);
+        }
+        let target_count = targets.len();
+
+        // 计算时间范围
+        let time_range = if !data.is_empty() {
+            let min_time = data.iter().map(|d| d.timestamp).min().unwrap();
+            let max_time = data.iter().map(|d| d.timestamp).max().unwrap();
+            Some((min_time, max_time))
+        } else {
+            None
+        };
+
+        // 如果启用了异常值过滤，计算移除的异常值数量
+        let (outliers_removed, outlier_method) = if params.remove_outliers {
+            if let Some(orig_count) = original_count {
+                let removed = orig_count.saturating_sub(total_points);
+                (Some(removed), Some(params.outlier_method.clone()))
+            } else {
+                (Some(0), Some(params.outlier_method.clone()))
+            }
+        } else {
+            (None, None)
+        };
+
+        Ok(DataStats {
+            total_points,
+            target_count,
+            time_range,
+            outliers_removed,
+            outlier_method,
+        })
+    }
+
+    fn get_filtered_count(&self, params: &QueryParams, _include_outlier_filter: bool) -> Result<usize> {
+        // 使用独立连接避免死锁
+        let conn = self.get_read_connection()?;
+
+        // 简化计数查询 - 直接计算基础筛选条件的数量，不进行复杂的异常值计算
+        let base_query = format!("SELECT COUNT(*) FROM {} WHERE 1=1", self.telemetry_source(&conn, params)?);
+
+        let mut query = base_query;
+        let mut bind_params: Vec<Box<dyn duckdb::ToSql>> = Vec::new();
+
+        // 添加相同的过滤条件（除了异常值过滤）
+        if let Some(asset) = &params.asset_name {
+            query.push_str(" AND asset_name = ?");
+            bind_params.push(Box::new(asset.clone()));
+        }
+
+        if let Some(assets) = &params.allowed_assets {
+            query.push_str(&format!(" AND {}", asset_scope_clause(assets, |_| "?".to_string())));
+            for asset in assets {
+                bind_params.push(Box::new(asset.clone()));
+            }
+        }
+
+        if let Some(device) = &params.device_name {
+            query.push_str(" AND d_name = ?");
+            bind_params.push(Box::new(device.clone()));
+        }
+
+        if !params.target_names.is_empty() {
+            let placeholders = params.target_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            query.push_str(&format!(" AND target_name IN ({})", placeholders));
+            for target in &params.target_names {
+                bind_params.push(Box::new(target.clone()));
+            }
+        }
+
+        if !params.key_names.is_empty() {
+            let placeholders = params.key_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            query.push_str(&format!(" AND key_name IN ({})", placeholders));
+            for key_name in &params.key_names {
+                bind_params.push(Box::new(key_name.clone()));
+            }
+        }
+
+        if let Some(start_time) = &params.start_time {
+            let start_ts = start_time.timestamp_millis();
+            query.push_str(" AND ts >= ?");
+            bind_params.push(Box::new(start_ts));
+        }
+
+        if let Some(end_time) = &params.end_time {
+            let end_ts = end_time.timestamp_millis();
+            query.push_str(" AND ts <= ?");
+            bind_params.push(Box::new(end_ts));
+        }
+
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = if bind_params.is_empty() {
+            stmt.query([])?
+        } else {
+            let params_refs: Vec<&dyn duckdb::ToSql> = bind_params.iter().map(|p| p.as_ref()).collect();
+            stmt.query(params_refs.as_slice())?
+        };
+
+        if let Some(row) = rows.next()? {
+            let count: i64 = row.get(0)?;
+            Ok(count as usize)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn build_outlier_filtered_query_with_conditions(&self, params: &QueryParams, source: &str) -> String {
+        // 构建基础筛选条件，用于优化统计计算范围
+        let mut base_conditions = Vec::new();
+
+        if params.asset_name.is_some() {
+            base_conditions.push("asset_name = ?".to_string());
+        }
+        if let Some(assets) = &params.allowed_assets {
+            base_conditions.push(asset_scope_clause(assets, |_| "?".to_string()));
+        }
+        if params.device_name.is_some() {
+            base_conditions.push("d_name = ?".to_string());
+        }
+        if !params.target_names.is_empty() {
+            let placeholders = params.target_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            base_conditions.push(format!("target_name IN ({})", placeholders));
+        }
+        if !params.key_names.is_empty() {
+            let placeholders = params.key_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            base_conditions.push(format!("key_name IN ({})", placeholders));
+        }
+        if params.start_time.is_some() {
+            base_conditions.push("ts >= ?".to_string());
+        }
+        if params.end_time.is_some() {
+            base_conditions.push("ts <= ?".to_string());
+        }
+
+        let where_clause = if base_conditions.is_empty() {
+            "".to_string()
+        } else {
+            format!("WHERE {}", base_conditions.join(" AND "))
+        };
+
+        match params.outlier_method.as_str() {
+            "zscore" => {
+                // 优化的Z-Score方法：先筛选再计算统计值
+                format!(
+                    "WITH base_data AS (
+                        SELECT ts, asset_name, d_name, target_name, key_name, dbl_v
+                        FROM {}
+                        {}
+                    ),
+                    stats AS (
+                        SELECT
+                            AVG(dbl_v) as mean_val,
+                            STDDEV(dbl_v) as std_val
+                        FROM base_data
+                    )
+                    SELECT b.ts, b.asset_name, b.d_name, b.target_name, b.key_name, b.dbl_v
+                    FROM base_data b, stats s
+                    WHERE ABS(b.dbl_v - s.mean_val) / NULLIF(s.std_val, 0) <= 3.0",
+                    source, where_clause
+                )
+            },
+            _ => {
+                // 优化的IQR方法：先筛选再计算四分位数
+                format!(
+                    "WITH base_data AS (
+                        SELECT ts, asset_name, d_name, target_name, key_name, dbl_v
+                        FROM {}
+                        {}
+                    ),
+                    quartiles AS (
+                        SELECT
+                            PERCENTILE_CONT(0.25) WITHIN GROUP (ORDER BY dbl_v) as q1,
+                            PERCENTILE_CONT(0.75) WITHIN GROUP (ORDER BY dbl_v) as q3
+                        FROM base_data
+                    ),
+                    bounds AS (
+                        SELECT
+                            q1 - 1.5 * (q3 - q1) as lower_bound,
+                            q3 + 1.5 * (q3 - q1) as upper_bound
+                        FROM quartiles
+                    )
+                    SELECT b.ts, b.asset_name, b.d_name, b.target_name, b.key_name, b.dbl_v
+                    FROM base_data b, bounds bo
+                    WHERE b.dbl_v >= bo.lower_bound AND b.dbl_v <= bo.upper_bound",
+                    source, where_clause
+                )
+            }
+        }
+    }
+
+    fn apply_sampling_and_limit(&self, base_query: &str, params: &QueryParams) -> String {
+        let mut query = base_query.to_string();
+        // 子查询中也可能出现ORDER BY（分位数、窗口函数），不能靠查找文本判断是否已排序
+        let mut ordered = false;
+
+        // 如果设置了采样配置，使用时间窗口采样（间隔必须为正，避免除零）
+        if let Some(sampling_config) = params.sampling_config.as_ref().filter(|c| c.interval_ms > 0) {
+            let interval_ms = sampling_config.interval_ms;
+
+            // 使用DuckDB原生聚合函数，统一处理所有采样方法
+            let (aggregation_func, _ts_func) = match sampling_config.method {
+                SamplingMethod::First => ("FIRST(dbl_v ORDER BY ts)", "MIN(ts)"),
+                SamplingMethod::Last => ("LAST(dbl_v ORDER BY ts)", "MAX(ts)"),
+                SamplingMethod::Avg => ("AVG(dbl_v)", "MIN(ts)"),
+                SamplingMethod::Max => ("MAX(dbl_v)", "MIN(ts)"),
+                SamplingMethod::Min => ("MIN(dbl_v)", "MIN(ts)"),
+            };
+
+            // 使用简化的时间分组策略，直接基于毫秒时间戳
+            let time_bucket_expr = format!(
+                "FLOOR(ts / {}) * {}",
+                interval_ms, interval_ms
+            );
+
+            query = format!(
+                "WITH time_bucketed AS (
+                    SELECT *,
+                           {} as time_bucket
+                    FROM ({}) base
+                    WHERE dbl_v IS NOT NULL
+                )
+                SELECT
+                    MIN(time_bucket) as ts,
+                    FIRST(asset_name ORDER BY ts) as asset_name,
+                    FIRST(d_name ORDER BY ts) as d_name,
+                    target_name,
+                    key_name,
+                    {} as dbl_v
+                FROM time_bucketed
+                GROUP BY target_name, key_name, time_bucket
+                HAVING COUNT(*) > 0
+                ORDER BY ts",
+                time_bucket_expr, query, aggregation_func
+            );
+            ordered = true;
+        }
+
+        // 确保有ORDER BY子句
+        if !ordered {
+            query.push_str(" ORDER BY ts");
+        }
+        
+        // 如果设置了限制，添加LIMIT子句（必须在ORDER BY之后），并限制上限
+        if let Some(limit) = params.limit {
+            query.push_str(&format!(" LIMIT {}", limit.min(MAX_QUERY_LIMIT)));
+        }
+
+        query
+    }
+
+    /// 每个序列减去参考值，参考值在SQL中按序列计算
+    ///
+    /// 参考值按标靶和数据类型指定，epoch和period_mean对同名标靶所在的每个资产和设备分别计算，
+    /// 从数据源的全部读数计算，不受查询时间范围限制；算不出参考值（没有读数）的序列不返回数据
+    fn apply_reference_values(&self, base_query: &str, reference_values: &[ReferenceValue], source: &str) -> String {
+        let quote = |s: &str| format!("'{}'", s.replace('\'', "''")); // 转义单引号
+        let mut seen = std::collections::HashSet::new();
+        let mut refs = Vec::new();
+
+        for ref_val in reference_values {
+            // 同一序列有多个参考值时以第一个为准
+            if !seen.insert((ref_val.target_name.as_str(), ref_val.key_name.as_str())) {
+                continue;
+            }
+            let (target, key) = (quote(&ref_val.target_name), quote(&ref_val.key_name));
+            // 固定值对所有资产和设备相同，asset_name为NULL时只按标靶和数据类型连接
+            let constant = |value: String| format!(
+                "SELECT CAST(NULL AS VARCHAR) AS asset_name, CAST(NULL AS VARCHAR) AS d_name,
+                        {} AS target_name, {} AS key_name, CAST({} AS DOUBLE) AS ref_v",
+                target, key, value
+            );
+            let per_device = |aggregate: String| format!(
+                "SELECT asset_name, d_name, {} AS target_name, {} AS key_name, CAST({} AS DOUBLE) AS ref_v
+                 FROM {} WHERE target_name = {} AND key_name = {} AND dbl_v IS NOT NULL
+                 GROUP BY asset_name, d_name",
+                target, key, aggregate, source, target, key
+            );
+            refs.push(match ref_val.mode {
+                ReferenceMode::Value => match ref_val.reference_value {
+                    Some(value) => constant(format!("{:?}", value)),
+                    None => constant("NULL".to_string()),
+                },
+                ReferenceMode::Epoch => match ref_val.reference_time {
+                    Some(time) => per_device(format!("arg_min(dbl_v, abs(ts - {}))", time.timestamp_millis())),
+                    None => constant("NULL".to_string()),
+                },
+                // 期间内没有读数的设备平均值为NULL，不返回数据
+                ReferenceMode::PeriodMean => match (ref_val.reference_start, ref_val.reference_end) {
+                    (Some(start), Some(end)) => per_device(format!(
+                        "AVG(dbl_v) FILTER (WHERE ts BETWEEN {} AND {})",
+                        start.timestamp_millis(), end.timestamp_millis()
+                    )),
+                    _ => constant("NULL".to_string()),
+                },
+            });
+        }
+
+        if refs.is_empty() {
+            return base_query.to_string();
+        }
+
+        // 将原查询包装，应用参考值减法
+        format!(
+            "SELECT
+                base.ts,
+                base.asset_name,
+                base.d_name,
+                base.target_name,
+                base.key_name,
+                CASE WHEN r.target_name IS NULL THEN base.dbl_v ELSE base.dbl_v - r.ref_v END as dbl_v
+            FROM ({}) base
+            LEFT JOIN ({}) r ON r.target_name = base.target_name AND r.key_name = base.key_name
+                AND (r.asset_name IS NULL OR (r.asset_name = base.asset_name AND r.d_name = base.d_name))
+            WHERE r.target_name IS NULL OR r.ref_v IS NOT NULL",
+            base_query, refs.join(" UNION ALL ")
+        )
+    }
+
+    fn apply_time_of_day_filter(&self, base_query: &str, time_filter: &TimeOfDayFilter) -> String {
+        if time_filter.time_ranges.is_empty() {
+            return base_query.to_string();
+        }
+
+        let mut conditions = Vec::new();
+
+        for range in &time_filter.time_ranges {
+            let start_minutes = range.start_hour as u32 * 60 + range.start_minute as u32;
+            let end_minutes = range.end_hour as u32 * 60 + range.end_minute as u32;
+
+            let condition = if start_minutes <= end_minutes {
+                // 正常情况：如 09:00 到 12:00
+                format!(
+                    "(EXTRACT(hour FROM to_timestamp(ts / 1000)) * 60 + EXTRACT(minute FROM to_timestamp(ts / 1000))) BETWEEN {} AND {}",
+                    start_minutes, end_minutes
+                )
+            } else {
+                // 跨午夜情况：如 22:00 到 06:00
+                format!(
+                    "((EXTRACT(hour FROM to_timestamp(ts / 1000)) * 60 + EXTRACT(minute FROM to_timestamp(ts / 1000))) >= {} OR (EXTRACT(hour FROM to_timestamp(ts / 1000)) * 60 + EXTRACT(minute FROM to_timestamp(ts / 1000))) <= {})",
+                    start_minutes, end_minutes
+                )
+            };
+
+            conditions.push(condition);
+        }
+
+        format!(
+            "SELECT ts, asset_name, d_name, target_name, key_name, dbl_v
+            FROM ({}) base
+            WHERE {}",
+            base_query,
+            conditions.join(" OR ")
+        )
+    }
+
+    pub fn create_operation(&self, operation: &DataOperation) -> Result<i64> {
+        // 使用新连接避免死锁
+        let conn = self.get_read_connection()?;
+        Self::insert_operation(&conn, operation)
+    }
+
+    // 在给定连接（或事务）中插入数据操作，返回新ID
+    fn insert_operation(conn: &Connection, operation: &DataOperation) -> Result<i64> {
+        let start_time_ms = operation.start_time.map(|t| t.timestamp_millis());
+        let end_time_ms = operation.end_time.map(|t| t.timestamp_millis());
+        let created_at_ms = operation.created_at.timestamp_millis();
+        let updated_at_ms = operation.updated_at.timestamp_millis();
+        
+        // 先获取下一个ID
+        let mut stmt = conn.prepare("SELECT nextval('seq_operations_id')")?;
+        let mut rows = stmt.query([])?;
+        let next_id: i64 = if let Some(row) = rows.next()? {
+            row.get(0)?
+        } else {
+            return Err(anyhow::anyhow!("Failed to get next ID"));
+        };
+        
+        conn.execute(
+            "INSERT INTO data_operations 
+            (id, name, description, target_name, key_name, operation_type, value, 
+             start_time, end_time, is_active, created_at, updated_at) 
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            duckdb::params![
+                &next_id,
+                &operation.name,
+                &operation.description,
+                &operation.target_name,
+                &operation.key_name,
+                operation.operation_type.as_str(),
+                &operation.value,
+                &start_time_ms,
+                &end_time_ms,
+                &operation.is_active,
+                &created_at_ms,
+                &updated_at_ms
+            ],
+        )?;
+        
+        Ok(next_id)
+    }
+
+    pub fn get_operations(&self, active_only: bool) -> Result<Vec<DataOperation>> {
+        // 使用独立连接避免死锁
+        let conn = self.get_read_connection()?;
+        
+        let query = if active_only {
+            "SELECT id, name, description, target_name, key_name, operation_type, 
+                    value, start_time, end_time, is_active, created_at, updated_at 
+             FROM data_operations WHERE is_active = true ORDER BY created_at DESC"
+        } else {
+            "SELECT id, name, description, target_name, key_name, operation_type, 
+                    value, start_time, end_time, is_active, created_at, updated_at 
+             FROM data_operations ORDER BY created_at DESC"
+        };
+        
+        let mut stmt = conn.prepare(query)?;
+        let mut rows = stmt.query([])?;
+        let mut operations = Vec::new();
+        
+        while let Some(row) = rows.next()? {
+            let operation_type_str: String = row.get(5)?;
+            let operation_type = OperationType::from_str(&operation_type_str)
+                .ok_or_else(|| anyhow::anyhow!("Invalid operation type: {}", operation_type_str))?;
+            
+            let start_time_ms: Option<i64> = row.get(7)?;
+            let end_time_ms: Option<i64> = row.get(8)?;
+            let created_at_ms: i64 = row.get(10)?;
+            let updated_at_ms: i64 = row.get(11)?;
+            
+            operations.push(DataOperation {
+                id: Some(row.get(0)?),
+                name: row.get(1)?,
+                description: row.get(2)?,
+                target_name: row.get(3)?,
+                key_name: row.get(4)?,
+                operation_type,
+                value: row.get(6)?,
+                start_time: start_time_ms.and_then(DateTime::from_timestamp_millis),
+                end_time: end_time_ms.and_then(DateTime::from_timestamp_millis),
+                is_active: row.get(9)?,
+                created_at: DateTime::from_timestamp_millis(created_at_ms)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid created_at timestamp"))?,
+                updated_at: DateTime::from_timestamp_millis(updated_at_ms)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid updated_at timestamp"))?,
+            });
+        }
+        
+        Ok(operations)
+    }
+
+    // 返回是否找到并更新了该操作
+    pub fn update_operation(&self, operation: &DataOperation) -> Result<bool> {
+        // 使用新连接避免死锁
+        let conn = self.get_read_connection()?;
+        
+        let id = operation.id.ok_or_else(|| anyhow::anyhow!("Operation ID is required for update"))?;
+        let start_time_ms = operation.start_time.map(|t| t.timestamp_millis());
+        let end_time_ms = operation.end_time.map(|t| t.timestamp_millis());
+        let updated_at_ms = Utc::now().timestamp_millis();
+        
+        let updated = conn.execute(
+            "UPDATE data_operations SET 
+             name = ?, description = ?, target_name = ?, key_name = ?, 
+             operation_type = ?, value = ?, start_time = ?, end_time = ?, 
+             is_active = ?, updated_at = ? 
+             WHERE id = ?",
+            duckdb::params![
+                &operation.name,
+                &operation.description,
+                &operation.target_name,
+                &operation.key_name,
+                operation.operation_type.as_str(),
+                &operation.value,
+                &start_time_ms,
+                &end_time_ms,
+                &operation.is_active,
+                &updated_at_ms,
+                &id
+            ],
+        )?;
+        
+        Ok(updated > 0)
+    }
+
+    // 返回是否找到并删除了该操作
+    pub fn delete_operation(&self, id: i64) -> Result<bool> {
+        // 使用新连接避免死锁
+        let conn = self.get_read_connection()?;
+        let deleted = conn.execute("DELETE FROM data_operations WHERE id = ?", [id])?;
+        Ok(deleted > 0)
+    }
+
+    // 返回是否找到并切换了该操作
+    pub fn toggle_operation(&self, id: i64) -> Result<bool> {
+        // 使用新连接避免死锁
+        let conn = self.get_read_connection()?;
+        let updated_at_ms = Utc::now().timestamp_millis();
+        
+        let updated = conn.execute(
+            "UPDATE data_operations SET 
+             is_active = NOT is_active, updated_at = ? 
+             WHERE id = ?",
+            duckdb::params![&updated_at_ms, &id],
+        )?;
+        
+        Ok(updated > 0)
+    }
+    
+    pub fn get_operation_target(&self, id: i64) -> Result<Option<String>> {
+        let conn = self.get_read_connection()?;
+        let mut stmt = conn.prepare("SELECT target_name FROM data_operations WHERE id = ?")?;
+        let mut rows = stmt.query([id])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    // 标靶是否属于给定资产范围内
+    pub fn target_in_assets(&self, target_name: &str, assets: &[String]) -> Result<bool> {
+        let conn = self.get_read_connection()?;
+        let query = format!(
+            "SELECT EXISTS (SELECT 1 FROM a_d_t_telemetry WHERE target_name = ? AND {})",
+            asset_scope_clause(assets, |_| "?".to_string())
+        );
+        let mut bind_params: Vec<&dyn duckdb::ToSql> = vec![&target_name];
+        bind_params.extend(assets.iter().map(|a| a as &dyn duckdb::ToSql));
+        let exists: bool = conn.query_row(&query, bind_params.as_slice(), |row| row.get(0))?;
+        Ok(exists)
+    }
+
+    /// 资产范围之外是否也有该名称的标靶
+    pub fn target_outside_assets(&self, target_name: &str, assets: &[String]) -> Result<bool> {
+        let conn = self.get_read_connection()?;
+        let query = format!(
+            "SELECT EXISTS (SELECT 1 FROM a_d_t_telemetry WHERE target_name = ? AND NOT ({}))",
+            asset_scope_clause(assets, |_| "?".to_string())
+        );
+        let mut bind_params: Vec<&dyn duckdb::ToSql> = vec![&target_name];
+        bind_params.extend(assets.iter().map(|a| a as &dyn duckdb::ToSql));
+        let exists: bool = conn.query_row(&query, bind_params.as_slice(), |row| row.get(0))?;
+        Ok(exists)
+    }
+
+    /// 资产范围内的全部标靶名称
+    pub fn targets_in_assets(&self, assets: &[String]) -> Result<HashSet<String>> {
+        let conn = self.get_read_connection()?;
+        let query = format!(
+            "SELECT DISTINCT target_name FROM a_d_t_telemetry WHERE {}",
+            asset_scope_clause(assets, |_| "?".to_string())
+        );
+        let bind_params: Vec<&dyn duckdb::ToSql> = assets.iter().map(|a| a as &dyn duckdb::ToSql).collect();
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = stmt.query(bind_params.as_slice())?;
+        let mut targets = HashSet::new();
+        while let Some(row) = rows.next()? {
+            targets.insert(row.get(0)?);
+        }
+        Ok(targets)
+    }
+
+    // 优化：只获取与查询相关的操作
+    pub fn get_relevant_operations(&self, target_names: &[String], key_names: &[String]) -> Result<Vec<DataOperation>> {
+        // 使用独立连接避免死锁
+        let conn = self.get_read_connection()?;
+        
+        let mut query = String::from(
+            "SELECT id, name, description, target_name, key_name, operation_type, 
+                    value, start_time, end_time, is_active, created_at, updated_at 
+             FROM data_operations 
+             WHERE is_active = true"
+        );
+        
+        let mut conditions = Vec::new();
+        
+        if !target_names.is_empty() {
+            let placeholders = target_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            conditions.push(format!("target_name IN ({})", placeholders));
+        }
+        
+        if !key_names.is_empty() {
+            let placeholders = key_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            conditions.push(format!("key_name IN ({})", placeholders));
+        }
+        
+        if !conditions.is_empty() {
+            query.push_str(" AND (");
+            query.push_str(&conditions.join(" OR "));
+            query.push(')');
         }
         
-        Ok(data)
-    }
-    
-    pub fn query_telemetry_data(&self, params: &QueryParams) -> Result<TelemetryResponse> {
-        // 使用新的连接避免阻塞
-        let conn = self.get_read_connection()?;
+        query.push_str(" ORDER BY created_at DESC");
         
-        // 设置性能限制，避免查询过多数据  
-        let effective_limit = params.limit.unwrap_or(1000).min(50000);
-
-        // 如果需要异常值统计，先获取原始数据量
-        let original_count = if params.remove_outliers {
-            Some(self.get_filtered_count(params, false)?)
-        } else {
-            None
-        };
-
-        // 构建查询（包含异常值过滤）
-        let (query, bind_params) = self.build_complete_query(params);
-
-        // 执行查询
         let mut stmt = conn.prepare(&query)?;
-        let mut rows = if bind_params.is_empty() {
+        
+        // 构建参数列表
+        let mut params: Vec<Box<dyn duckdb::ToSql>> = Vec::new();
+        for target in target_names {
+            params.push(Box::new(target.clone()));
+        }
+        for key in key_names {
+            params.push(Box::new(key.clone()));
+        }
+        
+        let mut rows = if params.is_empty() {
             stmt.query([])?
         } else {
-            let params_refs: Vec<&dyn duckdb::ToSql> = bind_params.iter().map(|p| p.as_ref()).collect();
+            let params_refs: Vec<&dyn duckdb::ToSql> = params.iter().map(|p| p.as_ref()).collect();
             stmt.query(params_refs.as_slice())?
         };
-
-        let mut data = Vec::with_capacity(effective_limit.min(10000));
-        let mut row_count = 0;
         
-        // 流式处理数据 - 避免一次性加载所有数据
+        let mut operations = Vec::new();
+        
         while let Some(row) = rows.next()? {
-            let ts_millis: i64 = row.get(0)?;
-            let timestamp = DateTime::from_timestamp_millis(ts_millis)
-                .ok_or_else(|| anyhow::anyhow!("Invalid timestamp: {}", ts_millis))?;
-
-            // 处理可能为NULL的dbl_v字段
-            let value: Option<f64> = row.get(5)?;
-            if let Some(val) = value {
-                data.push(TelemetryData {
-                    timestamp,
-                    asset_name: row.get(1)?,
-                    device_name: row.get(2)?,
-                    target_name: row.get(3)?,
-                    key_name: row.get(4)?,
-                    value: val,
-                });
-                
-                row_count += 1;
-                
-                // 达到限制时停止
-                if row_count >= effective_limit {
-                    break;
-                }
-            }
+            let operation_type_str: String = row.get(5)?;
+            let operation_type = OperationType::from_str(&operation_type_str)
+                .ok_or_else(|| anyhow::anyhow!("Invalid operation type: {}", operation_type_str))?;
+            
+            let start_time_ms: Option<i64> = row.get(7)?;
+            let end_time_ms: Option<i64> = row.get(8)?;
+            let created_at_ms: i64 = row.get(10)?;
+            let updated_at_ms: i64 = row.get(11)?;
+            
+            operations.push(DataOperation {
+                id: Some(row.get(0)?),
+                name: row.get(1)?,
+                description: row.get(2)?,
+                target_name: row.get(3)?,
+                key_name: row.get(4)?,
+                operation_type,
+                value: row.get(6)?,
+                start_time: start_time_ms.and_then(DateTime::from_timestamp_millis),
+                end_time: end_time_ms.and_then(DateTime::from_timestamp_millis),
+                is_active: row.get(9)?,
+                created_at: DateTime::from_timestamp_millis(created_at_ms)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid created_at timestamp"))?,
+                updated_at: DateTime::from_timestamp_millis(updated_at_ms)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid updated_at timestamp"))?,
+            });
         }
         
-        // 提前释放查询资源
-        drop(rows);
-        drop(stmt);
+        Ok(operations)
+    }
 
-        // 获取并应用激活的数据操作（优化：只获取相关的操作）
-        let active_operations = if !params.target_names.is_empty() || !params.key_names.is_empty() {
-            // 只获取与当前查询相关的操作
-            self.get_relevant_operations(&params.target_names, &params.key_names)?
+    // 查询用到的激活数据操作（优化：只获取相关的操作）
+    fn active_operations_for(&self, params: &QueryParams) -> Result<Vec<DataOperation>> {
+        if !params.target_names.is_empty() || !params.key_names.is_empty() {
+            self.get_relevant_operations(&params.target_names, &params.key_names)
         } else {
-            self.get_operations(true)?
-        };
-        
-        // 只有存在激活操作时才应用
-        if !active_operations.is_empty() {
-            self.apply_operations_to_data(&mut data, &active_operations);
+            self.get_operations(true)
         }
-
-        // 计算统计信息
-        let stats = self.calculate_stats(&data, params, original_count)?;
-
-        Ok(TelemetryResponse { data, stats })
     }
 
-    fn build_complete_query(&self, params: &QueryParams) -> (String, Vec<Box<dyn duckdb::ToSql>>) {
-        let mut bind_params: Vec<Box<dyn duckdb::ToSql>> = Vec::new();
+    /// 在SQL中按顺序应用数据操作，结果与 `apply_operations_to_data` 相同
+    ///
+    /// 同一序列的操作按时间范围的端点切分成区间，每个区间内生效的操作依次嵌套成一个算式，
+    /// 所有序列和区间合并成一个CASE，无论有多少操作都只增加一层投影
+    fn apply_operations_sql(&self, base_query: &str, operations: &[DataOperation]) -> String {
+        let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
 
-        if params.remove_outliers {
-            // 异常值过滤查询已经包含了所有筛选条件
-            let mut query = self.build_outlier_filtered_query_with_conditions(params);
+        // 按序列分组，保持操作的先后顺序；除数为0时不处理
+        let mut series: Vec<(&str, &str, Vec<&DataOperation>)> = Vec::new();
+        let effective = operations.iter()
+            .filter(|op| op.is_active && !(matches!(op.operation_type, OperationType::Divide) && op.value == 0.0));
+        for operation in effective {
+            match series.iter_mut().find(|(target, key, _)| *target == operation.target_name && *key == operation.key_name) {
+                Some((_, _, ops)) => ops.push(operation),
+                None => series.push((&operation.target_name, &operation.key_name, vec![operation])),
+            }
+        }
+        if series.is_empty() {
+            return base_query.to_string();
+        }
 
-            // 添加参数绑定（顺序要与查询中的?占位符一致）
-            self.add_basic_params(&mut bind_params, params);
+        let mut branches = Vec::new();
+        for (target, key, ops) in &series {
+            // 时间范围两端都包含，换成左闭右开的端点 [start, end + 1)
+            let range = |op: &DataOperation| {
+                (op.start_time.map(|t| t.timestamp_millis()), op.end_time.map(|t| t.timestamp_millis() + 1))
+            };
+            let mut bounds: Vec<i64> = ops.iter()
+                .flat_map(|op| {
+                    let (start, end) = range(op);
+                    [start, end]
+                })
+                .flatten()
+                .collect();
+            bounds.sort_unstable();
+            bounds.dedup();
 
-            // 添加自定义过滤条件
-            if let Some(custom_filter) = &params.custom_filter {
-                let (custom_conditions, custom_params) = self.build_custom_filter_conditions(custom_filter);
-                if !custom_conditions.is_empty() {
-                    query = format!("SELECT * FROM ({}) filtered WHERE {}", query, custom_conditions.join(" AND "));
-                    bind_params.extend(custom_params);
+            // 相邻端点之间的区间内生效的操作相同，两端为无界区间
+            for i in 0..=bounds.len() {
+                let lower = i.checked_sub(1).map(|j| bounds[j]);
+                let upper = bounds.get(i).copied();
+                let active = ops.iter().filter(|op| {
+                    let (start, end) = range(op);
+                    start.is_none_or(|start| lower.is_some_and(|lower| start <= lower))
+                        && end.is_none_or(|end| upper.is_some_and(|upper| upper <= end))
+                });
+                let expr = active.fold(String::new(), |expr, op| {
+                    let input = if expr.is_empty() { "dbl_v".to_string() } else { format!("({})", expr) };
+                    let value = op.value;
+                    match op.operation_type {
+                        OperationType::Add | OperationType::Offset => format!("{} + {:?}", input, value),
+                        OperationType::Subtract => format!("{} - {:?}", input, value),
+                        OperationType::Multiply => format!("{} * {:?}", input, value),
+                        OperationType::Divide => format!("{} / {:?}", input, value),
+                    }
+                });
+                if expr.is_empty() {
+                    continue;
                 }
-            }
 
-            let final_query = self.apply_sampling_and_limit(&query, params);
-            (final_query, bind_params)
-        } else {
-            // 普通查询
-            let mut query = "SELECT ts, asset_name, d_name, target_name, key_name, dbl_v FROM a_d_t_telemetry".to_string();
-            let mut where_conditions = Vec::new();
+                let mut condition = format!("target_name = {} AND key_name = {}", quote(target), quote(key));
+                if let Some(lower) = lower {
+                    condition.push_str(&format!(" AND ts >= {}", lower));
+                }
+                if let Some(upper) = upper {
+                    condition.push_str(&format!(" AND ts < {}", upper));
+                }
+                branches.push(format!("WHEN {} THEN CAST({} AS DOUBLE)", condition, expr));
+            }
+        }
 
-            // 添加基础筛选条件
-            self.add_basic_conditions(&mut where_conditions, &mut bind_params, params);
+        if branches.is_empty() {
+            return base_query.to_string();
+        }
+        format!(
+            "SELECT ts, asset_name, d_name, target_name, key_name,
+                    CASE {} ELSE dbl_v END AS dbl_v
+             FROM ({}) operations",
+            branches.join(" "), base_query
+        )
+    }
 
-            // 添加自定义过滤条件
-            if let Some(custom_filter) = &params.custom_filter {
-                let (custom_conditions, custom_params) = self.build_custom_filter_conditions(custom_filter);
-                where_conditions.extend(custom_conditions);
-                bind_params.extend(custom_params);
+    pub fn apply_operations_to_data(&self, data: &mut [TelemetryData], operations: &[DataOperation]) {
+        for operation in operations {
+            if !operation.is_active {
+                continue;
             }
-
-            if !where_conditions.is_empty() {
-                query.push_str(&format!(" WHERE {}", where_conditions.join(" AND ")));
+            
+            for item in data.iter_mut() {
+                // 检查是否匹配标靶和指标
+                if item.target_name != operation.target_name || item.key_name != operation.key_name {
+                    continue;
+                }
+                
+                // 检查时间范围
+                let in_time_range = match (operation.start_time, operation.end_time) {
+                    (Some(start), Some(end)) => item.timestamp >= start && item.timestamp <= end,
+                    (Some(start), None) => item.timestamp >= start,
+                    (None, Some(end)) => item.timestamp <= end,
+                    (None, None) => true,
+                };
+                
+                if !in_time_range {
+                    continue;
+                }
+                
+                // 应用操作
+                item.value = match operation.operation_type {
+                    OperationType::Add => item.value + operation.value,
+                    OperationType::Subtract => item.value - operation.value,
+                    OperationType::Multiply => item.value * operation.value,
+                    OperationType::Divide => {
+                        if operation.value != 0.0 {
+                            item.value / operation.value
+                        } else {
+                            item.value
+                        }
+                    },
+                    OperationType::Offset => item.value + operation.value, // 偏移等同于加法
+                };
             }
-
-            let final_query = self.apply_sampling_and_limit(&query, params);
-            (final_query, bind_params)
         }
     }
 
-    fn add_basic_params(&self, bind_params: &mut Vec<Box<dyn duckdb::ToSql>>, params: &QueryParams) {
-        if let Some(asset) = &params.asset_name {
-            bind_params.push(Box::new(asset.clone()));
-        }
-        if let Some(device) = &params.device_name {
-            bind_params.push(Box::new(device.clone()));
-        }
-        for target in &params.target_names {
-            bind_params.push(Box::new(target.clone()));
-        }
-        for key_name in &params.key_names {
-            bind_params.push(Box::new(key_name.clone()));
-        }
-        if let Some(start_time) = &params.start_time {
-            bind_params.push(Box::new(start_time.timestamp_millis()));
-        }
-        if let Some(end_time) = &params.end_time {
-            bind_params.push(Box::new(end_time.timestamp_millis()));
-        }
+    // ===== 用户与权限 =====
+
+    pub fn count_users(&self) -> Result<i64> {
+        let conn = self.get_read_connection()?;
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+        Ok(count)
     }
 
-    fn add_basic_conditions(&self, conditions: &mut Vec<String>, bind_params: &mut Vec<Box<dyn duckdb::ToSql>>, params: &QueryParams) {
-        if let Some(asset) = &params.asset_name {
-            conditions.push("asset_name = ?".to_string());
-            bind_params.push(Box::new(asset.clone()));
+    pub fn create_user(&self, username: &str, password_hash: &str, role: Role, assets: &[String]) -> Result<i64> {
+        let mut conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
+
+        // 用户和资产权限在同一事务中写入
+        let tx = conn.transaction()?;
+        let id: i64 = tx.query_row(
+            "INSERT INTO users (username, password_hash, role, is_active, created_at, updated_at)
+             VALUES (?, ?, ?, TRUE, ?, ?) RETURNING id",
+            duckdb::params![username, password_hash, role.as_str(), &now, &now],
+            |row| row.get(0),
+        )?;
+        for asset in assets {
+            tx.execute(
+                "INSERT INTO user_assets (user_id, asset_name) VALUES (?, ?)",
+                duckdb::params![&id, asset],
+            )?;
         }
-        if let Some(device) = &params.device_name {
-            conditions.push("d_name = ?".to_string());
-            bind_params.push(Box::new(device.clone()));
+        tx.commit()?;
+
+        Ok(id)
+    }
+
+    fn load_user_assets(conn: &Connection, user_id: i64) -> Result<Vec<String>> {
+        let mut stmt = conn.prepare("SELECT asset_name FROM user_assets WHERE user_id = ? ORDER BY asset_name")?;
+        let assets = stmt.query_map([user_id], |row| {
+            row.get::<_, String>(0)
+        })?.collect::<DuckResult<Vec<_>>>()?;
+        Ok(assets)
+    }
+
+    fn load_users(conn: &Connection, condition: &str, params: &[&dyn duckdb::ToSql]) -> Result<Vec<User>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, username, role, is_active, created_at, updated_at FROM users {} ORDER BY id",
+            condition
+        ))?;
+        let mut rows = stmt.query(params)?;
+
+        let mut users = Vec::new();
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let role_str: String = row.get(2)?;
+            users.push(User {
+                id,
+                username: row.get(1)?,
+                role: Role::from_str(&role_str)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid role: {}", role_str))?,
+                is_active: row.get(3)?,
+                assets: Self::load_user_assets(conn, id)?,
+                created_at: millis_to_datetime(row.get(4)?)?,
+                updated_at: millis_to_datetime(row.get(5)?)?,
+            });
         }
-        if !params.target_names.is_empty() {
-            let placeholders = params.target_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-            conditions.push(format!("target_name IN ({})", placeholders));
-            for target in &params.target_names {
-                bind_params.push(Box::new(target.clone()));
-            }
+        Ok(users)
+    }
+
+    pub fn list_users(&self) -> Result<Vec<User>> {
+        let conn = self.get_read_connection()?;
+        Self::load_users(&conn, "", &[])
+    }
+
+    // 登录用：返回用户及其密码哈希
+    pub fn get_user_credentials(&self, username: &str) -> Result<Option<(User, String)>> {
+        let conn = self.get_read_connection()?;
+        let user = match Self::load_users(&conn, "WHERE username = ?", &[&username])?.into_iter().next() {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        let password_hash: String = conn.query_row(
+            "SELECT password_hash FROM users WHERE id = ?",
+            [user.id],
+            |row| row.get(0),
+        )?;
+        Ok(Some((user, password_hash)))
+    }
+
+    // 返回是否找到并更新了该用户
+    pub fn update_user(&self, id: i64, update: &UserUpdate) -> Result<bool> {
+        let mut conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
+
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
+            "UPDATE users SET
+             password_hash = COALESCE(?, password_hash),
+             role = COALESCE(?, role),
+             is_active = COALESCE(?, is_active),
+             updated_at = ?
+             WHERE id = ?",
+            duckdb::params![
+                &update.password_hash,
+                update.role.as_ref().map(|r| r.as_str()),
+                &update.is_active,
+                &now,
+                &id
+            ],
+        )?;
+        if updated == 0 {
+            return Ok(false);
         }
-        if !params.key_names.is_empty() {
-            let placeholders = params.key_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-            conditions.push(format!("key_name IN ({})", placeholders));
-            for key_name in &params.key_names {
-                bind_params.push(Box::new(key_name.clone()));
+
+        if let Some(assets) = &update.assets {
+            tx.execute("DELETE FROM user_assets WHERE user_id = ?", [id])?;
+            for asset in assets {
+                tx.execute(
+                    "INSERT INTO user_assets (user_id, asset_name) VALUES (?, ?)",
+                    duckdb::params![&id, asset],
+                )?;
             }
         }
-        if let Some(start_time) = &params.start_time {
-            conditions.push("ts >= ?".to_string());
-            bind_params.push(Box::new(start_time.timestamp_millis()));
-        }
-        if let Some(end_time) = &params.end_time {
-            conditions.push("ts <= ?".to_string());
-            bind_params.push(Box::new(end_time.timestamp_millis()));
-        }
+
+        // 停用或修改密码后，已有会话全部失效
+        if update.is_active == Some(false) || update.password_hash.is_some() {
+            tx.execute("DELETE FROM sessions WHERE user_id = ?", [id])?;
+        }
+        tx.commit()?;
+
+        Ok(true)
     }
 
-    fn build_custom_filter_conditions(&self, custom_filter: &CustomFilter) -> (Vec<String>, Vec<Box<dyn duckdb::ToSql>>) {
-        let mut conditions = Vec::new();
-        let mut params = Vec::new();
+    // 返回是否找到并删除了该用户，同时清理其资产权限、会话和令牌
+    pub fn delete_user(&self, id: i64) -> Result<bool> {
+        let mut conn = self.get_read_connection()?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM user_assets WHERE user_id = ?", [id])?;
+        tx.execute("DELETE FROM sessions WHERE user_id = ?", [id])?;
+        tx.execute("DELETE FROM api_tokens WHERE user_id = ?", [id])?;
+        let deleted = tx.execute("DELETE FROM users WHERE id = ?", [id])?;
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
 
-        if let Some(min_val) = custom_filter.min_value {
-            conditions.push("dbl_v >= ?".to_string());
-            params.push(Box::new(min_val) as Box<dyn duckdb::ToSql>);
-        }
+    pub fn create_session(&self, token_hash: &str, user_id: i64, expires_at: DateTime<Utc>) -> Result<()> {
+        let conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
 
-        if let Some(max_val) = custom_filter.max_value {
-            conditions.push("dbl_v <= ?".to_string());
-            params.push(Box::new(max_val) as Box<dyn duckdb::ToSql>);
-        }
+        // 顺便清理过期会话
+        conn.execute("DELETE FROM sessions WHERE expires_at < ?", [now])?;
+        conn.execute(
+            "INSERT INTO sessions (token_hash, user_id, created_at, expires_at) VALUES (?, ?, ?, ?)",
+            duckdb::params![token_hash, &user_id, &now, &expires_at.timestamp_millis()],
+        )?;
+        Ok(())
+    }
 
-        if !custom_filter.exclude_values.is_empty() {
-            let placeholders = custom_filter.exclude_values.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-            conditions.push(format!("dbl_v NOT IN ({})", placeholders));
-            for val in &custom_filter.exclude_values {
-                params.push(Box::new(*val) as Box<dyn duckdb::ToSql>);
-            }
-        }
+    pub fn delete_session(&self, token_hash: &str) -> Result<()> {
+        let conn = self.get_read_connection()?;
+        conn.execute("DELETE FROM sessions WHERE token_hash = ?", [token_hash])?;
+        Ok(())
+    }
 
-        (conditions, params)
+    // 根据会话查找有效的启用用户
+    pub fn get_session_user(&self, token_hash: &str) -> Result<Option<User>> {
+        let conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
+        let users = Self::load_users(
+            &conn,
+            "WHERE is_active AND id IN (SELECT user_id FROM sessions WHERE token_hash = ? AND expires_at >= ?)",
+            &[&token_hash, &now],
+        )?;
+        Ok(users.into_iter().next())
     }
 
-    fn calculate_stats(&self, data: &[TelemetryData], params: &QueryParams, original_count: Option<usize>) -> Result<DataStats> {
-        let total_points = data.len();
+    pub fn create_api_token(&self, user_id: i64, name: &str, token_hash: &str, expires_at: Option<DateTime<Utc>>) -> Result<i64> {
+        let conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
+        let id: i64 = conn.query_row(
+            "INSERT INTO api_tokens (user_id, name, token_hash, created_at, expires_at)
+             VALUES (?, ?, ?, ?, ?) RETURNING id",
+            duckdb::params![&user_id, name, token_hash, &now, &expires_at.map(|t| t.timestamp_millis())],
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
 
-        // 计算唯一标靶数量
-        let mut targets = std::collections::HashSet::new();
-        for item in data {
-            targets.insert(&item.target_name);
+    // 根据API令牌查找有效的启用用户，并记录使用时间
+    pub fn get_token_user(&self, token_hash: &str) -> Result<Option<User>> {
+        let conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
+        let users = Self::load_users(
+            &conn,
+            "WHERE is_active AND id IN (SELECT user_id FROM api_tokens
+             WHERE token_hash = ? AND (expires_at IS NULL OR expires_at >= ?))",
+            &[&token_hash, &now],
+        )?;
+        if users.is_empty() {
+            return Ok(None);
         }
-        let target_count = targets.len();
+        conn.execute(
+            "UPDATE api_tokens SET last_used_at = ? WHERE token_hash = ?",
+            duckdb::params![&now, token_hash],
+        )?;
+        Ok(users.into_iter().next())
+    }
 
-        // 计算时间范围
-        let time_range = if !data.is_empty() {
-            let min_time = data.iter().map(|d| d.timestamp).min().unwrap();
-            let max_time = data.iter().map(|d| d.timestamp).max().unwrap();
-            Some((min_time, max_time))
-        } else {
-            None
-        };
+    // user_id为None时返回所有用户的令牌
+    pub fn list_api_tokens(&self, user_id: Option<i64>) -> Result<Vec<ApiToken>> {
+        let conn = self.get_read_connection()?;
+        let mut query = String::from(
+            "SELECT id, user_id, name, created_at, expires_at, last_used_at FROM api_tokens"
+        );
+        let mut bind_params: Vec<&dyn duckdb::ToSql> = Vec::new();
+        if let Some(user_id) = &user_id {
+            query.push_str(" WHERE user_id = ?");
+            bind_params.push(user_id);
+        }
+        query.push_str(" ORDER BY id");
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = stmt.query(bind_params.as_slice())?;
 
-        // 如果启用了异常值过滤，计算移除的异常值数量
-        let (outliers_removed, outlier_method) = if params.remove_outliers {
-            if let Some(orig_count) = original_count {
-                let removed = orig_count.saturating_sub(total_points);
-                (Some(removed), Some(params.outlier_method.clone()))
-            } else {
-                (Some(0), Some(params.outlier_method.clone()))
-            }
-        } else {
-            (None, None)
+        let mut tokens = Vec::new();
+        while let Some(row) = rows.next()? {
+            let expires_at: Option<i64> = row.get(4)?;
+            let last_used_at: Option<i64> = row.get(5)?;
+            tokens.push(ApiToken {
+                id: row.get(0)?,
+                user_id: row.get(1)?,
+                name: row.get(2)?,
+                created_at: millis_to_datetime(row.get(3)?)?,
+                expires_at: expires_at.and_then(DateTime::from_timestamp_millis),
+                last_used_at: last_used_at.and_then(DateTime::from_timestamp_millis),
+            });
+        }
+        Ok(tokens)
+    }
+
+    // 返回是否找到并删除了该令牌；user_id不为None时只能删除自己的令牌
+    pub fn delete_api_token(&self, id: i64, user_id: Option<i64>) -> Result<bool> {
+        let conn = self.get_read_connection()?;
+        let deleted = match user_id {
+            Some(user_id) => conn.execute(
+                "DELETE FROM api_tokens WHERE id = ? AND user_id = ?",
+                duckdb::params![&id, &user_id],
+            )?,
+            None => conn.execute("DELETE FROM api_tokens WHERE id = ?", [id])?,
         };
+        Ok(deleted > 0)
+    }
 
-        Ok(DataStats {
-            total_points,
-            target_count,
-            time_range,
-            outliers_removed,
-            outlier_method,
-        })
+    /// 保存一次检测的结果，并把保存后的异常ID写回 `anomalies`
+    ///
+    /// 已存在的待审核异常更新为最新的检测值，已审核的异常保持不变，避免重复检测产生重复记录
+    pub fn save_anomaly_run(
+        &self,
+        created_by: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        series_analyzed: Option<usize>,
+        anomalies: &mut [DetectedAnomaly],
+    ) -> Result<i64> {
+        self.save_run(created_by, start_time, end_time, series_analyzed, anomalies, &[])
     }
 
-    fn get_filtered_count(&self, params: &QueryParams, _include_outlier_filter: bool) -> Result<usize> {
-        // 使用独立连接避免死锁
-        let conn = self.get_read_connection()?;
+    /// 保存增量检测的结果，同一事务中更新各序列的状态
+    pub fn save_incremental_run(
+        &self,
+        created_by: &str,
+        series_analyzed: usize,
+        anomalies: &mut [DetectedAnomaly],
+        states: &[SeriesState],
+    ) -> Result<i64> {
+        self.save_run(created_by, None, None, Some(series_analyzed), anomalies, states)
+    }
 
-        // 简化计数查询 - 直接计算基础筛选条件的数量，不进行复杂的异常值计算
-        let base_query = "SELECT COUNT(*) FROM a_d_t_telemetry WHERE 1=1".to_string();
+    fn save_run(
+        &self,
+        created_by: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        series_analyzed: Option<usize>,
+        anomalies: &mut [DetectedAnomaly],
+        states: &[SeriesState],
+    ) -> Result<i64> {
+        let mut conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
 
-        let mut query = base_query;
-        let mut bind_params: Vec<Box<dyn duckdb::ToSql>> = Vec::new();
+        let tx = conn.transaction()?;
+        let run_id: i64 = tx.query_row(
+            "INSERT INTO anomaly_runs (created_by, created_at, start_time, end_time, series_analyzed, anomaly_count)
+             VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+            duckdb::params![
+                created_by,
+                &now,
+                start_time.map(|t| t.timestamp_millis()),
+                end_time.map(|t| t.timestamp_millis()),
+                series_analyzed.map(|n| n as i64),
+                anomalies.len() as i64
+            ],
+            |row| row.get(0),
+        )?;
 
-        // 添加相同的过滤条件（除了异常值过滤）
-        if let Some(asset) = &params.asset_name {
-            query.push_str(" AND asset_name = ?");
-            bind_params.push(Box::new(asset.clone()));
-        }
+        let mut find = tx.prepare(
+            "SELECT id, status FROM anomalies
+             WHERE asset_name = ? AND device_name = ? AND target_name = ? AND key_name = ?
+               AND anomaly_type = ? AND start_time = ?"
+        )?;
+        for anomaly in anomalies.iter_mut() {
+            let start_ms = anomaly.start_time.timestamp_millis();
+            let end_ms = anomaly.end_time.map(|t| t.timestamp_millis());
+            let suggested = anomaly.suggested_correction.as_ref()
+                .map(serde_json::to_string)
+                .transpose()?;
+            let peers = anomaly.peers.as_ref()
+                .map(serde_json::to_string)
+                .transpose()?;
 
-        if let Some(device) = &params.device_name {
-            query.push_str(" AND d_name = ?");
-            bind_params.push(Box::new(device.clone()));
-        }
+            let mut rows = find.query(duckdb::params![
+                &anomaly.asset_name,
+                &anomaly.device_name,
+                &anomaly.target_name,
+                &anomaly.key_name,
+                anomaly.anomaly_type.as_str(),
+                &start_ms
+            ])?;
+            let existing: Option<(i64, String)> = match rows.next()? {
+                Some(row) => Some((row.get(0)?, row.get(1)?)),
+                None => None,
+            };
 
-        if !params.target_names.is_empty() {
-            let placeholders = params.target_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-            query.push_str(&format!(" AND target_name IN ({})", placeholders));
-            for target in &params.target_names {
-                bind_params.push(Box::new(target.clone()));
-            }
+            let id = match existing {
+                Some((id, status)) => {
+                    if status == AnomalyStatus::New.as_str() {
+                        tx.execute(
+                            "UPDATE anomalies SET run_id = ?, end_time = ?, baseline_value = ?, anomaly_value = ?,
+                             jump_magnitude = ?, confidence = ?, baseline_noise = ?, observed_noise = ?,
+                             suggested_operation = ?, details = ?, peers = ?, updated_at = ?
+                             WHERE id = ?",
+                            duckdb::params![
+                                &run_id,
+                                &end_ms,
+                                &anomaly.baseline_value,
+                                &anomaly.anomaly_value,
+                                &anomaly.jump_magnitude,
+                                &anomaly.confidence,
+                                &anomaly.baseline_noise,
+                                &anomaly.observed_noise,
+                                &suggested,
+                                &anomaly.details,
+                                &peers,
+                                &now,
+                                &id
+                            ],
+                        )?;
+                    }
+                    id
+                }
+                None => tx.query_row(
+                    "INSERT INTO anomalies
+                     (run_id, asset_name, device_name, target_name, key_name, anomaly_type, start_time, end_time,
+                      baseline_value, anomaly_value, jump_magnitude, confidence, baseline_noise, observed_noise,
+                      suggested_operation, details, peers, status, created_at, updated_at)
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 'new', ?, ?) RETURNING id",
+                    duckdb::params![
+                        &run_id,
+                        &anomaly.asset_name,
+                        &anomaly.device_name,
+                        &anomaly.target_name,
+                        &anomaly.key_name,
+                        anomaly.anomaly_type.as_str(),
+                        &start_ms,
+                        &end_ms,
+                        &anomaly.baseline_value,
+                        &anomaly.anomaly_value,
+                        &anomaly.jump_magnitude,
+                        &anomaly.confidence,
+                        &anomaly.baseline_noise,
+                        &anomaly.observed_noise,
+                        &suggested,
+                        &anomaly.details,
+                        &peers,
+                        &now,
+                        &now
+                    ],
+                    |row| row.get(0),
+                )?,
+            };
+            anomaly.id = Some(id);
         }
+        drop(find);
 
-        if !params.key_names.is_empty() {
-            let placeholders = params.key_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-            query.push_str(&format!(" AND key_name IN ({})", placeholders));
-            for key_name in &params.key_names {
-                bind_params.push(Box::new(key_name.clone()));
-            }
+        for state in states {
+            tx.execute(
+                "INSERT OR REPLACE INTO anomaly_series_state
+                 (asset_name, device_name, target_name, key_name, fingerprint, watermark, context, detector_state, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                duckdb::params![
+                    &state.key.asset_name,
+                    &state.key.device_name,
+                    &state.key.target_name,
+                    &state.key.key_name,
+                    &state.fingerprint,
+                    state.watermark.timestamp_millis(),
+                    serde_json::to_string(&state.context)?,
+                    serde_json::to_string(&state.detectors)?,
+                    &now
+                ],
+            )?;
         }
+        tx.commit()?;
 
-        if let Some(start_time) = &params.start_time {
-            let start_ts = start_time.timestamp_millis();
-            query.push_str(" AND ts >= ?");
-            bind_params.push(Box::new(start_ts));
-        }
+        Ok(run_id)
+    }
 
-        if let Some(end_time) = &params.end_time {
-            let end_ts = end_time.timestamp_millis();
-            query.push_str(" AND ts <= ?");
-            bind_params.push(Box::new(end_ts));
+    /// 读取指定检测配置下保存的序列状态
+    pub fn load_series_states(&self, fingerprint: &str) -> Result<Vec<SeriesState>> {
+        let conn = self.get_read_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT asset_name, device_name, target_name, key_name, fingerprint, watermark, context, detector_state
+             FROM anomaly_series_state WHERE fingerprint = ?"
+        )?;
+        let mut rows = stmt.query([fingerprint])?;
+
+        let mut states = Vec::new();
+        while let Some(row) = rows.next()? {
+            let context: String = row.get(6)?;
+            let detectors: String = row.get(7)?;
+            states.push(SeriesState {
+                key: SeriesKey {
+                    asset_name: row.get(0)?,
+                    device_name: row.get(1)?,
+                    target_name: row.get(2)?,
+                    key_name: row.get(3)?,
+                },
+                fingerprint: row.get(4)?,
+                watermark: millis_to_datetime(row.get(5)?)?,
+                context: serde_json::from_str(&context)?,
+                detectors: serde_json::from_str(&detectors)?,
+            });
         }
+        Ok(states)
+    }
 
+    fn load_anomalies(conn: &Connection, condition: &str, params: &[&dyn duckdb::ToSql]) -> Result<Vec<StoredAnomaly>> {
+        let query = format!("SELECT {} FROM anomalies {}", ANOMALY_COLUMNS, condition);
         let mut stmt = conn.prepare(&query)?;
-        let mut rows = if bind_params.is_empty() {
-            stmt.query([])?
-        } else {
-            let params_refs: Vec<&dyn duckdb::ToSql> = bind_params.iter().map(|p| p.as_ref()).collect();
-            stmt.query(params_refs.as_slice())?
-        };
+        let mut rows = stmt.query(params)?;
 
-        if let Some(row) = rows.next()? {
-            let count: i64 = row.get(0)?;
-            Ok(count as usize)
-        } else {
-            Ok(0)
+        let mut anomalies = Vec::new();
+        while let Some(row) = rows.next()? {
+            let anomaly_type: String = row.get(6)?;
+            let end_time: Option<i64> = row.get(8)?;
+            let suggested: Option<String> = row.get(15)?;
+            let peers: Option<String> = row.get(24)?;
+            let status: String = row.get(16)?;
+            let reviewed_at: Option<i64> = row.get(18)?;
+            anomalies.push(StoredAnomaly {
+                anomaly: DetectedAnomaly {
+                    id: Some(row.get(0)?),
+                    asset_name: row.get(2)?,
+                    device_name: row.get(3)?,
+                    target_name: row.get(4)?,
+                    key_name: row.get(5)?,
+                    anomaly_type: AnomalyType::from_str(&anomaly_type)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid anomaly type: {}", anomaly_type))?,
+                    start_time: millis_to_datetime(row.get(7)?)?,
+                    end_time: end_time.and_then(DateTime::from_timestamp_millis),
+                    baseline_value: row.get(9)?,
+                    anomaly_value: row.get(10)?,
+                    jump_magnitude: row.get(11)?,
+                    confidence: row.get(12)?,
+                    suggested_correction: suggested.as_deref().map(serde_json::from_str).transpose()?,
+                    baseline_noise: row.get(13)?,
+                    observed_noise: row.get(14)?,
+                    details: row.get(23)?,
+                    peers: peers.as_deref().map(serde_json::from_str).transpose()?,
+                },
+                run_id: row.get(1)?,
+                status: AnomalyStatus::from_str(&status)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid anomaly status: {}", status))?,
+                reviewed_by: row.get(17)?,
+                reviewed_at: reviewed_at.and_then(DateTime::from_timestamp_millis),
+                review_comment: row.get(19)?,
+                operation_id: row.get(20)?,
+                created_at: millis_to_datetime(row.get(21)?)?,
+                updated_at: millis_to_datetime(row.get(22)?)?,
+            });
         }
+        Ok(anomalies)
     }
 
-    fn build_outlier_filtered_query_with_conditions(&self, params: &QueryParams) -> String {
-        // 构建基础筛选条件，用于优化统计计算范围
-        let mut base_conditions = Vec::new();
+    pub fn list_anomalies(&self, query: &AnomalyQuery) -> Result<Vec<StoredAnomaly>> {
+        let conn = self.get_read_connection()?;
+        let mut conditions = Vec::new();
+        let mut bind_params: Vec<&dyn duckdb::ToSql> = Vec::new();
 
-        if params.asset_name.is_some() {
-            base_conditions.push("asset_name = ?".to_string());
+        let status = query.status.map(|s| s.as_str().to_string());
+        if let Some(status) = &status {
+            conditions.push("status = ?".to_string());
+            bind_params.push(status);
         }
-        if params.device_name.is_some() {
-            base_conditions.push("d_name = ?".to_string());
+        if let Some(run_id) = &query.run_id {
+            conditions.push("run_id = ?".to_string());
+            bind_params.push(run_id);
         }
-        if !params.target_names.is_empty() {
-            let placeholders = params.target_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-            base_conditions.push(format!("target_name IN ({})", placeholders));
+        if let Some(target_name) = &query.target_name {
+            conditions.push("target_name = ?".to_string());
+            bind_params.push(target_name);
         }
-        if !params.key_names.is_empty() {
-            let placeholders = params.key_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-            base_conditions.push(format!("key_name IN ({})", placeholders));
+        if let Some(key_name) = &query.key_name {
+            conditions.push("key_name = ?".to_string());
+            bind_params.push(key_name);
         }
-        if params.start_time.is_some() {
-            base_conditions.push("ts >= ?".to_string());
+        if let Some(assets) = &query.allowed_assets {
+            conditions.push(asset_scope_clause(assets, |_| "?".to_string()));
+            bind_params.extend(assets.iter().map(|a| a as &dyn duckdb::ToSql));
         }
-        if params.end_time.is_some() {
-            base_conditions.push("ts <= ?".to_string());
+
+        let mut condition = String::new();
+        if !conditions.is_empty() {
+            condition = format!("WHERE {}", conditions.join(" AND "));
         }
+        condition.push_str(&format!(" ORDER BY start_time DESC, id DESC LIMIT {}", query.limit));
+        Self::load_anomalies(&conn, &condition, &bind_params)
+    }
 
-        let where_clause = if base_conditions.is_empty() {
-            "".to_string()
-        } else {
-            format!("WHERE {}", base_conditions.join(" AND "))
-        };
+    pub fn get_anomaly(&self, id: i64) -> Result<Option<StoredAnomaly>> {
+        let conn = self.get_read_connection()?;
+        let anomalies = Self::load_anomalies(&conn, "WHERE id = ?", &[&id])?;
+        Ok(anomalies.into_iter().next())
+    }
 
-        match params.outlier_method.as_str() {
-            "zscore" => {
-                // 优化的Z-Score方法：先筛选再计算统计值
-                format!(
-                    "WITH base_data AS (
-                        SELECT ts, asset_name, d_name, target_name, key_name, dbl_v
-                        FROM a_d_t_telemetry
-                        {}
-                    ),
-                    stats AS (
-                        SELECT
-                            AVG(dbl_v) as mean_val,
-                            STDDEV(dbl_v) as std_val
-                        FROM base_data
-                    )
-                    SELECT b.ts, b.asset_name, b.d_name, b.target_name, b.key_name, b.dbl_v
-                    FROM base_data b, stats s
-                    WHERE ABS(b.dbl_v - s.mean_val) / NULLIF(s.std_val, 0) <= 3.0",
-                    where_clause
-                )
-            },
-            _ => {
-                // 优化的IQR方法：先筛选再计算四分位数
+    /// 列出最近的检测批次
+    ///
+    /// 有资产限制时只返回用户自己执行的批次和包含其资产异常的批次，
+    /// 异常数只统计范围内的资产，他人批次的序列数不返回
+    pub fn list_anomaly_runs(&self, limit: usize, scope: Option<(&str, &[String])>) -> Result<Vec<AnomalyRun>> {
+        let conn = self.get_read_connection()?;
+        let mut bind_params: Vec<&dyn duckdb::ToSql> = Vec::new();
+        let query = match &scope {
+            None => format!(
+                "SELECT id, created_by, created_at, start_time, end_time, series_analyzed, anomaly_count
+                 FROM anomaly_runs ORDER BY id DESC LIMIT {}",
+                limit
+            ),
+            Some((username, assets)) => {
+                bind_params.extend(assets.iter().map(|a| a as &dyn duckdb::ToSql));
+                bind_params.push(username);
+                bind_params.push(username);
                 format!(
-                    "WITH base_data AS (
-                        SELECT ts, asset_name, d_name, target_name, key_name, dbl_v
-                        FROM a_d_t_telemetry
-                        {}
-                    ),
-                    quartiles AS (
-                        SELECT
-                            PERCENTILE_CONT(0.25) WITHIN GROUP (ORDER BY dbl_v) as q1,
-                            PERCENTILE_CONT(0.75) WITHIN GROUP (ORDER BY dbl_v) as q3
-                        FROM base_data
-                    ),
-                    bounds AS (
-                        SELECT
-                            q1 - 1.5 * (q3 - q1) as lower_bound,
-                            q3 + 1.5 * (q3 - q1) as upper_bound
-                        FROM quartiles
-                    )
-                    SELECT b.ts, b.asset_name, b.d_name, b.target_name, b.key_name, b.dbl_v
-                    FROM base_data b, bounds bo
-                    WHERE b.dbl_v >= bo.lower_bound AND b.dbl_v <= bo.upper_bound",
-                    where_clause
+                    "WITH scoped AS (
+                        SELECT run_id, COUNT(*) AS cnt FROM anomalies WHERE {} GROUP BY run_id
+                     )
+                     SELECT r.id, r.created_by, r.created_at, r.start_time, r.end_time,
+                            CASE WHEN r.created_by = ? THEN r.series_analyzed END,
+                            COALESCE(s.cnt, 0)
+                     FROM anomaly_runs r LEFT JOIN scoped s ON s.run_id = r.id
+                     WHERE r.created_by = ? OR s.cnt > 0
+                     ORDER BY r.id DESC LIMIT {}",
+                    asset_scope_clause(assets, |_| "?".to_string()),
+                    limit
                 )
             }
+        };
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = stmt.query(bind_params.as_slice())?;
+
+        let mut runs = Vec::new();
+        while let Some(row) = rows.next()? {
+            let start_time: Option<i64> = row.get(3)?;
+            let end_time: Option<i64> = row.get(4)?;
+            runs.push(AnomalyRun {
+                id: row.get(0)?,
+                created_by: row.get(1)?,
+                created_at: millis_to_datetime(row.get(2)?)?,
+                start_time: start_time.and_then(DateTime::from_timestamp_millis),
+                end_time: end_time.and_then(DateTime::from_timestamp_millis),
+                series_analyzed: row.get(5)?,
+                anomaly_count: row.get(6)?,
+            });
+        }
+        Ok(runs)
+    }
+
+    /// 审核异常；接受时在同一事务中创建建议的纠正操作（直接激活）并关联到异常
+    pub fn review_anomaly(
+        &self,
+        id: i64,
+        status: AnomalyStatus,
+        reviewer: &str,
+        comment: Option<&str>,
+    ) -> Result<ReviewOutcome> {
+        let mut conn = self.get_read_connection()?;
+        let now = Utc::now();
+
+        let tx = conn.transaction()?;
+        let Some(stored) = Self::load_anomalies(&tx, "WHERE id = ?", &[&id])?.into_iter().next() else {
+            return Ok(ReviewOutcome::NotFound);
+        };
+        if !stored.status.can_change_to(status) {
+            return Ok(ReviewOutcome::InvalidTransition(stored.status));
+        }
+
+        let operation_id = match (status, stored.anomaly.suggested_correction) {
+            (AnomalyStatus::Accepted, Some(mut operation)) => {
+                operation.is_active = true;
+                operation.created_at = now;
+                operation.updated_at = now;
+                Some(Self::insert_operation(&tx, &operation)?)
+            }
+            _ => stored.operation_id,
+        };
+
+        tx.execute(
+            "UPDATE anomalies SET status = ?, reviewed_by = ?, reviewed_at = ?, review_comment = ?,
+             operation_id = ?, updated_at = ?
+             WHERE id = ?",
+            duckdb::params![
+                status.as_str(),
+                reviewer,
+                now.timestamp_millis(),
+                comment,
+                &operation_id,
+                now.timestamp_millis(),
+                &id
+            ],
+        )?;
+        tx.commit()?;
+
+        Ok(ReviewOutcome::Reviewed { operation_id })
+    }
+
+    pub fn create_alarm_rule(&self, rule: &AlarmRule) -> Result<i64> {
+        let conn = self.get_read_connection()?;
+        let id = conn.query_row(
+            "INSERT INTO alarm_rules
+             (name, asset_name, device_name, target_name, key_name, metric, direction, levels, hysteresis,
+              duration, rate_window, reference_time, enabled, created_by, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            duckdb::params![
+                &rule.name,
+                &rule.asset_name,
+                &rule.device_name,
+                &rule.target_name,
+                &rule.key_name,
+                rule.metric.as_str(),
+                rule.direction.as_str(),
+                serde_json::to_string(&rule.levels)?,
+                &rule.hysteresis,
+                rule.duration as i64,
+                rule.rate_window.map(|w| w as i64),
+                rule.reference_time.map(|t| t.timestamp_millis()),
+                &rule.enabled,
+                &rule.created_by,
+                rule.created_at.timestamp_millis(),
+                rule.updated_at.timestamp_millis()
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
+
+    /// 获取告警规则，指定资产范围时只返回这些资产下的规则
+    pub fn list_alarm_rules(&self, allowed_assets: Option<&[String]>) -> Result<Vec<AlarmRule>> {
+        let conn = self.get_read_connection()?;
+        let mut bind_params: Vec<&dyn duckdb::ToSql> = Vec::new();
+        let mut condition = String::new();
+        if let Some(assets) = allowed_assets {
+            condition = format!("WHERE {}", asset_scope_clause(assets, |_| "?".to_string()));
+            bind_params.extend(assets.iter().map(|a| a as &dyn duckdb::ToSql));
+        }
+        condition.push_str(" ORDER BY id");
+        Self::load_alarm_rules(&conn, &condition, &bind_params)
+    }
+
+    pub fn get_alarm_rule(&self, id: i64) -> Result<Option<AlarmRule>> {
+        let conn = self.get_read_connection()?;
+        Ok(Self::load_alarm_rules(&conn, "WHERE id = ?", &[&id])?.into_iter().next())
+    }
+
+    /// 更新告警规则；判断条件可能已经改变，该规则未恢复的事件在同一事务中结束，持续时间和累计基准重新计算
+    pub fn update_alarm_rule(&self, rule: &AlarmRule) -> Result<bool> {
+        let mut conn = self.get_read_connection()?;
+        let id = rule.id.ok_or_else(|| anyhow::anyhow!("Alarm rule id is required for update"))?;
+
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
+            "UPDATE alarm_rules SET name = ?, asset_name = ?, device_name = ?, target_name = ?, key_name = ?,
+             metric = ?, direction = ?, levels = ?, hysteresis = ?, duration = ?, rate_window = ?,
+             reference_time = ?, enabled = ?, updated_at = ?
+             WHERE id = ?",
+            duckdb::params![
+                &rule.name,
+                &rule.asset_name,
+                &rule.device_name,
+                &rule.target_name,
+                &rule.key_name,
+                rule.metric.as_str(),
+                rule.direction.as_str(),
+                serde_json::to_string(&rule.levels)?,
+                &rule.hysteresis,
+                rule.duration as i64,
+                rule.rate_window.map(|w| w as i64),
+                rule.reference_time.map(|t| t.timestamp_millis()),
+                &rule.enabled,
+                rule.updated_at.timestamp_millis(),
+                &id
+            ],
+        )?;
+        if updated == 0 {
+            return Ok(false);
         }
+        Self::close_alarm_events(&tx, id)?;
+        tx.execute(
+            "UPDATE alarm_series_state SET pending_since = NULL, reference_value = NULL WHERE rule_id = ?",
+            [id],
+        )?;
+        tx.commit()?;
+        Ok(true)
     }
 
-    fn apply_sampling_and_limit(&self, base_query: &str, params: &QueryParams) -> String {
-        let mut query = base_query.to_string();
+    /// 删除告警规则及其判断进度，已有的事件保留，未恢复的事件结束
+    pub fn delete_alarm_rule(&self, id: i64) -> Result<bool> {
+        let mut conn = self.get_read_connection()?;
+        let tx = conn.transaction()?;
+        Self::close_alarm_events(&tx, id)?;
+        tx.execute("DELETE FROM alarm_series_state WHERE rule_id = ?", [id])?;
+        let deleted = tx.execute("DELETE FROM alarm_rules WHERE id = ?", [id])?;
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
 
-        // 如果设置了参考值，应用参考值减法
-        if let Some(ref_values) = &params.reference_values {
-            query = self.apply_reference_values(&query, ref_values);
+    // 以序列最后判断的数据时间结束规则的未恢复事件
+    fn close_alarm_events(conn: &Connection, rule_id: i64) -> Result<()> {
+        conn.execute(
+            "UPDATE alarm_events SET
+                 end_time = COALESCE((
+                     SELECT s.watermark FROM alarm_series_state s
+                     WHERE s.rule_id = alarm_events.rule_id AND s.asset_name = alarm_events.asset_name
+                       AND s.device_name = alarm_events.device_name AND s.target_name = alarm_events.target_name
+                       AND s.key_name = alarm_events.key_name
+                 ), start_time),
+                 updated_at = ?
+             WHERE rule_id = ? AND end_time IS NULL",
+            duckdb::params![Utc::now().timestamp_millis(), &rule_id],
+        )?;
+        Ok(())
+    }
+
+    fn load_alarm_rules(conn: &Connection, condition: &str, params: &[&dyn duckdb::ToSql]) -> Result<Vec<AlarmRule>> {
+        let query = format!("SELECT {} FROM alarm_rules {}", ALARM_RULE_COLUMNS, condition);
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = stmt.query(params)?;
+
+        let mut rules = Vec::new();
+        while let Some(row) = rows.next()? {
+            let metric: String = row.get(6)?;
+            let direction: String = row.get(7)?;
+            let levels: String = row.get(8)?;
+            let duration: i64 = row.get(10)?;
+            let rate_window: Option<i64> = row.get(11)?;
+            let reference_time: Option<i64> = row.get(12)?;
+            rules.push(AlarmRule {
+                id: Some(row.get(0)?),
+                name: row.get(1)?,
+                asset_name: row.get(2)?,
+                device_name: row.get(3)?,
+                target_name: row.get(4)?,
+                key_name: row.get(5)?,
+                metric: AlarmMetric::from_str(&metric)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid alarm metric: {}", metric))?,
+                direction: AlarmDirection::from_str(&direction)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid alarm direction: {}", direction))?,
+                levels: serde_json::from_str(&levels)?,
+                hysteresis: row.get(9)?,
+                duration: duration.max(0) as u64,
+                rate_window: rate_window.map(|w| w.max(0) as u64),
+                reference_time: reference_time.and_then(DateTime::from_timestamp_millis),
+                enabled: row.get(13)?,
+                created_by: row.get(14)?,
+                created_at: millis_to_datetime(row.get(15)?)?,
+                updated_at: millis_to_datetime(row.get(16)?)?,
+            });
         }
+        Ok(rules)
+    }
 
-        // 如果设置了每日时间段过滤，应用时间段过滤
-        if let Some(time_filter) = &params.time_of_day_filter {
-            query = self.apply_time_of_day_filter(&query, time_filter);
+    pub fn load_alarm_states(&self, rule_id: i64) -> Result<Vec<AlarmSeriesState>> {
+        let conn = self.get_read_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT asset_name, device_name, target_name, key_name, watermark, pending_since, reference_value
+             FROM alarm_series_state WHERE rule_id = ?"
+        )?;
+        let mut rows = stmt.query([rule_id])?;
+
+        let mut states = Vec::new();
+        while let Some(row) = rows.next()? {
+            let pending_since: Option<i64> = row.get(5)?;
+            states.push(AlarmSeriesState {
+                rule_id,
+                key: SeriesKey {
+                    asset_name: row.get(0)?,
+                    device_name: row.get(1)?,
+                    target_name: row.get(2)?,
+                    key_name: row.get(3)?,
+                },
+                watermark: millis_to_datetime(row.get(4)?)?,
+                pending_since: pending_since.and_then(DateTime::from_timestamp_millis),
+                reference_value: row.get(6)?,
+            });
         }
+        Ok(states)
+    }
 
-        // 如果设置了采样配置，使用时间窗口采样
-        if let Some(sampling_config) = &params.sampling_config {
-            let interval_ms = sampling_config.interval_ms;
+    /// 规则下未恢复的告警事件
+    pub fn load_open_alarm_events(&self, rule_id: i64) -> Result<Vec<AlarmEvent>> {
+        let conn = self.get_read_connection()?;
+        Self::load_alarm_events(&conn, "WHERE rule_id = ? AND end_time IS NULL", &[&rule_id])
+    }
 
-            // 使用DuckDB原生聚合函数，统一处理所有采样方法
-            let (aggregation_func, _ts_func) = match sampling_config.method {
-                SamplingMethod::First => ("FIRST(dbl_v ORDER BY ts)", "MIN(ts)"),
-                SamplingMethod::Last => ("LAST(dbl_v ORDER BY ts)", "MAX(ts)"),
-                SamplingMethod::Avg => ("AVG(dbl_v)", "MIN(ts)"),
-                SamplingMethod::Max => ("MAX(dbl_v)", "MIN(ts)"),
-                SamplingMethod::Min => ("MIN(dbl_v)", "MIN(ts)"),
-            };
+    /// 保存一次告警判断的结果：新事件插入、已有事件更新，同一事务中更新判断进度
+    pub fn save_alarm_evaluation(&self, events: &mut [AlarmEvent], states: &[AlarmSeriesState]) -> Result<()> {
+        let mut conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
 
-            // 使用简化的时间分组策略，直接基于毫秒时间戳
-            let time_bucket_expr = format!(
-                "FLOOR(ts / {}) * {}",
-                interval_ms, interval_ms
-            );
+        let tx = conn.transaction()?;
+        for event in events.iter_mut() {
+            let end_ms = event.end_time.map(|t| t.timestamp_millis());
+            match event.id {
+                Some(id) => {
+                    // 升级到新的最高级别时清除确认；判断期间被确认或被规则修改结束的事件不覆盖
+                    let peak_level = event.peak_level.as_str();
+                    tx.execute(
+                        "UPDATE alarm_events SET level = ?, end_time = ?, peak_value = ?, last_value = ?,
+                         acknowledged_by = CASE WHEN peak_level = ? THEN acknowledged_by END,
+                         acknowledged_at = CASE WHEN peak_level = ? THEN acknowledged_at END,
+                         ack_comment = CASE WHEN peak_level = ? THEN ack_comment END,
+                         peak_level = ?, updated_at = ?
+                         WHERE id = ? AND end_time IS NULL",
+                        duckdb::params![
+                            event.level.as_str(),
+                            &end_ms,
+                            &event.peak_value,
+                            &event.last_value,
+                            peak_level,
+                            peak_level,
+                            peak_level,
+                            peak_level,
+                            &now,
+                            &id
+                        ],
+                    )?;
+                }
+                None => {
+                    let id: i64 = tx.query_row(
+                        "INSERT INTO alarm_events
+                         (rule_id, rule_name, asset_name, device_name, target_name, key_name, level, peak_level,
+                          start_time, end_time, trigger_value, peak_value, last_value, created_at, updated_at)
+                         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
+                        duckdb::params![
+                            &event.rule_id,
+                            &event.rule_name,
+                            &event.asset_name,
+                            &event.device_name,
+                            &event.target_name,
+                            &event.key_name,
+                            event.level.as_str(),
+                            event.peak_level.as_str(),
+                            event.start_time.timestamp_millis(),
+                            &end_ms,
+                            &event.trigger_value,
+                            &event.peak_value,
+                            &event.last_value,
+                            &now,
+                            &now
+                        ],
+                        |row| row.get(0),
+                    )?;
+                    event.id = Some(id);
+                }
+            }
+        }
 
-            query = format!(
-                "WITH time_bucketed AS (
-                    SELECT *,
-                           {} as time_bucket
-                    FROM ({}) base
-                    WHERE dbl_v IS NOT NULL
-                )
-                SELECT
-                    MIN(time_bucket) as ts,
-                    FIRST(asset_name ORDER BY ts) as asset_name,
-                    FIRST(d_name ORDER BY ts) as d_name,
-                    target_name,
-                    key_name,
-                    {} as dbl_v
-                FROM time_bucketed
-                GROUP BY target_name, key_name, time_bucket
-                HAVING COUNT(*) > 0
-                ORDER BY ts",
-                time_bucket_expr, query, aggregation_func
-            );
+        for state in states {
+            tx.execute(
+                "INSERT OR REPLACE INTO alarm_series_state
+                 (rule_id, asset_name, device_name, target_name, key_name, watermark, pending_since, reference_value)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                duckdb::params![
+                    &state.rule_id,
+                    &state.key.asset_name,
+                    &state.key.device_name,
+                    &state.key.target_name,
+                    &state.key.key_name,
+                    state.watermark.timestamp_millis(),
+                    state.pending_since.map(|t| t.timestamp_millis()),
+                    &state.reference_value
+                ],
+            )?;
         }
+        tx.commit()?;
+        Ok(())
+    }
 
-        // 确保有ORDER BY子句
-        if !query.to_lowercase().contains("order by") {
-            query.push_str(" ORDER BY ts");
+    fn load_alarm_events(conn: &Connection, condition: &str, params: &[&dyn duckdb::ToSql]) -> Result<Vec<AlarmEvent>> {
+        let query = format!("SELECT {} FROM alarm_events {}", ALARM_EVENT_COLUMNS, condition);
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = stmt.query(params)?;
+
+        let level = |value: String| {
+            AlarmLevel::from_str(&value).ok_or_else(|| anyhow::anyhow!("Invalid alarm level: {}", value))
+        };
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            let end_time: Option<i64> = row.get(10)?;
+            let acknowledged_at: Option<i64> = row.get(15)?;
+            events.push(AlarmEvent {
+                id: Some(row.get(0)?),
+                rule_id: row.get(1)?,
+                rule_name: row.get(2)?,
+                asset_name: row.get(3)?,
+                device_name: row.get(4)?,
+                target_name: row.get(5)?,
+                key_name: row.get(6)?,
+                level: level(row.get(7)?)?,
+                peak_level: level(row.get(8)?)?,
+                start_time: millis_to_datetime(row.get(9)?)?,
+                end_time: end_time.and_then(DateTime::from_timestamp_millis),
+                trigger_value: row.get(11)?,
+                peak_value: row.get(12)?,
+                last_value: row.get(13)?,
+                acknowledged_by: row.get(14)?,
+                acknowledged_at: acknowledged_at.and_then(DateTime::from_timestamp_millis),
+                ack_comment: row.get(16)?,
+                created_at: millis_to_datetime(row.get(17)?)?,
+                updated_at: millis_to_datetime(row.get(18)?)?,
+            });
         }
-        
-        // 如果设置了限制，添加LIMIT子句（必须在ORDER BY之后）
-        if let Some(limit) = params.limit {
-            query.push_str(&format!(" LIMIT {}", limit));
+        Ok(events)
+    }
+
+    pub fn list_alarm_events(&self, query: &AlarmQuery) -> Result<Vec<AlarmEvent>> {
+        let conn = self.get_read_connection()?;
+        let mut conditions = Vec::new();
+        let mut bind_params: Vec<&dyn duckdb::ToSql> = Vec::new();
+
+        match query.active {
+            Some(true) => conditions.push("end_time IS NULL".to_string()),
+            Some(false) => conditions.push("end_time IS NOT NULL".to_string()),
+            None => {}
+        }
+        match query.acknowledged {
+            Some(true) => conditions.push("acknowledged_at IS NOT NULL".to_string()),
+            Some(false) => conditions.push("acknowledged_at IS NULL".to_string()),
+            None => {}
+        }
+        let levels: Vec<String> = [AlarmLevel::Blue, AlarmLevel::Yellow, AlarmLevel::Orange, AlarmLevel::Red]
+            .into_iter()
+            .filter(|level| query.min_level.is_some_and(|min| *level >= min))
+            .map(|level| level.as_str().to_string())
+            .collect();
+        if query.min_level.is_some() {
+            conditions.push(format!("peak_level IN ({})", vec!["?"; levels.len()].join(", ")));
+            bind_params.extend(levels.iter().map(|l| l as &dyn duckdb::ToSql));
+        }
+        if let Some(rule_id) = &query.rule_id {
+            conditions.push("rule_id = ?".to_string());
+            bind_params.push(rule_id);
+        }
+        if let Some(target_name) = &query.target_name {
+            conditions.push("target_name = ?".to_string());
+            bind_params.push(target_name);
+        }
+        if let Some(key_name) = &query.key_name {
+            conditions.push("key_name = ?".to_string());
+            bind_params.push(key_name);
+        }
+        let start_ms = query.start_time.map(|t| t.timestamp_millis());
+        if let Some(start_ms) = &start_ms {
+            conditions.push("(end_time IS NULL OR end_time >= ?)".to_string());
+            bind_params.push(start_ms);
+        }
+        let end_ms = query.end_time.map(|t| t.timestamp_millis());
+        if let Some(end_ms) = &end_ms {
+            conditions.push("start_time <= ?".to_string());
+            bind_params.push(end_ms);
+        }
+        if let Some(assets) = &query.allowed_assets {
+            conditions.push(asset_scope_clause(assets, |_| "?".to_string()));
+            bind_params.extend(assets.iter().map(|a| a as &dyn duckdb::ToSql));
         }
 
-        query
+        let mut condition = String::new();
+        if !conditions.is_empty() {
+            condition = format!("WHERE {}", conditions.join(" AND "));
+        }
+        condition.push_str(&format!(" ORDER BY start_time DESC, id DESC LIMIT {}", query.limit));
+        Self::load_alarm_events(&conn, &condition, &bind_params)
     }
 
-    fn apply_reference_values(&self, base_query: &str, reference_values: &[ReferenceValue]) -> String {
-        // 构建参考值的CASE WHEN语句
-        let mut case_when_parts = Vec::new();
+    pub fn get_alarm_event(&self, id: i64) -> Result<Option<AlarmEvent>> {
+        let conn = self.get_read_connection()?;
+        Ok(Self::load_alarm_events(&conn, "WHERE id = ?", &[&id])?.into_iter().next())
+    }
 
-        for ref_val in reference_values {
-            case_when_parts.push(format!(
-                "WHEN target_name = '{}' AND key_name = '{}' THEN dbl_v - {}",
-                ref_val.target_name.replace("'", "''"), // 转义单引号
-                ref_val.key_name.replace("'", "''"),
-                ref_val.reference_value
-            ));
+    /// 确认告警事件，每个事件只能确认一次（升级后确认会被清除，可以再次确认）
+    pub fn acknowledge_alarm_event(&self, id: i64, username: &str, comment: Option<&str>) -> Result<AcknowledgeOutcome> {
+        let mut conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
+
+        let tx = conn.transaction()?;
+        let Some(event) = Self::load_alarm_events(&tx, "WHERE id = ?", &[&id])?.into_iter().next() else {
+            return Ok(AcknowledgeOutcome::NotFound);
+        };
+        if let Some(acknowledged_by) = event.acknowledged_by {
+            return Ok(AcknowledgeOutcome::AlreadyAcknowledged(acknowledged_by));
         }
+        tx.execute(
+            "UPDATE alarm_events SET acknowledged_by = ?, acknowledged_at = ?, ack_comment = ?, updated_at = ?
+             WHERE id = ?",
+            duckdb::params![username, &now, comment, &now, &id],
+        )?;
+        tx.commit()?;
+        Ok(AcknowledgeOutcome::Acknowledged)
+    }
 
-        if case_when_parts.is_empty() {
-            return base_query.to_string();
+    /// 为每个通道写入一条待发送的通知
+    pub fn enqueue_notifications(&self, channels: &[String], event: &str, payload: &serde_json::Value) -> Result<usize> {
+        let mut conn = self.get_read_connection()?;
+        let tx = conn.transaction()?;
+        let now = Utc::now().timestamp_millis();
+        let payload = payload.to_string();
+        for channel in channels {
+            tx.execute(
+                "INSERT INTO notification_outbox (channel, event, payload, next_attempt_at, created_at)
+                 VALUES (?, ?, ?, ?, ?)",
+                duckdb::params![channel, event, &payload, &now, &now],
+            )?;
         }
+        tx.commit()?;
+        Ok(channels.len())
+    }
 
-        let case_when_clause = format!(
-            "CASE {} ELSE dbl_v END",
-            case_when_parts.join(" ")
-        );
+    fn load_notifications(conn: &Connection, condition: &str, params: &[&dyn duckdb::ToSql]) -> Result<Vec<OutboxEntry>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, channel, event, payload, status, attempts, next_attempt_at, last_error, created_at, sent_at
+             FROM notification_outbox {}",
+            condition
+        ))?;
+        let mut rows = stmt.query(params)?;
 
-        // 将原查询包装，应用参考值减法
-        format!(
-            "SELECT
-                ts,
-                asset_name,
-                d_name,
-                target_name,
-                key_name,
-                {} as dbl_v
-            FROM ({}) base",
-            case_when_clause, base_query
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next()? {
+            let payload: String = row.get(3)?;
+            let status: String = row.get(4)?;
+            let sent_at: Option<i64> = row.get(9)?;
+            entries.push(OutboxEntry {
+                id: row.get(0)?,
+                channel: row.get(1)?,
+                event: row.get(2)?,
+                payload: serde_json::from_str(&payload)?,
+                status: NotificationStatus::from_str(&status)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown notification status: {}", status))?,
+                attempts: row.get(5)?,
+                next_attempt_at: millis_to_datetime(row.get(6)?)?,
+                last_error: row.get(7)?,
+                created_at: millis_to_datetime(row.get(8)?)?,
+                sent_at: sent_at.and_then(DateTime::from_timestamp_millis),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// 到期待发送的通知，按到期时间排序
+    pub fn due_notifications(&self, now: DateTime<Utc>, limit: usize) -> Result<Vec<OutboxEntry>> {
+        let conn = self.get_read_connection()?;
+        Self::load_notifications(
+            &conn,
+            &format!("WHERE status = 'pending' AND next_attempt_at <= ? ORDER BY next_attempt_at, id LIMIT {}", limit),
+            &[&now.timestamp_millis()],
         )
     }
 
-    fn apply_time_of_day_filter(&self, base_query: &str, time_filter: &TimeOfDayFilter) -> String {
-        if time_filter.time_ranges.is_empty() {
-            return base_query.to_string();
+    pub fn list_notifications(&self, status: Option<NotificationStatus>, limit: usize) -> Result<Vec<OutboxEntry>> {
+        let conn = self.get_read_connection()?;
+        match status {
+            Some(status) => Self::load_notifications(
+                &conn,
+                &format!("WHERE status = ? ORDER BY id DESC LIMIT {}", limit),
+                &[&status.as_str()],
+            ),
+            None => Self::load_notifications(&conn, &format!("ORDER BY id DESC LIMIT {}", limit), &[]),
         }
+    }
 
-        let mut conditions = Vec::new();
+    pub fn mark_notification_sent(&self, id: i64, attempts: u32) -> Result<()> {
+        let conn = self.get_read_connection()?;
+        conn.execute(
+            "UPDATE notification_outbox SET status = 'sent', attempts = ?, last_error = NULL, sent_at = ? WHERE id = ?",
+            duckdb::params![&attempts, &Utc::now().timestamp_millis(), &id],
+        )?;
+        Ok(())
+    }
 
-        for range in &time_filter.time_ranges {
-            let start_minutes = range.start_hour as u32 * 60 + range.start_minute as u32;
-            let end_minutes = range.end_hour as u32 * 60 + range.end_minute as u32;
+    /// 记录发送失败；指定了重试时间时保持待发送，否则标记为失败
+    pub fn mark_notification_failed(
+        &self,
+        id: i64,
+        attempts: u32,
+        retry_at: Option<DateTime<Utc>>,
+        error: &str,
+    ) -> Result<()> {
+        let conn = self.get_read_connection()?;
+        let status = match retry_at {
+            Some(_) => NotificationStatus::Pending,
+            None => NotificationStatus::Failed,
+        };
+        conn.execute(
+            "UPDATE notification_outbox SET status = ?, attempts = ?, next_attempt_at = COALESCE(?, next_attempt_at),
+                 last_error = ? WHERE id = ?",
+            duckdb::params![status.as_str(), &attempts, &retry_at.map(|t| t.timestamp_millis()), error, &id],
+        )?;
+        Ok(())
+    }
 
-            let condition = if start_minutes <= end_minutes {
-                // 正常情况：如 09:00 到 12:00
-                format!(
-                    "(EXTRACT(hour FROM to_timestamp(ts / 1000)) * 60 + EXTRACT(minute FROM to_timestamp(ts / 1000))) BETWEEN {} AND {}",
-                    start_minutes, end_minutes
-                )
-            } else {
-                // 跨午夜情况：如 22:00 到 06:00
-                format!(
-                    "((EXTRACT(hour FROM to_timestamp(ts / 1000)) * 60 + EXTRACT(minute FROM to_timestamp(ts / 1000))) >= {} OR (EXTRACT(hour FROM to_timestamp(ts / 1000)) * 60 + EXTRACT(minute FROM to_timestamp(ts / 1000))) <= {})",
-                    start_minutes, end_minutes
-                )
-            };
+    /// 受速率限制的通知推迟到指定时间，不计入尝试次数
+    pub fn defer_notification(&self, id: i64, next_attempt_at: DateTime<Utc>) -> Result<()> {
+        let conn = self.get_read_connection()?;
+        conn.execute(
+            "UPDATE notification_outbox SET next_attempt_at = ? WHERE id = ?",
+            duckdb::params![&next_attempt_at.timestamp_millis(), &id],
+        )?;
+        Ok(())
+    }
 
-            conditions.push(condition);
+    pub fn create_derived_metric(&self, metric: &DerivedMetric) -> Result<i64> {
+        let conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
+        let id: i64 = conn.query_row(
+            "INSERT INTO derived_metrics (name, description, expression, inputs, tolerance_ms, created_by, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            duckdb::params![
+                &metric.name,
+                &metric.description,
+                &metric.expression,
+                &serde_json::to_string(&metric.inputs)?,
+                &metric.tolerance_ms,
+                &metric.created_by,
+                &now,
+                &now,
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
+
+    fn load_derived_metrics(conn: &Connection, condition: &str, params: &[&dyn duckdb::ToSql]) -> Result<Vec<DerivedMetric>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, name, description, expression, inputs, tolerance_ms, created_by, created_at, updated_at
+             FROM derived_metrics {}",
+            condition
+        ))?;
+        let mut rows = stmt.query(params)?;
+
+        let mut metrics = Vec::new();
+        while let Some(row) = rows.next()? {
+            let inputs: String = row.get(4)?;
+            metrics.push(DerivedMetric {
+                id: Some(row.get(0)?),
+                name: row.get(1)?,
+                description: row.get(2)?,
+                expression: row.get(3)?,
+                inputs: serde_json::from_str(&inputs)?,
+                tolerance_ms: row.get(5)?,
+                created_by: row.get(6)?,
+                created_at: millis_to_datetime(row.get(7)?)?,
+                updated_at: millis_to_datetime(row.get(8)?)?,
+            });
         }
+        Ok(metrics)
+    }
 
-        format!(
-            "SELECT ts, asset_name, d_name, target_name, key_name, dbl_v
-            FROM ({}) base
-            WHERE {}",
-            base_query,
-            conditions.join(" OR ")
-        )
+    pub fn list_derived_metrics(&self) -> Result<Vec<DerivedMetric>> {
+        let conn = self.get_read_connection()?;
+        Self::load_derived_metrics(&conn, "ORDER BY name", &[])
     }
 
-    pub fn create_operation(&self, operation: &DataOperation) -> Result<i64> {
-        // 使用新连接避免死锁
+    pub fn get_derived_metric(&self, id: i64) -> Result<Option<DerivedMetric>> {
         let conn = self.get_read_connection()?;
-        
-        let start_time_ms = operation.start_time.map(|t| t.timestamp_millis());
-        let end_time_ms = operation.end_time.map(|t| t.timestamp_millis());
-        let created_at_ms = operation.created_at.timestamp_millis();
-        let updated_at_ms = operation.updated_at.timestamp_millis();
-        
-        // 先获取下一个ID
-        let mut stmt = conn.prepare("SELECT nextval('seq_operations_id')")?;
-        let mut rows = stmt.query([])?;
-        let next_id: i64 = if let Some(row) = rows.next()? {
-            row.get(0)?
-        } else {
-            return Err(anyhow::anyhow!("Failed to get next ID"));
-        };
-        
-        conn.execute(
-            "INSERT INTO data_operations 
-            (id, name, description, target_name, key_name, operation_type, value, 
-             start_time, end_time, is_active, created_at, updated_at) 
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        Ok(Self::load_derived_metrics(&conn, "WHERE id = ?", &[&id])?.into_iter().next())
+    }
+
+    pub fn update_derived_metric(&self, metric: &DerivedMetric) -> Result<bool> {
+        let conn = self.get_read_connection()?;
+        let updated = conn.execute(
+            "UPDATE derived_metrics SET name = ?, description = ?, expression = ?, inputs = ?, tolerance_ms = ?, updated_at = ?
+             WHERE id = ?",
             duckdb::params![
-                &next_id,
-                &operation.name,
-                &operation.description,
-                &operation.target_name,
-                &operation.key_name,
-                operation.operation_type.as_str(),
-                &operation.value,
-                &start_time_ms,
-                &end_time_ms,
-                &operation.is_active,
-                &created_at_ms,
-                &updated_at_ms
+                &metric.name,
+                &metric.description,
+                &metric.expression,
+                &serde_json::to_string(&metric.inputs)?,
+                &metric.tolerance_ms,
+                &Utc::now().timestamp_millis(),
+                &metric.id,
             ],
         )?;
-        
-        Ok(next_id)
+        Ok(updated > 0)
     }
 
-    pub fn get_operations(&self, active_only: bool) -> Result<Vec<DataOperation>> {
-        // 使用独立连接避免死锁
+    pub fn delete_derived_metric(&self, id: i64) -> Result<bool> {
         let conn = self.get_read_connection()?;
-        
-        let query = if active_only {
-            "SELECT id, name, description, target_name, key_name, operation_type, 
-                    value, start_time, end_time, is_active, created_at, updated_at 
-             FROM data_operations WHERE is_active = true ORDER BY created_at DESC"
-        } else {
-            "SELECT id, name, description, target_name, key_name, operation_type, 
-                    value, start_time, end_time, is_active, created_at, updated_at 
-             FROM data_operations ORDER BY created_at DESC"
-        };
-        
-        let mut stmt = conn.prepare(query)?;
-        let mut rows = stmt.query([])?;
-        let mut operations = Vec::new();
-        
-        while let Some(row) = rows.next()? {
-            let operation_type_str: String = row.get(5)?;
-            let operation_type = OperationType::from_str(&operation_type_str)
-                .ok_or_else(|| anyhow::anyhow!("Invalid operation type: {}", operation_type_str))?;
-            
-            let start_time_ms: Option<i64> = row.get(7)?;
-            let end_time_ms: Option<i64> = row.get(8)?;
-            let created_at_ms: i64 = row.get(10)?;
-            let updated_at_ms: i64 = row.get(11)?;
-            
-            operations.push(DataOperation {
+        Ok(conn.execute("DELETE FROM derived_metrics WHERE id = ?", [id])? > 0)
+    }
+
+    pub fn create_baseline(&self, baseline: &Baseline) -> Result<i64> {
+        let conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
+        let id: i64 = conn.query_row(
+            "INSERT INTO baselines (name, description, reference_values, created_by, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+            duckdb::params![
+                &baseline.name,
+                &baseline.description,
+                &serde_json::to_string(&baseline.reference_values)?,
+                &baseline.created_by,
+                &now,
+                &now,
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
+
+    fn load_baselines(conn: &Connection, condition: &str, params: &[&dyn duckdb::ToSql]) -> Result<Vec<Baseline>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, name, description, reference_values, created_by, created_at, updated_at FROM baselines {}",
+            condition
+        ))?;
+        let mut rows = stmt.query(params)?;
+
+        let mut baselines = Vec::new();
+        while let Some(row) = rows.next()? {
+            let reference_values: String = row.get(3)?;
+            baselines.push(Baseline {
                 id: Some(row.get(0)?),
                 name: row.get(1)?,
                 description: row.get(2)?,
-                target_name: row.get(3)?,
-                key_name: row.get(4)?,
-                operation_type,
-                value: row.get(6)?,
-                start_time: start_time_ms.and_then(|ms| DateTime::from_timestamp_millis(ms)),
-                end_time: end_time_ms.and_then(|ms| DateTime::from_timestamp_millis(ms)),
-                is_active: row.get(9)?,
-                created_at: DateTime::from_timestamp_millis(created_at_ms)
-                    .ok_or_else(|| anyhow::anyhow!("Invalid created_at timestamp"))?,
-                updated_at: DateTime::from_timestamp_millis(updated_at_ms)
-                    .ok_or_else(|| anyhow::anyhow!("Invalid updated_at timestamp"))?,
+                reference_values: serde_json::from_str(&reference_values)?,
+                created_by: row.get(4)?,
+                created_at: millis_to_datetime(row.get(5)?)?,
+                updated_at: millis_to_datetime(row.get(6)?)?,
             });
         }
-        
-        Ok(operations)
+        Ok(baselines)
     }
 
-    pub fn update_operation(&self, operation: &DataOperation) -> Result<()> {
-        // 使用新连接避免死锁
+    pub fn list_baselines(&self) -> Result<Vec<Baseline>> {
         let conn = self.get_read_connection()?;
-        
-        let id = operation.id.ok_or_else(|| anyhow::anyhow!("Operation ID is required for update"))?;
-        let start_time_ms = operation.start_time.map(|t| t.timestamp_millis());
-        let end_time_ms = operation.end_time.map(|t| t.timestamp_millis());
-        let updated_at_ms = Utc::now().timestamp_millis();
-        
+        Self::load_baselines(&conn, "ORDER BY name", &[])
+    }
+
+    pub fn get_baseline(&self, id: i64) -> Result<Option<Baseline>> {
+        let conn = self.get_read_connection()?;
+        Ok(Self::load_baselines(&conn, "WHERE id = ?", &[&id])?.into_iter().next())
+    }
+
+    pub fn get_baseline_by_name(&self, name: &str) -> Result<Option<Baseline>> {
+        let conn = self.get_read_connection()?;
+        Ok(Self::load_baselines(&conn, "WHERE name = ?", &[&name])?.into_iter().next())
+    }
+
+    pub fn update_baseline(&self, baseline: &Baseline) -> Result<bool> {
+        let conn = self.get_read_connection()?;
+        let updated = conn.execute(
+            "UPDATE baselines SET name = ?, description = ?, reference_values = ?, updated_at = ? WHERE id = ?",
+            duckdb::params![
+                &baseline.name,
+                &baseline.description,
+                &serde_json::to_string(&baseline.reference_values)?,
+                &Utc::now().timestamp_millis(),
+                &baseline.id,
+            ],
+        )?;
+        Ok(updated > 0)
+    }
+
+    pub fn delete_baseline(&self, id: i64) -> Result<bool> {
+        let conn = self.get_read_connection()?;
+        Ok(conn.execute("DELETE FROM baselines WHERE id = ?", [id])? > 0)
+    }
+
+    pub fn create_saved_query(&self, saved: &SavedQuery) -> Result<()> {
+        let conn = self.get_read_connection()?;
+        let now = Utc::now().timestamp_millis();
         conn.execute(
-            "UPDATE data_operations SET 
-             name = ?, description = ?, target_name = ?, key_name = ?, 
-             operation_type = ?, value = ?, start_time = ?, end_time = ?, 
-             is_active = ?, updated_at = ? 
-             WHERE id = ?",
+            "INSERT INTO saved_queries (id, name, description, owner, tags, query, baseline, relative_start, relative_end, display, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             duckdb::params![
-                &operation.name,
-                &operation.description,
-                &operation.target_name,
-                &operation.key_name,
-                operation.operation_type.as_str(),
-                &operation.value,
-                &start_time_ms,
-                &end_time_ms,
-                &operation.is_active,
-                &updated_at_ms,
-                &id
+                &saved.id,
+                &saved.name,
+                &saved.description,
+                &saved.owner,
+                &serde_json::to_string(&saved.tags)?,
+                &serde_json::to_string(&saved.query)?,
+                &saved.baseline,
+                &saved.relative_start,
+                &saved.relative_end,
+                &saved.display.as_ref().map(|d| d.to_string()),
+                &now,
+                &now,
             ],
         )?;
-        
         Ok(())
     }
 
-    pub fn delete_operation(&self, id: i64) -> Result<()> {
-        // 使用新连接避免死锁
+    fn load_saved_queries(conn: &Connection, condition: &str, params: &[&dyn duckdb::ToSql]) -> Result<Vec<SavedQuery>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, name, description, owner, tags, query, baseline, relative_start, relative_end, display, created_at, updated_at
+             FROM saved_queries {}",
+            condition
+        ))?;
+        let mut rows = stmt.query(params)?;
+
+        let mut saved_queries = Vec::new();
+        while let Some(row) = rows.next()? {
+            let tags: String = row.get(4)?;
+            let query: String = row.get(5)?;
+            let display: Option<String> = row.get(9)?;
+            saved_queries.push(SavedQuery {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                description: row.get(2)?,
+                owner: row.get(3)?,
+                tags: serde_json::from_str(&tags)?,
+                query: serde_json::from_str(&query)?,
+                baseline: row.get(6)?,
+                relative_start: row.get(7)?,
+                relative_end: row.get(8)?,
+                display: display.map(|d| serde_json::from_str(&d)).transpose()?,
+                created_at: millis_to_datetime(row.get(10)?)?,
+                updated_at: millis_to_datetime(row.get(11)?)?,
+            });
+        }
+        Ok(saved_queries)
+    }
+
+    /// 按所有者和标签筛选保存的查询
+    pub fn list_saved_queries(&self, owner: Option<&str>, tag: Option<&str>) -> Result<Vec<SavedQuery>> {
         let conn = self.get_read_connection()?;
-        conn.execute("DELETE FROM data_operations WHERE id = ?", [id])?;
-        Ok(())
+        let mut saved_queries = match owner {
+            Some(owner) => Self::load_saved_queries(&conn, "WHERE owner = ? ORDER BY updated_at DESC", &[&owner])?,
+            None => Self::load_saved_queries(&conn, "ORDER BY updated_at DESC", &[])?,
+        };
+        if let Some(tag) = tag {
+            saved_queries.retain(|saved| saved.tags.iter().any(|t| t == tag));
+        }
+        Ok(saved_queries)
     }
 
-    pub fn toggle_operation(&self, id: i64) -> Result<()> {
-        // 使用新连接避免死锁
+    pub fn get_saved_query(&self, id: &str) -> Result<Option<SavedQuery>> {
         let conn = self.get_read_connection()?;
-        let updated_at_ms = Utc::now().timestamp_millis();
-        
-        conn.execute(
-            "UPDATE data_operations SET 
-             is_active = NOT is_active, updated_at = ? 
+        Ok(Self::load_saved_queries(&conn, "WHERE id = ?", &[&id])?.into_iter().next())
+    }
+
+    pub fn update_saved_query(&self, saved: &SavedQuery) -> Result<bool> {
+        let conn = self.get_read_connection()?;
+        let updated = conn.execute(
+            "UPDATE saved_queries SET name = ?, description = ?, tags = ?, query = ?, baseline = ?,
+             relative_start = ?, relative_end = ?, display = ?, updated_at = ?
              WHERE id = ?",
-            duckdb::params![&updated_at_ms, &id],
+            duckdb::params![
+                &saved.name,
+                &saved.description,
+                &serde_json::to_string(&saved.tags)?,
+                &serde_json::to_string(&saved.query)?,
+                &saved.baseline,
+                &saved.relative_start,
+                &saved.relative_end,
+                &saved.display.as_ref().map(|d| d.to_string()),
+                &Utc::now().timestamp_millis(),
+                &saved.id,
+            ],
         )?;
-        
-        Ok(())
+        Ok(updated > 0)
     }
-    
-    // 优化：只获取与查询相关的操作
-    pub fn get_relevant_operations(&self, target_names: &[String], key_names: &[String]) -> Result<Vec<DataOperation>> {
-        // 使用独立连接避免死锁
+
+    pub fn delete_saved_query(&self, id: &str) -> Result<bool> {
         let conn = self.get_read_connection()?;
-        
-        let mut query = String::from(
-            "SELECT id, name, description, target_name, key_name, operation_type, 
-                    value, start_time, end_time, is_active, created_at, updated_at 
-             FROM data_operations 
-             WHERE is_active = true"
-        );
-        
-        let mut conditions = Vec::new();
-        
-        if !target_names.is_empty() {
-            let placeholders = target_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-            conditions.push(format!("target_name IN ({})", placeholders));
+        Ok(conn.execute("DELETE FROM saved_queries WHERE id = ?", [id])? > 0)
+    }
+
+    /// 原始数据中是否存在该数据类型
+    pub fn key_name_exists(&self, key_name: &str) -> Result<bool> {
+        let conn = self.get_read_connection()?;
+        let exists = conn.query_row(
+            &format!("SELECT EXISTS (SELECT 1 FROM {} WHERE key_name = ?)", TELEMETRY_TABLE),
+            [key_name],
+            |row| row.get(0),
+        )?;
+        Ok(exists)
+    }
+
+    /// 查询的数据源：请求的数据类型中有派生指标时，把派生指标的数据并入原始数据表
+    ///
+    /// 返回的子查询与原始数据表的列和别名相同，可以直接替换查询中的表名。
+    /// 派生指标只计算 params 的时间范围和资产、设备范围内的数据，外层查询仍需加上这些条件
+    fn telemetry_source(&self, conn: &Connection, params: &QueryParams) -> Result<String> {
+        self.derived_source(conn, &params.key_names, Some(params), params.allowed_assets.as_deref())
+    }
+
+    /// 数据源和计算参考值的数据源：参考值不受查询时间和资产、设备条件限制，派生指标需要从全部历史计算（仍限于用户的资产范围）
+    fn telemetry_sources(&self, conn: &Connection, params: &QueryParams) -> Result<(String, String)> {
+        let source = self.telemetry_source(conn, params)?;
+        let reference_source = match &params.reference_values {
+            Some(_) => self.derived_source(conn, &params.key_names, None, params.allowed_assets.as_deref())?,
+            None => source.clone(),
+        };
+        Ok((source, reference_source))
+    }
+
+    fn derived_source(
+        &self,
+        conn: &Connection,
+        key_names: &[String],
+        filter: Option<&QueryParams>,
+        allowed_assets: Option<&[String]>,
+    ) -> Result<String> {
+        if key_names.is_empty() {
+            return Ok(TELEMETRY_TABLE.to_string());
         }
-        
-        if !key_names.is_empty() {
-            let placeholders = key_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-            conditions.push(format!("key_name IN ({})", placeholders));
+        let placeholders = key_names.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+        let params: Vec<&dyn duckdb::ToSql> = key_names.iter().map(|k| k as &dyn duckdb::ToSql).collect();
+        let metrics = Self::load_derived_metrics(conn, &format!("WHERE name IN ({}) ORDER BY id", placeholders), &params)?;
+        if metrics.is_empty() {
+            return Ok(TELEMETRY_TABLE.to_string());
         }
-        
-        if !conditions.is_empty() {
-            query.push_str(" AND (");
-            query.push_str(&conditions.join(" OR "));
-            query.push_str(")");
+
+        let mut parts = vec![format!("SELECT ts, asset_name, d_name, target_name, key_name, dbl_v FROM {}", TELEMETRY_TABLE)];
+        for (i, metric) in metrics.iter().enumerate() {
+            parts.push(format!("({})", derived_metric_sql(metric, &format!("d{}", i), filter, allowed_assets)?));
         }
-        
-        query.push_str(" ORDER BY created_at DESC");
-        
-        let mut stmt = conn.prepare(&query)?;
-        
-        // 构建参数列表
-        let mut params: Vec<Box<dyn duckdb::ToSql>> = Vec::new();
-        for target in target_names {
-            params.push(Box::new(target.clone()));
+        Ok(format!("({}) AS {}", parts.join(" UNION ALL "), TELEMETRY_TABLE))
+    }
+}
+
+/// 测试用的临时数据库
+#[cfg(test)]
+pub(crate) mod test_db {
+    use super::*;
+
+    // 原始数据表由测试用 (ts, asset, device, target, key, value) 填充，结束时删除数据库文件
+    pub(crate) struct TestDb {
+        pub(crate) db: DatabaseManager,
+        path: std::path::PathBuf,
+    }
+
+    impl TestDb {
+        pub(crate) fn new(name: &str, rows: &[(i64, &str, &str, &str, &str, f64)]) -> Self {
+            let path = std::env::temp_dir().join(format!("ldc_test_{}_{}.duckdb", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let db = DatabaseManager::new(path.to_str().unwrap()).unwrap();
+            db.get_read_connection().unwrap().execute(
+                &format!(
+                    "CREATE TABLE {} (ts BIGINT, asset_name VARCHAR, d_name VARCHAR, target_name VARCHAR, key_name VARCHAR, dbl_v DOUBLE)",
+                    TELEMETRY_TABLE
+                ),
+                [],
+            ).unwrap();
+            let test = Self { db, path };
+            test.insert(rows);
+            test
         }
-        for key in key_names {
-            params.push(Box::new(key.clone()));
+
+        pub(crate) fn insert(&self, rows: &[(i64, &str, &str, &str, &str, f64)]) {
+            let conn = self.db.get_read_connection().unwrap();
+            let mut appender = conn.appender(TELEMETRY_TABLE).unwrap();
+            for &(ts, asset, device, target, key, value) in rows {
+                appender.append_row(duckdb::params![ts, asset, device, target, key, value]).unwrap();
+            }
         }
-        
-        let mut rows = if params.is_empty() {
-            stmt.query([])?
-        } else {
-            let params_refs: Vec<&dyn duckdb::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-            stmt.query(params_refs.as_slice())?
-        };
-        
-        let mut operations = Vec::new();
-        
-        while let Some(row) = rows.next()? {
-            let operation_type_str: String = row.get(5)?;
-            let operation_type = OperationType::from_str(&operation_type_str)
-                .ok_or_else(|| anyhow::anyhow!("Invalid operation type: {}", operation_type_str))?;
-            
-            let start_time_ms: Option<i64> = row.get(7)?;
-            let end_time_ms: Option<i64> = row.get(8)?;
-            let created_at_ms: i64 = row.get(10)?;
-            let updated_at_ms: i64 = row.get(11)?;
-            
-            operations.push(DataOperation {
-                id: Some(row.get(0)?),
-                name: row.get(1)?,
-                description: row.get(2)?,
-                target_name: row.get(3)?,
-                key_name: row.get(4)?,
-                operation_type,
-                value: row.get(6)?,
-                start_time: start_time_ms.and_then(|ms| DateTime::from_timestamp_millis(ms)),
-                end_time: end_time_ms.and_then(|ms| DateTime::from_timestamp_millis(ms)),
-                is_active: row.get(9)?,
-                created_at: DateTime::from_timestamp_millis(created_at_ms)
-                    .ok_or_else(|| anyhow::anyhow!("Invalid created_at timestamp"))?,
-                updated_at: DateTime::from_timestamp_millis(updated_at_ms)
-                    .ok_or_else(|| anyhow::anyhow!("Invalid updated_at timestamp"))?,
-            });
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+            let _ = std::fs::remove_file(self.path.with_extension("duckdb.wal"));
         }
-        
-        Ok(operations)
     }
+}
 
-    pub fn apply_operations_to_data(&self, data: &mut Vec<TelemetryData>, operations: &[DataOperation]) {
-        for operation in operations {
-            if !operation.is_active {
-                continue;
-            }
-            
-            for item in data.iter_mut() {
-                // 检查是否匹配标靶和指标
-                if item.target_name != operation.target_name || item.key_name != operation.key_name {
-                    continue;
-                }
-                
-                // 检查时间范围
-                let in_time_range = match (operation.start_time, operation.end_time) {
-                    (Some(start), Some(end)) => item.timestamp >= start && item.timestamp <= end,
-                    (Some(start), None) => item.timestamp >= start,
-                    (None, Some(end)) => item.timestamp <= end,
-                    (None, None) => true,
-                };
-                
-                if !in_time_range {
-                    continue;
-                }
-                
-                // 应用操作
-                item.value = match operation.operation_type {
-                    OperationType::Add => item.value + operation.value,
-                    OperationType::Subtract => item.value - operation.value,
-                    OperationType::Multiply => item.value * operation.value,
-                    OperationType::Divide => {
-                        if operation.value != 0.0 {
-                            item.value / operation.value
-                        } else {
-                            item.value
-                        }
-                    },
-                    OperationType::Offset => item.value + operation.value, // 偏移等同于加法
-                };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_db::TestDb;
+
+    fn params(key_names: &[&str]) -> QueryParams {
+        QueryParams {
+            asset_name: None,
+            device_name: None,
+            target_names: vec![],
+            key_names: key_names.iter().map(|k| k.to_string()).collect(),
+            start_time: None,
+            end_time: None,
+            remove_outliers: false,
+            outlier_method: "iqr".to_string(),
+            custom_filter: None,
+            limit: None,
+            sampling_config: None,
+            reference_values: None,
+            time_of_day_filter: None,
+            allowed_assets: None,
+        }
+    }
+
+    fn values(db: &DatabaseManager, params: &QueryParams) -> Vec<(String, i64, f64)> {
+        db.query_telemetry_data(params).unwrap().data.into_iter()
+            .map(|d| (d.asset_name, d.timestamp.timestamp_millis(), d.value))
+            .collect()
+    }
+
+    // 每个标靶的 x 加上资产B中固定标靶 REF 的 z
+    fn create_relative_metric(db: &DatabaseManager) {
+        let now = Utc::now();
+        db.create_derived_metric(&DerivedMetric {
+            id: None,
+            name: "relative".to_string(),
+            description: None,
+            expression: "x + z".to_string(),
+            inputs: vec![
+                DerivedInput { variable: "x".to_string(), key_name: "x".to_string(), target_name: None },
+                DerivedInput { variable: "z".to_string(), key_name: "z".to_string(), target_name: Some("REF".to_string()) },
+            ],
+            tolerance_ms: 0,
+            created_by: "admin".to_string(),
+            created_at: now,
+            updated_at: now,
+        }).unwrap();
+    }
+
+    #[test]
+    fn derived_metric_fixed_input_respects_asset_scope() {
+        let test = TestDb::new("derived_scope", &[
+            (1000, "A", "D1", "T1", "x", 1.0),
+            (2000, "A", "D1", "T1", "x", 2.0),
+            (1000, "B", "D2", "REF", "z", 100.0),
+            (2000, "B", "D2", "REF", "z", 200.0),
+        ]);
+        create_relative_metric(&test.db);
+
+        // 不限资产范围时可以使用资产B中的固定标靶
+        assert_eq!(values(&test.db, &params(&["relative"])), [
+            ("A".to_string(), 1000, 101.0),
+            ("A".to_string(), 2000, 202.0),
+        ]);
+
+        // 只能访问资产A的用户读不到资产B的数据，也不能通过派生指标间接读取
+        let mut scoped = params(&["relative"]);
+        scoped.allowed_assets = Some(vec!["A".to_string()]);
+        assert!(values(&test.db, &scoped).is_empty());
+        scoped.reference_values = Some(vec![ReferenceValue {
+            target_name: "T1".to_string(),
+            key_name: "relative".to_string(),
+            mode: ReferenceMode::Epoch,
+            reference_value: None,
+            reference_time: DateTime::from_timestamp_millis(1000),
+            reference_start: None,
+            reference_end: None,
+        }]);
+        assert!(values(&test.db, &scoped).is_empty());
+    }
+
+    fn batch_values(db: &DatabaseManager, params: &QueryParams) -> Vec<(i64, f64)> {
+        let mut all = Vec::new();
+        loop {
+            let batch = db.query_telemetry_data_batch(params, all.len(), 2).unwrap();
+            if batch.is_empty() {
+                return all;
             }
+            all.extend(batch.iter().map(|d| (d.timestamp.timestamp_millis(), d.value)));
+        }
+    }
+
+    #[test]
+    fn stream_batches_apply_reference_values() {
+        let test = TestDb::new("stream_reference", &[
+            (1000, "A", "D1", "T1", "dx", 1.0),
+            (2000, "A", "D1", "T1", "dx", 3.0),
+            (3000, "A", "D1", "T1", "dx", 6.0),
+            (1000, "A", "D1", "T2", "dx", 10.0),
+        ]);
+        let mut query = params(&["dx"]);
+        query.target_names = vec!["T1".to_string()];
+        query.reference_values = Some(vec![ReferenceValue {
+            target_name: "T1".to_string(),
+            key_name: "dx".to_string(),
+            mode: ReferenceMode::Epoch,
+            reference_value: None,
+            reference_time: DateTime::from_timestamp_millis(1000),
+            reference_start: None,
+            reference_end: None,
+        }]);
+        assert_eq!(batch_values(&test.db, &query), [(3000, 5.0), (2000, 2.0), (1000, 0.0)]);
+    }
+
+    #[test]
+    fn stream_batches_apply_filters_and_sampling() {
+        // 01:00-01:05 和 03:00-03:05 每分钟一个点，值为分钟数
+        let rows: Vec<_> = [1, 3].iter()
+            .flat_map(|hour| (0..6).map(move |minute| (hour * 3_600_000 + minute * 60_000, "A", "D1", "T1", "dx", minute as f64)))
+            .collect();
+        let test = TestDb::new("stream_filters", &rows);
+
+        let mut query = params(&["dx"]);
+        query.custom_filter = Some(CustomFilter { min_value: None, max_value: Some(4.0), exclude_values: vec![1.0] });
+        query.end_time = DateTime::from_timestamp_millis(2 * 3_600_000);
+        query.sampling_config = Some(SamplingConfig { interval_ms: 120_000, method: SamplingMethod::Avg });
+        query.limit = Some(1);
+
+        // 排除1和5后按两分钟取平均；limit只限制流的总数，不限制每批
+        assert_eq!(batch_values(&test.db, &query), [(3_840_000, 4.0), (3_720_000, 2.5), (3_600_000, 0.0)]);
+    }
+
+    #[test]
+    fn target_outside_assets_finds_shared_target_names() {
+        let test = TestDb::new("target_outside", &[
+            (1000, "A", "D1", "T1", "dx", 1.0),
+            (1000, "B", "D2", "T1", "dx", 2.0),
+            (1000, "A", "D1", "T2", "dx", 3.0),
+        ]);
+        let a = vec!["A".to_string()];
+        let both = vec!["A".to_string(), "B".to_string()];
+        assert!(test.db.target_in_assets("T1", &a).unwrap());
+        assert!(test.db.target_outside_assets("T1", &a).unwrap());
+        assert!(!test.db.target_outside_assets("T1", &both).unwrap());
+        assert!(!test.db.target_outside_assets("T2", &a).unwrap());
+        assert!(test.db.target_outside_assets("T2", &[]).unwrap());
+        assert!(!test.db.target_outside_assets("T3", &a).unwrap());
+    }
+
+    fn operation(target: &str, key: &str, operation_type: OperationType, value: f64, range: (Option<i64>, Option<i64>)) -> DataOperation {
+        let now = Utc::now();
+        DataOperation {
+            id: None,
+            name: None,
+            description: None,
+            target_name: target.to_string(),
+            key_name: key.to_string(),
+            operation_type,
+            value,
+            start_time: range.0.and_then(DateTime::from_timestamp_millis),
+            end_time: range.1.and_then(DateTime::from_timestamp_millis),
+            is_active: true,
+            created_at: now,
+            updated_at: now,
         }
     }
+
+    #[test]
+    fn operations_sql_matches_operations_on_data() {
+        let rows: Vec<_> = (0..10)
+            .flat_map(|i| [
+                (i * 1000, "A", "D1", "T1", "dx", i as f64),
+                (i * 1000, "A", "D1", "T1", "dy", 10.0 + i as f64),
+                (i * 1000, "A", "D1", "T'2", "dx", -(i as f64)),
+            ])
+            .collect();
+        let test = TestDb::new("operations_sql", &rows);
+
+        let mut inactive = operation("T1", "dy", OperationType::Multiply, 100.0, (None, None));
+        inactive.is_active = false;
+        let operations = vec![
+            operation("T1", "dx", OperationType::Add, 1.5, (None, None)),
+            operation("T1", "dx", OperationType::Multiply, 2.0, (Some(3000), Some(6000))),
+            operation("T1", "dx", OperationType::Subtract, 0.25, (Some(5000), None)),
+            operation("T1", "dx", OperationType::Divide, 0.0, (None, None)),
+            operation("T1", "dx", OperationType::Divide, 4.0, (None, Some(3000))),
+            operation("T'2", "dx", OperationType::Offset, 7.0, (Some(2000), Some(2000))),
+            inactive,
+        ];
+
+        let base = format!("SELECT ts, asset_name, d_name, target_name, key_name, dbl_v FROM {}", TELEMETRY_TABLE);
+        let query = test.db.apply_operations_sql(&base, &operations);
+        // 所有操作合并在一层投影中
+        assert_eq!(query.matches("FROM (").count(), 1);
+
+        let conn = test.db.get_read_connection().unwrap();
+        let mut stmt = conn.prepare(&format!("{} ORDER BY target_name, key_name, ts", query)).unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        let mut actual = Vec::new();
+        while let Some(row) = rows.next().unwrap() {
+            let target: String = row.get(3).unwrap();
+            let key: String = row.get(4).unwrap();
+            actual.push((target, key, row.get::<_, i64>(0).unwrap(), row.get::<_, f64>(5).unwrap()));
+        }
+
+        let mut expected: Vec<TelemetryData> = rows_to_data(&test.db, &base);
+        test.db.apply_operations_to_data(&mut expected, &operations);
+        let expected: Vec<_> = expected.into_iter()
+            .map(|d| (d.target_name, d.key_name, d.timestamp.timestamp_millis(), d.value))
+            .collect();
+        assert_eq!(actual, expected);
+        assert_eq!(actual[2], ("T'2".to_string(), "dx".to_string(), 2000, 5.0));
+        assert_eq!(actual[13], ("T1".to_string(), "dx".to_string(), 3000, 2.25));
+        assert_eq!(actual[15], ("T1".to_string(), "dx".to_string(), 5000, 12.75));
+        assert_eq!(actual[29], ("T1".to_string(), "dy".to_string(), 9000, 19.0));
+
+        // 没有生效的操作时不改变查询
+        assert_eq!(test.db.apply_operations_sql(&base, &operations[3..4]), base);
+    }
+
+    fn rows_to_data(db: &DatabaseManager, base: &str) -> Vec<TelemetryData> {
+        let conn = db.get_read_connection().unwrap();
+        let mut stmt = conn.prepare(&format!("{} ORDER BY target_name, key_name, ts", base)).unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        let mut data = Vec::new();
+        while let Some(row) = rows.next().unwrap() {
+            data.push(TelemetryData {
+                timestamp: DateTime::from_timestamp_millis(row.get(0).unwrap()).unwrap(),
+                asset_name: row.get(1).unwrap(),
+                device_name: row.get(2).unwrap(),
+                target_name: row.get(3).unwrap(),
+                key_name: row.get(4).unwrap(),
+                value: row.get(5).unwrap(),
+            });
+        }
+        data
+    }
+
+    #[test]
+    fn derived_metric_filters_inputs_by_time_and_asset() {
+        let test = TestDb::new("derived_filter", &[
+            (1000, "A", "D1", "T1", "x", 1.0),
+            (2000, "A", "D1", "T1", "x", 2.0),
+            (2000, "C", "D3", "T1", "x", 5.0),
+            (1000, "B", "D2", "REF", "z", 100.0),
+            (2000, "B", "D2", "REF", "z", 200.0),
+        ]);
+        create_relative_metric(&test.db);
+
+        let mut query = params(&["relative"]);
+        query.start_time = DateTime::from_timestamp_millis(1500);
+        query.asset_name = Some("A".to_string());
+        // 固定标靶不受 asset_name 限制，时间范围外的读数不参与计算
+        assert_eq!(values(&test.db, &query), [("A".to_string(), 2000, 202.0)]);
+    }
 }
@@ -0,0 +1,190 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use utoipa::ToSchema;
+
+// 关联ID计数器，保证同一毫秒内生成的ID也不重复
+static CORRELATION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// 字段级错误详情
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FieldError {
+    /// 字段路径，例如 `start_time` 或 `operations[2].value`
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// API错误类型，每个变体对应固定的HTTP状态码和错误码
+#[derive(Debug)]
+pub enum ApiError {
+    /// 请求参数校验失败
+    Validation(Vec<FieldError>),
+    /// 未登录或凭据无效
+    Unauthorized(String),
+    /// 已登录但权限不足
+    Forbidden(String),
+    /// 请求的资源不存在
+    NotFound(String),
+    /// 与已有数据冲突
+    Conflict(String),
+    /// 数据库内部错误（细节只写入日志，不返回给客户端）
+    Database(anyhow::Error),
+    /// 查询超时
+    Timeout(String),
+}
+
+/// 错误响应体
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// 始终为false
+    success: bool,
+    #[schema(value_type = Option<Object>)]
+    data: Option<()>,
+    /// 可读的错误消息
+    error: String,
+    /// 机器可读错误码: VALIDATION_FAILED, UNAUTHORIZED, FORBIDDEN, NOT_FOUND, CONFLICT, DATABASE_ERROR, TIMEOUT
+    #[schema(value_type = String, example = "VALIDATION_FAILED")]
+    code: &'static str,
+    /// 字段级错误（仅校验失败时返回）
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    details: Vec<FieldError>,
+    /// 用于在服务端日志中定位内部错误
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+}
+
+impl ApiError {
+    /// 单个字段的校验错误
+    pub fn validation(field: impl Into<String>, message: impl Into<String>) -> Self {
+        ApiError::Validation(vec![FieldError::new(field, message)])
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ApiError::NotFound(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        ApiError::Forbidden(message.into())
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// 稳定的机器可读错误码
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Validation(_) => "VALIDATION_FAILED",
+            ApiError::Unauthorized(_) => "UNAUTHORIZED",
+            ApiError::Forbidden(_) => "FORBIDDEN",
+            ApiError::NotFound(_) => "NOT_FOUND",
+            ApiError::Conflict(_) => "CONFLICT",
+            ApiError::Database(_) => "DATABASE_ERROR",
+            ApiError::Timeout(_) => "TIMEOUT",
+        }
+    }
+}
+
+/// 生成用于关联日志和响应的ID
+pub fn new_correlation_id() -> String {
+    let seq = CORRELATION_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:04x}", Utc::now().timestamp_millis(), seq & 0xffff)
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Validation(details) => {
+                let fields = details.iter()
+                    .map(|d| format!("{}: {}", d.field, d.message))
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                write!(f, "Validation failed: {}", fields)
+            }
+            ApiError::Unauthorized(message) => write!(f, "{}", message),
+            ApiError::Forbidden(message) => write!(f, "{}", message),
+            ApiError::NotFound(message) => write!(f, "{}", message),
+            ApiError::Conflict(message) => write!(f, "{}", message),
+            ApiError::Database(e) => write!(f, "Database error: {:#}", e),
+            ApiError::Timeout(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        // DuckDB的约束冲突（主键/唯一键）视为资源冲突
+        let is_constraint = e.chain().any(|cause| {
+            cause.downcast_ref::<duckdb::Error>()
+                .map(|db_err| db_err.to_string().contains("Constraint Error"))
+                .unwrap_or(false)
+        });
+        if is_constraint {
+            eprintln!("Constraint violation: {:#}", e);
+            ApiError::Conflict("Request conflicts with existing data".to_string())
+        } else {
+            ApiError::Database(e)
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        let code = self.code();
+
+        // 校验错误的消息中包含全部字段错误，便于前端直接展示
+        let message = self.to_string();
+        let (error, details, correlation_id) = match self {
+            ApiError::Validation(details) => (message, details, None),
+            ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message) => (message, Vec::new(), None),
+            ApiError::Timeout(message) => {
+                let correlation_id = new_correlation_id();
+                eprintln!("[{}] Timeout: {}", correlation_id, message);
+                (message, Vec::new(), Some(correlation_id))
+            }
+            ApiError::Database(e) => {
+                // 内部错误只记录日志，客户端通过关联ID定位
+                let correlation_id = new_correlation_id();
+                eprintln!("[{}] Database error: {:#}", correlation_id, e);
+                ("Internal database error".to_string(), Vec::new(), Some(correlation_id))
+            }
+        };
+
+        let body = ErrorBody {
+            success: false,
+            data: None,
+            error,
+            code,
+            details,
+            correlation_id,
+        };
+
+        (status, Json(body)).into_response()
+    }
+}
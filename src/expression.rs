@@ -0,0 +1,418 @@
+//! 派生指标的算术表达式：解析并转换为DuckDB SQL
+//!
+//! 支持数字、变量、`+ - * / ^`、括号和白名单中的函数，例如 `sqrt(dx^2 + dy^2)`、`degrees(atan2(dy, dx))`
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(&'static str, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+// 函数名和参数个数（None表示至少两个）
+const FUNCTIONS: &[(&str, Option<usize>)] = &[
+    ("sqrt", Some(1)),
+    ("abs", Some(1)),
+    ("exp", Some(1)),
+    ("ln", Some(1)),
+    ("log10", Some(1)),
+    ("sin", Some(1)),
+    ("cos", Some(1)),
+    ("tan", Some(1)),
+    ("asin", Some(1)),
+    ("acos", Some(1)),
+    ("atan", Some(1)),
+    ("atan2", Some(2)),
+    ("pow", Some(2)),
+    ("degrees", Some(1)),
+    ("radians", Some(1)),
+    ("least", None),
+    ("greatest", None),
+];
+
+/// 变量名：字母或下划线开头，由字母、数字和下划线组成，不能与函数或常量同名
+pub fn is_valid_variable(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name != "pi"
+        && !FUNCTIONS.iter().any(|(f, _)| *f == name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(char),
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_digit() || c == '.' {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // 科学计数法，例如 1e-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text.parse::<f64>().ok().filter(|v| v.is_finite())
+                .ok_or_else(|| format!("Invalid number '{}' at position {}", text, start + 1))?;
+            tokens.push((start, Token::Number(value)));
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((start, Token::Ident(chars[start..i].iter().collect())));
+            continue;
+        }
+        let token = match c {
+            '+' | '-' | '*' | '/' | '^' => Token::Op(c),
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            _ => return Err(format!("Unexpected character '{}' at position {}", c, start + 1)),
+        };
+        tokens.push((start, token));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+// 括号、负号和乘方的最大嵌套深度，避免深层递归耗尽栈
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    len: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    // 当前位置（从1开始），用于错误信息
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map(|(p, _)| p + 1).unwrap_or(self.len + 1)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, t)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), String> {
+        let position = self.position();
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(format!("Expected {} at position {}", what, position)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        let mut left = self.term()?;
+        while let Some(Token::Op(c @ ('+' | '-'))) = self.peek() {
+            let op = if *c == '+' { BinaryOp::Add } else { BinaryOp::Subtract };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.term()?));
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Op(c @ ('*' | '/'))) = self.peek() {
+            let op = if *c == '*' { BinaryOp::Multiply } else { BinaryOp::Divide };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    // 每一层嵌套（括号、函数参数、负号和乘方的指数）都经过这里，在此限制深度
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("Expression is nested too deeply at position {}", self.position()));
+        }
+        self.depth += 1;
+        let result = self.signed();
+        self.depth -= 1;
+        result
+    }
+
+    // 负号的优先级低于乘方：-x^2 = -(x^2)
+    fn signed(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Op('-')) => {
+                self.pos += 1;
+                Ok(Expr::Negate(Box::new(self.unary()?)))
+            }
+            Some(Token::Op('+')) => {
+                self.pos += 1;
+                self.unary()
+            }
+            _ => self.power(),
+        }
+    }
+
+    // 乘方右结合：a^b^c = a^(b^c)
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.primary()?;
+        if let Some(Token::Op('^')) = self.peek() {
+            self.pos += 1;
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(BinaryOp::Power, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::LParen) => {
+                let inner = self.expression()?;
+                self.expect(Token::RParen, "')'")?;
+                Ok(inner)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() != Some(&Token::LParen) {
+                    return Ok(if name == "pi" { Expr::Number(std::f64::consts::PI) } else { Expr::Variable(name) });
+                }
+                let (function, arity) = FUNCTIONS.iter()
+                    .find(|(f, _)| *f == name)
+                    .copied()
+                    .ok_or_else(|| format!("Unknown function '{}' at position {}", name, position))?;
+                self.pos += 1;
+                let mut args = Vec::new();
+                if self.peek() != Some(&Token::RParen) {
+                    args.push(self.expression()?);
+                    while self.peek() == Some(&Token::Comma) {
+                        self.pos += 1;
+                        args.push(self.expression()?);
+                    }
+                }
+                self.expect(Token::RParen, "')'")?;
+                match arity {
+                    Some(n) if args.len() != n => {
+                        Err(format!("{} expects {} argument(s), got {} at position {}", function, n, args.len(), position))
+                    }
+                    None if args.len() < 2 => {
+                        Err(format!("{} expects at least 2 arguments at position {}", function, position))
+                    }
+                    _ => Ok(Expr::Call(function, args)),
+                }
+            }
+            _ => Err(format!("Expected a number, variable or '(' at position {}", position)),
+        }
+    }
+}
+
+/// 解析表达式，错误信息包含出错位置
+pub fn parse(source: &str) -> Result<Expr, String> {
+    let tokens = tokenize(source)?;
+    if tokens.is_empty() {
+        return Err("Expression is empty".to_string());
+    }
+    let mut parser = Parser { tokens, pos: 0, len: source.chars().count(), depth: 0 };
+    let expr = parser.expression()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(format!("Unexpected input at position {}", parser.position()));
+    }
+    Ok(expr)
+}
+
+impl Expr {
+    /// 表达式中使用的变量
+    pub fn variables(&self) -> BTreeSet<&str> {
+        let mut variables = BTreeSet::new();
+        self.collect_variables(&mut variables);
+        variables
+    }
+
+    fn collect_variables<'a>(&'a self, variables: &mut BTreeSet<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Variable(name) => {
+                variables.insert(name);
+            }
+            Expr::Negate(inner) => inner.collect_variables(variables),
+            Expr::Binary(_, left, right) => {
+                left.collect_variables(variables);
+                right.collect_variables(variables);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|arg| arg.collect_variables(variables)),
+        }
+    }
+
+    /// 转换为DuckDB SQL，`variable` 给出每个变量对应的列
+    ///
+    /// 超出定义域（负数开方、非正数取对数、除以0等）的结果为NULL，而不是让整个查询报错
+    pub fn to_sql(&self, variable: &dyn Fn(&str) -> String) -> String {
+        match self {
+            Expr::Number(value) => format!("CAST({:?} AS DOUBLE)", value),
+            Expr::Variable(name) => variable(name),
+            Expr::Negate(inner) => format!("(-{})", inner.to_sql(variable)),
+            Expr::Binary(op, left, right) => {
+                let (l, r) = (left.to_sql(variable), right.to_sql(variable));
+                match op {
+                    BinaryOp::Add => format!("({} + {})", l, r),
+                    BinaryOp::Subtract => format!("({} - {})", l, r),
+                    BinaryOp::Multiply => format!("({} * {})", l, r),
+                    BinaryOp::Divide => format!("({} / NULLIF({}, 0))", l, r),
+                    BinaryOp::Power => format!("pow({}, {})", l, r),
+                }
+            }
+            Expr::Call(function, args) => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_sql(variable)).collect();
+                let guard = |condition: String, call: String| format!("(CASE WHEN {} THEN {} END)", condition, call);
+                match *function {
+                    "sqrt" => guard(format!("{} >= 0", args[0]), format!("sqrt({})", args[0])),
+                    "ln" | "log10" => guard(format!("{} > 0", args[0]), format!("{}({})", function, args[0])),
+                    "asin" | "acos" => guard(
+                        format!("{} BETWEEN -1 AND 1", args[0]),
+                        format!("{}({})", function, args[0]),
+                    ),
+                    _ => format!("{}({})", function, args.join(", ")),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Variable(name.to_string()))
+    }
+
+    fn num(value: f64) -> Box<Expr> {
+        Box::new(Expr::Number(value))
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        // a + b * c = a + (b * c)
+        assert_eq!(
+            parse("a + b * c").unwrap(),
+            Expr::Binary(BinaryOp::Add, var("a"), Box::new(Expr::Binary(BinaryOp::Multiply, var("b"), var("c")))),
+        );
+        // a - b - c = (a - b) - c
+        assert_eq!(
+            parse("a - b - c").unwrap(),
+            Expr::Binary(BinaryOp::Subtract, Box::new(Expr::Binary(BinaryOp::Subtract, var("a"), var("b"))), var("c")),
+        );
+        // a ^ b ^ c = a ^ (b ^ c)
+        assert_eq!(
+            parse("a^b^c").unwrap(),
+            Expr::Binary(BinaryOp::Power, var("a"), Box::new(Expr::Binary(BinaryOp::Power, var("b"), var("c")))),
+        );
+        // 括号改变优先级
+        assert_eq!(
+            parse("(a + b) / 2").unwrap(),
+            Expr::Binary(BinaryOp::Divide, Box::new(Expr::Binary(BinaryOp::Add, var("a"), var("b"))), num(2.0)),
+        );
+    }
+
+    #[test]
+    fn unary_minus() {
+        // -x^2 = -(x^2)
+        assert_eq!(
+            parse("-x^2").unwrap(),
+            Expr::Negate(Box::new(Expr::Binary(BinaryOp::Power, var("x"), num(2.0)))),
+        );
+        // 2^-1 的指数可以带负号
+        assert_eq!(
+            parse("2^-1").unwrap(),
+            Expr::Binary(BinaryOp::Power, num(2.0), Box::new(Expr::Negate(num(1.0)))),
+        );
+        assert_eq!(parse("a * -b").unwrap(), Expr::Binary(BinaryOp::Multiply, var("a"), Box::new(Expr::Negate(var("b")))));
+        assert_eq!(parse("--a").unwrap(), Expr::Negate(Box::new(Expr::Negate(var("a")))));
+        assert_eq!(parse("+a").unwrap(), Expr::Variable("a".to_string()));
+    }
+
+    #[test]
+    fn functions_and_constants() {
+        let expr = parse("degrees(atan2(dy, dx)) + pi").unwrap();
+        assert_eq!(expr.variables().into_iter().collect::<Vec<_>>(), ["dx", "dy"]);
+        assert!(parse("sqrt(a, b)").unwrap_err().contains("sqrt expects 1 argument"));
+        assert!(parse("least(a)").unwrap_err().contains("at least 2 arguments"));
+        assert!(parse("foo(a)").unwrap_err().contains("Unknown function 'foo'"));
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        assert_eq!(parse("(a + b").unwrap_err(), "Expected ')' at position 7");
+        assert_eq!(parse("a + b)").unwrap_err(), "Unexpected input at position 6");
+        assert_eq!(parse("sqrt(a").unwrap_err(), "Expected ')' at position 7");
+        assert_eq!(parse("()").unwrap_err(), "Expected a number, variable or '(' at position 2");
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert_eq!(parse("  ").unwrap_err(), "Expression is empty");
+        assert_eq!(parse("a $ b").unwrap_err(), "Unexpected character '$' at position 3");
+        assert_eq!(parse("a +").unwrap_err(), "Expected a number, variable or '(' at position 4");
+        assert_eq!(parse("1e-3").unwrap(), Expr::Number(1e-3));
+    }
+
+    #[test]
+    fn nesting_depth_is_limited() {
+        let nested = |depth: usize| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH - 1)).is_ok());
+        assert!(parse(&nested(MAX_DEPTH)).unwrap_err().contains("nested too deeply"));
+        // 远超限制的输入返回错误而不是栈溢出
+        assert!(parse(&nested(100_000)).unwrap_err().contains("nested too deeply"));
+        assert!(parse(&format!("{}a", "-".repeat(100_000))).unwrap_err().contains("nested too deeply"));
+        assert!(parse(&format!("a{}", "^a".repeat(100_000))).unwrap_err().contains("nested too deeply"));
+    }
+
+    #[test]
+    fn sql_guards_domain() {
+        let sql = parse("sqrt(x) / y").unwrap().to_sql(&|name| format!("t.{}", name));
+        assert_eq!(sql, "((CASE WHEN t.x >= 0 THEN sqrt(t.x) END) / NULLIF(t.y, 0))");
+    }
+}
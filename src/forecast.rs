@@ -0,0 +1,440 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::database::SeriesData;
+use crate::trend::normal_quantile;
+
+// 一天的毫秒数，Holt-Winters的季节周期
+pub const DAY_MS: i64 = 86_400_000;
+
+/// 查询没有指定采样时使用的采样间隔（一小时）
+pub const DEFAULT_INTERVAL_MS: i64 = 3_600_000;
+
+// 规整后最多使用最近多少个点拟合
+const MAX_HISTORY_POINTS: usize = 20_000;
+
+// Holt线性模型至少需要的点数（两个点初始化，其余用于估计参数和误差）
+const MIN_HOLT_POINTS: usize = 4;
+
+// Holt-Winters至少需要的完整季节数（第一个季节初始化季节项，前两个季节估计初始趋势）
+const MIN_SEASONS: usize = 2;
+
+// 平滑参数网格搜索的取值
+const PARAMETER_GRID: [f64; 10] = [0.05, 0.15, 0.25, 0.35, 0.45, 0.55, 0.65, 0.75, 0.85, 0.95];
+
+/// 预测模型
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ForecastModel {
+    /// Holt线性趋势（水平+趋势）
+    #[default]
+    Holt,
+    /// 加法Holt-Winters，水平+趋势+日周期季节项
+    HoltWinters,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ForecastOptions {
+    pub model: ForecastModel,
+    /// 规整输入和预测的时间步长（毫秒），即采样间隔
+    pub interval_ms: i64,
+    /// 预测步数
+    pub steps: usize,
+    pub threshold: Option<f64>,
+}
+
+/// 一个预测点及其80%、95%预测区间
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ForecastPoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
+    pub lower_80: f64,
+    pub upper_80: f64,
+    pub lower_95: f64,
+    pub upper_95: f64,
+}
+
+/// 单个序列的预测结果
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SeriesForecast {
+    pub asset_name: String,
+    pub device_name: String,
+    pub target_name: String,
+    pub key_name: String,
+    /// 实际使用的模型，数据不足两天时Holt-Winters退回Holt
+    pub model: ForecastModel,
+    pub interval_ms: i64,
+    /// 规整后参与拟合的点数（含插值补齐的点）
+    pub points: usize,
+    pub last_time: Option<DateTime<Utc>>,
+    pub last_value: Option<f64>,
+    /// 水平的平滑参数
+    pub alpha: Option<f64>,
+    /// 趋势的平滑参数（误差修正形式，0 < beta < alpha）
+    pub beta: Option<f64>,
+    /// 季节项的平滑参数（误差修正形式），仅Holt-Winters
+    pub gamma: Option<f64>,
+    /// 一步预测误差的均方根
+    pub rmse: Option<f64>,
+    /// 点数不足时为空
+    pub forecast: Vec<ForecastPoint>,
+    pub threshold: Option<f64>,
+    /// 预测值到达阈值的时间，预测范围内未到达时为空
+    pub threshold_time: Option<DateTime<Utc>>,
+    /// 从last_time到threshold_time的毫秒数
+    pub time_to_threshold_ms: Option<i64>,
+    /// 95%预测区间最早到达阈值的时间
+    pub threshold_time_earliest: Option<DateTime<Utc>>,
+}
+
+// 拟合后的模型状态，季节项按 (时间步 % 周期) 存放
+struct ModelFit {
+    alpha: f64,
+    beta: f64,
+    gamma: f64,
+    level: f64,
+    trend: f64,
+    season: Vec<f64>,
+    sse: f64,
+    count: usize,
+}
+
+/// 把序列规整为等间隔后拟合模型，预测之后 `steps` 个时间步
+///
+/// 输入应已按 `interval_ms` 采样，时间戳为采样窗口的起点；缺失的窗口用前后两点线性插值
+pub fn forecast_series(series: &SeriesData, options: &ForecastOptions) -> SeriesForecast {
+    let (times, values) = regularize(series, options.interval_ms);
+    let period = (DAY_MS / options.interval_ms) as usize;
+
+    let use_seasonal = options.model == ForecastModel::HoltWinters
+        && DAY_MS % options.interval_ms == 0
+        && period >= 2
+        && values.len() > MIN_SEASONS * period;
+    let fit = if use_seasonal {
+        fit_holt_winters(&values, period)
+    } else {
+        fit_holt(&values)
+    };
+
+    let mut result = SeriesForecast {
+        asset_name: series.key.asset_name.clone(),
+        device_name: series.key.device_name.clone(),
+        target_name: series.key.target_name.clone(),
+        key_name: series.key.key_name.clone(),
+        model: if use_seasonal { ForecastModel::HoltWinters } else { ForecastModel::Holt },
+        interval_ms: options.interval_ms,
+        points: values.len(),
+        last_time: times.last().copied(),
+        last_value: values.last().copied(),
+        alpha: None,
+        beta: None,
+        gamma: None,
+        rmse: None,
+        forecast: Vec::new(),
+        threshold: options.threshold,
+        threshold_time: None,
+        time_to_threshold_ms: None,
+        threshold_time_earliest: None,
+    };
+    let (Some(fit), Some(&last_time), Some(&last_value)) = (fit, times.last(), values.last()) else {
+        return result;
+    };
+
+    let sigma = (fit.sse / fit.count as f64).sqrt();
+    let (z80, z95) = (normal_quantile(0.9), normal_quantile(0.975));
+    let mut variance_sum = 1.0;
+    for h in 1..=options.steps {
+        // h步预测误差的方差为 sigma²·(1 + Σ c_j²)，c_j = alpha + beta·j（季节项在整周期处再加gamma）
+        if h > 1 {
+            let j = h - 1;
+            let seasonal = if use_seasonal && j % period == 0 { fit.gamma } else { 0.0 };
+            variance_sum += (fit.alpha + fit.beta * j as f64 + seasonal).powi(2);
+        }
+        let season = if use_seasonal { fit.season[(values.len() - 1 + h) % period] } else { 0.0 };
+        let value = fit.level + fit.trend * h as f64 + season;
+        let spread = sigma * variance_sum.sqrt();
+        result.forecast.push(ForecastPoint {
+            timestamp: last_time + Duration::milliseconds(options.interval_ms * h as i64),
+            value,
+            lower_80: value - z80 * spread,
+            upper_80: value + z80 * spread,
+            lower_95: value - z95 * spread,
+            upper_95: value + z95 * spread,
+        });
+    }
+
+    if let Some(threshold) = options.threshold {
+        // 阈值不低于最后一个值时判断向上穿越，否则判断向下穿越
+        let rising = threshold >= last_value;
+        let reached = |value: f64| if rising { value >= threshold } else { value <= threshold };
+        result.threshold_time = result.forecast.iter().find(|p| reached(p.value)).map(|p| p.timestamp);
+        result.threshold_time_earliest = result.forecast.iter()
+            .find(|p| reached(if rising { p.upper_95 } else { p.lower_95 }))
+            .map(|p| p.timestamp);
+        result.time_to_threshold_ms = result.threshold_time.map(|t| (t - last_time).num_milliseconds());
+    }
+
+    result.alpha = Some(fit.alpha);
+    result.beta = Some(fit.beta);
+    result.gamma = use_seasonal.then_some(fit.gamma);
+    result.rmse = Some(sigma);
+    result
+}
+
+// 按采样间隔把序列放到等间隔的时间格上，缺失的格子线性插值；格子过多时只保留最近的部分
+fn regularize(series: &SeriesData, interval_ms: i64) -> (Vec<DateTime<Utc>>, Vec<f64>) {
+    let data = &series.data;
+    let (Some(first), Some(last)) = (data.first(), data.last()) else {
+        return (Vec::new(), Vec::new());
+    };
+    let bucket = |t: DateTime<Utc>| t.timestamp_millis().div_euclid(interval_ms);
+    let last_bucket = bucket(last.timestamp);
+    let first_bucket = bucket(first.timestamp).max(last_bucket - MAX_HISTORY_POINTS as i64 + 1);
+
+    let mut values: Vec<Option<f64>> = vec![None; (last_bucket - first_bucket + 1) as usize];
+    for item in data {
+        let b = bucket(item.timestamp);
+        if b >= first_bucket {
+            values[(b - first_bucket) as usize] = Some(item.value);
+        }
+    }
+
+    // 保留的范围可能从缺失的格子开始，从第一个有值的格子算起
+    let offset = values.iter().position(|v| v.is_some()).unwrap_or(0);
+    let mut filled = Vec::with_capacity(values.len());
+    let mut previous: Option<(usize, f64)> = None;
+    for (i, value) in values.iter().enumerate() {
+        let Some(v) = *value else { continue };
+        if let Some((p, pv)) = previous {
+            for k in p + 1..i {
+                filled.push(pv + (v - pv) * (k - p) as f64 / (i - p) as f64);
+            }
+        }
+        filled.push(v);
+        previous = Some((i, v));
+    }
+
+    let times = (0..filled.len())
+        .map(|i| {
+            let ms = (first_bucket + (offset + i) as i64) * interval_ms;
+            DateTime::from_timestamp_millis(ms).unwrap_or_default()
+        })
+        .collect();
+    (times, filled)
+}
+
+// 误差修正形式：水平 += alpha·e，趋势 += beta·e，网格搜索使一步预测误差平方和最小的参数
+fn fit_holt(values: &[f64]) -> Option<ModelFit> {
+    if values.len() < MIN_HOLT_POINTS {
+        return None;
+    }
+    let mut best: Option<ModelFit> = None;
+    for &alpha in &PARAMETER_GRID {
+        for &beta_ratio in &PARAMETER_GRID {
+            let beta = alpha * beta_ratio;
+            let mut level = values[0];
+            let mut trend = values[1] - values[0];
+            let mut sse = 0.0;
+            for &y in &values[1..] {
+                let error = y - (level + trend);
+                sse += error * error;
+                level += trend + alpha * error;
+                trend += beta * error;
+            }
+            if best.as_ref().is_none_or(|b| sse < b.sse) {
+                best = Some(ModelFit {
+                    alpha,
+                    beta,
+                    gamma: 0.0,
+                    level,
+                    trend,
+                    season: Vec::new(),
+                    sse,
+                    count: values.len() - 1,
+                });
+            }
+        }
+    }
+    best
+}
+
+// 加法Holt-Winters：第一个季节的均值和前两个季节均值之差初始化水平和趋势，季节项 += gamma·e
+fn fit_holt_winters(values: &[f64], period: usize) -> Option<ModelFit> {
+    if values.len() <= MIN_SEASONS * period {
+        return None;
+    }
+    let first_mean = values[..period].iter().sum::<f64>() / period as f64;
+    let second_mean = values[period..2 * period].iter().sum::<f64>() / period as f64;
+    let initial_trend = (second_mean - first_mean) / period as f64;
+    // 第一个季节的中点对应first_mean，外推到季节末尾作为初始水平
+    let center = (period - 1) as f64 / 2.0;
+    let initial_level = first_mean + initial_trend * center;
+    let initial_season: Vec<f64> = (0..period)
+        .map(|i| values[i] - (first_mean + initial_trend * (i as f64 - center)))
+        .collect();
+
+    let mut best: Option<ModelFit> = None;
+    for &alpha in &PARAMETER_GRID {
+        for &beta_ratio in &PARAMETER_GRID {
+            for &gamma_ratio in &PARAMETER_GRID {
+                let beta = alpha * beta_ratio;
+                let gamma = (1.0 - alpha) * gamma_ratio;
+                let mut level = initial_level;
+                let mut trend = initial_trend;
+                let mut season = initial_season.clone();
+                let mut sse = 0.0;
+                for (t, &y) in values.iter().enumerate().skip(period) {
+                    let slot = t % period;
+                    let error = y - (level + trend + season[slot]);
+                    sse += error * error;
+                    level += trend + alpha * error;
+                    trend += beta * error;
+                    season[slot] += gamma * error;
+                }
+                if best.as_ref().is_none_or(|b| sse < b.sse) {
+                    best = Some(ModelFit {
+                        alpha,
+                        beta,
+                        gamma,
+                        level,
+                        trend,
+                        season,
+                        sse,
+                        count: values.len() - period,
+                    });
+                }
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{SeriesKey, TelemetryData};
+
+    const HOUR_MS: i64 = 3_600_000;
+
+    // 从2024-01-01开始每个时间步一个点，None表示缺失
+    fn series(values: &[Option<f64>], interval_ms: i64) -> SeriesData {
+        let start = 1_704_067_200_000;
+        SeriesData {
+            key: SeriesKey {
+                asset_name: "A".to_string(),
+                device_name: "D".to_string(),
+                target_name: "T".to_string(),
+                key_name: "dz".to_string(),
+            },
+            data: values.iter().enumerate()
+                .filter_map(|(i, value)| Some(TelemetryData {
+                    timestamp: DateTime::from_timestamp_millis(start + i as i64 * interval_ms)?,
+                    asset_name: "A".to_string(),
+                    device_name: "D".to_string(),
+                    target_name: "T".to_string(),
+                    key_name: "dz".to_string(),
+                    value: (*value)?,
+                }))
+                .collect(),
+        }
+    }
+
+    fn options(model: ForecastModel, steps: usize, threshold: Option<f64>) -> ForecastOptions {
+        ForecastOptions { model, interval_ms: HOUR_MS, steps, threshold }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "{} != {} ± {}", actual, expected, tolerance);
+    }
+
+    #[test]
+    fn holt_recovers_linear_slope() {
+        let values: Vec<f64> = (0..50).map(|i| 5.0 + 0.5 * i as f64).collect();
+        let fit = fit_holt(&values).unwrap();
+        assert_close(fit.trend, 0.5, 1e-9);
+        assert_close(fit.level, 29.5, 1e-9);
+        assert!(fit.sse < 1e-12);
+        assert!(fit.beta < fit.alpha);
+
+        let result = forecast_series(&series(&values.into_iter().map(Some).collect::<Vec<_>>(), HOUR_MS), &options(ForecastModel::Holt, 10, None));
+        assert_eq!(result.model, ForecastModel::Holt);
+        assert_eq!(result.points, 50);
+        assert_eq!(result.forecast.len(), 10);
+        for (h, point) in result.forecast.iter().enumerate() {
+            assert_close(point.value, 29.5 + 0.5 * (h + 1) as f64, 1e-9);
+            assert_eq!(point.timestamp, result.last_time.unwrap() + Duration::hours(h as i64 + 1));
+        }
+        assert!(fit_holt(&[1.0, 2.0, 3.0]).is_none());
+    }
+
+    #[test]
+    fn holt_winters_recovers_daily_season() {
+        let truth = |t: usize| 10.0 + 0.02 * t as f64 + 2.0 * (2.0 * std::f64::consts::PI * t as f64 / 24.0).sin();
+        let values: Vec<Option<f64>> = (0..24 * 6).map(|t| Some(truth(t))).collect();
+        let result = forecast_series(&series(&values, HOUR_MS), &options(ForecastModel::HoltWinters, 24, None));
+
+        assert_eq!(result.model, ForecastModel::HoltWinters);
+        assert!(result.gamma.is_some());
+        assert!(result.rmse.unwrap() < 0.05, "{:?}", result.rmse);
+        for (h, point) in result.forecast.iter().enumerate() {
+            assert_close(point.value, truth(24 * 6 + h), 0.1);
+            assert!(point.lower_95 <= point.lower_80 && point.lower_80 <= point.value);
+            assert!(point.value <= point.upper_80 && point.upper_80 <= point.upper_95);
+        }
+
+        // Holt不能表示日周期，误差明显更大
+        let holt = forecast_series(&series(&values, HOUR_MS), &options(ForecastModel::Holt, 24, None));
+        assert!(holt.rmse.unwrap() > 5.0 * result.rmse.unwrap());
+
+        // 不满两个完整季节时退回Holt
+        let short = forecast_series(&series(&values[..48], HOUR_MS), &options(ForecastModel::HoltWinters, 24, None));
+        assert_eq!(short.model, ForecastModel::Holt);
+        assert!(short.gamma.is_none());
+    }
+
+    #[test]
+    fn regularize_interpolates_gaps() {
+        let input = series(&[Some(1.0), Some(2.0), None, None, Some(8.0), None, Some(4.0)], HOUR_MS);
+        let (times, values) = regularize(&input, HOUR_MS);
+        assert_eq!(values, [1.0, 2.0, 4.0, 6.0, 8.0, 6.0, 4.0]);
+        assert_eq!(times.len(), 7);
+        assert_eq!(times[0], input.data[0].timestamp);
+        assert_eq!(times[6], input.data.last().unwrap().timestamp);
+
+        // 不在格子起点的时间戳归入所在的格子
+        let mut shifted = input.clone();
+        for item in &mut shifted.data {
+            item.timestamp += Duration::minutes(20);
+        }
+        assert_eq!(regularize(&shifted, HOUR_MS), (times, values));
+
+        let empty = series(&[], HOUR_MS);
+        assert_eq!(regularize(&empty, HOUR_MS), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn threshold_crossing_in_both_directions() {
+        let rising: Vec<Option<f64>> = (0..20).map(|i| Some(i as f64)).collect();
+        let result = forecast_series(&series(&rising, HOUR_MS), &options(ForecastModel::Holt, 10, Some(22.5)));
+        let last_time = result.last_time.unwrap();
+        assert_eq!(result.threshold_time, Some(last_time + Duration::hours(4)));
+        assert_eq!(result.time_to_threshold_ms, Some(4 * HOUR_MS));
+        assert!(result.threshold_time_earliest.unwrap() <= result.threshold_time.unwrap());
+
+        let falling: Vec<Option<f64>> = (0..20).map(|i| Some(100.0 - 2.0 * i as f64)).collect();
+        let result = forecast_series(&series(&falling, HOUR_MS), &options(ForecastModel::Holt, 10, Some(55.0)));
+        assert_eq!(result.threshold_time, Some(result.last_time.unwrap() + Duration::hours(4)));
+        assert_eq!(result.time_to_threshold_ms, Some(4 * HOUR_MS));
+
+        // 预测范围内未到达
+        let result = forecast_series(&series(&falling, HOUR_MS), &options(ForecastModel::Holt, 10, Some(0.0)));
+        assert_eq!(result.threshold_time, None);
+        assert_eq!(result.time_to_threshold_ms, None);
+
+        // 噪声使95%区间比预测值更早到达阈值
+        let noisy: Vec<Option<f64>> = (0..60).map(|i| Some(i as f64 + if i % 2 == 0 { 1.0 } else { -1.0 })).collect();
+        let result = forecast_series(&series(&noisy, HOUR_MS), &options(ForecastModel::Holt, 48, Some(80.0)));
+        assert!(result.threshold_time_earliest.unwrap() < result.threshold_time.unwrap());
+    }
+}
@@ -0,0 +1,16 @@
+//! 服务端的全部模块，服务器（main.rs）和 bench_anomaly 等工具都依赖此库
+
+// 各枚举的 from_str 返回 Option，与数据库中的字符串对应，不实现 FromStr
+#![allow(clippy::should_implement_trait)]
+
+pub mod database;
+pub mod api;
+pub mod anomaly_detection;
+pub mod expression;
+pub mod alarms;
+pub mod notifications;
+pub mod auth;
+pub mod error;
+pub mod validation;
+pub mod trend;
+pub mod forecast;
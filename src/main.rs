@@ -1,23 +1,36 @@
-mod database;
-mod api;
-mod anomaly_detection;
-
 use axum::Router;
 use tower::ServiceBuilder;
 use tower_http::{cors::CorsLayer, services::ServeDir};
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use database::DatabaseManager;
-use api::create_router;
+use local_data_client::{alarms, auth, notifications};
+use local_data_client::database::DatabaseManager;
+use local_data_client::anomaly_detection::DetectorRegistry;
+use local_data_client::api::create_router;
+use local_data_client::notifications::Notifier;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // 初始化数据库连接
-    let db_manager = DatabaseManager::new("data.db")?;
+    let db_manager = Arc::new(DatabaseManager::new("data.db")?);
     println!("Database connection established");
 
+    // 首次启动时创建管理员账号
+    auth::ensure_admin_user(&db_manager)?;
+
+    // 异常检测器注册表，站点自定义的检测器在此注册
+    let detectors = DetectorRegistry::with_builtin();
+
+    // 通知通道，配置从 notifications.json 读取
+    let notifier = Arc::new(Notifier::from_env()?);
+    notifications::spawn_dispatcher(db_manager.clone(), notifier.clone());
+
+    // 后台定时判断告警规则
+    alarms::spawn_evaluator(db_manager.clone(), notifier.clone());
+
     // 创建API路由
-    let api_router = create_router(db_manager);
+    let api_router = create_router(db_manager, detectors, notifier);
 
     // 创建完整的应用路由
     let app = Router::new()
@@ -0,0 +1,998 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::database::{serialize_shanghai_time, AlarmLevel, DatabaseManager, OutboxEntry};
+
+// 通道配置文件，文件不存在时不发送通知
+const CONFIG_ENV: &str = "LDC_NOTIFY_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "notifications.json";
+
+// 发件箱的轮询间隔，发布通知时会立即唤醒
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+// 每轮最多处理的通知数
+const BATCH_SIZE: usize = 100;
+// 重试间隔从30秒开始加倍，最长1小时
+const RETRY_BASE_SECS: i64 = 30;
+const RETRY_MAX_SECS: i64 = 3600;
+const DEFAULT_MAX_ATTEMPTS: u32 = 6;
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+// 命令失败时错误信息中最多保留的标准错误字节数
+const MAX_STDERR_BYTES: u64 = 4096;
+
+/// 一条通知，服务器上的事件（告警、异常检测等）通过 `Notifier::publish` 发布
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Notification {
+    /// 事件名称，例如 "alarm.raised"、"anomaly.detected"
+    pub event: String,
+    pub title: String,
+    pub message: String,
+    /// 告警级别，其他事件为空
+    pub level: Option<AlarmLevel>,
+    #[serde(serialize_with = "serialize_shanghai_time")]
+    pub time: DateTime<Utc>,
+    /// 事件的详细数据
+    #[schema(value_type = Object)]
+    pub data: Value,
+}
+
+impl Notification {
+    pub fn new(event: impl Into<String>, title: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            event: event.into(),
+            title: title.into(),
+            message: message.into(),
+            level: None,
+            time: Utc::now(),
+            data: Value::Null,
+        }
+    }
+
+    pub fn with_level(mut self, level: AlarmLevel) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = data;
+        self
+    }
+}
+
+/// 通知通道，站点自定义的通道实现此trait后通过 `Notifier::register` 注册
+pub trait Channel: Send + Sync {
+    /// 通道类型，例如 "webhook"
+    fn kind(&self) -> &str;
+    /// 发送一条通知，在阻塞线程中调用，返回错误时按退避间隔重试
+    fn send(&self, notification: &Notification) -> Result<()>;
+}
+
+/// 通道订阅的通知
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subscription {
+    /// 事件名称，支持 "*" 和 "alarm.*" 这样的前缀匹配，默认全部
+    #[serde(default = "all_events")]
+    pub events: Vec<String>,
+    /// 只接收不低于此级别的告警，不影响没有级别的通知
+    pub min_level: Option<AlarmLevel>,
+}
+
+fn all_events() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self { events: all_events(), min_level: None }
+    }
+}
+
+impl Subscription {
+    fn matches(&self, notification: &Notification) -> bool {
+        let event_matches = self.events.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => notification.event.starts_with(prefix),
+            None => *pattern == notification.event,
+        });
+        let level_matches = match (self.min_level, notification.level) {
+            (Some(min_level), Some(level)) => level >= min_level,
+            _ => true,
+        };
+        event_matches && level_matches
+    }
+}
+
+/// 速率限制：每 seconds 秒最多发送 count 条
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub count: usize,
+    pub seconds: u64,
+}
+
+// 滑动窗口内的发送时间
+struct RateLimiter {
+    limit: RateLimit,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    // 可以发送时记录本次发送，否则返回需要等待的时间
+    fn acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        let window = Duration::from_secs(self.limit.seconds);
+        while self.sent.front().is_some_and(|&t| now.duration_since(t) >= window) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() < self.limit.count {
+            self.sent.push_back(now);
+            return Ok(());
+        }
+        let oldest = self.sent.front().copied().unwrap_or(now);
+        Err(window.saturating_sub(now.duration_since(oldest)))
+    }
+}
+
+struct RegisteredChannel {
+    name: String,
+    channel: Box<dyn Channel>,
+    subscription: Subscription,
+    limiter: Option<Mutex<RateLimiter>>,
+}
+
+/// 通道测试结果
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChannelTestResult {
+    pub channel: String,
+    pub kind: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// 通知通道的注册表和发件箱的发送者
+pub struct Notifier {
+    channels: Vec<RegisteredChannel>,
+    max_attempts: u32,
+    wake: tokio::sync::Notify,
+}
+
+/// 通知配置文件的结构
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifierConfig {
+    /// 每条通知最多尝试发送的次数，默认6
+    pub max_attempts: Option<u32>,
+    #[serde(default)]
+    pub channels: Vec<ChannelConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChannelConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ChannelKind,
+    #[serde(default = "all_events")]
+    pub events: Vec<String>,
+    pub min_level: Option<AlarmLevel>,
+    pub rate_limit: Option<RateLimit>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChannelKind {
+    Webhook(WebhookConfig),
+    Smtp(SmtpConfig),
+    Command(CommandConfig),
+}
+
+impl Notifier {
+    /// 没有通道的通知器，发布的通知直接丢弃
+    pub fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            wake: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// 从 LDC_NOTIFY_CONFIG 指定的文件（默认 notifications.json）读取通道配置
+    pub fn from_env() -> Result<Self> {
+        let path = std::env::var(CONFIG_ENV).unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                println!("No notification config at {}, notifications disabled", path);
+                return Ok(Self::new());
+            }
+            Err(e) => return Err(e).with_context(|| format!("Error reading {}", path)),
+        };
+        let config: NotifierConfig = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid notification config {}", path))?;
+        let notifier = Self::from_config(config)?;
+        println!("Loaded {} notification channel(s) from {}", notifier.channels.len(), path);
+        Ok(notifier)
+    }
+
+    pub fn from_config(config: NotifierConfig) -> Result<Self> {
+        let mut notifier = Self::new();
+        if let Some(max_attempts) = config.max_attempts {
+            anyhow::ensure!(max_attempts > 0, "max_attempts must be positive");
+            notifier.max_attempts = max_attempts;
+        }
+        for channel in config.channels {
+            let subscription = Subscription { events: channel.events, min_level: channel.min_level };
+            let built: Box<dyn Channel> = match channel.kind {
+                ChannelKind::Webhook(config) => Box::new(WebhookChannel::new(config)?),
+                ChannelKind::Smtp(config) => Box::new(SmtpChannel::new(config)?),
+                ChannelKind::Command(config) => Box::new(CommandChannel::new(config)?),
+            };
+            notifier.register(channel.name, built, subscription, channel.rate_limit)?;
+        }
+        Ok(notifier)
+    }
+
+    /// 注册一个通道，名称不能重复
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        channel: Box<dyn Channel>,
+        subscription: Subscription,
+        rate_limit: Option<RateLimit>,
+    ) -> Result<()> {
+        let name = name.into();
+        anyhow::ensure!(!name.trim().is_empty(), "Channel name must not be empty");
+        anyhow::ensure!(
+            !self.channels.iter().any(|c| c.name == name),
+            "Duplicate notification channel: {}", name
+        );
+        if let Some(limit) = rate_limit {
+            anyhow::ensure!(limit.count > 0 && limit.seconds > 0, "Channel {}: rate_limit must be positive", name);
+        }
+        self.channels.push(RegisteredChannel {
+            name,
+            channel,
+            subscription,
+            limiter: rate_limit.map(|limit| Mutex::new(RateLimiter { limit, sent: VecDeque::new() })),
+        });
+        Ok(())
+    }
+
+    pub fn channel_names(&self) -> Vec<String> {
+        self.channels.iter().map(|c| c.name.clone()).collect()
+    }
+
+    /// 为订阅了该事件的每个通道写入发件箱，返回写入的条数
+    pub fn publish(&self, db: &DatabaseManager, notification: &Notification) -> Result<usize> {
+        let channels: Vec<String> = self.channels.iter()
+            .filter(|c| c.subscription.matches(notification))
+            .map(|c| c.name.clone())
+            .collect();
+        if channels.is_empty() {
+            return Ok(0);
+        }
+        let payload = serde_json::to_value(notification)?;
+        let count = db.enqueue_notifications(&channels, &notification.event, &payload)?;
+        self.wake.notify_one();
+        Ok(count)
+    }
+
+    /// 发布通知，失败只记录日志，不影响发布者
+    pub fn publish_logged(&self, db: &DatabaseManager, notification: &Notification) {
+        if let Err(e) = self.publish(db, notification) {
+            eprintln!("Failed to publish notification {}: {:#}", notification.event, e);
+        }
+    }
+
+    /// 直接发送到指定通道（不指定时为全部通道），不经过发件箱和速率限制
+    pub fn send_test(&self, channel: Option<&str>, notification: &Notification) -> Vec<ChannelTestResult> {
+        self.channels.iter()
+            .filter(|c| channel.is_none_or(|name| c.name == name))
+            .map(|c| {
+                let result = c.channel.send(notification);
+                ChannelTestResult {
+                    channel: c.name.clone(),
+                    kind: c.channel.kind().to_string(),
+                    success: result.is_ok(),
+                    error: result.err().map(|e| format!("{:#}", e)),
+                }
+            })
+            .collect()
+    }
+
+    /// 发送到期的通知，返回发送成功的条数
+    pub fn dispatch_due(&self, db: &DatabaseManager) -> Result<usize> {
+        let mut sent = 0;
+        for entry in db.due_notifications(Utc::now(), BATCH_SIZE)? {
+            let Some(registered) = self.channels.iter().find(|c| c.name == entry.channel) else {
+                // 配置中已删除的通道
+                db.mark_notification_failed(entry.id, entry.attempts, None, "Channel is not configured")?;
+                continue;
+            };
+            if let Some(limiter) = &registered.limiter {
+                let acquired = limiter.lock().unwrap_or_else(|e| e.into_inner()).acquire(Instant::now());
+                if let Err(wait) = acquired {
+                    let wait = chrono::Duration::from_std(wait).unwrap_or_default();
+                    db.defer_notification(entry.id, Utc::now() + wait)?;
+                    continue;
+                }
+            }
+            if self.deliver(db, registered, &entry)? {
+                sent += 1;
+            }
+        }
+        Ok(sent)
+    }
+
+    // 发送一条通知并记录结果，失败时按指数退避安排重试
+    fn deliver(&self, db: &DatabaseManager, registered: &RegisteredChannel, entry: &OutboxEntry) -> Result<bool> {
+        let attempts = entry.attempts + 1;
+        let result = serde_json::from_value::<Notification>(entry.payload.clone())
+            .context("Invalid notification payload")
+            .and_then(|notification| registered.channel.send(&notification));
+        match result {
+            Ok(()) => {
+                db.mark_notification_sent(entry.id, attempts)?;
+                Ok(true)
+            }
+            Err(e) => {
+                let error = format!("{:#}", e);
+                let retry_at = (attempts < self.max_attempts).then(|| Utc::now() + retry_delay(attempts));
+                if retry_at.is_none() {
+                    eprintln!("Notification {} to {} failed after {} attempts: {}", entry.id, entry.channel, attempts, error);
+                }
+                db.mark_notification_failed(entry.id, attempts, retry_at, &error)?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn retry_delay(attempts: u32) -> chrono::Duration {
+    let secs = RETRY_BASE_SECS.saturating_mul(1 << attempts.saturating_sub(1).min(16)).min(RETRY_MAX_SECS);
+    chrono::Duration::seconds(secs)
+}
+
+/// 启动发件箱的后台发送任务，没有配置通道时不启动
+pub fn spawn_dispatcher(db: Arc<DatabaseManager>, notifier: Arc<Notifier>) {
+    if notifier.channels.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            let (task_db, task_notifier) = (db.clone(), notifier.clone());
+            match tokio::task::spawn_blocking(move || task_notifier.dispatch_due(&task_db)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => eprintln!("Notification dispatch failed: {:#}", e),
+                Err(e) => eprintln!("Notification dispatch task failed: {}", e),
+            }
+            tokio::select! {
+                _ = notifier.wake.notified() => {}
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+/// 用通知的字段替换模板中的 `{{字段}}`，例如 `{{title}}`、`{{level}}`、`{{data.target_name}}`
+pub fn render(template: &str, notification: &Notification) -> String {
+    let fields = serde_json::to_value(notification).unwrap_or(Value::Null);
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+        match lookup(&fields, rest[start + 2..start + 2 + end].trim()) {
+            Some(Value::String(s)) => output.push_str(s),
+            Some(Value::Null) | None => {}
+            Some(value) => output.push_str(&value.to_string()),
+        }
+        rest = &rest[start + 2 + end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+// JSON模板：只包含一个占位符的字符串替换为原始值，保留数字和对象的类型
+fn render_json(template: &Value, notification: &Notification) -> Value {
+    match template {
+        Value::String(s) => {
+            let trimmed = s.trim();
+            if let Some(path) = trimmed.strip_prefix("{{").and_then(|t| t.strip_suffix("}}")) {
+                if !path.contains("{{") {
+                    let fields = serde_json::to_value(notification).unwrap_or(Value::Null);
+                    return lookup(&fields, path.trim()).cloned().unwrap_or(Value::Null);
+                }
+            }
+            Value::String(render(s, notification))
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|v| render_json(v, notification)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter().map(|(k, v)| (k.clone(), render_json(v, notification))).collect()
+        ),
+        other => other.clone(),
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    })
+}
+
+/// HTTP Webhook：POST JSON，默认发送整条通知，设置template时按模板生成
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub template: Option<Value>,
+    /// 超时时间（秒），默认10
+    pub timeout: Option<u64>,
+}
+
+pub struct WebhookChannel {
+    config: WebhookConfig,
+    agent: ureq::Agent,
+}
+
+impl WebhookChannel {
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        anyhow::ensure!(
+            config.url.starts_with("http://") || config.url.starts_with("https://"),
+            "Webhook url must start with http:// or https://: {}", config.url
+        );
+        let timeout = Duration::from_secs(config.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS));
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(timeout))
+            .build()
+            .new_agent();
+        Ok(Self { config, agent })
+    }
+}
+
+impl Channel for WebhookChannel {
+    fn kind(&self) -> &str {
+        "webhook"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<()> {
+        let body = match &self.config.template {
+            Some(template) => render_json(template, notification),
+            None => serde_json::to_value(notification)?,
+        };
+        let mut request = self.agent.post(&self.config.url).header("Content-Type", "application/json");
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+        // 非2xx状态码作为错误返回
+        request.send(body.to_string()).context("Webhook request failed")?;
+        Ok(())
+    }
+}
+
+/// SMTP邮件，只支持明文连接（可选AUTH PLAIN），需要加密时通过本地中继发送
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// 默认25
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// 主题模板，默认 "{{title}}"
+    pub subject: Option<String>,
+    /// 正文模板，默认为通知内容和时间
+    pub body: Option<String>,
+    /// EHLO使用的主机名，默认 "localhost"
+    pub hello_name: Option<String>,
+    /// 超时时间（秒），默认10
+    pub timeout: Option<u64>,
+}
+
+pub struct SmtpChannel {
+    config: SmtpConfig,
+}
+
+const DEFAULT_SMTP_BODY: &str = "{{message}}\n\nevent: {{event}}\ntime: {{time}}\n";
+
+impl SmtpChannel {
+    pub fn new(config: SmtpConfig) -> Result<Self> {
+        anyhow::ensure!(!config.to.is_empty(), "SMTP channel needs at least one recipient");
+        // 地址和主机名直接写入SMTP命令和邮件头，换行会插入额外的命令或邮件头
+        for value in std::iter::once(&config.from).chain(&config.to).chain(&config.hello_name) {
+            anyhow::ensure!(!value.contains(['\r', '\n']), "SMTP address must not contain line breaks: {:?}", value);
+        }
+        anyhow::ensure!(
+            config.username.is_some() == config.password.is_some(),
+            "SMTP username and password must be set together"
+        );
+        Ok(Self { config })
+    }
+
+    fn message(&self, notification: &Notification) -> String {
+        let subject = render(self.config.subject.as_deref().unwrap_or("{{title}}"), notification);
+        let body = render(self.config.body.as_deref().unwrap_or(DEFAULT_SMTP_BODY), notification);
+        // 正文用base64编码，避免8位字符和以"."开头的行
+        let encoded = base64_encode(body.as_bytes());
+        let body_lines: Vec<&str> = encoded.as_bytes().chunks(76)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+            .collect();
+        format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n",
+            self.config.from,
+            self.config.to.join(", "),
+            encode_header(&subject),
+            Utc::now().to_rfc2822(),
+            body_lines.join("\r\n"),
+        )
+    }
+}
+
+impl Channel for SmtpChannel {
+    fn kind(&self) -> &str {
+        "smtp"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<()> {
+        let config = &self.config;
+        let address = (config.host.as_str(), config.port.unwrap_or(25));
+        let timeout = Duration::from_secs(config.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS));
+        let stream = TcpStream::connect(address)
+            .with_context(|| format!("Error connecting to {}:{}", address.0, address.1))?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut session = SmtpSession { reader: BufReader::new(stream.try_clone()?), writer: stream };
+
+        session.expect(220)?;
+        session.command(&format!("EHLO {}", config.hello_name.as_deref().unwrap_or("localhost")), 250)?;
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            let credentials = base64_encode(format!("\0{}\0{}", username, password).as_bytes());
+            session.command(&format!("AUTH PLAIN {}", credentials), 235)?;
+        }
+        session.command(&format!("MAIL FROM:<{}>", config.from), 250)?;
+        for to in &config.to {
+            session.command(&format!("RCPT TO:<{}>", to), 250)?;
+        }
+        session.command("DATA", 354)?;
+        session.writer.write_all(self.message(notification).as_bytes())?;
+        session.command(".", 250)?;
+        // 邮件已被接受，QUIT失败不影响结果
+        session.command("QUIT", 221).ok();
+        Ok(())
+    }
+}
+
+struct SmtpSession {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl SmtpSession {
+    fn command(&mut self, line: &str, expected: u16) -> Result<()> {
+        self.writer.write_all(format!("{}\r\n", line).as_bytes())?;
+        let verb = line.split(' ').next().unwrap_or(line);
+        self.expect(expected).with_context(|| format!("SMTP {} failed", verb))
+    }
+
+    // 读取一个（可能多行的）应答并检查状态码
+    fn expect(&mut self, expected: u16) -> Result<()> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                anyhow::bail!("Connection closed by server");
+            }
+            reply.push_str(line.trim_end());
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+            reply.push(' ');
+        }
+        let code: u16 = reply.get(..3).and_then(|c| c.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("Invalid SMTP reply: {}", reply))?;
+        // 251: 收件人不在本地但会转发
+        if code == expected || (expected == 250 && code == 251) {
+            Ok(())
+        } else {
+            anyhow::bail!("{}", reply)
+        }
+    }
+}
+
+// 非ASCII的邮件头按RFC 2047编码，每段不超过45字节；模板渲染出的换行替换为空格，避免插入额外的邮件头
+fn encode_header(value: &str) -> String {
+    let value: String = value.chars().map(|c| if c == '\r' || c == '\n' { ' ' } else { c }).collect();
+    if value.is_ascii() {
+        return value;
+    }
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in value.chars() {
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(format!("=?UTF-8?B?{}?=", base64_encode(chunk.as_bytes())));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        words.push(format!("=?UTF-8?B?{}?=", base64_encode(chunk.as_bytes())));
+    }
+    words.join("\r\n ")
+}
+
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/// 本地命令：通知的JSON写入标准输入，参数中可以使用模板，退出码非0视为失败
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandConfig {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// 超时时间（秒），超时后结束进程，默认10
+    pub timeout: Option<u64>,
+}
+
+pub struct CommandChannel {
+    config: CommandConfig,
+}
+
+impl CommandChannel {
+    pub fn new(config: CommandConfig) -> Result<Self> {
+        anyhow::ensure!(!config.program.trim().is_empty(), "Command program must not be empty");
+        Ok(Self { config })
+    }
+}
+
+impl Channel for CommandChannel {
+    fn kind(&self) -> &str {
+        "command"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<()> {
+        let args: Vec<String> = self.config.args.iter().map(|arg| render(arg, notification)).collect();
+        let mut child = Command::new(&self.config.program)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Error starting {}", self.config.program))?;
+
+        // 写入标准输入和读取标准错误都在单独的线程中进行，命令不读取输入或输出大量错误信息时
+        // 管道写满也不会阻塞超时检查；命令不读取标准输入时写入会失败，忽略
+        let payload = serde_json::to_vec(notification)?;
+        if let Some(mut stdin) = child.stdin.take() {
+            std::thread::spawn(move || stdin.write_all(&payload).ok());
+        }
+        let stderr = child.stderr.take().map(|mut pipe| {
+            std::thread::spawn(move || {
+                let mut stderr = Vec::new();
+                pipe.by_ref().take(MAX_STDERR_BYTES).read_to_end(&mut stderr).ok();
+                // 超出的部分读出丢弃，命令不会因管道写满而挂起
+                std::io::copy(&mut pipe, &mut std::io::sink()).ok();
+                String::from_utf8_lossy(&stderr).into_owned()
+            })
+        });
+
+        let timeout = Duration::from_secs(self.config.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS));
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() >= timeout {
+                child.kill().ok();
+                child.wait().ok();
+                anyhow::bail!("{} timed out after {} seconds", self.config.program, timeout.as_secs());
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+
+        if status.success() {
+            return Ok(());
+        }
+        let stderr = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+        anyhow::bail!("{} exited with {}: {}", self.config.program, status, stderr.trim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    fn notification(title: &str) -> Notification {
+        Notification::new("alarm.raised", title, "T1/dx raised at level red")
+            .with_level(AlarmLevel::Red)
+            .with_data(serde_json::json!({"target_name": "T1", "value": 12.5}))
+    }
+
+    // 本地HTTP服务器：接受一个请求，返回指定状态码，结束后得到收到的请求
+    fn http_server(status: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            let mut writer = stream;
+            write!(writer, "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    // 本地SMTP服务器：按命令返回应答，结束后得到收到的全部内容
+    fn smtp_server(rcpt_reply: &'static str) -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut transcript = String::new();
+            writer.write_all(b"220 test ESMTP\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                transcript.push_str(&line);
+                let reply = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    "250 queued\r\n"
+                } else {
+                    match line.split([' ', '\r']).next().unwrap() {
+                        "EHLO" => "250-test\r\n250 AUTH PLAIN\r\n",
+                        "AUTH" => "235 ok\r\n",
+                        "MAIL" => "250 ok\r\n",
+                        "RCPT" => rcpt_reply,
+                        "DATA" => {
+                            in_data = true;
+                            "354 go ahead\r\n"
+                        }
+                        "QUIT" => {
+                            writer.write_all(b"221 bye\r\n").unwrap();
+                            break;
+                        }
+                        _ => "500 unknown\r\n",
+                    }
+                };
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+            transcript
+        });
+        (port, handle)
+    }
+
+    fn smtp_config(port: u16) -> SmtpConfig {
+        SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+            from: "ldc@example.com".to_string(),
+            to: vec!["ops@example.com".to_string(), "oncall@example.com".to_string()],
+            subject: None,
+            body: None,
+            hello_name: None,
+            timeout: Some(5),
+        }
+    }
+
+    #[test]
+    fn webhook_posts_notification() {
+        let (url, server) = http_server("200 OK");
+        let channel = WebhookChannel::new(WebhookConfig {
+            url,
+            headers: BTreeMap::from([("X-Token".to_string(), "abc".to_string())]),
+            template: None,
+            timeout: Some(5),
+        }).unwrap();
+        channel.send(&notification("[red] settlement raised")).unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.to_ascii_lowercase().contains("x-token: abc\r\n"));
+        let body: Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["event"], "alarm.raised");
+        assert_eq!(body["title"], "[red] settlement raised");
+        assert_eq!(body["data"]["target_name"], "T1");
+    }
+
+    #[test]
+    fn webhook_renders_template() {
+        let (url, server) = http_server("200 OK");
+        let channel = WebhookChannel::new(WebhookConfig {
+            url,
+            headers: BTreeMap::new(),
+            template: Some(serde_json::json!({"text": "{{title}}: {{data.target_name}}", "value": "{{data.value}}"})),
+            timeout: Some(5),
+        }).unwrap();
+        channel.send(&notification("raised")).unwrap();
+
+        let request = server.join().unwrap();
+        let body: Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({"text": "raised: T1", "value": 12.5}));
+    }
+
+    #[test]
+    fn webhook_error_status_fails() {
+        let (url, server) = http_server("500 Internal Server Error");
+        let channel = WebhookChannel::new(WebhookConfig {
+            url,
+            headers: BTreeMap::new(),
+            template: None,
+            timeout: Some(5),
+        }).unwrap();
+        assert!(channel.send(&notification("raised")).is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn smtp_sends_message() {
+        let (port, server) = smtp_server("250 ok\r\n");
+        SmtpChannel::new(smtp_config(port)).unwrap().send(&notification("告警 raised")).unwrap();
+
+        let transcript = server.join().unwrap();
+        let lines: Vec<&str> = transcript.lines().collect();
+        assert_eq!(lines[0], "EHLO localhost");
+        assert_eq!(lines[1], format!("AUTH PLAIN {}", base64_encode(b"\0user\0secret")));
+        assert_eq!(lines[2], "MAIL FROM:<ldc@example.com>");
+        assert_eq!(lines[3], "RCPT TO:<ops@example.com>");
+        assert_eq!(lines[4], "RCPT TO:<oncall@example.com>");
+        assert_eq!(lines[5], "DATA");
+        assert!(lines.contains(&"To: ops@example.com, oncall@example.com"));
+        assert!(lines.contains(&format!("Subject: =?UTF-8?B?{}?=", base64_encode("告警 raised".as_bytes())).as_str()));
+        assert_eq!(&lines[lines.len() - 2..], [".", "QUIT"]);
+    }
+
+    #[test]
+    fn smtp_rejected_recipient_fails() {
+        let (port, server) = smtp_server("550 no such user\r\n");
+        let error = SmtpChannel::new(smtp_config(port)).unwrap().send(&notification("raised")).unwrap_err();
+        assert!(format!("{:#}", error).contains("550 no such user"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn smtp_subject_cannot_inject_headers() {
+        let (port, server) = smtp_server("250 ok\r\n");
+        let channel = SmtpChannel::new(smtp_config(port)).unwrap();
+        channel.send(&notification("raised\r\nBcc: attacker@example.com")).unwrap();
+
+        let transcript = server.join().unwrap();
+        assert!(transcript.contains("Subject: raised  Bcc: attacker@example.com\r\n"));
+        assert!(!transcript.lines().any(|line| line.starts_with("Bcc:")));
+    }
+
+    #[test]
+    fn smtp_rejects_line_breaks_in_addresses() {
+        let mut config = smtp_config(25);
+        config.to = vec!["ops@example.com>\r\nRCPT TO:<attacker@example.com".to_string()];
+        assert!(SmtpChannel::new(config).is_err());
+    }
+
+    fn command(script: &str, timeout: u64) -> CommandChannel {
+        CommandChannel::new(CommandConfig {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string(), "sh".to_string(), "{{title}}".to_string()],
+            timeout: Some(timeout),
+        }).unwrap()
+    }
+
+    #[test]
+    fn command_receives_notification_on_stdin() {
+        let path = std::env::temp_dir().join(format!("ldc_command_{}.json", std::process::id()));
+        let channel = command(&format!("cat > '{}'; test \"$1\" = 'Alarm'", path.display()), 10);
+        channel.send(&notification("Alarm")).unwrap();
+        let written: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(written["title"], "Alarm");
+        assert_eq!(written["data"]["target_name"], "T1");
+    }
+
+    #[test]
+    fn command_large_stderr_does_not_block() {
+        // 1MB的标准错误远超管道缓冲区，不读取时命令会一直挂起到超时
+        let started = Instant::now();
+        let error = command("head -c 1048576 /dev/zero | tr '\\0' e >&2; exit 3", 5)
+            .send(&notification("Alarm"))
+            .unwrap_err()
+            .to_string();
+        assert!(started.elapsed() < Duration::from_secs(5), "{}", error);
+        assert!(error.contains("exit status: 3"), "{}", error);
+        assert!(error.ends_with(&"e".repeat(MAX_STDERR_BYTES as usize)), "{}", &error[..100]);
+    }
+
+    #[test]
+    fn command_ignoring_stdin_and_timeout() {
+        command("exit 0", 5).send(&notification("Alarm")).unwrap();
+
+        let started = Instant::now();
+        let error = command("sleep 10", 1).send(&notification("Alarm")).unwrap_err().to_string();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(error, "sh timed out after 1 seconds");
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_max() {
+        let delays: Vec<i64> = (1..=9).map(|attempts| retry_delay(attempts).num_seconds()).collect();
+        assert_eq!(delays, [30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(retry_delay(0).num_seconds(), 30);
+        assert_eq!(retry_delay(u32::MAX).num_seconds(), 3600);
+    }
+
+    #[test]
+    fn rate_limiter_uses_sliding_window() {
+        let mut limiter = RateLimiter { limit: RateLimit { count: 2, seconds: 10 }, sent: VecDeque::new() };
+        let start = Instant::now();
+        assert_eq!(limiter.acquire(start), Ok(()));
+        assert_eq!(limiter.acquire(start + Duration::from_secs(4)), Ok(()));
+        assert_eq!(limiter.acquire(start + Duration::from_secs(6)), Err(Duration::from_secs(4)));
+        // 第一条发送满10秒后空出一个名额
+        assert_eq!(limiter.acquire(start + Duration::from_secs(10)), Ok(()));
+        assert_eq!(limiter.acquire(start + Duration::from_secs(11)), Err(Duration::from_secs(3)));
+        assert_eq!(limiter.acquire(start + Duration::from_secs(14)), Ok(()));
+    }
+}
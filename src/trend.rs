@@ -0,0 +1,456 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::database::SeriesData;
+
+// 一天的毫秒数，斜率和速度按每天计
+const DAY_MS: i64 = 86_400_000;
+
+// 拟合直线和滚动窗口至少需要的点数（置信区间需要 n-2 个自由度）
+const MIN_FIT_POINTS: usize = 3;
+
+// Theil–Sen要计算两两斜率，点数超过此值时按时间等间隔抽取
+const MAX_THEIL_SEN_POINTS: usize = 2000;
+
+/// 趋势斜率的拟合方法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TrendMethod {
+    /// 最小二乘
+    #[default]
+    Ols,
+    /// Theil–Sen（两两斜率的中位数），不受离群点影响
+    TheilSen,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrendOptions {
+    pub method: TrendMethod,
+    /// 滚动速度和加速度的窗口（毫秒）
+    pub window_ms: i64,
+    /// 斜率置信区间的置信度，例如0.95
+    pub confidence: f64,
+}
+
+/// 某一时刻的变化率
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RatePoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
+}
+
+/// 单个序列的趋势分析结果，斜率和速度的单位为每天，加速度为每天²
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SeriesTrend {
+    pub asset_name: String,
+    pub device_name: String,
+    pub target_name: String,
+    pub key_name: String,
+    pub method: TrendMethod,
+    /// 参与分析的点数
+    pub points: usize,
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    /// 拟合斜率（每天），点数不足或时间都相同时为空
+    pub slope: Option<f64>,
+    /// 斜率置信区间下限
+    pub slope_lower: Option<f64>,
+    /// 斜率置信区间上限
+    pub slope_upper: Option<f64>,
+    /// 拟合直线在start_time处的值
+    pub intercept: Option<f64>,
+    /// 决定系数，Theil–Sen直线的R²可能为负；所有值相同时为空
+    pub r_squared: Option<f64>,
+    /// 滚动速度：以每个点为末端的窗口内的最小二乘斜率
+    pub velocity: Vec<RatePoint>,
+    /// 滚动加速度：速度序列在同样窗口内的最小二乘斜率
+    pub acceleration: Vec<RatePoint>,
+}
+
+// 拟合的直线，x为距第一个点的天数
+struct LineFit {
+    slope: f64,
+    intercept: f64,
+    lower: f64,
+    upper: f64,
+    r_squared: Option<f64>,
+}
+
+/// 分析一个序列的整体趋势和滚动速度、加速度，数据需按时间升序排列
+pub fn analyze_trend(series: &SeriesData, options: &TrendOptions) -> SeriesTrend {
+    let data = &series.data;
+    let times: Vec<DateTime<Utc>> = data.iter().map(|d| d.timestamp).collect();
+    let values: Vec<f64> = data.iter().map(|d| d.value).collect();
+    let x = days_since_first(&times);
+
+    let fit = match options.method {
+        TrendMethod::Ols => ols_fit(&x, &values, options.confidence),
+        TrendMethod::TheilSen => theil_sen_fit(&x, &values, options.confidence),
+    };
+
+    let velocity = rolling_slope(&times, &values, options.window_ms);
+    let velocity_times: Vec<DateTime<Utc>> = velocity.iter().map(|p| p.timestamp).collect();
+    let velocity_values: Vec<f64> = velocity.iter().map(|p| p.value).collect();
+    let acceleration = rolling_slope(&velocity_times, &velocity_values, options.window_ms);
+
+    SeriesTrend {
+        asset_name: series.key.asset_name.clone(),
+        device_name: series.key.device_name.clone(),
+        target_name: series.key.target_name.clone(),
+        key_name: series.key.key_name.clone(),
+        method: options.method,
+        points: data.len(),
+        start_time: times.first().copied(),
+        end_time: times.last().copied(),
+        slope: fit.as_ref().map(|f| f.slope),
+        slope_lower: fit.as_ref().map(|f| f.lower),
+        slope_upper: fit.as_ref().map(|f| f.upper),
+        intercept: fit.as_ref().map(|f| f.intercept),
+        r_squared: fit.and_then(|f| f.r_squared),
+        velocity,
+        acceleration,
+    }
+}
+
+fn days_since_first(times: &[DateTime<Utc>]) -> Vec<f64> {
+    let Some(&first) = times.first() else {
+        return Vec::new();
+    };
+    times.iter().map(|t| (*t - first).num_milliseconds() as f64 / DAY_MS as f64).collect()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// 已排序数据的中位数
+fn median_sorted(sorted: &[f64]) -> f64 {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+// 直线的残差平方和与总平方和算出R²
+fn r_squared(x: &[f64], y: &[f64], slope: f64, intercept: f64) -> Option<f64> {
+    let mean_y = mean(y);
+    let sst: f64 = y.iter().map(|v| (v - mean_y).powi(2)).sum();
+    let sse: f64 = x.iter().zip(y).map(|(xi, yi)| (yi - intercept - slope * xi).powi(2)).sum();
+    (sst > 0.0).then(|| 1.0 - sse / sst)
+}
+
+// 最小二乘直线，斜率的置信区间用t分布
+fn ols_fit(x: &[f64], y: &[f64], confidence: f64) -> Option<LineFit> {
+    let n = x.len();
+    if n < MIN_FIT_POINTS {
+        return None;
+    }
+    let (mean_x, mean_y) = (mean(x), mean(y));
+    let sxx: f64 = x.iter().map(|xi| (xi - mean_x).powi(2)).sum();
+    if sxx <= 0.0 {
+        return None;
+    }
+    let sxy: f64 = x.iter().zip(y).map(|(xi, yi)| (xi - mean_x) * (yi - mean_y)).sum();
+    let slope = sxy / sxx;
+    let intercept = mean_y - slope * mean_x;
+
+    let sse: f64 = x.iter().zip(y).map(|(xi, yi)| (yi - intercept - slope * xi).powi(2)).sum();
+    let standard_error = (sse / (n - 2) as f64 / sxx).sqrt();
+    let margin = student_t_quantile(0.5 + confidence / 2.0, n - 2) * standard_error;
+
+    Some(LineFit {
+        slope,
+        intercept,
+        lower: slope - margin,
+        upper: slope + margin,
+        r_squared: r_squared(x, y, slope, intercept),
+    })
+}
+
+// Theil–Sen直线：斜率取两两斜率的中位数，截距取 y - slope*x 的中位数，置信区间按Sen的秩方法
+fn theil_sen_fit(x: &[f64], y: &[f64], confidence: f64) -> Option<LineFit> {
+    let n = x.len();
+    if n < MIN_FIT_POINTS {
+        return None;
+    }
+    let indices: Vec<usize> = if n > MAX_THEIL_SEN_POINTS {
+        (0..MAX_THEIL_SEN_POINTS).map(|k| k * (n - 1) / (MAX_THEIL_SEN_POINTS - 1)).collect()
+    } else {
+        (0..n).collect()
+    };
+
+    let mut slopes = Vec::with_capacity(indices.len() * (indices.len() - 1) / 2);
+    for (a, &i) in indices.iter().enumerate() {
+        for &j in &indices[a + 1..] {
+            let dx = x[j] - x[i];
+            if dx > 0.0 {
+                slopes.push((y[j] - y[i]) / dx);
+            }
+        }
+    }
+    if slopes.is_empty() {
+        return None;
+    }
+    slopes.sort_by(|a, b| a.total_cmp(b));
+    let slope = median_sorted(&slopes);
+
+    let mut offsets: Vec<f64> = x.iter().zip(y).map(|(xi, yi)| yi - slope * xi).collect();
+    offsets.sort_by(|a, b| a.total_cmp(b));
+    let intercept = median_sorted(&offsets);
+
+    // Kendall统计量的标准差决定置信区间在排序斜率中的秩
+    let m = indices.len() as f64;
+    let spread = normal_quantile(0.5 + confidence / 2.0) * (m * (m - 1.0) * (2.0 * m + 5.0) / 18.0).sqrt();
+    let count = slopes.len() as f64;
+    let last = slopes.len() - 1;
+    let lower_rank = (((count - spread) / 2.0).floor().max(0.0) as usize).min(last);
+    let upper_rank = (((count + spread) / 2.0).ceil().max(0.0) as usize).min(last);
+
+    Some(LineFit {
+        slope,
+        intercept,
+        lower: slopes[lower_rank],
+        upper: slopes[upper_rank],
+        r_squared: r_squared(x, y, slope, intercept),
+    })
+}
+
+// 以每个点为末端、向前window_ms的窗口内做最小二乘，得到该时刻的斜率（每天）；窗口内点数不足时不输出
+fn rolling_slope(times: &[DateTime<Utc>], values: &[f64], window_ms: i64) -> Vec<RatePoint> {
+    let x = days_since_first(times);
+    let mut result = Vec::new();
+    let (mut sum_x, mut sum_y, mut sum_xx, mut sum_xy) = (0.0, 0.0, 0.0, 0.0);
+    let mut begin = 0;
+
+    for end in 0..times.len() {
+        sum_x += x[end];
+        sum_y += values[end];
+        sum_xx += x[end] * x[end];
+        sum_xy += x[end] * values[end];
+        while (times[end] - times[begin]).num_milliseconds() > window_ms {
+            sum_x -= x[begin];
+            sum_y -= values[begin];
+            sum_xx -= x[begin] * x[begin];
+            sum_xy -= x[begin] * values[begin];
+            begin += 1;
+        }
+
+        let count = end - begin + 1;
+        if count < MIN_FIT_POINTS || times[end] == times[begin] {
+            continue;
+        }
+        let n = count as f64;
+        let denominator = n * sum_xx - sum_x * sum_x;
+        if denominator > 0.0 {
+            result.push(RatePoint {
+                timestamp: times[end],
+                value: (n * sum_xy - sum_x * sum_y) / denominator,
+            });
+        }
+    }
+    result
+}
+
+/// 标准正态分布的分位数（Acklam近似，相对误差约1e-9），p须在(0, 1)内
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e+01, 2.209460984245205e+02, -2.759285104469687e+02,
+        1.38357751867269e+02, -3.066479806614716e+01, 2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01, 1.615858368580409e+02, -1.556989798598866e+02,
+        6.680131188771972e+01, -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03, -3.223964580411365e-01, -2.400758277161838e+00,
+        -2.549732539343734e+00, 4.374664141464968e+00, 2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-03, 3.224671290700398e-01, 2.445134137142996e+00, 3.754408661907416e+00,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// t分布的分位数：1、2个自由度用解析式，其余用Cornish–Fisher展开（3个自由度时误差约0.1%）
+pub fn student_t_quantile(p: f64, degrees_of_freedom: usize) -> f64 {
+    match degrees_of_freedom {
+        0 => f64::NAN,
+        1 => (std::f64::consts::PI * (p - 0.5)).tan(),
+        2 => (2.0 * p - 1.0) / (2.0 * p * (1.0 - p)).sqrt(),
+        df => {
+            let z = normal_quantile(p);
+            let v = df as f64;
+            let (z3, z5, z7, z9) = (z.powi(3), z.powi(5), z.powi(7), z.powi(9));
+            z + (z3 + z) / (4.0 * v)
+                + (5.0 * z5 + 16.0 * z3 + 3.0 * z) / (96.0 * v.powi(2))
+                + (3.0 * z7 + 19.0 * z5 + 17.0 * z3 - 15.0 * z) / (384.0 * v.powi(3))
+                + (79.0 * z9 + 776.0 * z7 + 1482.0 * z5 - 1920.0 * z3 - 945.0 * z) / (92160.0 * v.powi(4))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{SeriesKey, TelemetryData};
+
+    const HOUR_MS: i64 = 3_600_000;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "{} != {}", actual, expected);
+    }
+
+    // 每小时一个点，值由距第一个点的天数计算
+    fn hourly_series(hours: usize, value: impl Fn(f64) -> f64) -> SeriesData {
+        let origin = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let data = (0..hours)
+            .map(|h| TelemetryData {
+                timestamp: origin + chrono::Duration::milliseconds(h as i64 * HOUR_MS),
+                asset_name: "A".to_string(),
+                device_name: "D".to_string(),
+                target_name: "T".to_string(),
+                key_name: "dx".to_string(),
+                value: value(h as f64 / 24.0),
+            })
+            .collect();
+        SeriesData {
+            key: SeriesKey {
+                asset_name: "A".to_string(),
+                device_name: "D".to_string(),
+                target_name: "T".to_string(),
+                key_name: "dx".to_string(),
+            },
+            data,
+        }
+    }
+
+    #[test]
+    fn normal_quantile_matches_table() {
+        assert_close(normal_quantile(0.5), 0.0, 1e-9);
+        assert_close(normal_quantile(0.9), 1.281552, 1e-6);
+        assert_close(normal_quantile(0.975), 1.959964, 1e-6);
+        // 尾部分支
+        assert_close(normal_quantile(0.999), 3.090232, 1e-6);
+        for p in [0.001, 0.01, 0.2, 0.4] {
+            assert_close(normal_quantile(p), -normal_quantile(1.0 - p), 1e-9);
+        }
+    }
+
+    #[test]
+    fn student_t_quantile_matches_table() {
+        // 3个自由度时Cornish–Fisher展开的误差约0.1%
+        for (df, expected, relative) in [
+            (1, 12.706, 1e-4), (2, 4.303, 1e-4), (3, 3.182, 2e-3), (5, 2.571, 5e-4), (10, 2.228, 2e-4), (30, 2.042, 2e-4),
+        ] {
+            assert_close(student_t_quantile(0.975, df), expected, expected * relative);
+        }
+        assert_close(student_t_quantile(0.5, 4), 0.0, 1e-9);
+        assert!(student_t_quantile(0.975, 0).is_nan());
+    }
+
+    #[test]
+    fn ols_fit_slope_interval_and_r_squared() {
+        let fit = ols_fit(&[1.0, 2.0, 3.0, 4.0, 5.0], &[2.0, 4.0, 5.0, 4.0, 5.0], 0.95).unwrap();
+        assert_close(fit.slope, 0.6, 1e-12);
+        assert_close(fit.intercept, 2.2, 1e-12);
+        assert_close(fit.r_squared.unwrap(), 0.6, 1e-12);
+        // 标准误 sqrt(2.4 / 3 / 10)，t(0.975, 3) = 3.182
+        assert_close(fit.upper - fit.slope, 0.9, 0.01);
+        assert_close(fit.slope - fit.lower, 0.9, 0.01);
+
+        // 落在直线上的点置信区间退化为一点
+        let fit = ols_fit(&[0.0, 1.0, 2.0, 3.0], &[1.0, 3.0, 5.0, 7.0], 0.95).unwrap();
+        assert_close(fit.slope, 2.0, 1e-12);
+        assert_close(fit.lower, 2.0, 1e-12);
+        assert_close(fit.upper, 2.0, 1e-12);
+
+        // 所有值相同时R²为空
+        assert_eq!(ols_fit(&[0.0, 1.0, 2.0], &[4.0, 4.0, 4.0], 0.95).unwrap().r_squared, None);
+    }
+
+    #[test]
+    fn theil_sen_ignores_outliers() {
+        let x: Vec<f64> = (0..20).map(f64::from).collect();
+        let mut y: Vec<f64> = x.iter().map(|xi| 2.0 * xi + 1.0).collect();
+        y[15] += 100.0;
+
+        let robust = theil_sen_fit(&x, &y, 0.95).unwrap();
+        assert_close(robust.slope, 2.0, 1e-12);
+        assert_close(robust.intercept, 1.0, 1e-12);
+        assert!(robust.lower <= 2.0 && robust.upper >= 2.0);
+        assert!(ols_fit(&x, &y, 0.95).unwrap().slope > 2.5);
+
+        // 超过上限的点数按等间隔抽样
+        let x: Vec<f64> = (0..5000).map(|i| i as f64 / 100.0).collect();
+        let mut y: Vec<f64> = x.iter().map(|xi| -0.5 * xi + 3.0).collect();
+        for i in (0..5000).step_by(250) {
+            y[i] -= 50.0;
+        }
+        let sampled = theil_sen_fit(&x, &y, 0.95).unwrap();
+        assert_close(sampled.slope, -0.5, 1e-9);
+        assert_close(sampled.intercept, 3.0, 1e-9);
+    }
+
+    #[test]
+    fn fits_need_distinct_times() {
+        for fit in [ols_fit, theil_sen_fit] {
+            assert!(fit(&[0.0, 1.0], &[1.0, 2.0], 0.95).is_none());
+            assert!(fit(&[1.0, 1.0, 1.0], &[1.0, 2.0, 3.0], 0.95).is_none());
+        }
+
+        let mut series = hourly_series(5, |t| t);
+        let first = series.data[0].timestamp;
+        for item in &mut series.data {
+            item.timestamp = first;
+        }
+        let options = TrendOptions { method: TrendMethod::Ols, window_ms: DAY_MS, confidence: 0.95 };
+        let trend = analyze_trend(&series, &options);
+        assert_eq!(trend.points, 5);
+        assert_eq!(trend.slope, None);
+        assert!(trend.velocity.is_empty() && trend.acceleration.is_empty());
+    }
+
+    #[test]
+    fn rolling_rates_follow_quadratic() {
+        // 位移 t²（t为天）：窗口 w 内等间隔点的最小二乘斜率等于窗口中点的导数 2t - w，加速度为2
+        let series = hourly_series(10 * 24 + 1, |t| t * t);
+        let options = TrendOptions { method: TrendMethod::Ols, window_ms: 2 * DAY_MS, confidence: 0.95 };
+        let trend = analyze_trend(&series, &options);
+
+        assert_close(trend.slope.unwrap(), 10.0, 1e-9);
+        assert!(trend.slope_lower.unwrap() < 10.0 && trend.slope_upper.unwrap() > 10.0);
+
+        let last = trend.velocity.last().unwrap();
+        assert_eq!(last.timestamp, trend.end_time.unwrap());
+        assert_close(last.value, 18.0, 1e-6);
+        // 窗口填满后的速度点都满足 2t - w
+        for point in trend.velocity.iter().skip(48) {
+            let t = (point.timestamp - trend.start_time.unwrap()).num_milliseconds() as f64 / DAY_MS as f64;
+            assert_close(point.value, 2.0 * t - 2.0, 1e-6);
+        }
+        for point in trend.acceleration.iter().skip(96) {
+            assert_close(point.value, 2.0, 1e-6);
+        }
+
+        // 窗口内不足3个点时不输出
+        assert_eq!(trend.velocity.first().unwrap().timestamp, series.data[2].timestamp);
+    }
+}
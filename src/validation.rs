@@ -0,0 +1,1141 @@
+use chrono::{DateTime, Duration, Utc};
+use std::sync::Arc;
+
+use crate::api::{
+    AcknowledgeAlarmRequest, AlarmListQuery, AlarmRuleRequest, DerivedMetricRequest, BaselineRequest, SavedQueryRequest, NotificationListQuery, TestNotificationRequest, AnomalyDetectionAllRequest, AnomalyDetectionRequest, AnomalyListQuery, ConsensusDetectionRequest, DecomposeQuery, DetectorSelection, DetectionOptions, AnomalyRunQuery, CreateOperationRequest,
+    CreateTokenRequest, CreateUserRequest, LoginRequest, ReviewAnomalyRequest, TelemetryQuery, TrendQuery, ForecastQuery, UpdateOperationRequest,
+    UpdateUserRequest,
+};
+use crate::anomaly_detection::{AnomalyDetectionConfig, Detector, DetectorRegistry, Seasonality};
+use crate::database::{AlarmDirection, AlarmLevel, AlarmMetric, AlarmQuery, AlarmThreshold, AnomalyQuery, NotificationStatus, AnomalyStatus, CustomFilter, OperationType, QueryParams, SavedQuery, ReferenceMode, ReferenceValue, SamplingConfig, SamplingMethod, TimeOfDayFilter, TimeRange, MAX_QUERY_LIMIT};
+use crate::error::{ApiError, FieldError};
+use crate::expression;
+use crate::forecast::{self, ForecastModel, ForecastOptions};
+use crate::trend::TrendOptions;
+
+/// 收集全部校验错误，最后一次性返回
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn error(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(FieldError::new(field, message));
+    }
+
+    pub fn check(&mut self, ok: bool, field: impl Into<String>, message: impl Into<String>) {
+        if !ok {
+            self.error(field, message);
+        }
+    }
+
+    /// 解析可选的RFC3339时间
+    pub fn time(&mut self, field: &str, value: Option<&str>) -> Option<DateTime<Utc>> {
+        let time_str = value?;
+        match DateTime::parse_from_rfc3339(time_str) {
+            Ok(dt) => Some(dt.with_timezone(&Utc)),
+            Err(_) => {
+                self.error(field, format!("Invalid {} format, expected RFC 3339", leaf(field)));
+                None
+            }
+        }
+    }
+
+    /// 检查开始时间不晚于结束时间
+    pub fn time_order(&mut self, field: &str, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) {
+        if let (Some(start), Some(end)) = (start, end) {
+            self.check(start <= end, field, "start_time must not be later than end_time");
+        }
+    }
+
+    /// 字符串字段不能为空
+    pub fn non_empty(&mut self, field: &str, value: &str) {
+        self.check(!value.trim().is_empty(), field, format!("{} must not be empty", leaf(field)));
+    }
+
+    /// 数值必须是有限值
+    pub fn finite(&mut self, field: &str, value: f64) {
+        self.check(value.is_finite(), field, format!("{} must be a finite number", leaf(field)));
+    }
+
+    pub fn finish(self) -> Result<(), ApiError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::Validation(self.errors))
+        }
+    }
+}
+
+// 取字段路径的最后一段，用于错误消息
+fn leaf(field: &str) -> &str {
+    field.rsplit('.').next().unwrap_or(field)
+}
+
+// 拼接字段路径，例如 "[0].operations[1]" + "value"
+fn path(prefix: &str, field: &str) -> String {
+    if prefix.is_empty() {
+        field.to_string()
+    } else {
+        format!("{}.{}", prefix, field)
+    }
+}
+
+/// 校验查询参数本身的约束（时间顺序、采样间隔、数据量上限等）
+pub fn validate_query_params(v: &mut Validator, params: &QueryParams) {
+    v.time_order("end_time", params.start_time, params.end_time);
+
+    if let Some(limit) = params.limit {
+        v.check(
+            limit > 0 && limit <= MAX_QUERY_LIMIT,
+            "limit",
+            format!("limit must be between 1 and {}", MAX_QUERY_LIMIT),
+        );
+    }
+
+    if let Some(sampling_config) = &params.sampling_config {
+        v.check(sampling_config.interval_ms > 0, "sampling_interval", "sampling_interval must be a positive number of milliseconds");
+    }
+
+    v.check(
+        matches!(params.outlier_method.as_str(), "iqr" | "zscore"),
+        "outlier_method",
+        format!("Unknown outlier_method: {}, expected \"iqr\" or \"zscore\"", params.outlier_method),
+    );
+
+    if let Some(custom_filter) = &params.custom_filter {
+        if let Some(min_value) = custom_filter.min_value {
+            v.finite("min_value", min_value);
+        }
+        if let Some(max_value) = custom_filter.max_value {
+            v.finite("max_value", max_value);
+        }
+        if let (Some(min_value), Some(max_value)) = (custom_filter.min_value, custom_filter.max_value) {
+            v.check(min_value <= max_value, "max_value", "max_value must not be less than min_value");
+        }
+    }
+
+    if let Some(reference_values) = &params.reference_values {
+        reference_entries(v, "reference_values", reference_values);
+    }
+}
+
+// 校验参考值配置
+fn reference_entries(v: &mut Validator, field: &str, reference_values: &[ReferenceValue]) {
+    for (i, reference) in reference_values.iter().enumerate() {
+        let prefix = format!("{}[{}]", field, i);
+        v.non_empty(&path(&prefix, "target_name"), &reference.target_name);
+        v.non_empty(&path(&prefix, "key_name"), &reference.key_name);
+
+        // 每个字段只属于一种计算方式
+        for (name, present, owner) in [
+            ("reference_value", reference.reference_value.is_some(), ReferenceMode::Value),
+            ("reference_time", reference.reference_time.is_some(), ReferenceMode::Epoch),
+            ("reference_start", reference.reference_start.is_some(), ReferenceMode::PeriodMean),
+            ("reference_end", reference.reference_end.is_some(), ReferenceMode::PeriodMean),
+        ] {
+            if present && reference.mode != owner {
+                v.error(path(&prefix, name), format!("{} is only used when mode is \"{}\"", name, owner.as_str()));
+            } else if !present && reference.mode == owner {
+                v.error(path(&prefix, name), format!("{} is required when mode is \"{}\"", name, owner.as_str()));
+            }
+        }
+
+        if let Some(value) = reference.reference_value {
+            v.finite(&path(&prefix, "reference_value"), value);
+        }
+        if let (Some(start), Some(end)) = (reference.reference_start, reference.reference_end) {
+            v.check(start <= end, path(&prefix, "reference_end"), "reference_start must not be later than reference_end");
+        }
+    }
+}
+
+/// 解析并校验遥测查询参数，返回全部错误
+pub fn telemetry_query(query: TelemetryQuery) -> Result<QueryParams, ApiError> {
+    let mut v = Validator::new();
+
+    let target_names = split_list(query.target_names.as_deref());
+    let key_names = split_list(query.key_names.as_deref());
+
+    let now = Utc::now();
+    let start_time = time_bound(&mut v, "start", query.start.as_deref(), "start_time", query.start_time.as_deref(), now);
+    let end_time = time_bound(&mut v, "end", query.end.as_deref(), "end_time", query.end_time.as_deref(), now);
+    v.check(query.saved.is_none(), "saved", "saved cannot be used here");
+
+    // 解析自定义过滤参数
+    let custom_filter = if query.min_value.is_some() || query.max_value.is_some() || query.exclude_values.is_some() {
+        let mut exclude_values = Vec::new();
+        if let Some(exclude_str) = &query.exclude_values {
+            for (i, s) in exclude_str.split(',').map(str::trim).filter(|s| !s.is_empty()).enumerate() {
+                match s.parse::<f64>() {
+                    Ok(value) if value.is_finite() => exclude_values.push(value),
+                    _ => v.error(format!("exclude_values[{}]", i), format!("Invalid number: {}", s)),
+                }
+            }
+        }
+
+        Some(CustomFilter {
+            min_value: query.min_value,
+            max_value: query.max_value,
+            exclude_values,
+        })
+    } else {
+        None
+    };
+
+    // 构建采样配置
+    let sampling_method = match query.sampling_method.as_deref().unwrap_or("first") {
+        "first" => Some(SamplingMethod::First),
+        "last" => Some(SamplingMethod::Last),
+        "avg" => Some(SamplingMethod::Avg),
+        "max" => Some(SamplingMethod::Max),
+        "min" => Some(SamplingMethod::Min),
+        other => {
+            v.error("sampling_method", format!("Unknown sampling_method: {}, expected one of first, last, avg, max, min", other));
+            None
+        }
+    };
+    let sampling_config = match (query.sampling_interval, sampling_method) {
+        (Some(interval_ms), Some(method)) => Some(SamplingConfig { interval_ms, method }),
+        _ => None,
+    };
+    if let (Some(interval_ms), None) = (query.sampling_interval, &sampling_config) {
+        // 采样方法无效时仍需报告间隔本身的问题
+        v.check(interval_ms > 0, "sampling_interval", "sampling_interval must be a positive number of milliseconds");
+    }
+
+    // 解析参考值配置
+    v.check(
+        query.baseline.is_none() || query.reference_values.is_none(),
+        "baseline",
+        "baseline and reference_values cannot be used together",
+    );
+    if let Some(baseline) = &query.baseline {
+        v.non_empty("baseline", baseline);
+    }
+    let reference_values = match &query.reference_values {
+        Some(ref_str) => match serde_json::from_str::<Vec<ReferenceValue>>(ref_str) {
+            Ok(refs) => Some(refs),
+            Err(e) => {
+                v.error("reference_values", format!("Invalid reference_values format: {}", e));
+                None
+            }
+        },
+        None => None,
+    };
+
+    // 解析每日时间段过滤
+    let time_of_day_filter = match &query.time_ranges {
+        Some(time_ranges_str) => {
+            let time_ranges = parse_time_ranges(&mut v, time_ranges_str);
+            if time_ranges.is_empty() {
+                None
+            } else {
+                Some(TimeOfDayFilter { time_ranges })
+            }
+        }
+        None => None,
+    };
+
+    let params = QueryParams {
+        asset_name: query.asset_name,
+        device_name: query.device_name,
+        target_names,
+        key_names,
+        start_time,
+        end_time,
+        remove_outliers: query.remove_outliers.unwrap_or(false),
+        outlier_method: query.outlier_method.unwrap_or_else(|| "iqr".to_string()),
+        custom_filter,
+        limit: query.limit,
+        sampling_config,
+        reference_values,
+        time_of_day_filter,
+        allowed_assets: None,
+    };
+
+    validate_query_params(&mut v, &params);
+    v.finish()?;
+    Ok(params)
+}
+
+/// 相对当前时间的偏移："now" 或 "-7d" 这样的负数加单位（m, h, d, w）
+pub fn relative_offset(value: &str) -> Option<Duration> {
+    if value == "now" {
+        return Some(Duration::zero());
+    }
+    let digits = value.strip_prefix('-')?;
+    let unit = digits.chars().last()?;
+    let amount: i64 = digits[..digits.len() - unit.len_utf8()].parse().ok()?;
+    match unit {
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        'w' => Duration::try_weeks(amount),
+        _ => None,
+    }
+}
+
+// start/end 接受相对时间或RFC 3339时间，不能与 start_time/end_time 同时使用
+fn time_bound(
+    v: &mut Validator,
+    field: &str,
+    value: Option<&str>,
+    absolute_field: &str,
+    absolute: Option<&str>,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    match (value, absolute) {
+        (Some(_), Some(_)) => {
+            v.error(field, format!("{} and {} cannot be used together", field, absolute_field));
+            None
+        }
+        (Some(value), None) => match relative_offset(value) {
+            Some(offset) => {
+                let time = now.checked_sub_signed(offset);
+                v.check(time.is_some(), field, format!("{} is too far in the past: {}", field, value));
+                time
+            }
+            None => match DateTime::parse_from_rfc3339(value) {
+                Ok(dt) => Some(dt.with_timezone(&Utc)),
+                Err(_) => {
+                    v.error(field, format!("Invalid {}: {}, expected RFC 3339, \"now\" or a relative time such as \"-7d\"", field, value));
+                    None
+                }
+            },
+        },
+        (None, absolute) => v.time(absolute_field, absolute),
+    }
+}
+
+// 拆分逗号分隔的列表，忽略空项
+fn split_list(value: Option<&str>) -> Vec<String> {
+    value
+        .map(|s| {
+            s.split(',')
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .map(|item| item.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// 解析时间字符串 "HH:MM" 格式
+fn parse_time_string(time_str: &str) -> Option<(u8, u8)> {
+    let parts: Vec<&str> = time_str.split(':').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+
+    let hour = parts[0].parse::<u8>().ok()?;
+    let minute = parts[1].parse::<u8>().ok()?;
+
+    if hour > 23 || minute > 59 {
+        return None;
+    }
+
+    Some((hour, minute))
+}
+
+// 解析时间段数组 JSON格式: [{"start":"HH:MM","end":"HH:MM"}]
+fn parse_time_ranges(v: &mut Validator, time_ranges_str: &str) -> Vec<TimeRange> {
+    #[derive(serde::Deserialize)]
+    struct TimeRangeInput {
+        start: String,
+        end: String,
+    }
+
+    let input_ranges: Vec<TimeRangeInput> = match serde_json::from_str(time_ranges_str) {
+        Ok(ranges) => ranges,
+        Err(e) => {
+            v.error("time_ranges", format!("JSON parse error: {}", e));
+            return Vec::new();
+        }
+    };
+
+    let mut time_ranges = Vec::new();
+
+    for (i, input_range) in input_ranges.iter().enumerate() {
+        let start = parse_time_string(&input_range.start);
+        let end = parse_time_string(&input_range.end);
+
+        if start.is_none() {
+            v.error(format!("time_ranges[{}].start", i), format!("Invalid start time format: {}, expected HH:MM", input_range.start));
+        }
+        if end.is_none() {
+            v.error(format!("time_ranges[{}].end", i), format!("Invalid end time format: {}, expected HH:MM", input_range.end));
+        }
+
+        if let (Some((start_hour, start_minute)), Some((end_hour, end_minute))) = (start, end) {
+            time_ranges.push(TimeRange {
+                start_hour,
+                start_minute,
+                end_hour,
+                end_minute,
+            });
+        }
+    }
+
+    time_ranges
+}
+
+/// 已解析的数据操作字段
+pub struct OperationFields {
+    pub operation_type: OperationType,
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+}
+
+/// 校验单个数据操作的字段，prefix为字段路径前缀
+#[allow(clippy::too_many_arguments)]
+pub fn operation_fields(
+    v: &mut Validator,
+    prefix: &str,
+    target_name: &str,
+    key_name: &str,
+    operation_type: &str,
+    value: f64,
+    start_time: Option<&str>,
+    end_time: Option<&str>,
+) -> Option<OperationFields> {
+    v.non_empty(&path(prefix, "target_name"), target_name);
+    v.non_empty(&path(prefix, "key_name"), key_name);
+    v.finite(&path(prefix, "value"), value);
+
+    let parsed_type = OperationType::from_str(operation_type);
+    if parsed_type.is_none() {
+        v.error(
+            path(prefix, "operation_type"),
+            format!("Invalid operation type: {}, expected one of add, subtract, multiply, divide, offset", operation_type),
+        );
+    }
+    if matches!(parsed_type, Some(OperationType::Divide)) && value == 0.0 {
+        v.error(path(prefix, "value"), "Division by zero is not allowed");
+    }
+
+    let start = v.time(&path(prefix, "start_time"), start_time);
+    let end = v.time(&path(prefix, "end_time"), end_time);
+    v.time_order(&path(prefix, "end_time"), start, end);
+
+    Some(OperationFields {
+        operation_type: parsed_type?,
+        start_time: start,
+        end_time: end,
+    })
+}
+
+/// 校验创建操作请求
+pub fn create_operation(request: &CreateOperationRequest) -> Result<OperationFields, ApiError> {
+    let mut v = Validator::new();
+    let fields = operation_fields(
+        &mut v,
+        "",
+        &request.target_name,
+        &request.key_name,
+        &request.operation_type,
+        request.value,
+        request.start_time.as_deref(),
+        request.end_time.as_deref(),
+    );
+    finish_with(v, fields)
+}
+
+/// 校验更新操作请求，路径中的ID必须与请求体一致
+pub fn update_operation(id: i64, request: &UpdateOperationRequest) -> Result<OperationFields, ApiError> {
+    let mut v = Validator::new();
+    v.check(request.id == id, "id", format!("id {} does not match operation {} in path", request.id, id));
+    let fields = operation_fields(
+        &mut v,
+        "",
+        &request.target_name,
+        &request.key_name,
+        &request.operation_type,
+        request.value,
+        request.start_time.as_deref(),
+        request.end_time.as_deref(),
+    );
+    finish_with(v, fields)
+}
+
+/// 解析后的可选时间范围 (start_time, end_time)
+pub type TimeBounds = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// 校验单标靶异常检测请求，返回解析后的时间范围
+pub fn anomaly_request(request: &AnomalyDetectionRequest) -> Result<TimeBounds, ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("target_name", &request.target_name);
+    v.non_empty("key_name", &request.key_name);
+    let time_range = anomaly_common(&mut v, request.start_time.as_deref(), request.end_time.as_deref(), &request.options);
+    if let Some(expected_interval) = request.expected_interval {
+        v.check(expected_interval > 0, "expected_interval", "expected_interval must be a positive number of seconds");
+    }
+    v.finish()?;
+    Ok(time_range)
+}
+
+/// 校验全量异常检测请求，返回解析后的时间范围
+pub fn anomaly_all_request(request: &AnomalyDetectionAllRequest) -> Result<TimeBounds, ApiError> {
+    let mut v = Validator::new();
+    let time_range = anomaly_common(&mut v, request.start_time.as_deref(), request.end_time.as_deref(), &request.options);
+    if request.incremental.unwrap_or(false) || request.full_rebuild.unwrap_or(false) {
+        v.check(request.start_time.is_none(), "start_time", "start_time cannot be used with incremental detection");
+        v.check(request.end_time.is_none(), "end_time", "end_time cannot be used with incremental detection");
+    }
+    for (i, key_name) in request.key_names.iter().flatten().enumerate() {
+        v.non_empty(&format!("key_names[{}]", i), key_name);
+    }
+    if let Some(max_workers) = request.max_workers {
+        v.check((1..=64).contains(&max_workers), "max_workers", "max_workers must be between 1 and 64");
+    }
+    for (key_name, &interval) in request.expected_intervals.iter().flatten() {
+        v.check(!key_name.trim().is_empty(), "expected_intervals", "data type names must not be empty");
+        v.check(interval > 0, format!("expected_intervals.{}", key_name), "expected interval must be a positive number of seconds");
+    }
+    v.finish()?;
+    Ok(time_range)
+}
+
+/// 按请求选择并配置检测器，未选择时使用注册表中的全部检测器
+pub fn detectors(
+    registry: &DetectorRegistry,
+    selections: Option<&[DetectorSelection]>,
+    config: &AnomalyDetectionConfig,
+) -> Result<Vec<Arc<dyn Detector>>, ApiError> {
+    let mut v = Validator::new();
+    let mut detectors = Vec::new();
+    match selections {
+        None => {
+            for detector in registry.detectors() {
+                match detector.configure(config, &serde_json::Value::Null) {
+                    Ok(configured) => detectors.push(configured),
+                    Err(message) => v.error(format!("detectors.{}", detector.name()), message),
+                }
+            }
+        }
+        Some(selections) => {
+            v.check(!selections.is_empty(), "detectors", "at least one detector must be selected");
+            for (i, selection) in selections.iter().enumerate() {
+                let Some(detector) = registry.get(&selection.name) else {
+                    v.error(format!("detectors[{}].name", i), format!("Unknown detector: {}", selection.name));
+                    continue;
+                };
+                match detector.configure(config, &selection.params) {
+                    Ok(configured) => detectors.push(configured),
+                    Err(message) => v.error(format!("detectors[{}].params", i), message),
+                }
+            }
+        }
+    }
+    v.finish()?;
+    Ok(detectors)
+}
+
+/// 校验一致性检测请求，返回解析后的时间范围
+pub fn consensus_request(request: &ConsensusDetectionRequest) -> Result<TimeBounds, ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("asset_name", &request.asset_name);
+    v.non_empty("key_name", &request.key_name);
+    let start = v.time("start_time", request.start_time.as_deref());
+    let end = v.time("end_time", request.end_time.as_deref());
+    v.time_order("end_time", start, end);
+    if let Some(threshold) = request.threshold {
+        v.check(threshold.is_finite() && threshold > 0.0, "threshold", "threshold must be a positive number");
+    }
+    if let Some(min_peers) = request.min_peers {
+        v.check(min_peers >= 1, "min_peers", "min_peers must be at least 1");
+    }
+    v.finish()?;
+    Ok((start, end))
+}
+
+// 滚动速度窗口的上限（一年）
+const MAX_TREND_WINDOW_MS: i64 = 365 * 86_400_000;
+
+/// 校验趋势分析参数，未指定的使用默认值（最小二乘、一天窗口、95%置信度）
+pub fn trend_query(query: &TrendQuery) -> Result<TrendOptions, ApiError> {
+    let mut v = Validator::new();
+    let window_ms = query.window_ms.unwrap_or(86_400_000);
+    v.check(
+        window_ms > 0 && window_ms <= MAX_TREND_WINDOW_MS,
+        "window_ms",
+        format!("window_ms must be between 1 and {}", MAX_TREND_WINDOW_MS),
+    );
+    let confidence = query.confidence.unwrap_or(0.95);
+    v.check(
+        (0.5..=0.999).contains(&confidence),
+        "confidence",
+        "confidence must be between 0.5 and 0.999",
+    );
+    v.finish()?;
+    Ok(TrendOptions {
+        method: query.method.unwrap_or_default(),
+        window_ms,
+        confidence,
+    })
+}
+
+// 预测范围的上限（30天）和最多预测的步数
+const MAX_FORECAST_HORIZON_MS: i64 = 30 * forecast::DAY_MS;
+const MAX_FORECAST_STEPS: i64 = 10_000;
+
+/// 校验预测参数，`interval_ms` 为规整输入用的采样间隔；默认Holt模型、预测3天
+pub fn forecast_query(query: &ForecastQuery, interval_ms: i64) -> Result<ForecastOptions, ApiError> {
+    let mut v = Validator::new();
+    let model = query.model.unwrap_or_default();
+    let horizon_ms = query.horizon_ms.unwrap_or(3 * forecast::DAY_MS);
+    let steps = if interval_ms > 0 { (horizon_ms + interval_ms - 1) / interval_ms } else { 0 };
+    if horizon_ms > 0 && horizon_ms <= MAX_FORECAST_HORIZON_MS {
+        v.check(
+            steps <= MAX_FORECAST_STEPS,
+            "horizon_ms",
+            format!("horizon_ms covers more than {} sampling intervals", MAX_FORECAST_STEPS),
+        );
+    } else {
+        v.error("horizon_ms", format!("horizon_ms must be between 1 and {}", MAX_FORECAST_HORIZON_MS));
+    }
+    if model == ForecastModel::HoltWinters {
+        v.check(
+            interval_ms > 0 && interval_ms < forecast::DAY_MS && forecast::DAY_MS % interval_ms == 0,
+            "sampling_interval",
+            "holt_winters requires a sampling_interval shorter than one day that divides it evenly",
+        );
+    }
+    if let Some(threshold) = query.threshold {
+        v.finite("threshold", threshold);
+    }
+    v.finish()?;
+    Ok(ForecastOptions {
+        model,
+        interval_ms,
+        steps: steps.max(1) as usize,
+        threshold: query.threshold,
+    })
+}
+
+/// 校验季节性分解查询，返回解析后的时间范围
+pub fn decompose_query(query: &DecomposeQuery) -> Result<TimeBounds, ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("target_name", &query.target_name);
+    v.non_empty("key_name", &query.key_name);
+    let start = v.time("start_time", query.start_time.as_deref());
+    let end = v.time("end_time", query.end_time.as_deref());
+    v.time_order("end_time", start, end);
+    v.check(
+        query.seasonality != Some(Seasonality::None),
+        "seasonality",
+        "seasonality must be \"daily\" or \"daily_weekly\"",
+    );
+    v.finish()?;
+    Ok((start, end))
+}
+
+fn anomaly_common(
+    v: &mut Validator,
+    start_time: Option<&str>,
+    end_time: Option<&str>,
+    options: &DetectionOptions,
+) -> TimeBounds {
+    let start = v.time("start_time", start_time);
+    let end = v.time("end_time", end_time);
+    v.time_order("end_time", start, end);
+
+    if let Some(sensitivity) = options.sensitivity {
+        v.check(sensitivity.is_finite() && sensitivity > 0.0, "sensitivity", "sensitivity must be a positive number");
+    }
+    if let Some(gap_multiplier) = options.gap_multiplier {
+        v.check(gap_multiplier.is_finite() && gap_multiplier >= 1.0, "gap_multiplier", "gap_multiplier must be at least 1");
+    }
+    if let Some(flatline_min_points) = options.flatline_min_points {
+        v.check(flatline_min_points >= 2, "flatline_min_points", "flatline_min_points must be at least 2");
+    }
+    if let Some(flatline_tolerance) = options.flatline_tolerance {
+        v.check(
+            flatline_tolerance.is_finite() && flatline_tolerance >= 0.0,
+            "flatline_tolerance",
+            "flatline_tolerance must be a non-negative number",
+        );
+    }
+    if let Some(flatline_min_duration) = options.flatline_min_duration {
+        v.check(flatline_min_duration > 0, "flatline_min_duration", "flatline_min_duration must be a positive number of seconds");
+    }
+    for (key_name, limits) in options.value_limits.iter().flatten() {
+        let prefix = format!("value_limits.{}", key_name);
+        v.check(!key_name.trim().is_empty(), "value_limits", "data type names must not be empty");
+        if let Some(min) = limits.min {
+            v.finite(&path(&prefix, "min"), min);
+        }
+        if let Some(max) = limits.max {
+            v.finite(&path(&prefix, "max"), max);
+        }
+        if let (Some(min), Some(max)) = (limits.min, limits.max) {
+            v.check(min <= max, path(&prefix, "max"), "max must not be less than min");
+        }
+        if let Some(max_rate) = limits.max_rate {
+            v.check(max_rate.is_finite() && max_rate > 0.0, path(&prefix, "max_rate"), "max_rate must be a positive number");
+        }
+    }
+
+    (start, end)
+}
+
+// 异常和检测批次列表单次最多返回的条数
+const MAX_LIST_LIMIT: usize = 1000;
+
+// 审核意见最大长度
+const MAX_COMMENT_LENGTH: usize = 1000;
+
+fn list_limit(v: &mut Validator, limit: Option<usize>, default: usize) -> usize {
+    let limit = limit.unwrap_or(default);
+    v.check(
+        (1..=MAX_LIST_LIMIT).contains(&limit),
+        "limit",
+        format!("limit must be between 1 and {}", MAX_LIST_LIMIT),
+    );
+    limit
+}
+
+/// 校验异常列表查询参数
+pub fn anomaly_list_query(query: AnomalyListQuery) -> Result<AnomalyQuery, ApiError> {
+    let mut v = Validator::new();
+    let status = query.status.as_deref().and_then(|s| {
+        let status = AnomalyStatus::from_str(s);
+        v.check(
+            status.is_some(),
+            "status",
+            format!("Unknown status: {}, expected \"new\", \"accepted\", \"rejected\" or \"fixed\"", s),
+        );
+        status
+    });
+    let limit = list_limit(&mut v, query.limit, 100);
+    v.finish()?;
+
+    Ok(AnomalyQuery {
+        status,
+        run_id: query.run_id,
+        target_name: query.target_name.filter(|s| !s.is_empty()),
+        key_name: query.key_name.filter(|s| !s.is_empty()),
+        allowed_assets: None,
+        limit,
+    })
+}
+
+pub fn anomaly_run_query(query: &AnomalyRunQuery) -> Result<usize, ApiError> {
+    let mut v = Validator::new();
+    let limit = list_limit(&mut v, query.limit, 20);
+    v.finish()?;
+    Ok(limit)
+}
+
+pub fn review_anomaly(request: &ReviewAnomalyRequest) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    if let Some(comment) = &request.comment {
+        v.check(
+            comment.chars().count() <= MAX_COMMENT_LENGTH,
+            "comment",
+            format!("comment must be at most {} characters", MAX_COMMENT_LENGTH),
+        );
+    }
+    v.finish()
+}
+
+pub fn notification_list_query(query: &NotificationListQuery) -> Result<(Option<NotificationStatus>, usize), ApiError> {
+    let mut v = Validator::new();
+    let status = query.status.as_deref().and_then(|s| {
+        let status = NotificationStatus::from_str(s);
+        v.check(
+            status.is_some(),
+            "status",
+            format!("Unknown status: {}, expected \"pending\", \"sent\" or \"failed\"", s),
+        );
+        status
+    });
+    let limit = list_limit(&mut v, query.limit, 100);
+    v.finish()?;
+    Ok((status, limit))
+}
+
+pub fn test_notification(request: &TestNotificationRequest) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    if let Some(channel) = &request.channel {
+        v.non_empty("channel", channel);
+    }
+    if let Some(message) = &request.message {
+        v.non_empty("message", message);
+        v.check(
+            message.chars().count() <= MAX_COMMENT_LENGTH,
+            "message",
+            format!("message must be at most {} characters", MAX_COMMENT_LENGTH),
+        );
+    }
+    v.finish()
+}
+
+/// 校验告警规则请求，返回解析后的累计变化起算时间
+pub fn alarm_rule(request: &AlarmRuleRequest) -> Result<Option<DateTime<Utc>>, ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("name", &request.name);
+    v.non_empty("key_name", &request.key_name);
+    for (field, value) in [
+        ("asset_name", &request.asset_name),
+        ("device_name", &request.device_name),
+        ("target_name", &request.target_name),
+    ] {
+        if let Some(value) = value {
+            v.non_empty(field, value);
+        }
+    }
+
+    let direction = request.direction.unwrap_or(AlarmDirection::Above);
+    v.check(!request.levels.is_empty(), "levels", "At least one level is required");
+    let mut levels: Vec<(usize, &AlarmThreshold)> = request.levels.iter().enumerate().collect();
+    for &(i, level) in &levels {
+        v.finite(&format!("levels[{}].threshold", i), level.threshold);
+        if direction == AlarmDirection::Both {
+            v.check(level.threshold > 0.0, format!("levels[{}].threshold", i), "threshold must be positive when direction is \"both\"");
+        }
+    }
+    // 级别越高阈值越严格
+    levels.sort_by_key(|(_, level)| level.level);
+    for pair in levels.windows(2) {
+        let ((_, lower), (i, higher)) = (pair[0], pair[1]);
+        if lower.level == higher.level {
+            v.error(format!("levels[{}].level", i), format!("Duplicate level: {}", higher.level.as_str()));
+        } else if severity_order(direction, lower.threshold, higher.threshold) {
+            v.error(
+                format!("levels[{}].threshold", i),
+                format!("threshold of {} must be more severe than that of {}", higher.level.as_str(), lower.level.as_str()),
+            );
+        }
+    }
+
+    if let Some(hysteresis) = request.hysteresis {
+        v.check(hysteresis.is_finite() && hysteresis >= 0.0, "hysteresis", "hysteresis must be a non-negative number");
+    }
+    let metric = request.metric.unwrap_or(AlarmMetric::Value);
+    match (metric, request.rate_window) {
+        (AlarmMetric::Rate, None) => v.error("rate_window", "rate_window is required when metric is \"rate\""),
+        (AlarmMetric::Rate, Some(window)) => v.check(window > 0, "rate_window", "rate_window must be a positive number of seconds"),
+        (_, Some(_)) => v.error("rate_window", "rate_window is only used when metric is \"rate\""),
+        (_, None) => {}
+    }
+    let reference_time = v.time("reference_time", request.reference_time.as_deref());
+    match metric {
+        AlarmMetric::Cumulative => v.check(
+            request.reference_time.is_some(),
+            "reference_time",
+            "reference_time is required when metric is \"cumulative\"",
+        ),
+        _ => v.check(
+            request.reference_time.is_none(),
+            "reference_time",
+            "reference_time is only used when metric is \"cumulative\"",
+        ),
+    }
+    v.finish()?;
+    Ok(reference_time)
+}
+
+// 较高级别的阈值不比较低级别的更严格
+fn severity_order(direction: AlarmDirection, lower: f64, higher: f64) -> bool {
+    match direction {
+        AlarmDirection::Above | AlarmDirection::Both => higher <= lower,
+        AlarmDirection::Below => higher >= lower,
+    }
+}
+
+/// 校验告警事件查询参数
+pub fn alarm_list_query(query: AlarmListQuery) -> Result<AlarmQuery, ApiError> {
+    let mut v = Validator::new();
+    let active = query.status.as_deref().and_then(|s| match s {
+        "active" => Some(true),
+        "cleared" => Some(false),
+        _ => {
+            v.error("status", format!("Unknown status: {}, expected \"active\" or \"cleared\"", s));
+            None
+        }
+    });
+    let min_level = query.min_level.as_deref().and_then(|s| {
+        let level = AlarmLevel::from_str(s);
+        v.check(
+            level.is_some(),
+            "min_level",
+            format!("Unknown level: {}, expected \"blue\", \"yellow\", \"orange\" or \"red\"", s),
+        );
+        level
+    });
+    let start_time = v.time("start_time", query.start_time.as_deref());
+    let end_time = v.time("end_time", query.end_time.as_deref());
+    v.time_order("end_time", start_time, end_time);
+    let limit = list_limit(&mut v, query.limit, 100);
+    v.finish()?;
+
+    Ok(AlarmQuery {
+        active,
+        acknowledged: query.acknowledged,
+        min_level,
+        rule_id: query.rule_id,
+        target_name: query.target_name.filter(|s| !s.is_empty()),
+        key_name: query.key_name.filter(|s| !s.is_empty()),
+        start_time,
+        end_time,
+        allowed_assets: None,
+        limit,
+    })
+}
+
+pub fn acknowledge_alarm(request: &AcknowledgeAlarmRequest) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    if let Some(comment) = &request.comment {
+        v.check(
+            comment.chars().count() <= MAX_COMMENT_LENGTH,
+            "comment",
+            format!("comment must be at most {} characters", MAX_COMMENT_LENGTH),
+        );
+    }
+    v.finish()
+}
+
+// 保存的查询最多的标签数
+const MAX_TAGS: usize = 20;
+
+/// 校验保存的查询，返回查询参数和相对时间范围
+///
+/// start/end 为相对时间时保存原文，回放时重新计算；其他时间保存为绝对时间
+pub fn saved_query(request: &SavedQueryRequest) -> Result<(QueryParams, Option<String>, Option<String>), ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("name", &request.name);
+    if let Some(description) = &request.description {
+        v.check(
+            description.chars().count() <= MAX_COMMENT_LENGTH,
+            "description",
+            format!("description must be at most {} characters", MAX_COMMENT_LENGTH),
+        );
+    }
+    let tags = request.tags.as_deref().unwrap_or(&[]);
+    v.check(tags.len() <= MAX_TAGS, "tags", format!("At most {} tags are allowed", MAX_TAGS));
+    for (i, tag) in tags.iter().enumerate() {
+        v.non_empty(&format!("tags[{}]", i), tag);
+    }
+
+    let result = telemetry_query(request.query.clone());
+    if let Err(ApiError::Validation(errors)) = &result {
+        for error in errors {
+            v.error(path("query", &error.field), error.message.clone());
+        }
+    }
+    v.finish()?;
+
+    let mut params = result?;
+    let relative = |value: &Option<String>| value.clone().filter(|s| relative_offset(s).is_some());
+    let (relative_start, relative_end) = (relative(&request.query.start), relative(&request.query.end));
+    if relative_start.is_some() {
+        params.start_time = None;
+    }
+    if relative_end.is_some() {
+        params.end_time = None;
+    }
+    Ok((params, relative_start, relative_end))
+}
+
+/// 回放保存的查询：只能另外指定时间范围，相对时间按当前时间计算
+pub fn replay_saved_query(query: &TelemetryQuery, saved: SavedQuery) -> Result<QueryParams, ApiError> {
+    let mut v = Validator::new();
+    for (field, present) in [
+        ("asset_name", query.asset_name.is_some()),
+        ("device_name", query.device_name.is_some()),
+        ("target_names", query.target_names.is_some()),
+        ("key_names", query.key_names.is_some()),
+        ("remove_outliers", query.remove_outliers.is_some()),
+        ("outlier_method", query.outlier_method.is_some()),
+        ("min_value", query.min_value.is_some()),
+        ("max_value", query.max_value.is_some()),
+        ("exclude_values", query.exclude_values.is_some()),
+        ("limit", query.limit.is_some()),
+        ("sampling_interval", query.sampling_interval.is_some()),
+        ("sampling_method", query.sampling_method.is_some()),
+        ("reference_values", query.reference_values.is_some()),
+        ("time_ranges", query.time_ranges.is_some()),
+        ("baseline", query.baseline.is_some()),
+    ] {
+        v.check(!present, field, format!("{} cannot be used with saved, only the time range can be overridden", field));
+    }
+
+    let now = Utc::now();
+    let mut params = saved.query;
+    let resolve = |relative: &Option<String>| relative.as_deref().and_then(relative_offset).and_then(|offset| now.checked_sub_signed(offset));
+    params.start_time = match time_bound(&mut v, "start", query.start.as_deref(), "start_time", query.start_time.as_deref(), now) {
+        Some(start) => Some(start),
+        None if query.start.is_none() && query.start_time.is_none() => resolve(&saved.relative_start).or(params.start_time),
+        None => None,
+    };
+    params.end_time = match time_bound(&mut v, "end", query.end.as_deref(), "end_time", query.end_time.as_deref(), now) {
+        Some(end) => Some(end),
+        None if query.end.is_none() && query.end_time.is_none() => resolve(&saved.relative_end).or(params.end_time),
+        None => None,
+    };
+    v.time_order("end_time", params.start_time, params.end_time);
+    v.finish()?;
+    Ok(params)
+}
+
+/// 校验基准：名称非空，至少一个参考值
+pub fn baseline(request: &BaselineRequest) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("name", &request.name);
+    if let Some(description) = &request.description {
+        v.check(
+            description.chars().count() <= MAX_COMMENT_LENGTH,
+            "description",
+            format!("description must be at most {} characters", MAX_COMMENT_LENGTH),
+        );
+    }
+    v.check(!request.reference_values.is_empty(), "reference_values", "At least one reference value is required");
+    reference_entries(&mut v, "reference_values", &request.reference_values);
+    v.finish()
+}
+
+// 派生指标名称的最大长度
+const MAX_DERIVED_NAME_LENGTH: usize = 64;
+// 对齐时间戳的最大容差：1天
+const MAX_TOLERANCE_MS: i64 = 86_400_000;
+// 表达式的最大长度，避免解析和生成的SQL过大
+const MAX_EXPRESSION_LENGTH: usize = 1000;
+
+/// 校验派生指标：表达式能解析，用到的变量都有输入，每个输入都被用到
+pub fn derived_metric(request: &DerivedMetricRequest) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("name", &request.name);
+    v.check(
+        request.name.trim().chars().count() <= MAX_DERIVED_NAME_LENGTH,
+        "name",
+        format!("name must be at most {} characters", MAX_DERIVED_NAME_LENGTH),
+    );
+    if let Some(description) = &request.description {
+        v.check(
+            description.chars().count() <= MAX_COMMENT_LENGTH,
+            "description",
+            format!("description must be at most {} characters", MAX_COMMENT_LENGTH),
+        );
+    }
+    if let Some(tolerance) = request.tolerance_ms {
+        v.check(
+            (0..=MAX_TOLERANCE_MS).contains(&tolerance),
+            "tolerance_ms",
+            format!("tolerance_ms must be between 0 and {}", MAX_TOLERANCE_MS),
+        );
+    }
+
+    v.check(!request.inputs.is_empty(), "inputs", "At least one input is required");
+    let mut defined = std::collections::BTreeSet::new();
+    for (i, input) in request.inputs.iter().enumerate() {
+        let field = |name: &str| format!("inputs[{}].{}", i, name);
+        if !expression::is_valid_variable(&input.variable) {
+            v.error(field("variable"), format!(
+                "Invalid variable name: {}, expected letters, digits and underscores, not a function name or pi",
+                input.variable
+            ));
+        } else if !defined.insert(input.variable.as_str()) {
+            v.error(field("variable"), format!("Duplicate variable: {}", input.variable));
+        }
+        v.non_empty(&field("key_name"), &input.key_name);
+        v.check(
+            input.key_name != request.name.trim(),
+            field("key_name"),
+            "A derived metric cannot use itself as input",
+        );
+        if let Some(target_name) = &input.target_name {
+            v.non_empty(&field("target_name"), target_name);
+        }
+    }
+
+    if request.expression.chars().count() > MAX_EXPRESSION_LENGTH {
+        v.error("expression", format!("expression must be at most {} characters", MAX_EXPRESSION_LENGTH));
+        return v.finish();
+    }
+    match expression::parse(&request.expression) {
+        Ok(expr) => {
+            let used = expr.variables();
+            for variable in used.difference(&defined) {
+                v.error("expression", format!("Undefined variable: {}", variable));
+            }
+            for (i, input) in request.inputs.iter().enumerate() {
+                if defined.contains(input.variable.as_str()) && !used.contains(input.variable.as_str()) {
+                    v.error(format!("inputs[{}].variable", i), format!("Variable {} is not used in the expression", input.variable));
+                }
+            }
+        }
+        Err(message) => v.error("expression", message),
+    }
+    v.finish()
+}
+
+// 密码最短长度
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+pub fn login_request(request: &LoginRequest) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("username", &request.username);
+    v.check(!request.password.is_empty(), "password", "password must not be empty");
+    v.finish()
+}
+
+fn password(v: &mut Validator, password: &str) {
+    v.check(
+        password.chars().count() >= MIN_PASSWORD_LENGTH,
+        "password",
+        format!("password must be at least {} characters", MIN_PASSWORD_LENGTH),
+    );
+}
+
+fn assets(v: &mut Validator, assets: &[String]) {
+    for (i, asset) in assets.iter().enumerate() {
+        v.non_empty(&format!("assets[{}]", i), asset);
+    }
+}
+
+pub fn create_user(request: &CreateUserRequest) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("username", &request.username);
+    v.check(request.username.chars().count() <= 64, "username", "username must be at most 64 characters");
+    password(&mut v, &request.password);
+    assets(&mut v, request.assets.as_deref().unwrap_or_default());
+    v.finish()
+}
+
+/// 管理员不能停用自己或修改自己的角色，避免失去管理权限
+pub fn update_user(id: i64, current_user_id: i64, request: &UpdateUserRequest) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    if let Some(new_password) = &request.password {
+        password(&mut v, new_password);
+    }
+    if let Some(new_assets) = &request.assets {
+        assets(&mut v, new_assets);
+    }
+    if id == current_user_id {
+        v.check(request.role.is_none(), "role", "You cannot change your own role");
+        v.check(request.is_active != Some(false), "is_active", "You cannot deactivate your own account");
+    }
+    v.finish()
+}
+
+pub fn create_token(request: &CreateTokenRequest) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    v.non_empty("name", &request.name);
+    if let Some(days) = request.expires_in_days {
+        v.check((1..=3650).contains(&days), "expires_in_days", "expires_in_days must be between 1 and 3650");
+    }
+    v.finish()
+}
+
+// 没有错误时返回解析结果
+fn finish_with<T>(v: Validator, value: Option<T>) -> Result<T, ApiError> {
+    v.finish()?;
+    // 校验通过时解析结果必然存在
+    Ok(value.expect("validated fields must be parsed"))
+}
@@ -1,3 +1,35 @@
+// 接口返回401（未登录或会话过期）时跳转到登录页
+const originalFetch = window.fetch.bind(window);
+window.fetch = async function(input, init) {
+    const response = await originalFetch(input, init);
+    const url = typeof input === 'string' ? input : input.url;
+    if (response.status === 401 && url.startsWith('/api/')) {
+        redirectToLogin();
+    }
+    return response;
+};
+
+function redirectToLogin() {
+    const next = encodeURIComponent(window.location.pathname + window.location.search);
+    window.location.href = `login.html?next=${next}`;
+}
+
+// 在页头显示当前用户
+async function loadCurrentUser() {
+    const response = await fetch('/api/auth/me');
+    if (!response.ok) return;
+    const result = await response.json();
+    const userInfo = document.getElementById('currentUser');
+    if (userInfo && result.success) {
+        userInfo.textContent = `${result.data.username} (${result.data.role})`;
+    }
+}
+
+async function logout() {
+    await fetch('/api/auth/logout', { method: 'POST' });
+    redirectToLogin();
+}
+
 // 全局变量
 let filterOptions = {};
 let currentData = [];
@@ -2178,6 +2210,7 @@ function loadTimeRanges() {
 
 // 页面加载时初始化
 document.addEventListener('DOMContentLoaded', function() {
+    loadCurrentUser();
     setupEventListeners();
     initializeChart();
     loadFilterOptions();
@@ -2766,25 +2799,17 @@ async function saveOperation() {
             });
         }
         
-        // 先检查响应是否正常
-        if (!response.ok) {
-            const text = await response.text();
-            console.error('响应错误:', response.status, text);
-            showError(`保存失败: HTTP ${response.status}`);
-            return;
-        }
-        
         // 获取响应文本
         const responseText = await response.text();
         console.log('服务器响应:', responseText);
         
-        // 尝试解析JSON
+        // 尝试解析JSON（错误响应同样是JSON格式）
         let result;
         try {
             result = JSON.parse(responseText);
         } catch (jsonError) {
-            console.error('JSON解析失败:', responseText);
-            showError('保存失败: 服务器返回了无效的JSON格式');
+            console.error('响应错误:', response.status, responseText);
+            showError(response.ok ? '保存失败: 服务器返回了无效的JSON格式' : `保存失败: HTTP ${response.status}`);
             return;
         }
         